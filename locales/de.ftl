@@ -0,0 +1,38 @@
+backup = { $kind ->
+    [full] Vollsicherung
+    [uploads] Sicherung der Medien
+    [code] Sicherung des Codes
+    [database] Datenbanksicherung
+    [config] Sicherung der Konfiguration
+   *[other] Sicherung ({ $kind })
+}
+
+## Notifications
+
+subject-success = mer-de-glace: { backup } von { $site } erfolgreich
+subject-suspicious = mer-de-glace: { backup } von { $site } erfolgreich, aber verdächtig
+subject-failure = mer-de-glace: { backup } von { $site } fehlgeschlagen
+email-success = Die { backup } von { $site } war nach { $duration } erfolgreich, das Archiv belegt { $size }.
+email-suspicious = Die { backup } von { $site } war nach { $duration } erfolgreich, das Archiv belegt { $size }, wirkt aber verdächtig: { $reason }
+email-failure = Die { backup } von { $site } ist nach { $duration } fehlgeschlagen: { $error }
+email-attestation = Der Lauf ist im Attestierungsprotokoll vermerkt, Kopf { $head }.
+subject-sla-breached = mer-de-glace: SLA der { backup } von { $site } verletzt
+subject-sla-met = mer-de-glace: SLA der { backup } von { $site } wieder eingehalten
+email-sla-breached = Die letzte erfolgreiche { backup } von { $site } liegt { $age } zurück, das SLA beträgt { $sla } (Eskalationsstufe { $level }).
+email-sla-met = Die { backup } von { $site } hält ihr SLA von { $sla } wieder ein.
+subject-bit-rot = mer-de-glace: Archiv der { backup } von { $site } beschädigt
+email-bit-rot = { $corrupt } von { $checked } geprüften 1-MB-Blöcken von { $archive } passen nicht mehr zu ihrem Hash (Blöcke { $chunks }). Rufen Sie das Archiv bei Bedarf aus Glacier ab.
+
+## describe --human
+
+date-format = %d.%m.%Y um %H:%M
+describe-taken = Dies ist eine { backup } von { $site }, erstellt am { $date } UTC.
+describe-unnamed-site = Ihrer WordPress-Website
+describe-wordpress = Auf der Website lief WordPress { $version }.
+describe-contents = Sie enthält { $database } und { $files } Dateien (davon { $media } Mediendateien) mit insgesamt { $size }.
+describe-database = eine Kopie der Datenbank mit { $size }
+describe-no-database = keine Kopie der Datenbank
+describe-compressed = Komprimiert belegt die Sicherung { $size }.
+describe-local-copy = Eine Kopie liegt auf dem Sicherungsserver unter { $path }.
+describe-uploaded = Sie wurde am { $date } UTC im AWS-Glacier-Tresor „{ $vault }“ ({ $region }) gespeichert, Archiv-ID { $id }.
+describe-not-uploaded = Es gibt keinen Nachweis, dass sie in AWS Glacier gespeichert wurde.
@@ -0,0 +1,40 @@
+# Text for people, in Fluent syntax (https://projectfluent.org). Copy this file to translate or reword any of it,
+# LOCALE_FILE overrides the messages it defines. $kind is one of full, uploads, code, database and config.
+
+# the backup of a kind, referred to by the messages below
+backup = { $kind } backup
+
+## Notifications: the subject, and the body of emails to SNS subscriptions; other subscriptions and SQS get the run as JSON
+
+subject-success = mer-de-glace: { backup } of { $site } succeeded
+subject-suspicious = mer-de-glace: { backup } of { $site } succeeded but looks suspicious
+subject-failure = mer-de-glace: { backup } of { $site } failed
+email-success = The { backup } of { $site } succeeded in { $duration }, the archive takes { $size }.
+email-suspicious = The { backup } of { $site } succeeded in { $duration }, the archive takes { $size }, but it looks suspicious: { $reason }
+email-failure = The { backup } of { $site } failed after { $duration }: { $error }
+email-attestation = The run is recorded in the attestation log, head { $head }.
+
+# SLA alerts, $age is the time since the last successful backup and $level from 1 (breached) to 3 (breached twice over)
+subject-sla-breached = mer-de-glace: SLA of the { backup } of { $site } breached
+subject-sla-met = mer-de-glace: SLA of the { backup } of { $site } met again
+email-sla-breached = The last successful { backup } of { $site } was { $age } ago, the SLA is { $sla } (escalation level { $level }).
+email-sla-met = The { backup } of { $site } meets its SLA of { $sla } again.
+
+# bit rot found by integrity sampling, $chunks lists the corrupt 1 MB chunks of $archive
+subject-bit-rot = mer-de-glace: bit rot in a { backup } archive of { $site }
+email-bit-rot = { $corrupt } of { $checked } sampled 1 MB chunks of { $archive } no longer match their hash (chunks { $chunks }). Retrieve the archive from Glacier if it is needed.
+
+## describe --human
+
+# strftime, see https://docs.rs/chrono/0.4/chrono/format/strftime
+date-format = %B %-d, %Y at %H:%M
+describe-taken = This is a { backup } of { $site }, taken on { $date } UTC.
+describe-unnamed-site = the wordpress site
+describe-wordpress = The site was running WordPress { $version }.
+describe-contents = It contains { $database } and { $files } files ({ $media } of them media files) totalling { $size }.
+describe-database = a { $size } copy of the database
+describe-no-database = no database copy
+describe-compressed = Compressed, the backup takes { $size }.
+describe-local-copy = A copy is kept on the backup server at { $path }.
+describe-uploaded = It was stored in the AWS Glacier vault "{ $vault }" ({ $region }) on { $date } UTC, archive id { $id }.
+describe-not-uploaded = There is no record of it being stored in AWS Glacier.
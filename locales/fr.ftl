@@ -0,0 +1,38 @@
+backup = { $kind ->
+    [full] sauvegarde complète
+    [uploads] sauvegarde des médias
+    [code] sauvegarde du code
+    [database] sauvegarde de la base de données
+    [config] sauvegarde de la configuration
+   *[other] sauvegarde { $kind }
+}
+
+## Notifications
+
+subject-success = mer-de-glace : { backup } de { $site } réussie
+subject-suspicious = mer-de-glace : { backup } de { $site } réussie mais suspecte
+subject-failure = mer-de-glace : échec de la { backup } de { $site }
+email-success = La { backup } de { $site } a réussi en { $duration }, l'archive occupe { $size }.
+email-suspicious = La { backup } de { $site } a réussi en { $duration }, l'archive occupe { $size }, mais elle semble suspecte : { $reason }
+email-failure = La { backup } de { $site } a échoué après { $duration } : { $error }
+email-attestation = L'exécution est consignée dans le journal d'attestation, tête { $head }.
+subject-sla-breached = mer-de-glace : SLA de la { backup } de { $site } non respecté
+subject-sla-met = mer-de-glace : SLA de la { backup } de { $site } de nouveau respecté
+email-sla-breached = La dernière { backup } réussie de { $site } remonte à { $age }, le SLA est de { $sla } (niveau d'escalade { $level }).
+email-sla-met = La { backup } de { $site } respecte de nouveau son SLA de { $sla }.
+subject-bit-rot = mer-de-glace : archive de { backup } de { $site } altérée
+email-bit-rot = { $corrupt } des { $checked } blocs de 1 Mo échantillonnés de { $archive } ne correspondent plus à leur empreinte (blocs { $chunks }). Récupérez l'archive depuis Glacier si elle est nécessaire.
+
+## describe --human
+
+date-format = %d/%m/%Y à %H:%M
+describe-taken = Ceci est une { backup } de { $site }, effectuée le { $date } UTC.
+describe-unnamed-site = votre site WordPress
+describe-wordpress = Le site utilisait WordPress { $version }.
+describe-contents = Elle contient { $database } et { $files } fichiers (dont { $media } fichiers multimédias), soit { $size } au total.
+describe-database = une copie de la base de données de { $size }
+describe-no-database = aucune copie de la base de données
+describe-compressed = Compressée, la sauvegarde occupe { $size }.
+describe-local-copy = Une copie est conservée sur le serveur de sauvegarde dans { $path }.
+describe-uploaded = Elle a été stockée le { $date } UTC dans le coffre AWS Glacier « { $vault } » ({ $region }), identifiant d'archive { $id }.
+describe-not-uploaded = Rien n'indique qu'elle ait été stockée dans AWS Glacier.
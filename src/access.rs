@@ -0,0 +1,193 @@
+// Who may use the admin server. `ADMIN_ALLOW=10.0.0.0/8,192.168.1.20` limits it to those clients, probes included.
+// `/status` and on-demand backups take `ADMIN_TOKEN` as a bearer token or `ADMIN_BASIC_AUTH=user:password` when either is set (or their `_REF`);
+// without them on-demand backups are only accepted from the host itself. The probes never ask for credentials.
+
+use crate::secrets::Secret;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An address, or a network in CIDR notation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once ('/') {
+            Some ((address, prefix)) => (address, Some (prefix)),
+            None => (s, None)
+        };
+        let address = address.parse::<IpAddr>()
+            .map_err (|_| anyhow::anyhow!("Invalid address {} in ADMIN_ALLOW, expected e.g. 10.0.0.0/8 or 192.168.1.20", s))?;
+        let bits = if address.is_ipv4 () { 32 } else { 128 };
+        let prefix = match prefix {
+            Some (prefix) => prefix.parse::<u8>().ok ().filter (|prefix| *prefix <= bits)
+                .ok_or_else (|| anyhow::anyhow!("Invalid prefix length in {}, expected at most {}", s, bits))?,
+            None => bits
+        };
+        Ok (Network { address, prefix })
+    }
+}
+
+impl Network {
+    pub fn contains (&self, ip: IpAddr) -> bool {
+        match (self.address, unmapped (ip)) {
+            (IpAddr::V4 (network), IpAddr::V4 (ip)) =>
+                u32::from (network).checked_shr (32 - self.prefix as u32).unwrap_or (0) == u32::from (ip).checked_shr (32 - self.prefix as u32).unwrap_or (0),
+            (IpAddr::V6 (network), IpAddr::V6 (ip)) =>
+                u128::from (network).checked_shr (128 - self.prefix as u32).unwrap_or (0) == u128::from (ip).checked_shr (128 - self.prefix as u32).unwrap_or (0),
+            _ => false
+        }
+    }
+}
+
+pub fn parse_allow (s: &str) -> Result<Vec<Network>, anyhow::Error> {
+    s.split (',')
+        .map (str::trim)
+        .filter (|network| !network.is_empty ())
+        .map (Network::from_str)
+        .collect ()
+}
+
+#[derive(Clone, Default)]
+pub struct Access {
+    /// clients allowed to connect, anyone if empty
+    pub allow: Vec<Network>,
+    /// the bearer token, or a reference to it in a secret store
+    pub token: Option<Secret>,
+    /// `user:password`, or a reference to it in a secret store
+    pub basic: Option<Secret>,
+}
+
+impl fmt::Debug for Access {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct ("Access")
+            .field ("allow", &self.allow)
+            .field ("token", &self.token)
+            .field ("basic", &self.basic)
+            .finish ()
+    }
+}
+
+impl Access {
+    pub fn allows (&self, ip: IpAddr) -> bool {
+        self.allow.is_empty () || self.allow.iter ().any (|network| network.contains (ip))
+    }
+
+    pub fn requires_credentials (&self) -> bool {
+        self.token.is_some () || self.basic.is_some ()
+    }
+
+    /// Whether the `Authorization` header carries the token or the user and password, with the secrets as resolved.
+    pub fn authorized (token: Option<&str>, basic: Option<&str>, authorization: Option<&str>) -> bool {
+        let (scheme, credentials) = match authorization.and_then (|authorization| authorization.split_once (' ')) {
+            Some (split) => split,
+            None => return false
+        };
+        match (scheme.to_ascii_lowercase ().as_str (), token, basic) {
+            ("bearer", Some (token), _) => constant_time_eq (credentials.trim ().as_bytes (), token.as_bytes ()),
+            ("basic", _, Some (basic)) => base64::decode (credentials.trim ())
+                .is_ok_and (|decoded| constant_time_eq (&decoded, basic.as_bytes ())),
+            _ => false
+        }
+    }
+}
+
+/// IPv4 clients of a server bound to an IPv6 address show up mapped.
+fn unmapped (ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6 (ip) => ip.to_ipv4_mapped ().map_or (IpAddr::V6 (ip), IpAddr::V4),
+        ip => ip
+    }
+}
+
+pub fn is_local (ip: IpAddr) -> bool {
+    unmapped (ip).is_loopback ()
+}
+
+/// Compares without giving away through timing how much of `a` matches.
+fn constant_time_eq (a: &[u8], b: &[u8]) -> bool {
+    a.len () == b.len () && a.iter ().zip (b).fold (0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network (s: &str) -> Network {
+        s.parse ().unwrap ()
+    }
+
+    fn ip (s: &str) -> IpAddr {
+        s.parse ().unwrap ()
+    }
+
+    #[test]
+    fn ipv4_networks () {
+        assert!(network ("0.0.0.0/0").contains (ip ("203.0.113.7")));
+        assert!(network ("10.0.0.0/8").contains (ip ("10.255.1.2")));
+        assert!(!network ("10.0.0.0/8").contains (ip ("11.0.0.1")));
+        assert!(network ("192.168.1.20/32").contains (ip ("192.168.1.20")));
+        assert!(!network ("192.168.1.20/32").contains (ip ("192.168.1.21")));
+        assert_eq!(network ("192.168.1.20"), network ("192.168.1.20/32"));
+        // clients of a server bound to ::
+        assert!(network ("10.0.0.0/8").contains (ip ("::ffff:10.1.2.3")));
+        assert!(!network ("10.0.0.0/8").contains (ip ("fd00::1")));
+    }
+
+    #[test]
+    fn ipv6_networks () {
+        assert!(network ("::/0").contains (ip ("2001:db8::1")));
+        assert!(network ("2001:db8::/32").contains (ip ("2001:db8:ffff::1")));
+        assert!(!network ("2001:db8::/32").contains (ip ("2001:db9::1")));
+        assert!(network ("fd00::1/128").contains (ip ("fd00::1")));
+        assert!(!network ("fd00::1/128").contains (ip ("fd00::2")));
+        assert!(!network ("::/0").contains (ip ("10.0.0.1")));
+    }
+
+    #[test]
+    fn invalid_networks () {
+        for s in ["10.0.0.0/33", "::/129", "10.0.0.0/-1", "10.0.0/8", "example.com", "10.0.0.0/"] {
+            assert!(s.parse::<Network>().is_err (), "{}", s);
+        }
+        assert_eq!(parse_allow (" 10.0.0.0/8, ,::1 ").unwrap (), vec! [network ("10.0.0.0/8"), network ("::1")]);
+    }
+
+    #[test]
+    fn allow_list () {
+        let access = Access { allow: parse_allow ("10.0.0.0/8,::1").unwrap (), ..Access::default () };
+        assert!(access.allows (ip ("10.1.1.1")));
+        assert!(access.allows (ip ("::1")));
+        assert!(!access.allows (ip ("127.0.0.1")));
+        assert!(Access::default ().allows (ip ("203.0.113.7")));
+    }
+
+    #[test]
+    fn bearer_and_basic_credentials () {
+        let basic = format!("Basic {}", base64::encode ("admin:secret"));
+        assert!(Access::authorized (Some ("token"), None, Some ("Bearer token")));
+        assert!(Access::authorized (Some ("token"), None, Some ("bearer  token ")));
+        assert!(!Access::authorized (Some ("token"), None, Some ("Bearer toke")));
+        assert!(!Access::authorized (Some ("token"), None, Some ("Bearer")));
+        assert!(!Access::authorized (Some ("token"), None, None));
+        assert!(!Access::authorized (Some ("token"), None, Some (&basic)));
+        assert!(Access::authorized (None, Some ("admin:secret"), Some (&basic)));
+        assert!(!Access::authorized (None, Some ("admin:other"), Some (&basic)));
+        assert!(!Access::authorized (None, Some ("admin:secret"), Some ("Basic !!!")));
+        assert!(!Access::authorized (None, None, Some ("Bearer token")));
+    }
+
+    #[test]
+    fn only_loopback_is_local () {
+        assert!(is_local (ip ("127.0.0.1")));
+        assert!(is_local (ip ("::1")));
+        assert!(is_local (ip ("::ffff:127.0.0.1")));
+        assert!(!is_local (ip ("10.0.0.1")));
+        assert!(!is_local (ip ("0.0.0.0")));
+    }
+}
@@ -0,0 +1,210 @@
+// The admin server: liveness and readiness probes for container supervisors.
+// `/healthz` fails when a schedule stopped, `/readyz` when credentials can't be resolved or the state can't be written.
+// `/status` reports the last successes and recent runs, failures with their class, and the backups queued or running.
+// `POST /backups/{kind}` starts an on-demand backup, ahead of the scheduled ones. Who may do what: see `access`, over TLS with `tls`.
+
+use crate::access::{self, Access};
+use crate::kind::BackupKind;
+use crate::{lease, queue, scheduler, secrets, state, tls, AnyResult, Config};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{debug, info, warn};
+use rusoto_core::credential::{DefaultCredentialsProvider, ProvideAwsCredentials};
+use std::convert::Infallible;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::{task, time};
+
+const CREDENTIALS_TIMEOUT: Duration = Duration::from_secs (5);
+/// how many of the most recent runs `/status` reports
+const STATUS_RUNS: usize = 20;
+
+/// The token and the user and password, read from a secret store if they refer to one.
+struct Credentials {
+    token: Option<String>,
+    basic: Option<String>,
+}
+
+/// The admin server, bound and with its credentials resolved.
+pub struct Server {
+    listener: TcpListener,
+    acceptor: Option<tokio_native_tls::TlsAcceptor>,
+    credentials: Arc<Credentials>,
+}
+
+/// Resolves the credentials, sets up TLS and binds `address`, so that the daemon doesn't start without its admin server.
+pub async fn bind (config: &Config, address: SocketAddr) -> AnyResult<Server> {
+    // read once, rotating them takes a restart
+    let credentials = Arc::new (Credentials {
+        token: match &config.admin_access.token {
+            Some (token) => Some (secrets::resolve (&config.secrets.providers, token).await?),
+            None => None
+        },
+        basic: match &config.admin_access.basic {
+            Some (basic) => Some (secrets::resolve (&config.secrets.providers, basic).await?),
+            None => None
+        },
+    });
+    let acceptor = config.admin_tls.as_ref ().map (|tls| tls::acceptor (tls, &address)).transpose ()?;
+    let listener = TcpListener::bind (&address).await
+        .map_err (|err| anyhow::anyhow!("Could not bind the admin server to {}: {}", address, err))?;
+    info!("Serving /healthz and /readyz on {}{}", address, if acceptor.is_some () { " over TLS" } else { "" });
+    Ok (Server { listener, acceptor, credentials })
+}
+
+/// Serves the probes until the process exits.
+pub async fn serve (config: Config, server: Server) {
+    let Server { listener, acceptor, credentials } = server;
+    loop {
+        let (stream, remote) = match listener.accept ().await {
+            Ok (accepted) => accepted,
+            Err (err) => {
+                // e.g. out of file descriptors, which takes a moment to recover from
+                warn!("Admin server could not accept a connection: {}", err);
+                time::sleep (Duration::from_millis (100)).await;
+                continue;
+            }
+        };
+        if !config.admin_access.allows (remote.ip ()) {
+            warn!("Refused an admin connection from {}, which ADMIN_ALLOW doesn't allow", remote.ip ());
+            continue;
+        }
+
+        let (config, credentials, acceptor) = (config.clone (), credentials.clone (), acceptor.clone ());
+        tokio::spawn (async move {
+            let service = service_fn (move |request| {
+                let (config, credentials) = (config.clone (), credentials.clone ());
+                async move { Ok::<_, Infallible> (authorize (&config, &credentials, remote, request).await) }
+            });
+            let served = match acceptor {
+                Some (acceptor) => match acceptor.accept (stream).await {
+                    Ok (stream) => Http::new ().serve_connection (stream, service).await,
+                    Err (err) => {
+                        debug!("TLS handshake with {} failed: {}", remote, err);
+                        return;
+                    }
+                },
+                None => Http::new ().serve_connection (stream, service).await
+            };
+            if let Err (err) = served {
+                debug!("Admin connection from {} failed: {}", remote, err);
+            }
+        });
+    }
+}
+
+/// Handles `request` if the client may make it, the probes are open to any client allowed to connect.
+async fn authorize (config: &Config, credentials: &Credentials, remote: SocketAddr, request: Request<Body>) -> Response<Body> {
+    let probe = matches!(request.uri ().path (), "/healthz" | "/readyz");
+    if !probe && config.admin_access.requires_credentials () {
+        let authorization = request.headers ().get (hyper::header::AUTHORIZATION).and_then (|value| value.to_str ().ok ());
+        if !Access::authorized (credentials.token.as_deref (), credentials.basic.as_deref (), authorization) {
+            warn!("Refused an unauthorized {} {} from {}", request.method (), request.uri ().path (), remote.ip ());
+            let mut response = response (StatusCode::UNAUTHORIZED, String::from ("unauthorized\n"));
+            let challenge = if credentials.token.is_some () { "Bearer realm=\"mer-de-glace\"" } else { "Basic realm=\"mer-de-glace\"" };
+            response.headers_mut ().insert (hyper::header::WWW_AUTHENTICATE, hyper::header::HeaderValue::from_static (challenge));
+            return response;
+        }
+    } else if request.method () == Method::POST && !access::is_local (remote.ip ()) {
+        return response (StatusCode::FORBIDDEN, String::from ("on-demand backups from other hosts take ADMIN_TOKEN or ADMIN_BASIC_AUTH\n"));
+    }
+    handle (config, request).await
+}
+
+async fn handle (config: &Config, request: Request<Body>) -> Response<Body> {
+    let checks = match (request.method (), request.uri ().path ()) {
+        (&Method::GET, "/healthz") => vec! [("scheduler", scheduler_running (config))],
+        (&Method::GET, "/status") => return status (config),
+        (&Method::POST, path) if path.starts_with ("/backups/") => return trigger (config, &path ["/backups/".len ()..]),
+        (&Method::GET, "/readyz") => vec! [("credentials", credentials ().await),
+                                           ("state", state_writable (config).await)],
+        _ => return response (StatusCode::NOT_FOUND, String::from ("not found\n"))
+    };
+
+    let status = if checks.iter ().all (|(_, result)| result.is_ok ()) { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let body = checks.iter ()
+        .map (|(name, result)| match result {
+            Ok (message) => format!("{}: ok, {}\n", name, message),
+            Err (message) => format!("{}: failed, {}\n", name, message)
+        })
+        .collect ();
+    response (status, body)
+}
+
+fn response (status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new (Body::from (body));
+    *response.status_mut () = status;
+    response
+}
+
+fn status (config: &Config) -> Response<Body> {
+    match state::load (&config.backups_directory) {
+        Ok (state) => {
+            let recent = &state.history [state.history.len ().saturating_sub (STATUS_RUNS)..];
+            let body = serde_json::json!({ "last_success": state.last_success, "runs": recent, "queue": queue::snapshot () });
+            let mut response = response (StatusCode::OK, format!("{}\n", body));
+            response.headers_mut ().insert (hyper::header::CONTENT_TYPE, hyper::header::HeaderValue::from_static ("application/json"));
+            response
+        },
+        Err (err) => response (StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", err))
+    }
+}
+
+/// Queues an on-demand backup of `kind` and answers right away, its outcome is reported like any other run's.
+fn trigger (config: &Config, kind: &str) -> Response<Body> {
+    let kind = match kind.parse::<BackupKind>() {
+        Ok (kind) => kind,
+        Err (err) => return response (StatusCode::NOT_FOUND, format!("{}\n", err))
+    };
+    if lease::standing_by () {
+        return response (StatusCode::CONFLICT, String::from ("standing by for the leader lease, the leader backs up\n"));
+    }
+    let config = config.clone ();
+    tokio::spawn (async move {
+        if let Err (err) = scheduler::on_demand (&config, kind).await {
+            warn!("On-demand {} backup failed: {}", kind, err);
+        }
+    });
+    response (StatusCode::ACCEPTED, format!("{} backup queued\n", kind))
+}
+
+fn scheduler_running (config: &Config) -> Result<String, String> {
+    if lease::standing_by () {
+        return Ok (String::from ("standing by for the leader lease"));
+    }
+    let running = scheduler::running ();
+    if running < config.schedules.len () {
+        return Err (format!("{} of {} schedules running", running, config.schedules.len ()));
+    }
+    Ok (format!("{} schedules running", running))
+}
+
+async fn credentials () -> Result<String, String> {
+    let provider = DefaultCredentialsProvider::new ().map_err (|err| err.to_string ())?;
+    match time::timeout (CREDENTIALS_TIMEOUT, provider.credentials ()).await {
+        Ok (Ok (_)) => Ok (String::from ("resolved")),
+        Ok (Err (err)) => Err (err.to_string ()),
+        Err (_) => Err (format!("not resolved within {} seconds", CREDENTIALS_TIMEOUT.as_secs ()))
+    }
+}
+
+/// Whether a file can be created next to the state, without taking the state lock runs need or rewriting the state.
+async fn state_writable (config: &Config) -> Result<String, String> {
+    static PROBES: AtomicUsize = AtomicUsize::new (0);
+    let directory = config.backups_directory.clone ();
+    let probe = Path::new (&directory).join (format!(".readyz.{}.{}.tmp", process::id (), PROBES.fetch_add (1, Ordering::SeqCst)));
+    // a hung file system blocks, not a worker of the runtime
+    let written = task::spawn_blocking (move || fs::write (&probe, b"").and_then (|_| fs::remove_file (&probe))).await;
+    match written {
+        Ok (Ok (_)) => Ok (format!("{} is writable", directory)),
+        Ok (Err (err)) => Err (format!("{}: {}", directory, err)),
+        Err (err) => Err (format!("{}: {}", directory, err))
+    }
+}
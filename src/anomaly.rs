@@ -0,0 +1,92 @@
+// Flags successful backups whose archive size is far off the recent average, configured as `SIZE_ANOMALY_THRESHOLD=50` (percent).
+// A much smaller archive usually means an exclusion bug or an empty dump, a much bigger one a runaway log or cache,
+// neither of which fails the backup itself.
+
+use crate::kind::BackupKind;
+use crate::state::Run;
+
+/// fewer earlier sizes than that are too few for an average worth comparing to
+const MIN_SAMPLES: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct SizeAnomaly {
+    /// percent the size may deviate from the average
+    pub threshold: f64,
+    /// how many of the latest successful runs are averaged
+    pub window: usize,
+}
+
+impl SizeAnomaly {
+    pub fn new (threshold: f64, window: usize) -> Result<Self, anyhow::Error> {
+        if threshold.is_nan () || threshold <= 0.0 {
+            return Err (anyhow::anyhow!("SIZE_ANOMALY_THRESHOLD must be a positive percentage, not {}", threshold));
+        }
+        if window < MIN_SAMPLES {
+            return Err (anyhow::anyhow!("SIZE_ANOMALY_WINDOW must be at least {}, not {}", MIN_SAMPLES, window));
+        }
+        Ok (SizeAnomaly { threshold, window })
+    }
+
+    /// Why an archive of `size` bytes is suspicious given the `history`, None when it isn't (or there's too little history).
+    pub fn check (&self, history: &[Run], kind: BackupKind, size: u64) -> Option<String> {
+        let sizes : Vec<u64> = history.iter ().rev ()
+            .filter (|run| run.kind == kind)
+            .filter_map (|run| run.size)
+            .take (self.window)
+            .collect ();
+        let average = sizes.iter ().sum::<u64>() as f64 / sizes.len () as f64;
+        if sizes.len () < MIN_SAMPLES || average <= 0.0 {
+            return None;
+        }
+
+        let deviation = (size as f64 - average) / average * 100.0;
+        if deviation.abs () <= self.threshold {
+            return None;
+        }
+        Some (format!("archive of {} bytes is {:.0}% {} than the average {:.0} bytes of the last {} {} backups",
+                      size, deviation.abs (), if deviation < 0.0 { "smaller" } else { "bigger" }, average, sizes.len (), kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn run (kind: BackupKind, size: Option<u64>) -> Run {
+        Run { kind, at: Utc::now (), outcome: String::from ("success"), class: None, size, suspicious: None }
+    }
+
+    #[test]
+    fn thresholds_and_windows_are_validated () {
+        assert!(SizeAnomaly::new (50.0, 3).is_ok ());
+        assert!(SizeAnomaly::new (0.0, 5).is_err ());
+        assert!(SizeAnomaly::new (f64::NAN, 5).is_err ());
+        assert!(SizeAnomaly::new (50.0, 2).is_err ());
+    }
+
+    #[test]
+    fn sizes_beyond_the_threshold_are_flagged () {
+        let anomaly = SizeAnomaly::new (50.0, 3).unwrap ();
+        // the oldest full backup falls out of the window, failures and other kinds don't count
+        let history = vec! [run (BackupKind::Full, Some (10_000)), run (BackupKind::Full, Some (900)), run (BackupKind::Full, None),
+                            run (BackupKind::Uploads, Some (1)), run (BackupKind::Full, Some (1_000)), run (BackupKind::Full, Some (1_100))];
+
+        assert_eq!(anomaly.check (&history, BackupKind::Full, 1_500), None);
+        assert_eq!(anomaly.check (&history, BackupKind::Full, 500), None);
+        assert_eq!(anomaly.check (&history, BackupKind::Full, 499),
+                   Some (String::from ("archive of 499 bytes is 50% smaller than the average 1000 bytes of the last 3 full backups")));
+        assert_eq!(anomaly.check (&history, BackupKind::Full, 1_600),
+                   Some (String::from ("archive of 1600 bytes is 60% bigger than the average 1000 bytes of the last 3 full backups")));
+    }
+
+    #[test]
+    fn too_little_history_flags_nothing () {
+        let anomaly = SizeAnomaly::new (10.0, 5).unwrap ();
+        let history = vec! [run (BackupKind::Full, Some (1_000)), run (BackupKind::Full, Some (1_000)), run (BackupKind::Code, Some (1_000))];
+
+        assert_eq!(anomaly.check (&history, BackupKind::Full, 1), None);
+        assert_eq!(anomaly.check (&[], BackupKind::Full, 1), None);
+        assert_eq!(anomaly.check (&vec! [run (BackupKind::Full, Some (0)); 3], BackupKind::Full, 1), None);
+    }
+}
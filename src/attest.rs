@@ -0,0 +1,148 @@
+// Append-only attestation log of backup runs, configured as `ATTESTATION_LOG=/wp_backups/attestations.jsonl`.
+// Every run's outcome is a JSON line carrying the hash of the line before it, so editing, dropping or reordering
+// past entries breaks the chain. Publishing the head hash elsewhere (notifications) also catches a truncated log.
+
+use crate::kind::BackupKind;
+use crate::{signature, upload_record, AnyResult, Config};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// what the first entry chains to
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+lazy_static! {
+    // the backup kinds run concurrently, appends must chain to one another
+    static ref LOCK: Mutex<()> = Mutex::new (());
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attestation {
+    pub seq: u64,
+    pub site: String,
+    pub kind: BackupKind,
+    pub outcome: String,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    /// file name of the archive, if the run got that far
+    pub archive: Option<String>,
+    pub tree_hash: Option<String>,
+    /// `glacier:{region}/{vault}/{archive id}`, once uploaded
+    pub destination: Option<String>,
+    /// public key of the archive's signature, if signed
+    pub signer: Option<String>,
+    /// hash of the previous entry
+    pub prev: String,
+    /// hash of this entry, with `hash` empty
+    pub hash: String,
+}
+
+impl Attestation {
+    pub fn new (site: &str, kind: BackupKind, outcome: &str, started: DateTime<Utc>) -> Self {
+        Attestation {
+            seq: 0,
+            site: String::from (site),
+            kind,
+            outcome: String::from (outcome),
+            started,
+            finished: Utc::now (),
+            archive: None,
+            tree_hash: None,
+            destination: None,
+            signer: None,
+            prev: String::new (),
+            hash: String::new (),
+        }
+    }
+
+    /// Attests `archive_path` with its hash, where it was uploaded and who signed it, as recorded next to it.
+    pub fn of_archive (mut self, archive_path: &str, tree_hash: &str) -> AnyResult<Self> {
+        self.archive = Path::new (archive_path).file_name ().map (|name| name.to_string_lossy ().to_string ());
+        self.tree_hash = Some (String::from (tree_hash));
+        self.destination = upload_record::UploadRecord::read (archive_path)?
+            .map (|record| format!("glacier:{}/{}/{}", record.region, record.vault_name, record.archive_id));
+        if Path::new (&format!("{}{}", archive_path, signature::SIGNATURE_SUFFIX)).exists () {
+            self.signer = Some (signature::read_sidecar (archive_path)?.public_key);
+        }
+        Ok (self)
+    }
+
+    fn digest (&self) -> AnyResult<String> {
+        let unhashed = Attestation { hash: String::new (), ..self.clone () };
+        Ok (hex::encode (Sha256::digest (&serde_json::to_vec (&unhashed)?)))
+    }
+}
+
+/// Attests a run if the log is enabled, the archive given as its path and tree hash. Returns the new head hash.
+/// An attestation failing to be appended is logged, it doesn't fail the backup.
+pub fn record (config: &Config, kind: BackupKind, outcome: &str, started: DateTime<Utc>, archive: Option<(&str, &str)>) -> Option<String> {
+    let path = config.attestation_log.as_ref ()?;
+    let attestation = Attestation::new (&config.site, kind, outcome, started);
+    let appended = match archive {
+        Some ((archive_path, tree_hash)) => attestation.of_archive (archive_path, tree_hash),
+        None => Ok (attestation)
+    }.and_then (|attestation| append (path, attestation));
+    match appended {
+        Ok (head) => {
+            info!("Attested the {} run in {}, head {}", kind, path, head);
+            Some (head)
+        },
+        Err (err) => {
+            error!("Could not append the {} run to the attestation log {}: {}", kind, path, err);
+            None
+        }
+    }
+}
+
+/// Chains `attestation` to the log at `path` and appends it, returns the new head hash.
+pub fn append (path: &str, mut attestation: Attestation) -> AnyResult<String> {
+    let _guard = LOCK.lock ().unwrap ();
+    let last = read (path)?.pop ();
+    attestation.seq = last.as_ref ().map_or (1, |last| last.seq + 1);
+    attestation.prev = last.map_or (String::from (GENESIS), |last| last.hash);
+    attestation.hash = attestation.digest ()?;
+
+    let mut line = serde_json::to_vec (&attestation)?;
+    line.push (b'\n');
+    let mut file = OpenOptions::new ().create (true).append (true).open (path)?;
+    file.write_all (&line)?;
+    file.sync_data ()?;
+    Ok (attestation.hash)
+}
+
+/// Checks every entry's hash and link, returns the number of entries and the head hash.
+pub fn verify (path: &str) -> AnyResult<(usize, String)> {
+    let attestations = read (path)?;
+    let mut prev = String::from (GENESIS);
+    for (index, attestation) in attestations.iter ().enumerate () {
+        if attestation.seq != index as u64 + 1 {
+            return Err (anyhow::anyhow!("Entry {} of {} has sequence number {}: entries were dropped or reordered", index + 1, path, attestation.seq));
+        }
+        if attestation.prev != prev {
+            return Err (anyhow::anyhow!("Entry {} of {} doesn't chain to the one before it", attestation.seq, path));
+        }
+        if attestation.digest ()? != attestation.hash {
+            return Err (anyhow::anyhow!("Entry {} of {} was altered, its hash doesn't match", attestation.seq, path));
+        }
+        prev = attestation.hash.clone ();
+    }
+    Ok ((attestations.len (), prev))
+}
+
+fn read (path: &str) -> AnyResult<Vec<Attestation>> {
+    let content = match fs::read_to_string (path) {
+        Ok (content) => content,
+        Err (err) if err.kind () == std::io::ErrorKind::NotFound => return Ok (Vec::new ()),
+        Err (err) => return Err (err.into ())
+    };
+    content.lines ()
+        .enumerate ()
+        .filter (|(_, line)| !line.trim ().is_empty ())
+        .map (|(number, line)| serde_json::from_str (line).map_err (|err| anyhow::anyhow!("Line {} of {} is not an attestation: {}", number + 1, path, err)))
+        .collect ()
+}
@@ -0,0 +1,82 @@
+// Signed requests to AWS services without a rusoto client crate of their own (CloudWatch, EventBridge, ...),
+// dispatched through rusoto_core. Requests made for a run are signed with its temporary credentials, as its Glacier requests are,
+// the others with the default provider chain's.
+
+use crate::AnyResult;
+use log::warn;
+use regex::Regex;
+use rusoto_core::credential::{AwsCredentials, StaticProvider};
+use rusoto_core::request::{BufferedHttpResponse, HttpClient};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{Client, Region, RusotoError};
+use std::convert::Infallible;
+use std::str::FromStr;
+
+lazy_static! {
+    static ref REGION_RE: Regex = Regex::new (r"^[a-z]{2}(-[a-z]+)+-\d+$").unwrap ();
+}
+
+/// The region of `service`, at `endpoint` if overridden. Regions rusoto doesn't know yet (e.g. newer aws-us-gov or aws-cn ones)
+/// get the usual endpoint of their partition, names that aren't a region's are an error.
+pub fn region (service: &str, name: &str, endpoint: &Option<String>) -> AnyResult<Region> {
+    if let Some (endpoint) = endpoint {
+        return Ok (Region::Custom { name: String::from (name), endpoint: endpoint.clone () });
+    }
+    match Region::from_str (name) {
+        Ok (region) => Ok (region),
+        Err (err) if !REGION_RE.is_match (name) => Err (err.into ()),
+        Err (_) => {
+            let domain = if name.starts_with ("cn-") { "amazonaws.com.cn" } else { "amazonaws.com" };
+            let endpoint = format!("https://{}.{}.{}", service, name, domain);
+            warn!("Region {} is not known to rusoto, using endpoint {}", name, endpoint);
+            Ok (Region::Custom { name: String::from (name), endpoint })
+        }
+    }
+}
+
+/// The partition ARNs of resources in region `name` start with.
+pub fn partition (name: &str) -> &'static str {
+    if name.starts_with ("cn-") {
+        "aws-cn"
+    } else if name.starts_with ("us-gov-") {
+        "aws-us-gov"
+    } else {
+        "aws"
+    }
+}
+
+/// A client signing with `credentials`, or with the default provider chain's without.
+pub fn client (credentials: Option<&AwsCredentials>) -> AnyResult<Client> {
+    match credentials {
+        Some (credentials) => Ok (Client::new_with (StaticProvider::from (credentials.clone ()), HttpClient::new ()?)),
+        None => Ok (Client::shared ())
+    }
+}
+
+/// Signs `request` with `credentials` and sends it, any response but a 2xx is an error carrying the response body.
+pub async fn dispatch (credentials: Option<&AwsCredentials>, request: SignedRequest) -> AnyResult<BufferedHttpResponse> {
+    let response = client (credentials)?.sign_and_dispatch (request).await
+        .map_err (|err| anyhow::anyhow!("{}", RusotoError::<Infallible>::from (err)))?
+        .buffer ().await?;
+    if !response.status.is_success () {
+        return Err (anyhow::anyhow!("{}: {}", response.status, String::from_utf8_lossy (&response.body)));
+    }
+    Ok (response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_resolve_to_their_partition () {
+        assert_eq!(region ("glacier", "us-east-2", &None).unwrap (), Region::UsEast2);
+        assert_eq!(region ("glacier", "us-east-2", &Some (String::from ("http://localhost:4566"))).unwrap (),
+                   Region::Custom { name: String::from ("us-east-2"), endpoint: String::from ("http://localhost:4566") });
+        assert_eq!(region ("sts", "cn-southwest-9", &None).unwrap (),
+                   Region::Custom { name: String::from ("cn-southwest-9"), endpoint: String::from ("https://sts.cn-southwest-9.amazonaws.com.cn") });
+        assert_eq!(region ("glacier", "us-gov-central-3", &None).unwrap (),
+                   Region::Custom { name: String::from ("us-gov-central-3"), endpoint: String::from ("https://glacier.us-gov-central-3.amazonaws.com") });
+        assert!(region ("glacier", "Ohio", &None).is_err ());
+    }
+}
@@ -0,0 +1,118 @@
+// Blackout periods, e.g. Black Friday week for a store, during which scheduled backups are skipped
+// or limited to the database, configured as `BLACKOUT_PERIODS=2026-11-23..2026-11-30:db-only,2026-12-24..2026-12-26`
+
+use chrono::{DateTime, NaiveDate, Utc};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlackoutMode {
+    /// no backups at all
+    Skip,
+    /// full backups shrink to the database dump, the other kinds are skipped
+    DbOnly,
+}
+
+#[derive(Debug, Clone)]
+pub struct Blackout {
+    /// first day, UTC
+    pub start: NaiveDate,
+    /// last day, inclusive
+    pub end: NaiveDate,
+    pub mode: BlackoutMode,
+}
+
+impl Blackout {
+    pub fn contains (&self, at: DateTime<Utc>) -> bool {
+        let day = at.date ().naive_utc ();
+        self.start <= day && day <= self.end
+    }
+
+    /// midnight after the last day
+    pub fn ends (&self) -> DateTime<Utc> {
+        DateTime::from_utc (self.end.succ ().and_hms (0, 0, 0), Utc)
+    }
+}
+
+impl FromStr for Blackout {
+    type Err = anyhow::Error;
+
+    /// `<start>..<end>` with an optional `:skip` (the default) or `:db-only`
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let (period, mode) = match s.trim ().split_once (':') {
+            Some ((period, "skip")) => (period, BlackoutMode::Skip),
+            Some ((period, "db-only")) => (period, BlackoutMode::DbOnly),
+            Some ((_, mode)) => return Err (anyhow::anyhow!("Unknown blackout mode: {}, expected one of skip, db-only", mode)),
+            None => (s.trim (), BlackoutMode::Skip)
+        };
+        let (start, end) = period.split_once ("..")
+            .ok_or_else (|| anyhow::anyhow!("Invalid blackout period: {}, expected <start>..<end>", s))?;
+        let blackout = Blackout {
+            start: NaiveDate::parse_from_str (start, "%Y-%m-%d")?,
+            end: NaiveDate::parse_from_str (end, "%Y-%m-%d")?,
+            mode,
+        };
+        if blackout.end < blackout.start {
+            return Err (anyhow::anyhow!("Blackout period {} ends before it starts", s));
+        }
+        Ok (blackout)
+    }
+}
+
+pub fn parse (s: &str) -> Result<Vec<Blackout>, anyhow::Error> {
+    s.split (',')
+        .filter (|period| !period.trim ().is_empty ())
+        .map (Blackout::from_str)
+        .collect ()
+}
+
+/// The blackout `at` falls into, a skipping one wins over a db-only one.
+pub fn active (blackouts: &[Blackout], at: DateTime<Utc>) -> Option<&Blackout> {
+    blackouts.iter ()
+        .filter (|blackout| blackout.contains (at))
+        .min_by_key (|blackout| blackout.mode != BlackoutMode::Skip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn periods_are_parsed_with_their_mode () {
+        let blackouts = parse ("2026-11-23..2026-11-30:db-only, 2026-12-24..2026-12-26,").unwrap ();
+
+        assert_eq!(blackouts.len (), 2);
+        assert_eq!(blackouts [0].start, NaiveDate::from_ymd (2026, 11, 23));
+        assert_eq!(blackouts [0].end, NaiveDate::from_ymd (2026, 11, 30));
+        assert_eq!(blackouts [0].mode, BlackoutMode::DbOnly);
+        assert_eq!(blackouts [1].mode, BlackoutMode::Skip);
+        assert!(parse ("").unwrap ().is_empty ());
+    }
+
+    #[test]
+    fn invalid_periods_are_rejected () {
+        for period in &["2026-11-23", "2026-11-30..2026-11-23", "2026-11-23..2026-11-30:weekly", "2026-11-23..tomorrow"] {
+            assert!(parse (period).is_err (), "{} should be rejected", period);
+        }
+    }
+
+    #[test]
+    fn the_last_day_is_inclusive () {
+        let blackout = "2026-11-23..2026-11-30".parse::<Blackout>().unwrap ();
+
+        assert!(!blackout.contains (Utc.ymd (2026, 11, 22).and_hms (23, 59, 59)));
+        assert!(blackout.contains (Utc.ymd (2026, 11, 23).and_hms (0, 0, 0)));
+        assert!(blackout.contains (Utc.ymd (2026, 11, 30).and_hms (23, 59, 59)));
+        assert!(!blackout.contains (Utc.ymd (2026, 12, 1).and_hms (0, 0, 0)));
+        assert_eq!(blackout.ends (), Utc.ymd (2026, 12, 1).and_hms (0, 0, 0));
+    }
+
+    #[test]
+    fn skipping_wins_over_db_only () {
+        let blackouts = parse ("2026-11-20..2026-11-30:db-only,2026-11-25..2026-11-26:skip").unwrap ();
+
+        assert_eq!(active (&blackouts, Utc.ymd (2026, 11, 21).and_hms (12, 0, 0)).map (|blackout| blackout.mode), Some (BlackoutMode::DbOnly));
+        assert_eq!(active (&blackouts, Utc.ymd (2026, 11, 25).and_hms (12, 0, 0)).map (|blackout| blackout.mode), Some (BlackoutMode::Skip));
+        assert!(active (&blackouts, Utc.ymd (2026, 12, 1).and_hms (12, 0, 0)).is_none ());
+    }
+}
@@ -0,0 +1,153 @@
+// Failures injected at chosen points, for rehearsing runbooks: `CHAOS=dump,timeout:2,checksum` fails the database dump,
+// times out uploading part 2 and has Glacier reject the checksum of the next part uploaded. Parts are numbered as in the
+// upload journal, 0 for an archive uploaded in one request and from 1 when seeding. A fault fires the first time its point
+// is reached in the process, so the retry after it goes through; `CHAOS_REPEAT=true` fires it every time.
+// An injected upload failure replaces the request, nothing is sent to AWS.
+// Faults are only injected against a test endpoint, `AWS_GLACIER_ENDPOINT` on a host that is not AWS',
+// unless `CHAOS_ALLOW_PRODUCTION=true` says the real vault is what is being rehearsed with.
+
+use crate::AnyResult;
+use log::warn;
+use rusoto_core::request::HttpDispatchError;
+use rusoto_core::RusotoError;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// the faults that fired, shared by the runs of the process
+    static ref FIRED: Mutex<Vec<Fault>> = Mutex::new (Vec::new ());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// the database dump fails
+    Dump,
+    /// uploading the part times out, any part if None
+    Timeout (Option<u64>),
+    /// Glacier rejects the checksum of the part, any part if None
+    Checksum (Option<u64>),
+}
+
+impl FromStr for Fault {
+    type Err = anyhow::Error;
+
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let (point, part) = match s.split_once (':') {
+            Some ((point, part)) => (point, Some (part.parse::<u64>()
+                                                  .map_err (|_| anyhow::anyhow!("Invalid part {} in CHAOS, expected its number", part))?)),
+            None => (s, None)
+        };
+        match (point, part) {
+            ("dump", None) => Ok (Fault::Dump),
+            ("timeout", part) => Ok (Fault::Timeout (part)),
+            ("checksum", part) => Ok (Fault::Checksum (part)),
+            _ => Err (anyhow::anyhow!("Invalid fault {} in CHAOS, expected dump, timeout[:PART] or checksum[:PART]", s))
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Dump => write!(f, "dump"),
+            Fault::Timeout (None) => write!(f, "timeout"),
+            Fault::Timeout (Some (part)) => write!(f, "timeout:{}", part),
+            Fault::Checksum (None) => write!(f, "checksum"),
+            Fault::Checksum (Some (part)) => write!(f, "checksum:{}", part),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Chaos {
+    pub faults: Vec<Fault>,
+    /// faults fire every time rather than once
+    pub repeat: bool,
+}
+
+pub fn parse (s: &str) -> AnyResult<Vec<Fault>> {
+    s.split (',')
+        .map (str::trim)
+        .filter (|fault| !fault.is_empty ())
+        .map (Fault::from_str)
+        .collect ()
+}
+
+/// True if the Glacier `endpoint` is a test setup rather than AWS.
+pub fn is_rehearsal (endpoint: Option<&str>) -> bool {
+    let endpoint = match endpoint {
+        Some (endpoint) => endpoint,
+        None => return false
+    };
+    let host = endpoint.split_once ("://").map_or (endpoint, |(_, rest)| rest);
+    let host = host.split (['/', ':']).next ().unwrap_or_default ().to_ascii_lowercase ();
+    !["amazonaws.com", "amazonaws.com.cn", "api.aws"].iter ()
+        .any (|domain| host == *domain || host.ends_with (&format!(".{}", domain)))
+}
+
+impl Chaos {
+
+    /// The first configured fault `at` matches, if it fires now.
+    fn fire (&self, at: impl Fn (&Fault) -> bool) -> Option<Fault> {
+        let mut fired = FIRED.lock ().unwrap ();
+        let fault = *self.faults.iter ()
+            .filter (|fault| self.repeat || !fired.contains (fault))
+            .find (|fault| at (fault))?;
+        fired.push (fault);
+        warn!("Injecting the {} fault of CHAOS", fault);
+        Some (fault)
+    }
+
+    /// Fails the database dump.
+    pub fn dump (&self) -> AnyResult<()> {
+        match self.fire (|fault| *fault == Fault::Dump) {
+            Some (_) => Err (anyhow::anyhow!("Injected failure of the database dump (CHAOS=dump)")),
+            None => Ok (())
+        }
+    }
+
+    /// The failure of uploading `part` with `checksum`, if one is injected: a timeout, or Glacier's answer to a checksum
+    /// mismatch as the `invalid_parameter` error of the upload operation.
+    pub fn upload<E> (&self, part: u64, checksum: &str, invalid_parameter: fn (String) -> E) -> Option<RusotoError<E>> {
+        let fault = self.fire (|fault| match fault {
+            Fault::Timeout (at) | Fault::Checksum (at) => at.is_none_or (|at| at == part),
+            Fault::Dump => false
+        })?;
+        Some (match fault {
+            Fault::Checksum (_) => RusotoError::Service (invalid_parameter (
+                format!("Checksum mismatch: expected {}, but calculated {} (injected, CHAOS={})", checksum, "0".repeat (64), fault))),
+            _ => RusotoError::HttpDispatch (HttpDispatchError::new (format!("Request timed out (injected, CHAOS={})", fault)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faults_are_parsed () {
+        assert_eq!(parse ("dump, timeout:2,checksum,").unwrap (), vec! [Fault::Dump, Fault::Timeout (Some (2)), Fault::Checksum (None)]);
+        assert!(parse ("").unwrap ().is_empty ());
+        for faults in &["dump:1", "timeout:first", "disk", "checksum:-1"] {
+            assert!(parse (faults).is_err (), "{} should be rejected", faults);
+        }
+        for fault in &["dump", "timeout", "timeout:3", "checksum", "checksum:0"] {
+            assert_eq!(fault.parse::<Fault>().unwrap ().to_string (), *fault);
+        }
+    }
+
+    #[test]
+    fn only_test_endpoints_are_rehearsals () {
+        assert!(is_rehearsal (Some ("http://localhost:4566")));
+        assert!(is_rehearsal (Some ("http://127.0.0.1:9000/glacier")));
+        assert!(is_rehearsal (Some ("https://glacier.example.com")));
+        assert!(is_rehearsal (Some ("https://notamazonaws.com")));
+
+        assert!(!is_rehearsal (None));
+        assert!(!is_rehearsal (Some ("https://glacier.us-east-2.amazonaws.com")));
+        assert!(!is_rehearsal (Some ("https://GLACIER.cn-north-1.amazonaws.com.cn/")));
+        assert!(!is_rehearsal (Some ("glacier.us-east-2.api.aws:443")));
+    }
+}
@@ -0,0 +1,141 @@
+// Publishes the outcome of every backup run to CloudWatch metrics and as an EventBridge event,
+// for alarms and automation in AWS without running Prometheus. Publishing never fails a backup.
+
+use crate::aws;
+use crate::error::{self, BackupError};
+use crate::kind::BackupKind;
+use log::{info, warn};
+use rusoto_core::credential::AwsCredentials;
+use rusoto_core::param::{Params, ServiceParams};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::Region;
+use std::time::Duration;
+
+pub const EVENT_SOURCE: &str = "mer-de-glace";
+
+#[derive(Debug, Clone)]
+pub struct CloudWatch {
+    /// metrics are published under this namespace, if any
+    pub namespace: Option<String>,
+    pub metrics_region: Region,
+    /// events are put on this bus, if any
+    pub event_bus: Option<String>,
+    pub events_region: Region,
+}
+
+pub struct Run<'a> {
+    pub site: &'a str,
+    pub kind: BackupKind,
+    pub duration: Duration,
+    /// the archive size of a successful run, the error of a failed one
+    pub result: Result<u64, &'a anyhow::Error>,
+    /// why a successful run looks wrong nevertheless, see `anomaly::SizeAnomaly`
+    pub suspicious: Option<&'a str>,
+    /// head of the attestation log once the run is attested, see `attest`
+    pub attestation: Option<&'a str>,
+}
+
+impl Run<'_> {
+
+    pub fn outcome (&self) -> &'static str {
+        match (&self.result, self.suspicious) {
+            (Ok (_), None) => "success",
+            (Ok (_), Some (_)) => "suspicious",
+            (Err (_), _) => "failure",
+        }
+    }
+
+    /// The run as JSON, the detail of events and the body of notifications.
+    pub fn detail (&self) -> serde_json::Value {
+        let mut detail = serde_json::json!({ "site": self.site, "kind": self.kind.to_string (), "outcome": self.outcome (),
+                                             "duration_seconds": self.duration.as_secs_f64 () });
+        match &self.result {
+            Ok (bytes) => {
+                detail ["archive_bytes"] = serde_json::json!(bytes);
+                if let Some (reason) = self.suspicious {
+                    detail ["suspicious"] = serde_json::json!(reason);
+                }
+            },
+            Err (err) => {
+                detail ["error"] = serde_json::json!(format!("{:#}", err));
+                detail ["error_class"] = serde_json::json!(error::of (err).map_or ("unclassified", BackupError::class));
+                detail ["retryable"] = serde_json::json!(error::of (err).is_some_and (BackupError::retryable));
+            },
+        }
+        if let Some (head) = self.attestation {
+            detail ["attestation_head"] = serde_json::json!(head);
+        }
+        detail
+    }
+}
+
+/// Signed with `credentials`, the run's temporary ones if it has some.
+pub async fn publish (cloudwatch: &CloudWatch, credentials: Option<&AwsCredentials>, run: &Run<'_>) {
+    if let Some (namespace) = &cloudwatch.namespace {
+        match put_metrics (cloudwatch, credentials, namespace, run).await {
+            Ok (()) => info!("Published {} backup metrics to CloudWatch namespace {}", run.kind, namespace),
+            Err (err) => warn!("Could not publish metrics to CloudWatch: {}", err)
+        }
+    }
+    if let Some (event_bus) = &cloudwatch.event_bus {
+        match put_event (cloudwatch, credentials, event_bus, run).await {
+            Ok (()) => info!("Put {} backup event on EventBridge bus {}", run.kind, event_bus),
+            Err (err) => warn!("Could not put event on EventBridge: {}", err)
+        }
+    }
+}
+
+/// `Succeeded` and `Failed` counts, the `Duration` and (on success) `ArchiveSize`, by site and kind.
+async fn put_metrics (cloudwatch: &CloudWatch, credentials: Option<&AwsCredentials>, namespace: &str, run: &Run<'_>) -> Result<(), anyhow::Error> {
+    let mut metrics = vec! [("Succeeded", run.result.is_ok () as u64 as f64, "Count"),
+                            ("Failed", run.result.is_err () as u64 as f64, "Count"),
+                            ("Suspicious", run.suspicious.is_some () as u64 as f64, "Count"),
+                            ("Duration", run.duration.as_secs_f64 (), "Seconds")];
+    if let Ok (bytes) = run.result {
+        metrics.push (("ArchiveSize", bytes as f64, "Bytes"));
+    }
+
+    let mut params = Params::new ();
+    params.put ("Action", "PutMetricData");
+    params.put ("Version", "2010-08-01");
+    params.put ("Namespace", namespace);
+    for (i, (name, value, unit)) in metrics.iter ().enumerate () {
+        let member = format!("MetricData.member.{}", i + 1);
+        params.put (&format!("{}.MetricName", member), name);
+        params.put (&format!("{}.Value", member), value);
+        params.put (&format!("{}.Unit", member), unit);
+        params.put (&format!("{}.Dimensions.member.1.Name", member), "Site");
+        params.put (&format!("{}.Dimensions.member.1.Value", member), run.site);
+        params.put (&format!("{}.Dimensions.member.2.Name", member), "Kind");
+        params.put (&format!("{}.Dimensions.member.2.Value", member), run.kind.to_string ());
+    }
+
+    let mut request = SignedRequest::new ("POST", "monitoring", &cloudwatch.metrics_region, "/");
+    request.set_params (params);
+    aws::dispatch (credentials, request).await?;
+    Ok (())
+}
+
+/// A `Backup Run Succeeded` or `Backup Run Failed` event from source `mer-de-glace`.
+async fn put_event (cloudwatch: &CloudWatch, credentials: Option<&AwsCredentials>, event_bus: &str, run: &Run<'_>) -> Result<(), anyhow::Error> {
+    let body = serde_json::json!({
+        "Entries": [{
+            "Source": EVENT_SOURCE,
+            "DetailType": if run.result.is_ok () { "Backup Run Succeeded" } else { "Backup Run Failed" },
+            "Detail": run.detail ().to_string (),
+            "EventBusName": event_bus,
+        }]
+    });
+
+    let mut request = SignedRequest::new ("POST", "events", &cloudwatch.events_region, "/");
+    request.add_header ("x-amz-target", "AWSEvents.PutEvents");
+    request.set_content_type (String::from ("application/x-amz-json-1.1"));
+    request.set_payload (Some (serde_json::to_vec (&body)?));
+    let response = aws::dispatch (credentials, request).await?;
+
+    let response : serde_json::Value = serde_json::from_slice (&response.body)?;
+    if response ["FailedEntryCount"].as_u64 ().unwrap_or (0) > 0 {
+        return Err (anyhow::anyhow!("event rejected: {}", response ["Entries"]));
+    }
+    Ok (())
+}
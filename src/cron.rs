@@ -0,0 +1,308 @@
+// Snapshot of the site's WP-Cron schedule and key options, stored in the manifest of archives including the database.
+// After a restore `cron-check` compares it with the live database, catching scheduled jobs the restore lost,
+// e.g. WooCommerce subscription renewals, before customers notice. Options captured: `CRON_SNAPSHOT_OPTIONS`.
+
+use crate::doctor::Check;
+use crate::{site, Config};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+/// the wp_options row WP-Cron keeps its schedule in, php serialized
+const CRON_OPTION: &str = "cron";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub captured: DateTime<Utc>,
+    pub events: Vec<Event>,
+    /// the captured options, None if missing
+    pub options: BTreeMap<String, Option<String>>,
+    /// transients with an expiry, and how many of them expired without being cleaned up
+    pub transients: u64,
+    pub expired_transients: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub hook: String,
+    pub next_run: DateTime<Utc>,
+    /// the recurrence, None for a single event
+    pub schedule: Option<String>,
+    pub interval: Option<u64>,
+    pub args: Value,
+}
+
+impl Event {
+    /// What tells two events apart, the next run changes as a recurring one runs.
+    fn key (&self) -> (String, String, Option<String>) {
+        (self.hook.clone (), self.args.to_string (), self.schedule.clone ())
+    }
+
+    fn describe (&self) -> String {
+        let args = match &self.args {
+            Value::Array (args) if args.is_empty () => String::new (),
+            args => format!(" {}", args)
+        };
+        format!("{}{} ({})", self.hook, args, self.schedule.as_deref ().unwrap_or ("once"))
+    }
+}
+
+/// Reads the live schedule, `options` and the transients from the site's database.
+pub fn capture (config: &Config, options: &[String]) -> Result<Snapshot, anyhow::Error> {
+    let prefix = site::table_prefix (config);
+    let cron = site::query (config, &format!("SELECT option_value FROM {}options WHERE option_name = '{}'", prefix, CRON_OPTION))?;
+    let events = match cron.first () {
+        Some (value) => events (&unserialize (&site::unescape (value))?),
+        None => Vec::new ()
+    };
+
+    let mut captured : BTreeMap<String, Option<String>> = options.iter ().map (|option| (option.clone (), None)).collect ();
+    if !options.is_empty () {
+        let names = options.iter ().map (|option| format!("'{}'", option.replace ('\'', "''"))).collect::<Vec<_>>().join (", ");
+        for row in site::query (config, &format!("SELECT option_name, option_value FROM {}options WHERE option_name IN ({})", prefix, names))? {
+            let mut columns = row.splitn (2, '\t');
+            if let (Some (name), Some (value)) = (columns.next (), columns.next ()) {
+                captured.insert (String::from (name), Some (String::from_utf8_lossy (&site::unescape (value)).into_owned ()));
+            }
+        }
+    }
+
+    let counts = site::query (config, &format!("SELECT COUNT(*), COALESCE(SUM(option_value < UNIX_TIMESTAMP()), 0) FROM {}options WHERE option_name LIKE '%\\_transient\\_timeout\\_%'", prefix))?;
+    let mut counts = counts.first ().map_or ("0\t0", String::as_str).split ('\t').map (|count| count.parse::<u64>().unwrap_or (0));
+
+    Ok (Snapshot {
+        captured: Utc::now (),
+        events,
+        options: captured,
+        transients: counts.next ().unwrap_or (0),
+        expired_transients: counts.next ().unwrap_or (0),
+    })
+}
+
+/// The events of the unserialized cron option: `{timestamp: {hook: {key: {schedule, args, interval}}}, version: 2}`.
+fn events (cron: &Value) -> Vec<Event> {
+    let mut events = Vec::new ();
+    for (timestamp, hooks) in cron.as_object ().into_iter ().flatten () {
+        let next_run = match timestamp.parse::<i64>() {
+            Ok (timestamp) => Utc.timestamp (timestamp, 0),
+            // the version of the option's format
+            Err (_) => continue
+        };
+        for (hook, instances) in hooks.as_object ().into_iter ().flatten () {
+            for instance in instances.as_object ().into_iter ().flatten ().map (|(_, instance)| instance) {
+                events.push (Event {
+                    hook: hook.clone (),
+                    next_run,
+                    schedule: instance ["schedule"].as_str ().map (String::from),
+                    interval: instance ["interval"].as_u64 (),
+                    args: instance ["args"].clone (),
+                });
+            }
+        }
+    }
+    events.sort_by (|a, b| (a.next_run, &a.hook).cmp (&(b.next_run, &b.hook)));
+    events
+}
+
+/// How the live state compares to `snapshot`. Recurring events and single events still to come must all be there.
+pub fn compare (snapshot: &Snapshot, live: &Snapshot) -> Vec<Check> {
+    let mut checks = Vec::new ();
+
+    let missing : Vec<&Event> = snapshot.events.iter ()
+        .filter (|event| !live.events.iter ().any (|other| other.key () == event.key ()))
+        .collect ();
+    // a single event that was due has likely run since the snapshot
+    let (lost, ran) : (Vec<&Event>, Vec<&Event>) = missing.into_iter ()
+        .partition (|event| event.schedule.is_some () || event.next_run > live.captured);
+    let added = live.events.iter ()
+        .filter (|event| !snapshot.events.iter ().any (|other| other.key () == event.key ()))
+        .count ();
+    checks.push (Check {
+        name: "cron events",
+        result: if lost.is_empty () {
+            Ok (format!("all {} scheduled events of {} are there ({} single events ran since, {} added)",
+                        snapshot.events.len () - ran.len (), snapshot.captured.to_rfc3339 (), ran.len (), added))
+        } else {
+            Err (format!("{} of {} scheduled events are missing: {}",
+                         lost.len (), snapshot.events.len (), lost.iter ().map (|event| event.describe ()).collect::<Vec<_>>().join (", ")))
+        },
+    });
+
+    let changed : Vec<&String> = snapshot.options.iter ()
+        .filter (|(name, value)| live.options.get (*name).is_some_and (|live| live != *value))
+        .map (|(name, _)| name)
+        .collect ();
+    if !snapshot.options.is_empty () {
+        checks.push (Check {
+            name: "options",
+            result: if changed.is_empty () {
+                Ok (format!("{} unchanged", snapshot.options.keys ().cloned ().collect::<Vec<_>>().join (", ")))
+            } else {
+                Err (format!("{} differ from the snapshot", changed.iter ().map (|name| name.as_str ()).collect::<Vec<_>>().join (", ")))
+            },
+        });
+    }
+
+    checks.push (Check {
+        name: "transients",
+        result: Ok (format!("{} with an expiry, {} expired (were {} and {})",
+                            live.transients, live.expired_transients, snapshot.transients, snapshot.expired_transients)),
+    });
+    checks
+}
+
+/// Parses a php serialized value, arrays and objects as JSON objects (lists as arrays).
+fn unserialize (serialized: &[u8]) -> Result<Value, anyhow::Error> {
+    parse (serialized, &mut 0)
+}
+
+fn parse (s: &[u8], position: &mut usize) -> Result<Value, anyhow::Error> {
+    let kind = *s.get (*position).ok_or_else (|| anyhow::anyhow!("Serialized value ends early"))?;
+    *position += 1;
+    if kind == b'N' {
+        expect (s, position, b';')?;
+        return Ok (Value::Null);
+    }
+    expect (s, position, b':')?;
+    Ok (match kind {
+        b'b' => json!(read_until (s, position, b';')? == "1"),
+        b'i' => json!(read_until (s, position, b';')?.parse::<i64>()?),
+        b'd' => json!(read_until (s, position, b';')?.parse::<f64>()?),
+        b's' => {
+            let content = read_string (s, position)?;
+            expect (s, position, b';')?;
+            json!(String::from_utf8_lossy (content))
+        },
+        b'a' | b'O' => {
+            if kind == b'O' {
+                // the class name, `O:8:"stdClass":1:{...}`
+                read_string (s, position)?;
+                expect (s, position, b':')?;
+            }
+            let count = read_until (s, position, b':')?.parse::<usize>()?;
+            expect (s, position, b'{')?;
+            let mut map = Map::new ();
+            let mut list = Vec::new ();
+            for index in 0..count {
+                let key = parse (s, position)?;
+                let value = parse (s, position)?;
+                if key.as_u64 () == Some (index as u64) {
+                    list.push (value.clone ());
+                }
+                map.insert (key.as_str ().map_or_else (|| key.to_string (), String::from), value);
+            }
+            expect (s, position, b'}')?;
+            if list.len () == count { Value::Array (list) } else { Value::Object (map) }
+        },
+        other => return Err (anyhow::anyhow!("Unsupported serialized type {}", other as char))
+    })
+}
+
+/// `"content"` prefixed by its length in bytes and a colon.
+fn read_string<'a> (s: &'a [u8], position: &mut usize) -> Result<&'a [u8], anyhow::Error> {
+    let length = read_until (s, position, b':')?.parse::<usize>()?;
+    expect (s, position, b'"')?;
+    let content = s.get (*position..*position + length).ok_or_else (|| anyhow::anyhow!("Serialized string ends early"))?;
+    *position += length;
+    expect (s, position, b'"')?;
+    Ok (content)
+}
+
+fn expect (s: &[u8], position: &mut usize, byte: u8) -> Result<(), anyhow::Error> {
+    if s.get (*position) != Some (&byte) {
+        return Err (anyhow::anyhow!("Expected {} at {} of the serialized value", byte as char, position));
+    }
+    *position += 1;
+    Ok (())
+}
+
+/// The text up to `delimiter`, which is skipped.
+fn read_until (s: &[u8], position: &mut usize, delimiter: u8) -> Result<String, anyhow::Error> {
+    let length = s [*position..].iter ().position (|byte| *byte == delimiter)
+        .ok_or_else (|| anyhow::anyhow!("Serialized value ends early"))?;
+    let text = String::from_utf8_lossy (&s [*position..*position + length]).into_owned ();
+    *position += length + 1;
+    Ok (text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what wp_options holds for a daily renewal job, a single event and the format version
+    const CRON: &str = concat!(
+        r#"a:3:{i:1614600000;a:1:{s:27:"woocommerce_scheduled_sales";a:1:{s:32:"40cd750bba9870f18aada2478b24840a";"#,
+        r#"a:3:{s:8:"schedule";s:5:"daily";s:4:"args";a:0:{}s:8:"interval";i:86400;}}}"#,
+        r#"i:1614700000;a:1:{s:17:"send_reminder_now";a:1:{s:32:"c6ad15cc8a1c2cd1a2d9c3c0e4c0d1a2";"#,
+        r#"a:2:{s:8:"schedule";b:0;s:4:"args";a:1:{i:0;i:42;}}}}"#,
+        r#"s:7:"version";i:2;}"#);
+
+    fn snapshot (captured: i64, events: Vec<Event>) -> Snapshot {
+        Snapshot {
+            captured: Utc.timestamp (captured, 0),
+            events,
+            options: BTreeMap::new (),
+            transients: 0,
+            expired_transients: 0,
+        }
+    }
+
+    #[test]
+    fn events_are_read_from_the_cron_option () {
+        let events = events (&unserialize (CRON.as_bytes ()).unwrap ());
+
+        assert_eq!(events, vec! [
+            Event {
+                hook: String::from ("woocommerce_scheduled_sales"),
+                next_run: Utc.timestamp (1614600000, 0),
+                schedule: Some (String::from ("daily")),
+                interval: Some (86400),
+                args: json!([]),
+            },
+            Event {
+                hook: String::from ("send_reminder_now"),
+                next_run: Utc.timestamp (1614700000, 0),
+                schedule: None,
+                interval: None,
+                args: json!([42]),
+            },
+        ]);
+        assert!(unserialize (br#"a:1:{s:3:"key";s:20:"truncated";}"#).is_err ());
+        assert!(unserialize (b"x:1;").is_err ());
+    }
+
+    #[test]
+    fn lost_events_fail_the_comparison () {
+        let events = events (&unserialize (CRON.as_bytes ()).unwrap ());
+        let taken = snapshot (1614500000, events.clone ());
+
+        // the recurring event ran and moved on, which is fine
+        let mut rescheduled = events.clone ();
+        rescheduled [0].next_run = Utc.timestamp (1614686400, 0);
+        assert!(compare (&taken, &snapshot (1614650000, rescheduled)) [0].result.is_ok ());
+
+        // the single event was due before the restore, it likely ran
+        let checks = compare (&taken, &snapshot (1614800000, vec! [events [0].clone ()]));
+        assert_eq!(checks [0].result, Ok (format!("all 1 scheduled events of {} are there (1 single events ran since, 0 added)", taken.captured.to_rfc3339 ())));
+
+        // a single event still to come and a recurring one must be there
+        assert_eq!(compare (&taken, &snapshot (1614650000, vec! [events [0].clone ()])) [0].result,
+                   Err (String::from ("1 of 2 scheduled events are missing: send_reminder_now [42] (once)")));
+        assert_eq!(compare (&taken, &snapshot (1614650000, vec! [events [1].clone ()])) [0].result,
+                   Err (String::from ("1 of 2 scheduled events are missing: woocommerce_scheduled_sales (daily)")));
+    }
+
+    #[test]
+    fn changed_options_are_reported () {
+        let mut taken = snapshot (1614500000, Vec::new ());
+        taken.options.insert (String::from ("woocommerce_currency"), Some (String::from ("EUR")));
+        taken.options.insert (String::from ("siteurl"), Some (String::from ("https://example.com")));
+        let mut live = snapshot (1614650000, Vec::new ());
+        live.options = taken.options.clone ();
+
+        assert!(compare (&taken, &live) [1].result.is_ok ());
+        live.options.insert (String::from ("woocommerce_currency"), Some (String::from ("USD")));
+        assert_eq!(compare (&taken, &live) [1].result, Err (String::from ("woocommerce_currency differ from the snapshot")));
+    }
+}
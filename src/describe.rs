@@ -0,0 +1,119 @@
+// Summary of what a local archive contains, as JSON or in plain language for clients and compliance tickets
+
+use crate::locale::Locale;
+use crate::manifest::{Manifest, MANIFEST_NAME};
+use crate::upload_record::UploadRecord;
+use flate2::read::GzDecoder;
+use fluent::{fluent_args, FluentArgs};
+use regex::Regex;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::Read;
+
+lazy_static! {
+    static ref WP_VERSION_RE: Regex = Regex::new(r#"\$wp_version\s*=\s*['"]([^'"]+)['"]"#).unwrap();
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub archive: String,
+    pub manifest: Manifest,
+    pub wordpress_version: Option<String>,
+    pub database_size: Option<u64>,
+    pub media_files: u64,
+    pub files: u64,
+    pub content_size: u64,
+    pub archive_size: u64,
+    pub upload: Option<UploadRecord>,
+}
+
+/// Reads the archive once, collecting what the summary needs.
+pub fn summarize (archive_path: &str) -> Result<Summary, anyhow::Error> {
+    let manifest = Manifest::read_from_archive (archive_path)?;
+    let mut archive = tar::Archive::new (GzDecoder::new (File::open (archive_path)?));
+
+    let mut wordpress_version = None;
+    let mut database_size = None;
+    let (mut media_files, mut files, mut content_size) = (0, 0, 0);
+
+    for entry in archive.entries ()? {
+        let mut entry = entry?;
+        if !entry.header ().entry_type ().is_file () {
+            continue;
+        }
+        let path = entry.path ()?.display ().to_string ();
+        let size = entry.header ().size ()?;
+
+        if path == MANIFEST_NAME {
+            continue;
+        }
+        files += 1;
+        content_size += size;
+
+        if Some (&path) == manifest.sql_dump.as_ref () {
+            database_size = Some (size);
+        } else if path.contains ("/wp-content/uploads/") {
+            media_files += 1;
+        } else if path.ends_with ("/wp-includes/version.php") {
+            let mut content = String::new ();
+            entry.read_to_string (&mut content)?;
+            wordpress_version = WP_VERSION_RE.captures (&content).map (|captures| captures [1].to_string ());
+        }
+    }
+
+    Ok (Summary {
+        archive: String::from (archive_path),
+        manifest,
+        wordpress_version,
+        database_size,
+        media_files,
+        files,
+        content_size,
+        archive_size: fs::metadata (archive_path)?.len (),
+        upload: UploadRecord::read (archive_path)?,
+    })
+}
+
+pub fn human (summary: &Summary, locale: &Locale) -> String {
+    let manifest = &summary.manifest;
+    let site = manifest.site.clone ()
+        .or_else (|| manifest.config.as_ref ().and_then (|config| config ["wordpress_directory"].as_str ().map (String::from)))
+        .unwrap_or_else (|| locale.text ("describe-unnamed-site", FluentArgs::new ()));
+
+    let mut lines = vec! [
+        locale.text ("describe-taken", fluent_args!["kind" => manifest.kind.to_string (), "site" => site, "date" => locale.date (manifest.created)]),
+    ];
+    if let Some (version) = &summary.wordpress_version {
+        lines.push (locale.text ("describe-wordpress", fluent_args!["version" => version.as_str ()]));
+    }
+    let database = match summary.database_size {
+        Some (size) => locale.text ("describe-database", fluent_args!["size" => human_size (size)]),
+        None => locale.text ("describe-no-database", FluentArgs::new ()),
+    };
+    lines.push (locale.text ("describe-contents", fluent_args!["database" => database, "files" => summary.files, "media" => summary.media_files,
+                                                                "size" => human_size (summary.content_size)]));
+    lines.push (locale.text ("describe-compressed", fluent_args!["size" => human_size (summary.archive_size)]));
+    lines.push (locale.text ("describe-local-copy", fluent_args!["path" => summary.archive.as_str ()]));
+    match &summary.upload {
+        Some (upload) => lines.push (locale.text ("describe-uploaded", fluent_args!["vault" => upload.vault_name.as_str (), "region" => upload.region.as_str (),
+                                                                                      "date" => locale.date (upload.uploaded), "id" => upload.archive_id.as_str ()])),
+        None => lines.push (locale.text ("describe-not-uploaded", FluentArgs::new ())),
+    }
+
+    lines.join ("\n")
+}
+
+pub fn human_size (bytes: u64) -> String {
+    let units = ["bytes", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len () - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units [0])
+    } else {
+        format!("{:.1} {}", size, units [unit])
+    }
+}
@@ -0,0 +1,149 @@
+// Differential database dumps, configured as `DIFFERENTIAL_TABLES=wp_actionscheduler_logs:log_id,wp_statistics_visit:ID`.
+// Append-mostly tables (logs, analytics) are dumped in full only every `DIFFERENTIAL_FULL_INTERVAL` (7 days by default);
+// the dumps in between hold just their rows past the high-water mark of the previous dump, the largest value of a column
+// growing with every row added, recorded in the state. Rows changed or deleted below the mark wait for the next full dump.
+// Restoring a differential dump loads the dumps it builds on first, back to the full one.
+
+use crate::{dump_command, run_dump, site, AnyResult, Config};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct Differential {
+    /// table to the column growing with every row added, e.g. an auto-increment id
+    pub tables: BTreeMap<String, String>,
+    /// how old the full dump may get, the next dump after that is a full one again
+    pub full_interval: Duration,
+}
+
+/// How far the last dump went, kept in the state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Marks {
+    /// when the full dump the differential ones build on was taken
+    pub full: DateTime<Utc>,
+    /// file name of the archive holding the last dump
+    pub archive: String,
+    pub tables: BTreeMap<String, Mark>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mark {
+    pub column: String,
+    /// the largest value dumped, None while the table is empty
+    pub value: Option<String>,
+}
+
+/// What a differential dump holds, recorded in the manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dump {
+    /// file name of the archive with the dump this one builds on, loaded before it
+    pub previous: String,
+    /// the rows of every differential table dumped: its column past `after` up to `up_to`
+    pub tables: BTreeMap<String, Rows>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rows {
+    pub column: String,
+    pub after: Option<String>,
+    pub up_to: Option<String>,
+}
+
+/// A dump taken with differential tables configured.
+pub struct Dumped {
+    pub sql: Vec<u8>,
+    /// when the full dump it builds on was taken, now for a full one
+    pub full: DateTime<Utc>,
+    pub marks: BTreeMap<String, Mark>,
+    /// None for a full dump
+    pub differential: Option<Dump>,
+}
+
+/// `<table>:<column>` pairs, comma separated.
+pub fn parse (s: &str) -> AnyResult<BTreeMap<String, String>> {
+    s.split (',')
+        .map (str::trim)
+        .filter (|table| !table.is_empty ())
+        .map (|table| match table.split_once (':') {
+            Some ((table, column)) if !table.is_empty () && !column.is_empty () => Ok ((String::from (table), String::from (column))),
+            _ => Err (anyhow::anyhow!("Invalid differential table {}, expected TABLE:COLUMN", table))
+        })
+        .collect ()
+}
+
+/// The marks the next dump continues from, None if it has to be a full one:
+/// there is no full dump recent enough, or the differential tables changed since.
+pub fn base<'a> (differential: &Differential, marks: Option<&'a Marks>, now: DateTime<Utc>) -> Option<&'a Marks> {
+    let marks = marks?;
+    let recent = (now - marks.full).to_std ().unwrap_or_default () < differential.full_interval;
+    let unchanged = marks.tables.len () == differential.tables.len ()
+        && differential.tables.iter ().all (|(table, column)| marks.tables.get (table).is_some_and (|mark| &mark.column == column));
+    Some (marks).filter (|_| recent && unchanged)
+}
+
+/// Dumps the database, the differential tables past the marks of `base` only if there is one.
+pub fn dump (config: &Config, differential: &Differential, base: Option<&Marks>, now: DateTime<Utc>) -> AnyResult<Dumped> {
+    // read before dumping: rows added meanwhile may be dumped twice, which the inserts of differential dumps ignore, but never missed
+    let marks = differential.tables.iter ()
+        .map (|(table, column)| Ok ((table.clone (), Mark { column: column.clone (), value: largest (config, table, column)? })))
+        .collect::<AnyResult<BTreeMap<_, _>>>()?;
+
+    let base = match base {
+        Some (base) => base,
+        None => {
+            info!("Dumping the differential tables in full, as differential dumps build on it for {} days",
+                  differential.full_interval.as_secs () / 86400);
+            return Ok (Dumped { sql: crate::dump_sql (config, false)?, full: now, marks, differential: None });
+        }
+    };
+
+    let mut command = dump_command (config);
+    command.arg ("--databases").arg (&config.mysql_database);
+    for table in differential.tables.keys () {
+        command.arg (format!("--ignore-table={}.{}", config.mysql_database, table));
+    }
+    let mut sql = run_dump (config, command)?;
+
+    let mut tables = BTreeMap::new ();
+    for (table, mark) in &marks {
+        let after = base.tables [table].value.clone ();
+        let mut command = dump_command (config);
+        command
+            .arg ("--no-create-info")
+            .arg ("--insert-ignore")
+            .arg (format!("--where={}", condition (&mark.column, after.as_deref (), mark.value.as_deref ())))
+            .arg (&config.mysql_database)
+            .arg (table);
+        sql.extend (run_dump (config, command)?);
+        tables.insert (table.clone (), Rows { column: mark.column.clone (), after, up_to: mark.value.clone () });
+    }
+    info!("Dumped the rows of {} added since {}", tables.keys ().cloned ().collect::<Vec<_>>().join (", "), base.archive);
+
+    Ok (Dumped {
+        sql,
+        full: base.full,
+        marks,
+        differential: Some (Dump { previous: base.archive.clone (), tables }),
+    })
+}
+
+/// The largest value of `column`, None if `table` is empty.
+fn largest (config: &Config, table: &str, column: &str) -> AnyResult<Option<String>> {
+    let rows = site::query (config, &format!("SELECT MAX(`{}`) FROM `{}`", column.replace ('`', "``"), table.replace ('`', "``")))?;
+    Ok (rows.into_iter ().next ().filter (|value| value != "NULL"))
+}
+
+/// The rows past `after` up to `up_to`, as a `--where` clause.
+fn condition (column: &str, after: Option<&str>, up_to: Option<&str>) -> String {
+    let column = format!("`{}`", column.replace ('`', "``"));
+    let quote = |value: &str| format!("'{}'", value.replace ('\\', "\\\\").replace ('\'', "\\'"));
+    match (after, up_to) {
+        // emptied since
+        (_, None) => String::from ("1 = 0"),
+        (None, Some (up_to)) => format!("{} <= {}", column, quote (up_to)),
+        (Some (after), Some (up_to)) => format!("{} > {} AND {} <= {}", column, quote (after), column, quote (up_to))
+    }
+}
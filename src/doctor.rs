@@ -0,0 +1,125 @@
+// Configuration checks run before anything else, turning the cryptic errors a misconfigured region or vault
+// causes deep into a run into actionable messages up front
+
+use crate::aws;
+use crate::upload::is_credentials_error;
+use log::info;
+use rusoto_core::{Region, RusotoError};
+use rusoto_glacier::{DescribeVaultError, DescribeVaultInput, Glacier, GlacierClient};
+use std::str::FromStr;
+
+/// regions searched for a vault missing from the configured one
+const GLACIER_REGIONS: &[&str] = &[
+    "us-east-1", "us-east-2", "us-west-1", "us-west-2", "ca-central-1", "sa-east-1",
+    "eu-west-1", "eu-west-2", "eu-west-3", "eu-central-1", "eu-north-1", "eu-south-1",
+    "ap-east-1", "ap-south-1", "ap-northeast-1", "ap-northeast-2", "ap-northeast-3", "ap-southeast-1", "ap-southeast-2",
+    "me-south-1", "af-south-1",
+];
+
+pub struct Check {
+    pub name: &'static str,
+    /// what was found, or what is wrong and how to fix it
+    pub result: Result<String, String>,
+}
+
+/// Checks that the region is valid, the credentials are accepted there and the vault exists in it,
+/// in `account_id` if it belongs to another account. Stops at the first failing check, the later ones depend on it.
+pub async fn run (region_name: &str, endpoint: &Option<String>, account_id: Option<&str>, vault_name: &str) -> Vec<Check> {
+    let mut checks = Vec::new ();
+
+    let region = match aws::region ("glacier", region_name, endpoint) {
+        Ok (region) => region,
+        Err (err) => {
+            checks.push (Check {
+                name: "region",
+                result: Err (format!("AWS_REGION {} is not a valid region ({}), expected a name like us-east-2 or an AWS_GLACIER_ENDPOINT", region_name, err)),
+            });
+            return checks;
+        }
+    };
+    checks.push (Check { name: "region", result: Ok (format!("{} ({})", region.name (), endpoint_of (&region))) });
+
+    let client = GlacierClient::new (region.clone ());
+    let owner = account_id.map_or (String::new (), |account_id| format!(" of account {}", account_id));
+    match describe_vault (&client, account_id, vault_name).await {
+        Ok (()) => {
+            checks.push (Check { name: "credentials", result: Ok (format!("accepted in {}", region.name ())) });
+            checks.push (Check { name: "vault", result: Ok (format!("{}{} exists in {}", vault_name, owner, region.name ())) });
+        },
+        Err (RusotoError::Service (DescribeVaultError::ResourceNotFound (_))) => {
+            checks.push (Check { name: "credentials", result: Ok (format!("accepted in {}", region.name ())) });
+            let result = match (find_vault (account_id, vault_name, &region, endpoint).await, account_id) {
+                (Some (found), _) => Err (format!("vault {}{} exists in {}, not {}: set AWS_REGION={}", vault_name, owner, found, region.name (), found)),
+                (None, Some (_)) => Err (format!("{}{} does not exist in {}, it is created by that account", vault_name, owner, region.name ())),
+                (None, None) => Ok (format!("{} does not exist in {} yet, it is created before the first upload", vault_name, region.name ()))
+            };
+            checks.push (Check { name: "vault", result });
+        },
+        Err (RusotoError::Unknown (response)) if account_id.is_some () && response.status.as_u16 () == 403
+            && String::from_utf8_lossy (&response.body).contains ("AccessDenied") => {
+            checks.push (Check { name: "credentials", result: Ok (format!("accepted in {}", region.name ())) });
+            checks.push (Check {
+                name: "vault",
+                result: Err (format!("access to vault {}{} in {} is denied, its access policy has to allow glacier:DescribeVault and glacier:UploadArchive for this account or role",
+                                     vault_name, owner, region.name ())),
+            });
+        },
+        Err (err) if is_credentials_error (&err) => {
+            checks.push (Check {
+                name: "credentials",
+                result: Err (format!("AWS credentials are rejected in {}, check AWS_ACCESS_KEY_ID / AWS_SECRET_ACCESS_KEY or the profile in use: {}", region.name (), err)),
+            });
+        },
+        Err (RusotoError::HttpDispatch (err)) => {
+            checks.push (Check {
+                name: "connection",
+                result: Err (format!("Glacier in {} is unreachable at {}, check the region name, AWS_GLACIER_ENDPOINT and the network: {}", region.name (), endpoint_of (&region), err)),
+            });
+        },
+        Err (err) => {
+            checks.push (Check { name: "vault", result: Err (format!("describing vault {} in {} failed: {}", vault_name, region.name (), err)) });
+        }
+    }
+
+    checks
+}
+
+async fn describe_vault (client: &GlacierClient, account_id: Option<&str>, vault_name: &str) -> Result<(), RusotoError<DescribeVaultError>> {
+    client.describe_vault (DescribeVaultInput {
+        account_id: String::from (account_id.unwrap_or ("-")),
+        vault_name: String::from (vault_name),
+    }).await?;
+    Ok (())
+}
+
+/// The other standard region the vault exists in, not searched when an explicit endpoint is configured.
+async fn find_vault (account_id: Option<&str>, vault_name: &str, configured: &Region, endpoint: &Option<String>) -> Option<String> {
+    if endpoint.is_some () {
+        return None;
+    }
+    for name in GLACIER_REGIONS.iter ().filter (|name| **name != configured.name ()) {
+        let region = Region::from_str (name).ok ()?;
+        info!("Looking for vault {} in {}", vault_name, name);
+        if describe_vault (&GlacierClient::new (region), account_id, vault_name).await.is_ok () {
+            return Some (String::from (*name));
+        }
+    }
+    None
+}
+
+fn endpoint_of (region: &Region) -> String {
+    match region {
+        Region::Custom { endpoint, .. } => endpoint.clone (),
+        region => format!("https://glacier.{}.amazonaws.com", region.name ())
+    }
+}
+
+pub fn report (checks: &[Check]) -> String {
+    checks.iter ()
+        .map (|check| match &check.result {
+            Ok (message) => format!("ok     {:<12} {}", check.name, message),
+            Err (message) => format!("FAILED {:<12} {}", check.name, message),
+        })
+        .collect::<Vec<_>>()
+        .join ("\n")
+}
@@ -0,0 +1,93 @@
+// Checks the sql dump before it goes into the archive: a dump cut short (e.g. by a dropped connection)
+// would otherwise make for an archive that looks like a successful backup but can't be restored.
+// A complete dump left behind by a run interrupted while archiving is reused, so only the archiving starts over.
+
+use log::info;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+lazy_static! {
+    // dumps of the runs in progress, which other runs must not take for leftovers
+    static ref IN_USE: Mutex<HashSet<PathBuf>> = Mutex::new (HashSet::new ());
+}
+
+/// Keeps a dump from being reused by another run for as long as it lives.
+pub struct Claim {
+    path: PathBuf,
+}
+
+impl Drop for Claim {
+    fn drop (&mut self) {
+        IN_USE.lock ().unwrap ().remove (&self.path);
+    }
+}
+
+pub fn claim (path: &str) -> Claim {
+    let path = PathBuf::from (path);
+    IN_USE.lock ().unwrap ().insert (path.clone ());
+    Claim { path }
+}
+
+/// The newest complete dump of `database` in the backups directory no other run uses, if it is younger than `max_age`.
+pub fn leftover (backups_directory: &str, database: &str, max_age: Duration) -> Option<(String, Claim)> {
+    let in_use = IN_USE.lock ().unwrap ();
+    let (path, age) = fs::read_dir (backups_directory).ok ()?
+        .filter_map (Result::ok)
+        .filter (|entry| {
+            let name = entry.file_name ().to_string_lossy ().to_string ();
+            name.starts_with ("dump_") && name.ends_with (".sql")
+        })
+        .filter (|entry| !in_use.contains (&entry.path ()))
+        .filter_map (|entry| Some ((entry.path (), entry.metadata ().ok ()?.modified ().ok ()?.elapsed ().unwrap_or_default ())))
+        .filter (|(_, age)| *age < max_age)
+        .min_by_key (|(_, age)| *age)?;
+    drop (in_use);
+
+    let path = path.display ().to_string ();
+    // an incomplete one is what the interrupted run was writing when it stopped
+    validate (&path, database).ok ()?;
+    info!("Reusing the complete sql dump {} left behind {} minutes ago by an interrupted run", path, age.as_secs () / 60);
+    let claim = claim (&path);
+    Some ((path, claim))
+}
+
+/// The last line mysqldump writes, unless told to leave out comments
+const COMPLETED_MARKER: &str = "-- Dump completed";
+
+/// Fails unless the dump at `path` is complete and holds the tables of `database`.
+pub fn validate (path: &str, database: &str) -> Result<(), anyhow::Error> {
+    let file = File::open (path)?;
+    if file.metadata ()?.len () == 0 {
+        return Err (anyhow::anyhow!("The sql dump {} is empty", path));
+    }
+
+    let use_database = format!("USE `{}`;", database);
+    let (mut uses_database, mut tables, mut last_line) = (false, 0, String::new ());
+    for line in BufReader::new (file).split (b'\n') {
+        // dumps are not necessarily valid utf-8
+        let line = String::from_utf8_lossy (&line?).into_owned ();
+        if line == use_database {
+            uses_database = true;
+        } else if uses_database && line.starts_with ("CREATE TABLE ") {
+            tables += 1;
+        }
+        if !line.trim ().is_empty () {
+            last_line = line;
+        }
+    }
+
+    if !last_line.starts_with (COMPLETED_MARKER) {
+        return Err (anyhow::anyhow!("The sql dump {} is truncated, it doesn't end with \"{}\"", path, COMPLETED_MARKER));
+    }
+    if !uses_database {
+        return Err (anyhow::anyhow!("The sql dump {} doesn't contain the database {}", path, database));
+    }
+    if tables == 0 {
+        return Err (anyhow::anyhow!("The sql dump {} has no tables of the database {}", path, database));
+    }
+    Ok (())
+}
@@ -0,0 +1,104 @@
+// What failed, so that automation can branch on it: every failure of a run is classified by the stage it happened in,
+// and the class shows up in the logs, the run history, notifications, the admin API and the exit code.
+// Errors are still passed around as `anyhow::Error`s, carrying a `BackupError` once classified.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum BackupError {
+    /// missing or invalid configuration, or an environment (region, credentials, vault) that doesn't match it
+    Config (String),
+    /// dumping the database
+    Dump (String),
+    /// writing, verifying or signing the archive
+    Archive (String),
+    /// getting the archive into Glacier, retryable when the same upload may well succeed later
+    Upload { message: String, retryable: bool },
+    /// pruning local archives
+    Retention (String),
+    /// AWS rejecting the credentials even once re-resolved, e.g. an STS session token that expired mid-run,
+    /// retryable as the next run resolves them anew
+    Credentials (String),
+}
+
+impl BackupError {
+
+    /// A non-retryable upload error.
+    pub fn upload (message: String) -> BackupError {
+        BackupError::Upload { message, retryable: false }
+    }
+
+    pub fn class (&self) -> &'static str {
+        match self {
+            BackupError::Config (_) => "config",
+            BackupError::Dump (_) => "dump",
+            BackupError::Archive (_) => "archive",
+            BackupError::Upload { .. } => "upload",
+            BackupError::Retention (_) => "retention",
+            BackupError::Credentials (_) => "credentials",
+        }
+    }
+
+    pub fn retryable (&self) -> bool {
+        matches!(self, BackupError::Upload { retryable: true, .. } | BackupError::Credentials (_))
+    }
+
+    pub fn exit_code (&self) -> i32 {
+        match self {
+            BackupError::Config (_) => 2,
+            BackupError::Dump (_) => 3,
+            BackupError::Archive (_) => 4,
+            BackupError::Upload { retryable: true, .. } => 5,
+            BackupError::Upload { retryable: false, .. } => 6,
+            BackupError::Retention (_) => 7,
+            BackupError::Credentials (_) => 8,
+        }
+    }
+
+    fn message (&self) -> &str {
+        match self {
+            BackupError::Config (message) | BackupError::Dump (message) | BackupError::Archive (message)
+                | BackupError::Upload { message, .. } | BackupError::Retention (message) | BackupError::Credentials (message) => message,
+        }
+    }
+}
+
+impl fmt::Display for BackupError {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.retryable () {
+            write!(f, "{} error (retryable): {}", self.class (), self.message ())
+        } else {
+            write!(f, "{} error: {}", self.class (), self.message ())
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+/// The class of `err`, if it was classified.
+pub fn of (err: &anyhow::Error) -> Option<&BackupError> {
+    err.downcast_ref::<BackupError>()
+}
+
+/// 1 for errors that were not classified.
+pub fn exit_code (err: &anyhow::Error) -> i32 {
+    of (err).map_or (1, BackupError::exit_code)
+}
+
+pub trait Classify<T> {
+    /// Classifies the error as `class`, unless an earlier stage classified it already.
+    fn classify (self, class: fn (String) -> BackupError) -> Result<T, anyhow::Error>;
+}
+
+impl<T, E: Into<anyhow::Error>> Classify<T> for Result<T, E> {
+    fn classify (self, class: fn (String) -> BackupError) -> Result<T, anyhow::Error> {
+        self.map_err (|err| {
+            let err = err.into ();
+            if of (&err).is_some () {
+                err
+            } else {
+                class (format!("{:#}", err)).into ()
+            }
+        })
+    }
+}
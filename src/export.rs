@@ -0,0 +1,93 @@
+// Copies an archive onto an external drive for an offline (air-gapped) copy: the archive, its sidecars
+// (upload record, signature, leaf hashes) and an export record with the manifest and what a restore needs to check it.
+// Every copy is synced and read back before the export counts as done.
+
+use crate::manifest::Manifest;
+use crate::tree_hash::{self, to_hex_string};
+use crate::upload_record::UploadRecord;
+use crate::SIDECAR_SUFFIXES;
+use chrono::Utc;
+use log::info;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const EXPORT_SUFFIX: &str = ".export.json";
+
+pub struct Exported {
+    pub archive: PathBuf,
+    pub files: usize,
+    pub tree_hash: String,
+}
+
+/// Exports `archive_path` into `<device>/mer-de-glace/<site>/`, the device has to be mounted already.
+pub fn export (archive_path: &str, device: &Path) -> Result<Exported, anyhow::Error> {
+    if !device.is_dir () {
+        return Err (anyhow::anyhow!("{} is not a directory, is the drive mounted?", device.display ()));
+    }
+    let manifest = Manifest::read_from_archive (archive_path)?;
+    let record = UploadRecord::read (archive_path)?;
+    // what was uploaded is what the copy has to match, archives never uploaded are hashed now
+    let tree_hash = match &record {
+        Some (record) => record.tree_hash.clone (),
+        None => to_hex_string (&tree_hash::tree_hash (archive_path)?)
+    };
+
+    let directory = device.join ("mer-de-glace").join (manifest.site.as_deref ().unwrap_or ("unknown"));
+    fs::create_dir_all (&directory)?;
+    let file_name = Path::new (archive_path).file_name ()
+        .ok_or_else (|| anyhow::anyhow!("{} is not a file", archive_path))?
+        .to_string_lossy ().to_string ();
+
+    let destination = directory.join (&file_name);
+    info!("Copying {} to {}", archive_path, destination.display ());
+    copy_synced (Path::new (archive_path), &destination)?;
+    let copied_hash = to_hex_string (&tree_hash::tree_hash (&destination.display ().to_string ())?);
+    if copied_hash != tree_hash {
+        return Err (anyhow::anyhow!("The copy {} has tree hash {}, expected {}", destination.display (), copied_hash, tree_hash));
+    }
+    let mut files = 1;
+
+    for suffix in SIDECAR_SUFFIXES {
+        let sidecar = PathBuf::from (format!("{}{}", archive_path, suffix));
+        if sidecar.exists () {
+            let copy = directory.join (format!("{}{}", file_name, suffix));
+            copy_synced (&sidecar, &copy)?;
+            if fs::read (&sidecar)? != fs::read (&copy)? {
+                return Err (anyhow::anyhow!("The copy {} differs from {}", copy.display (), sidecar.display ()));
+            }
+            files += 1;
+        }
+    }
+
+    let export_record = serde_json::json!({
+        "name": manifest.name (),
+        "archive": file_name,
+        "tree_hash": tree_hash,
+        "size": fs::metadata (&destination)?.len (),
+        "exported": Utc::now (),
+        "tool_version": env!("CARGO_PKG_VERSION"),
+        "glacier": record,
+        "manifest": manifest,
+    });
+    let export_path = directory.join (format!("{}{}", file_name, EXPORT_SUFFIX));
+    fs::write (&export_path, serde_json::to_vec_pretty (&export_record)?)?;
+    File::open (&export_path)?.sync_all ()?;
+    File::open (&directory)?.sync_all ()?;
+    files += 1;
+
+    Ok (Exported { archive: destination, files, tree_hash })
+}
+
+/// Copies through a temporary file so that an interrupted copy never looks complete.
+fn copy_synced (source: &Path, destination: &Path) -> Result<(), anyhow::Error> {
+    let temporary = PathBuf::from (format!("{}.tmp", destination.display ()));
+    {
+        let mut reader = File::open (source)?;
+        let mut writer = File::create (&temporary)?;
+        io::copy (&mut reader, &mut writer)?;
+        writer.sync_all ()?;
+    }
+    fs::rename (&temporary, destination)?;
+    Ok (())
+}
@@ -0,0 +1,79 @@
+// The database users of the site and their grants, archived next to the dump with `BACKUP_GRANTS=true`:
+// recovering onto a new database server also takes the user WordPress connects as, with its password.
+// The users are the ones granted privileges on `MYSQL_DATABASE`, and the one backing up. Their `CREATE USER` statements
+// hold the password hashes; `restore --db-only --grants` creates the users missing on the target and applies the grants.
+
+use crate::restore::{mysql_command, MysqlTarget};
+use crate::{site, AnyResult, Config};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::Stdio;
+
+/// The grants in an archive, recorded in its manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Archived {
+    /// entry holding the statements
+    pub entry: String,
+    /// the database they were captured for
+    pub database: String,
+    /// `'user'@'host'` of every user captured
+    pub users: Vec<String>,
+}
+
+/// Statements re-creating the users of the site's database and their grants, and who they are.
+pub fn capture (config: &Config) -> AnyResult<(Vec<u8>, Vec<String>)> {
+    let mut sql = format!("-- users and grants of `{}`, mer-de-glace {}\n", config.mysql_database, crate::version::LONG_VERSION);
+    let users = users (config)?;
+    for user in &users {
+        sql.push_str (&format!("\n-- {}\n", user));
+        // binary password hashes (caching_sha2_password) don't survive the text output, MySQL 8 prints them as hex on request
+        let create = site::query (config, &format!("SET SESSION print_identified_with_as_hex = ON; SHOW CREATE USER {}", user))
+            .or_else (|_| site::query (config, &format!("SHOW CREATE USER {}", user)))?;
+        for statement in create {
+            let statement = String::from_utf8_lossy (&site::unescape (&statement)).into_owned ();
+            sql.push_str (&format!("{};\n", statement.replacen ("CREATE USER ", "CREATE USER IF NOT EXISTS ", 1)));
+        }
+        for grant in site::query (config, &format!("SHOW GRANTS FOR {}", user))? {
+            sql.push_str (&format!("{};\n", String::from_utf8_lossy (&site::unescape (&grant))));
+        }
+    }
+    info!("Captured the grants of {}", users.join (", "));
+    Ok ((sql.into_bytes (), users))
+}
+
+/// `'user'@'host'` of every user granted privileges on the database, and of the one backing up.
+fn users (config: &Config) -> AnyResult<Vec<String>> {
+    let current = "SELECT CONCAT(QUOTE(SUBSTRING_INDEX(CURRENT_USER(), '@', 1)), '@', QUOTE(SUBSTRING_INDEX(CURRENT_USER(), '@', -1)))";
+    let granted = format!("SELECT DISTINCT CONCAT(QUOTE(User), '@', QUOTE(Host)) FROM mysql.db WHERE Db = '{}'",
+                          config.mysql_database.replace ('\'', "''"));
+    let mut users = match site::query (config, &format!("{} UNION {}", granted, current)) {
+        Ok (users) => users,
+        Err (err) => {
+            warn!("Could not read the users granted privileges on {}, capturing just the grants of {}: {}", config.mysql_database, config.mysql_user, err);
+            site::query (config, current)?
+        }
+    };
+    users.sort ();
+    users.dedup ();
+    Ok (users)
+}
+
+/// Applies captured `grants` on `target`, for `database` where they were captured for `original`.
+pub fn apply (grants: &[u8], original: &str, target: &MysqlTarget) -> AnyResult<()> {
+    let mut grants = String::from_utf8_lossy (grants).into_owned ();
+    if original != target.database {
+        grants = grants.replace (&format!(" ON `{}`.", original), &format!(" ON `{}`.", target.database));
+    }
+    let mut mysql = mysql_command (target, None)
+        .stdin (Stdio::piped ())
+        .stdout (Stdio::null ())
+        .stderr (Stdio::piped ())
+        .spawn ()?;
+    mysql.stdin.take ().unwrap ().write_all (grants.as_bytes ())?;
+    let output = mysql.wait_with_output ()?;
+    if !output.status.success () {
+        return Err (anyhow::anyhow!("Applying the grants failed: {}", String::from_utf8_lossy (&output.stderr).trim ()));
+    }
+    Ok (())
+}
@@ -0,0 +1,91 @@
+// Cheap bit rot detection for local archives: instead of re-hashing whole archives,
+// a random sample of their 1 MB chunks is re-hashed and compared against the stored leaf hashes.
+// Corrupt archives are logged as errors, and notified if notifications are configured.
+
+use crate::kind::BackupKind;
+use crate::leaves;
+use crate::notify::{self, Alert};
+use crate::queue;
+use crate::{local_archives, Config};
+use fluent::fluent_args;
+use log::{error, info, warn};
+use rand::seq::index;
+use std::time::Duration;
+use tokio::time;
+
+pub struct Sample {
+    pub checked: usize,
+    /// indices of the chunks whose content no longer matches their hash
+    pub corrupt: Vec<usize>,
+}
+
+/// Re-hashes up to `chunks` randomly chosen chunks of the archive, None if it has no stored leaf hashes.
+pub fn sample (archive_path: &str, chunks: usize) -> Result<Option<Sample>, anyhow::Error> {
+    let leaves = match leaves::read_sidecar (archive_path)? {
+        Some (leaves) => leaves,
+        None => return Ok (None)
+    };
+
+    let mut indices = index::sample (&mut rand::thread_rng (), leaves.len (), chunks.min (leaves.len ())).into_vec ();
+    indices.sort_unstable ();
+    let corrupt = leaves::verify_chunks (archive_path, &leaves, indices.iter ().copied ())?;
+
+    Ok (Some (Sample { checked: indices.len (), corrupt }))
+}
+
+/// Never returns, samples every local archive of `config` once per `interval` and logs and notifies every corrupt one.
+pub async fn monitor (config: Config, interval: Duration) {
+    let mut interval = time::interval (interval);
+    loop {
+        interval.tick ().await;
+        let (backups_directory, chunks) = (config.backups_directory.clone (), config.integrity_sample_chunks);
+        let corrupt = match tokio::task::spawn_blocking (move || sample_all (&backups_directory, chunks)).await {
+            Ok (Ok (corrupt)) => corrupt,
+            Ok (Err (err)) => {
+                warn!("Integrity sampling failed: {}", err);
+                continue;
+            },
+            Err (_) => continue
+        };
+        if let Some (notifications) = &config.notifications {
+            for (kind, archive, sample) in &corrupt {
+                notify::alert (notifications, &alert (&config, *kind, archive, sample)).await;
+            }
+        }
+    }
+}
+
+/// The archives with bit rot, of which kind and what was found.
+fn sample_all (backups_directory: &str, chunks: usize) -> Result<Vec<(BackupKind, String, Sample)>, anyhow::Error> {
+    let mut corrupt = Vec::new ();
+    for kind in BackupKind::ALL {
+        for (archive, _) in local_archives (backups_directory, *kind)? {
+            // backups need the disk more
+            queue::pause_while_busy ("integrity sampling");
+            match sample (&archive, chunks)? {
+                Some (sample) if !sample.corrupt.is_empty () => {
+                    error!("Bit rot detected in {}: {} of {} sampled chunks don't match their hash (chunks {:?})",
+                           archive, sample.corrupt.len (), sample.checked, sample.corrupt);
+                    corrupt.push ((*kind, archive, sample));
+                },
+                Some (sample) => info!("Sampled {} chunks of {}, all intact", sample.checked, archive),
+                None => info!("{} has no stored leaf hashes, not sampling it", archive)
+            }
+        }
+    }
+    Ok (corrupt)
+}
+
+/// The `bit-rot` alert of `archive`, in the locale of `config`.
+fn alert (config: &Config, kind: BackupKind, archive: &str, sample: &Sample) -> Alert {
+    let (kind, site) = (kind.to_string (), config.site.as_str ());
+    let chunks = sample.corrupt.iter ().map (usize::to_string).collect::<Vec<_>>().join (", ");
+    Alert {
+        event: "bit-rot",
+        subject: config.locale.text ("subject-bit-rot", fluent_args!["kind" => kind.clone (), "site" => site]),
+        body: config.locale.text ("email-bit-rot", fluent_args!["kind" => kind.clone (), "site" => site, "archive" => archive,
+                                                                 "corrupt" => sample.corrupt.len (), "checked" => sample.checked, "chunks" => chunks]),
+        detail: serde_json::json!({ "site": site, "kind": kind, "outcome": "bit-rot", "archive": archive,
+                                    "checked_chunks": sample.checked, "corrupt_chunks": sample.corrupt }),
+    }
+}
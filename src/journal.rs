@@ -0,0 +1,83 @@
+// Journal of an archive's upload attempts, kept next to the local archive as `<archive>.journal`, a JSON line per attempt:
+// the part (with its offset and tree hash), the HTTP status Glacier answered with or the error, and how long it took.
+// Every attempt is flushed to disk before the next one, so the part an unattended upload failed on and why outlives the run.
+// An upload starts the journal over, a seeding resumed by a later run included.
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use rusoto_core::RusotoError;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::Instant;
+
+pub const JOURNAL_SUFFIX: &str = ".journal";
+
+/// What an attempt uploaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Part {
+    /// 0 for an archive uploaded in one request, counted from 1 when seeding in parts
+    pub part: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub tree_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attempt {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub part: Part,
+    /// None if no response came, or it was not kept
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub millis: u64,
+}
+
+pub fn path (archive_path: &str) -> String {
+    format!("{}{}", archive_path, JOURNAL_SUFFIX)
+}
+
+pub struct Journal {
+    path: String,
+}
+
+impl Journal {
+
+    /// Starts the journal of uploading `archive_path` over.
+    pub fn start (archive_path: &str) -> Journal {
+        let path = path (archive_path);
+        if let Err (err) = File::create (&path) {
+            warn!("Could not start the upload journal {}: {}", path, err);
+        }
+        Journal { path }
+    }
+
+    /// Records the outcome of uploading `part`, started at `started`, with `success` as the status Glacier answers a success with.
+    pub fn record<T, E: std::error::Error + 'static> (&self, part: Part, started: Instant, result: &Result<T, RusotoError<E>>, success: u16) {
+        let attempt = Attempt {
+            at: Utc::now (),
+            part,
+            status: match result {
+                Ok (_) => Some (success),
+                Err (RusotoError::Unknown (response)) => Some (response.status.as_u16 ()),
+                // rusoto parses the other errors out of the response, dropping its status
+                Err (_) => None
+            },
+            error: result.as_ref ().err ().map (|err| err.to_string ()),
+            millis: started.elapsed ().as_millis () as u64,
+        };
+        if let Err (err) = self.append (&attempt) {
+            warn!("Could not journal the upload attempt in {}: {}", self.path, err);
+        }
+    }
+
+    fn append (&self, attempt: &Attempt) -> Result<(), anyhow::Error> {
+        let mut line = serde_json::to_vec (attempt)?;
+        line.push (b'\n');
+        let mut file = OpenOptions::new ().append (true).create (true).open (&self.path)?;
+        file.write_all (&line)?;
+        file.sync_data ()?;
+        Ok (())
+    }
+}
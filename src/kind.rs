@@ -0,0 +1,157 @@
+// Kinds of backups, each created on its own schedule and kept for its own rolling period
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupKind {
+    /// the whole wordpress directory and the database dump
+    Full,
+    /// just the media library, it changes the most
+    Uploads,
+    /// themes and plugins, they change on deploys
+    Code,
+    /// just the database dump, what a full backup shrinks to during a db-only blackout
+    Database,
+    /// the files most often broken by manual edits and the database schema, small enough to take hourly
+    Config,
+}
+
+impl BackupKind {
+
+    pub const ALL: &'static [BackupKind] = &[BackupKind::Full, BackupKind::Uploads, BackupKind::Code, BackupKind::Database, BackupKind::Config];
+
+    /// prefix of the archive names, distinct per kind so that retention never mixes them up
+    pub fn archive_root (&self) -> &'static str {
+        match self {
+            BackupKind::Full => "wordpress_backup",
+            BackupKind::Uploads => "wordpress_uploads",
+            BackupKind::Code => "wordpress_code",
+            BackupKind::Database => "wordpress_database",
+            BackupKind::Config => "wordpress_config",
+        }
+    }
+
+    /// directories archived, relative to the wordpress directory
+    pub fn directories (&self) -> &'static [&'static str] {
+        match self {
+            BackupKind::Full => &[""],
+            BackupKind::Uploads => &["wp-content/uploads"],
+            BackupKind::Code => &["wp-content/themes", "wp-content/plugins"],
+            BackupKind::Database => &[],
+            // and the active theme, which only the database knows
+            BackupKind::Config => &[],
+        }
+    }
+
+    /// single files archived, relative to the wordpress directory
+    pub fn files (&self) -> &'static [&'static str] {
+        match self {
+            BackupKind::Config => &["wp-config.php", ".htaccess"],
+            _ => &[],
+        }
+    }
+
+    /// whether wordpress core, plugins and themes are archived, and so their versions worth recording
+    pub fn includes_code (&self) -> bool {
+        matches!(self, BackupKind::Full | BackupKind::Code)
+    }
+
+    pub fn includes_database (&self) -> bool {
+        matches!(self, BackupKind::Full | BackupKind::Database)
+    }
+
+    /// whether the active theme, server configuration files and a schema only dump are archived
+    pub fn is_config (&self) -> bool {
+        matches!(self, BackupKind::Config)
+    }
+}
+
+impl fmt::Display for BackupKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupKind::Full => write!(f, "full"),
+            BackupKind::Uploads => write!(f, "uploads"),
+            BackupKind::Code => write!(f, "code"),
+            BackupKind::Database => write!(f, "database"),
+            BackupKind::Config => write!(f, "config"),
+        }
+    }
+}
+
+impl FromStr for BackupKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase ().as_str () {
+            "full" => Ok (BackupKind::Full),
+            "uploads" => Ok (BackupKind::Uploads),
+            "code" => Ok (BackupKind::Code),
+            "database" => Ok (BackupKind::Database),
+            "config" => Ok (BackupKind::Config),
+            _ => Err (anyhow::anyhow!("Unknown backup kind: {}, expected one of full, uploads, code, database, config", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub kind: BackupKind,
+    /// None when left to an external scheduler, every `--once` run then backs up
+    pub interval: Option<Duration>,
+    /// days to keep local archives for
+    pub rolling_period: u32,
+}
+
+/// Parses an interval: a plain number of days (the historical format) or a number suffixed with `m`, `h` or `d`.
+pub fn parse_interval (s: &str) -> Result<Duration, anyhow::Error> {
+    let s = s.trim ();
+    let (value, unit) = match s.char_indices ().last () {
+        Some ((i, unit)) if unit.is_ascii_alphabetic () => (&s [..i], unit),
+        _ => (s, 'd'),
+    };
+    let value = value.parse::<u64>()?;
+    let seconds = match unit {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return Err (anyhow::anyhow!("Unknown interval unit in {}, expected one of m, h, d", s))
+    };
+    if value == 0 {
+        return Err (anyhow::anyhow!("Interval {} must be positive", s));
+    }
+    Ok (Duration::from_secs (value * seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_default_to_days () {
+        assert_eq!(parse_interval ("1").unwrap (), Duration::from_secs (86400));
+        assert_eq!(parse_interval (" 7 ").unwrap (), Duration::from_secs (7 * 86400));
+        assert_eq!(parse_interval ("30m").unwrap (), Duration::from_secs (1800));
+        assert_eq!(parse_interval ("6h").unwrap (), Duration::from_secs (6 * 3600));
+        assert_eq!(parse_interval ("2d").unwrap (), Duration::from_secs (2 * 86400));
+    }
+
+    #[test]
+    fn invalid_intervals_are_rejected () {
+        for interval in &["", "0", "0h", "-1", "1w", "h", "1.5d", "daily"] {
+            assert!(parse_interval (interval).is_err (), "{} should be rejected", interval);
+        }
+    }
+
+    #[test]
+    fn kinds_round_trip () {
+        for kind in BackupKind::ALL {
+            assert_eq!(kind.to_string ().parse::<BackupKind>().unwrap (), *kind);
+        }
+        assert_eq!("Full".parse::<BackupKind>().unwrap (), BackupKind::Full);
+        assert!("weekly".parse::<BackupKind>().is_err ());
+    }
+}
@@ -0,0 +1,115 @@
+// Leader election between replicas sharing storage, e.g. Kubernetes pods mounting one volume:
+// a lease file naming its holder and when it expires, renewed by the leader, taken over by a standby once it expired.
+// Unlike the pid file of `lock` it works across hosts and containers, where pids mean nothing.
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::time;
+
+/// how long a freshly written lease is left alone before trusting it, lets a racing takeover show
+const SETTLE: Duration = Duration::from_secs (2);
+
+/// true while waiting for another replica's lease, which the liveness probe takes as healthy
+static STANDING_BY: AtomicBool = AtomicBool::new (false);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    holder: String,
+    expires: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Lease {
+    path: PathBuf,
+    duration: Duration,
+    /// who we are: the pod (host) name and pid
+    holder: String,
+}
+
+pub fn standing_by () -> bool {
+    STANDING_BY.load (Ordering::SeqCst)
+}
+
+impl Lease {
+
+    pub fn new (path: &str, duration: Duration) -> Lease {
+        let host = std::env::var ("HOSTNAME").ok ()
+            .or_else (|| fs::read_to_string ("/proc/sys/kernel/hostname").ok ())
+            .unwrap_or_else (|| String::from ("unknown"));
+        Lease {
+            path: PathBuf::from (path),
+            duration,
+            holder: format!("{}:{}", host.trim (), std::process::id ()),
+        }
+    }
+
+    fn read (&self) -> Option<Record> {
+        fs::read (&self.path).ok ().and_then (|content| serde_json::from_slice (&content).ok ())
+    }
+
+    fn write (&self) -> Result<(), anyhow::Error> {
+        let record = Record { holder: self.holder.clone (), expires: Utc::now () + chrono::Duration::from_std (self.duration)? };
+        let temporary = PathBuf::from (format!("{}.{}.tmp", self.path.display (), std::process::id ()));
+        fs::write (&temporary, serde_json::to_vec (&record)?)?;
+        fs::rename (&temporary, &self.path)?;
+        Ok (())
+    }
+
+    /// The other replica holding an unexpired lease, if any.
+    fn other_holder (&self) -> Option<String> {
+        self.read ()
+            .filter (|record| record.holder != self.holder && record.expires > Utc::now ())
+            .map (|record| record.holder)
+    }
+
+    /// Takes (or renews) the lease unless another replica holds it, waiting out a racing takeover.
+    pub async fn try_acquire (&self) -> Result<bool, anyhow::Error> {
+        if self.other_holder ().is_some () {
+            return Ok (false);
+        }
+        self.write ()?;
+        time::sleep (SETTLE).await;
+        Ok (self.read ().is_some_and (|record| record.holder == self.holder))
+    }
+
+    /// Stands by until this replica becomes the leader.
+    pub async fn acquire (&self) -> Result<(), anyhow::Error> {
+        STANDING_BY.store (true, Ordering::SeqCst);
+        let mut logged = false;
+        while !self.try_acquire ().await? {
+            if !logged {
+                info!("Standing by, {} holds the lease {}", self.other_holder ().unwrap_or_default (), self.path.display ());
+                logged = true;
+            }
+            time::sleep (self.duration / 3).await;
+        }
+        STANDING_BY.store (false, Ordering::SeqCst);
+        info!("Holding the lease {} as {}", self.path.display (), self.holder);
+        Ok (())
+    }
+
+    /// Renews the lease, returns an error once another replica took it over so the leader stops backing up.
+    pub async fn keep (self) -> Result<(), anyhow::Error> {
+        loop {
+            time::sleep (self.duration / 3).await;
+            if let Some (holder) = self.other_holder () {
+                return Err (anyhow::anyhow!("Lost the lease {} to {}", self.path.display (), holder));
+            }
+            if let Err (err) = self.write () {
+                warn!("Could not renew the lease {}: {}", self.path.display (), err);
+            }
+        }
+    }
+
+    /// Gives the lease up, so a standby takes over without waiting for it to expire.
+    pub fn release (&self) {
+        if self.read ().is_some_and (|record| record.holder == self.holder) {
+            fs::remove_file (&self.path).unwrap_or_else (|why| warn!("Could not remove {} {}", self.path.display (), why));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// The 1 MB leaf hashes of an archive's tree hash, kept next to it as `<archive>.leaves`:
+// the raw 32 byte sha256 of every chunk, in order, so any region of an archive can be checked
+// (sampled, or against a ranged retrieval) without hashing the whole archive
+
+use crate::tree_hash::{self, run_sha256, ONE_MB};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::RangeInclusive;
+
+pub const LEAVES_SUFFIX: &str = ".leaves";
+const HASH_SIZE: usize = 32;
+
+pub fn write_sidecar (archive_path: &str, leaves: &[Vec<u8>]) -> Result<(), anyhow::Error> {
+    fs::write (format!("{}{}", archive_path, LEAVES_SUFFIX), leaves.concat ())?;
+    Ok (())
+}
+
+/// The leaf hashes of a local archive, `None` if they were never stored.
+pub fn read_sidecar (archive_path: &str) -> Result<Option<Vec<Vec<u8>>>, anyhow::Error> {
+    let content = match fs::read (format!("{}{}", archive_path, LEAVES_SUFFIX)) {
+        Ok (content) => content,
+        Err (err) if err.kind () == std::io::ErrorKind::NotFound => return Ok (None),
+        Err (err) => return Err (err.into ())
+    };
+    if content.len () % HASH_SIZE != 0 {
+        return Err (anyhow::anyhow!("{}{} is truncated", archive_path, LEAVES_SUFFIX));
+    }
+    Ok (Some (content.chunks (HASH_SIZE).map (|hash| hash.to_vec ()).collect ()))
+}
+
+/// Hashes an archive created before leaf hashes were stored and stores them, returning its tree hash.
+pub fn rebuild (archive_path: &str) -> Result<String, anyhow::Error> {
+    let (tree_hash, leaves) = tree_hash::tree_hash_with_leaves (archive_path)?;
+    write_sidecar (archive_path, &leaves)?;
+    Ok (tree_hash::to_hex_string (&tree_hash))
+}
+
+/// Indices of the chunks holding the bytes `start..=end`.
+pub fn chunks_of_range (start: u64, end: u64) -> RangeInclusive<usize> {
+    (start / ONE_MB as u64) as usize ..= (end / ONE_MB as u64) as usize
+}
+
+/// The tree hash of the bytes `start..=end`, what Glacier reports for a retrieval of that range.
+/// Only ranges starting and ending (but for the archive's end) on a megabyte boundary have one.
+pub fn range_tree_hash (leaves: &[Vec<u8>], size: u64, start: u64, end: u64) -> Result<String, anyhow::Error> {
+    let chunk = ONE_MB as u64;
+    if !start.is_multiple_of (chunk) || (end + 1 != size && !(end + 1).is_multiple_of (chunk)) || end >= size || start > end {
+        return Err (anyhow::anyhow!("Range {}-{} is not megabyte aligned within the {} bytes of the archive", start, end, size));
+    }
+    let chunks = chunks_of_range (start, end);
+    Ok (tree_hash::to_hex_string (&tree_hash::tree_hash_of_leaves (&leaves [chunks])))
+}
+
+/// Re-hashes the chunks at `indices`, returning those whose content no longer matches their stored hash.
+pub fn verify_chunks (archive_path: &str, leaves: &[Vec<u8>], indices: impl IntoIterator<Item = usize>) -> Result<Vec<usize>, anyhow::Error> {
+    let mut file = File::open (archive_path)?;
+    let mut buffer = Vec::with_capacity (ONE_MB);
+    let mut corrupt = Vec::new ();
+    for index in indices {
+        buffer.clear ();
+        file.seek (SeekFrom::Start ((index * ONE_MB) as u64))?;
+        (&mut file).take (ONE_MB as u64).read_to_end (&mut buffer)?;
+        if leaves.get (index).is_none_or (|leaf| run_sha256 (&buffer) != *leaf) {
+            corrupt.push (index);
+        }
+    }
+    Ok (corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree_hash::TreeHasher;
+
+    /// 5.5 MB of bytes that differ from chunk to chunk.
+    fn archive () -> Vec<u8> {
+        (0..ONE_MB * 11 / 2).map (|i| (i % 251) as u8 ^ (i / ONE_MB) as u8).collect ()
+    }
+
+    fn tree_hasher (bytes: &[u8]) -> (String, Vec<Vec<u8>>) {
+        let mut hasher = TreeHasher::new ();
+        hasher.update (bytes);
+        let (tree_hash, leaves) = hasher.finalize ();
+        (tree_hash::to_hex_string (&tree_hash), leaves)
+    }
+
+    #[test]
+    fn tree_hash_of_leaves_matches_the_tree_hasher () {
+        let bytes = archive ();
+        for length in (1..=5).flat_map (|chunks| [chunks * ONE_MB - 1, chunks * ONE_MB]).chain ([bytes.len ()]) {
+            let (expected, leaves) = tree_hasher (&bytes [..length]);
+            assert_eq!(tree_hash::to_hex_string (&tree_hash::tree_hash_of_leaves (&leaves)), expected, "{} bytes", length);
+        }
+    }
+
+    #[test]
+    fn range_tree_hash_matches_the_tree_hasher_over_the_range () {
+        let bytes = archive ();
+        let size = bytes.len () as u64;
+        let (whole, leaves) = tree_hasher (&bytes);
+        let mb = ONE_MB as u64;
+        assert_eq!(range_tree_hash (&leaves, size, 0, size - 1).unwrap (), whole);
+        for (start, end) in [(mb, 4 * mb - 1), (0, 3 * mb - 1), (2 * mb, 3 * mb - 1), (5 * mb, size - 1), (3 * mb, size - 1)] {
+            let (expected, _) = tree_hasher (&bytes [start as usize ..= end as usize]);
+            assert_eq!(range_tree_hash (&leaves, size, start, end).unwrap (), expected, "range {}-{}", start, end);
+        }
+    }
+
+    #[test]
+    fn unaligned_ranges_have_no_tree_hash () {
+        let bytes = archive ();
+        let size = bytes.len () as u64;
+        let (_, leaves) = tree_hasher (&bytes);
+        let mb = ONE_MB as u64;
+        for (start, end) in [(1, mb - 1), (0, mb), (mb, 2 * mb - 2), (mb + 1, size - 1), (0, size), (2 * mb, mb - 1)] {
+            assert!(range_tree_hash (&leaves, size, start, end).is_err (), "range {}-{}", start, end);
+        }
+    }
+
+    #[test]
+    fn chunks_of_range_covers_partial_chunks () {
+        let mb = ONE_MB as u64;
+        assert_eq!(chunks_of_range (0, mb - 1), 0..=0);
+        assert_eq!(chunks_of_range (mb - 1, mb), 0..=1);
+        assert_eq!(chunks_of_range (2 * mb + 5, 4 * mb), 2..=4);
+    }
+}
@@ -0,0 +1,110 @@
+// Text for people rather than automation, the subject and email body of notifications and `describe --human`,
+// in English, French or German: `LOCALE=fr`. The messages are Fluent (https://projectfluent.org), `LOCALE_FILE`
+// overrides any of them, for another language or to reword them; see `locales/en.ftl`. English fills in messages a locale lacks.
+
+use crate::AnyResult;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use fluent::concurrent::FluentBundle;
+use fluent::{FluentArgs, FluentResource};
+use fluent_syntax::ast;
+use log::warn;
+use std::fmt;
+use std::fs;
+use unic_langid::LanguageIdentifier;
+
+const EN: &str = include_str!("../locales/en.ftl");
+const FR: &str = include_str!("../locales/fr.ftl");
+const DE: &str = include_str!("../locales/de.ftl");
+
+pub struct Locale {
+    name: String,
+    file: Option<String>,
+    bundle: FluentBundle<FluentResource>,
+}
+
+impl fmt::Debug for Locale {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct ("Locale")
+            .field ("name", &self.name)
+            .field ("file", &self.file)
+            .finish ()
+    }
+}
+
+impl Locale {
+
+    /// `name` such as `fr` or `de-CH`, with the messages of `file` over the built-in ones.
+    pub fn new (name: &str, file: Option<&str>) -> AnyResult<Locale> {
+        let language = name.parse::<LanguageIdentifier>()
+            .map_err (|_| anyhow::anyhow!("Invalid locale {}, expected e.g. fr or de-CH", name))?;
+        let translation = match language.language.as_str () {
+            "en" => None,
+            "fr" => Some (FR),
+            "de" => Some (DE),
+            _ if file.is_some () => None,
+            _ => return Err (anyhow::anyhow!("There is no {} translation, the built-in ones are en, fr and de, LOCALE_FILE may hold one", name))
+        };
+
+        let mut bundle = FluentBundle::new_concurrent (vec! [language]);
+        // no unicode isolation marks around the values, emails and terminals show them
+        bundle.set_use_isolating (false);
+        let english = resource ("the English messages", EN)?;
+        let known = ids (&english);
+        bundle.add_resource_overriding (english);
+        if let Some (translation) = translation {
+            bundle.add_resource_overriding (resource (name, translation)?);
+        }
+        if let Some (file) = file {
+            let source = fs::read_to_string (file).map_err (|err| anyhow::anyhow!("Could not read {}: {}", file, err))?;
+            let overrides = resource (file, &source)?;
+            // a typo would leave the message it meant to override as it is, unnoticed
+            if let Some (unknown) = ids (&overrides).into_iter ().find (|id| !known.contains (id)) {
+                return Err (anyhow::anyhow!("Unknown message {} in {}, see locales/en.ftl for the messages there are", unknown, file));
+            }
+            bundle.add_resource_overriding (overrides);
+        }
+
+        let locale = Locale { name: String::from (name), file: file.map (String::from), bundle };
+        let date_format = locale.text ("date-format", FluentArgs::new ());
+        if StrftimeItems::new (&date_format).any (|item| item == Item::Error) {
+            return Err (anyhow::anyhow!("Invalid date-format {} of locale {}", date_format, name));
+        }
+        Ok (locale)
+    }
+
+    /// The message `id` with `args`, the id itself should it be missing.
+    pub fn text (&self, id: &str, args: FluentArgs) -> String {
+        let pattern = match self.bundle.get_message (id).and_then (|message| message.value ()) {
+            Some (pattern) => pattern,
+            None => return String::from (id)
+        };
+        let mut errors = vec! [];
+        let text = self.bundle.format_pattern (pattern, Some (&args), &mut errors).into_owned ();
+        if !errors.is_empty () {
+            warn!("Message {} of locale {} is not what was expected: {:?}", id, self.name, errors);
+        }
+        text
+    }
+
+    pub fn date (&self, at: DateTime<Utc>) -> String {
+        at.format (&self.text ("date-format", FluentArgs::new ())).to_string ()
+    }
+}
+
+fn resource (name: &str, source: &str) -> AnyResult<FluentResource> {
+    FluentResource::try_new (String::from (source)).map_err (|(_, errors)| {
+        let errors = errors.iter ().map (|err| err.to_string ()).collect::<Vec<_>>();
+        anyhow::anyhow!("Invalid messages in {}: {}", name, errors.join ("; "))
+    })
+}
+
+/// The ids of the messages of `resource`.
+fn ids (resource: &FluentResource) -> Vec<String> {
+    resource.entries ()
+        .filter_map (|entry| match entry {
+            ast::Entry::Message (message) => Some (String::from (message.id.name)),
+            _ => None
+        })
+        .collect ()
+}
@@ -0,0 +1,100 @@
+// Keeps independent invocations (the daemon, cron or systemd triggered `--once` runs) from backing up at the same time.
+// The lock is a pid file in the backups directory, one left behind by a process that is gone is taken over.
+
+use log::warn;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+pub const LOCK_FILE: &str = "mer-de-glace.pid";
+
+/// Held for as long as the run lasts, released when dropped.
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// None when another live process holds the lock.
+    pub fn acquire (backups_directory: &str) -> Result<Option<RunLock>, anyhow::Error> {
+        RunLock::acquire_file (Path::new (backups_directory).join (LOCK_FILE))
+    }
+
+    /// Locks with the pid file at `path`, None when another live process holds it.
+    pub fn acquire_file (path: PathBuf) -> Result<Option<RunLock>, anyhow::Error> {
+        for _ in 0..2 {
+            match OpenOptions::new ().write (true).create_new (true).open (&path) {
+                Ok (mut file) => {
+                    write!(file, "{}", process::id ())?;
+                    return Ok (Some (RunLock { path }));
+                },
+                Err (err) if err.kind () == ErrorKind::AlreadyExists => {
+                    let holder = fs::read_to_string (&path).unwrap_or_default ();
+                    if live (holder.trim ()).is_some () {
+                        return Ok (None);
+                    }
+                    warn!("Taking over the lock {} left behind by process {}", path.display (), holder.trim ());
+                    fs::remove_file (&path)?;
+                },
+                Err (err) => return Err (err.into ())
+            }
+        }
+        Ok (None)
+    }
+}
+
+/// The live process holding the pid file at `path`, if any.
+pub fn holder (path: &Path) -> Option<u32> {
+    live (fs::read_to_string (path).ok ()?.trim ())
+}
+
+/// Whether the pid file at `path` was left behind, the process it names being gone.
+/// One naming this process is held by it, e.g. by another of its threads.
+pub fn is_stale (path: &Path) -> bool {
+    match fs::read_to_string (path).ok ().and_then (|pid| pid.trim ().parse::<u32>().ok ()) {
+        Some (pid) => pid != process::id () && !Path::new ("/proc").join (pid.to_string ()).exists (),
+        // written right after the file is created
+        None => true
+    }
+}
+
+/// `pid` if it's a process other than this one, which never waits for a lock it holds itself:
+/// a pid file naming this process was left by an earlier one with the same pid, e.g. pid 1 of a restarted container.
+fn live (pid: &str) -> Option<u32> {
+    pid.parse::<u32>().ok ()
+        .filter (|pid| *pid != process::id () && Path::new ("/proc").join (pid.to_string ()).exists ())
+}
+
+impl Drop for RunLock {
+    fn drop (&mut self) {
+        fs::remove_file (&self.path).unwrap_or_else (| why | { warn!("Could not remove {} {}", self.path.display (), why) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_of_gone_processes_are_stale () {
+        let path = std::env::temp_dir ().join (format!("mer-de-glace-lock-{}.pid", process::id ()));
+        fs::write (&path, process::id ().to_string ()).unwrap ();
+        assert!(!is_stale (&path));
+        // above the largest pid linux hands out
+        fs::write (&path, "99999999").unwrap ();
+        assert!(is_stale (&path));
+        fs::write (&path, "").unwrap ();
+        assert!(is_stale (&path));
+        fs::remove_file (&path).unwrap ();
+    }
+
+    #[test]
+    fn locks_left_behind_are_taken_over () {
+        let path = std::env::temp_dir ().join (format!("mer-de-glace-takeover-{}.pid", process::id ()));
+        fs::write (&path, "99999999").unwrap ();
+        let lock = RunLock::acquire_file (path.clone ()).unwrap ().expect ("taken over");
+        assert_eq!(fs::read_to_string (&path).unwrap (), process::id ().to_string ());
+        drop (lock);
+        assert!(!path.exists ());
+    }
+}
@@ -0,0 +1,85 @@
+// Log verbosity: `VERBOSITY` is the global level, `LOG_FILTERS` overrides it per subsystem (e.g. `upload=debug,scheduler=warn`).
+// With `LOG_FILTERS_FILE` the overrides are read from that file instead, and re-read on SIGHUP.
+
+use log::{info, warn, Log, Metadata, Record};
+use std::fs;
+use std::sync::RwLock;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// modules whose logs can be filtered by their short name
+pub const SUBSYSTEMS: &[&str] = &["upload", "scheduler", "seed", "attest", "blackout", "cloudwatch", "export", "integrity", "lease", "lock", "maintenance", "notify", "quarantine", "queue", "restore", "retrieval", "site", "sla", "ssh", "standby", "state", "sts", "symlinks", "version", "walk"];
+
+static LOGGER: Reloadable = Reloadable { inner: RwLock::new (None) };
+
+/// env_logger, swapped for a freshly configured one on reload
+struct Reloadable {
+    inner: RwLock<Option<env_logger::Logger>>,
+}
+
+impl Log for Reloadable {
+    fn enabled (&self, metadata: &Metadata) -> bool {
+        self.inner.read ().unwrap ().as_ref ().is_some_and (|logger| logger.enabled (metadata))
+    }
+
+    fn log (&self, record: &Record) {
+        if let Some (logger) = self.inner.read ().unwrap ().as_ref () {
+            logger.log (record);
+        }
+    }
+
+    fn flush (&self) {
+        if let Some (logger) = self.inner.read ().unwrap ().as_ref () {
+            logger.flush ();
+        }
+    }
+}
+
+/// The env_logger filter spec: the global level followed by the overrides,
+/// subsystem names are expanded to their module, anything else (e.g. `rusoto_core=debug`) is passed as is.
+pub fn spec (verbosity: &str, filters: &str) -> String {
+    let mut directives = vec! [String::from (verbosity)];
+    for filter in filters.split (',').map (str::trim).filter (|filter| !filter.is_empty ()) {
+        match filter.split_once ('=') {
+            Some ((name, level)) if SUBSYSTEMS.contains (&name) => directives.push (format!("mer_de_glace::{}={}", name, level)),
+            _ => directives.push (String::from (filter))
+        }
+    }
+    directives.join (",")
+}
+
+pub fn init (verbosity: &str, filters: Option<String>, filters_file: Option<&str>) -> Result<(), anyhow::Error> {
+    let filters = match filters_file {
+        Some (path) => fs::read_to_string (path)?,
+        None => filters.unwrap_or_default ()
+    };
+    apply (&spec (verbosity, &filters));
+    log::set_logger (&LOGGER).map_err (|err| anyhow::anyhow!("Could not install the logger: {}", err))
+}
+
+fn apply (spec: &str) {
+    let logger = env_logger::Builder::new ().parse_filters (spec).build ();
+    log::set_max_level (logger.filter ());
+    *LOGGER.inner.write ().unwrap () = Some (logger);
+}
+
+/// Never returns, re-reads the overrides from `filters_file` whenever the process gets a SIGHUP.
+pub async fn reload_on_hangup (verbosity: String, filters_file: String) {
+    let mut hangups = match signal (SignalKind::hangup ()) {
+        Ok (hangups) => hangups,
+        Err (err) => {
+            warn!("Could not listen for SIGHUP, log filters can't be reloaded: {}", err);
+            return;
+        }
+    };
+
+    while hangups.recv ().await.is_some () {
+        match fs::read_to_string (&filters_file) {
+            Ok (filters) => {
+                let spec = spec (&verbosity, &filters);
+                apply (&spec);
+                info!("Reloaded log filters from {}: {}", filters_file, spec);
+            },
+            Err (err) => warn!("Could not read log filters from {}, keeping the current ones: {}", filters_file, err)
+        }
+    }
+}
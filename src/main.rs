@@ -1,35 +1,126 @@
+mod access;
+mod admin;
+mod anomaly;
+mod attest;
+mod aws;
+mod blackout;
+mod chaos;
+mod cloudwatch;
+mod cron;
+mod describe;
+mod differential;
+mod doctor;
+mod dump;
+mod error;
+mod export;
+mod grants;
+mod integrity;
+mod journal;
+mod kind;
+mod lease;
+mod leaves;
+mod locale;
+mod lock;
+mod logging;
+mod maintenance;
+mod manifest;
+mod notify;
+mod outbox;
+mod output;
+mod pipeline;
+mod profile;
+mod provision;
+mod queue;
+mod quarantine;
+mod restore;
+mod retention;
+mod retrieval;
+mod rto;
+mod sanitize;
+mod scheduler;
+mod secrets;
+mod seed;
+mod signature;
+mod simulation;
+mod site;
+mod sla;
+mod ssh;
+mod standby;
+mod sts;
+mod symlinks;
+mod state;
+mod throttle;
+mod tls;
 mod tree_hash;
+mod upload;
+mod upload_record;
+mod verify;
+mod version;
+mod versions;
+mod walk;
 
-use bytes::Bytes;
 use chrono::{Utc, DateTime};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use error::{BackupError, Classify};
+use kind::{BackupKind, Schedule};
 use std::time::Duration as Duration;
 use flate2::Compression;
 use flate2::write::GzEncoder;
 use log::{info, warn};
 use regex::Regex;
-use rusoto_core::Region;
-use rusoto_glacier::{Glacier, GlacierClient, DescribeVaultInput, CreateVaultInput, UploadArchiveInput, ArchiveCreationOutput};
+use rusoto_glacier::GlacierClient;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::{File, create_dir_all};
 use std::fs;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::Path;
-use std::process::{Command, Output};
+use std::process::{self, Command, Output};
+use std::net::SocketAddr;
 use std::str::FromStr;
-use tokio::time;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
 #[macro_use] extern crate lazy_static;
 
-const ARCHIVE_ROOT: &str = "wordpress_backup";
+const PARTIAL_SUFFIX: &str = ".partial";
+/// files kept next to an archive, removed together with it
+const SIDECAR_SUFFIXES: &[&str] = &[signature::SIGNATURE_SUFFIX, profile::PROFILE_SUFFIX, upload_record::UPLOAD_RECORD_SUFFIX, leaves::LEAVES_SUFFIX,
+                                      journal::JOURNAL_SUFFIX, outbox::QUEUED_SUFFIX];
 
 lazy_static! {
     static ref RE: Regex = Regex::new(r"\d{4}-\d{2}-\d{2}").unwrap();
+    // a single segment of the canonical `{site}/{kind}/{timestamp}` archive name
+    static ref SITE_RE: Regex = Regex::new(r"^[A-Za-z0-9][A-Za-z0-9._-]*$").unwrap();
+}
+
+/// What to do when the archive about to be created already exists on disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CollisionPolicy {
+    Overwrite,
+    Suffix,
+    Abort,
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase ().as_str () {
+            "overwrite" => Ok (CollisionPolicy::Overwrite),
+            "suffix" => Ok (CollisionPolicy::Suffix),
+            "abort" => Ok (CollisionPolicy::Abort),
+            _ => Err (anyhow::anyhow!("Unknown collision policy: {}, expected one of overwrite, suffix, abort", s))
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Config {
-    interval: u32,
-    archive_rolling_period: u32,
+    /// names the archives of this site in a vault shared with others
+    site: String,
+    schedules: Vec<Schedule>,
     wordpress_directory: String,
     mysql_host: String,
     mysql_port: String,
@@ -39,225 +130,1720 @@ struct Config {
     backups_directory: String,
     aws_region: String,
     aws_glacier_vault_name: String,
+    aws_glacier_endpoint: Option<String>,
+    /// the account owning the vault, if not the one of the credentials
+    aws_glacier_account_id: Option<String>,
+    /// the vault's tags, notifications and access policy kept as configured
+    vault_provisioning: provision::Provisioning,
+    collision_policy: CollisionPolicy,
+    stale_file_threshold: u32,
+    /// archives smaller than that many bytes are quarantined rather than counted as backups
+    min_archive_size: u64,
+    update_check: bool,
+    verify_command: Option<String>,
+    signing_key: Option<signature::SigningKey>,
+    embed_config: bool,
+    profile: bool,
+    standby: standby::Standby,
+    walk_threads: usize,
+    reproducible: bool,
+    /// bytes per second read from the wordpress directory while archiving, if capped
+    read_limit: Option<u64>,
+    slas: Vec<sla::Sla>,
+    blackouts: Vec<blackout::Blackout>,
+    /// how often to sample local archives for bit rot, if at all
+    integrity_sample_interval: Option<Duration>,
+    integrity_sample_chunks: usize,
+    /// how many backups may run at once, one per schedule unless set
+    backup_concurrency: Option<usize>,
+    /// how long an archive being restored is protected from pruning at most
+    restore_grace: Duration,
+    /// where the health probes are served, if at all
+    admin_address: Option<SocketAddr>,
+    /// who may use the admin server
+    admin_access: access::Access,
+    /// the admin server's certificate, plain http without
+    admin_tls: Option<tls::Tls>,
+    /// the leader lease on storage shared by replicas, if any
+    lease: Option<lease::Lease>,
+    /// where run outcomes are published in CloudWatch and EventBridge, if anywhere
+    cloudwatch: Option<cloudwatch::CloudWatch>,
+    /// where run outcomes are sent in SNS and SQS, if anywhere
+    notifications: Option<notify::Notifications>,
+    /// the language of notifications
+    locale: Arc<locale::Locale>,
+    /// how the initial full backup is uploaded, if rate limited
+    seeding: Option<seed::Seeding>,
+    /// invoked by an external scheduler with `--once`, rather than running as a daemon
+    once: bool,
+    /// archives are queued for `mer-de-glace uploader` rather than uploaded
+    enqueue: bool,
+    /// how long a backup waits for a wordpress update in progress at most
+    update_wait: Duration,
+    /// the site's retention hint, recorded in archive descriptions
+    retention_tag: Option<String>,
+    /// the web host the site is archived from, when not this one
+    ssh: Option<ssh::Ssh>,
+    /// when a successful backup's size is suspicious, if ever
+    size_anomaly: Option<anomaly::SizeAnomaly>,
+    /// the role every run assumes for credentials of its own, if any
+    sts: Option<sts::Sts>,
+    /// the temporary credentials of the run, the default provider chain's otherwise
+    credentials: Option<rusoto_core::credential::AwsCredentials>,
+    /// where run outcomes are attested, if anywhere
+    attestation_log: Option<String>,
+    /// how old a complete dump left behind by an interrupted run may be to be reused, if reused at all
+    dump_reuse_max_age: Option<Duration>,
+    /// files from outside the wordpress directory archived by config backups, e.g. the nginx vhost
+    server_config_files: Vec<String>,
+    /// which file names are escaped in archives
+    filename_sanitization: sanitize::Mode,
+    /// wp_options rows captured with the cron schedule
+    cron_snapshot_options: Vec<String>,
+    /// the secrets kept in a secret store, and how to read them
+    secrets: secrets::Secrets,
+    /// the append-mostly tables dumped differentially, if any
+    differential: Option<differential::Differential>,
+    /// whether the database users and their grants are archived with the dump
+    backup_grants: bool,
+    /// failures injected for rehearsals, none unless `CHAOS` is set
+    chaos: chaos::Chaos,
 }
 
 type AnyResult<T> = Result<T, anyhow::Error>;
 
 #[tokio::main]
-async fn main() -> AnyResult<()> {
+async fn main () {
+    if let Err (err) = run ().await {
+        eprintln!("Error: {:?}", err);
+        process::exit (error::exit_code (&err));
+    }
+}
+
+async fn run () -> AnyResult<()> {
+
+    let matches = App::new ("mer-de-glace")
+        .version (version::LONG_VERSION)
+        .about ("Rolling backups of wordpress installations to AWS Glacier")
+        .arg (Arg::with_name ("once")
+              .long ("once")
+              .help ("Runs the backups that are due once and exits, for running from cron or systemd timers"))
+        .arg (Arg::with_name ("enqueue")
+              .long ("enqueue")
+              .help ("Only archives, queuing the archives for mer-de-glace uploader to upload rather than uploading them"))
+        .arg (Arg::with_name ("profile")
+              .long ("profile")
+              .help ("Records fine grained timings of every backup and writes them next to the archive"))
+        .arg (Arg::with_name ("output")
+              .long ("output")
+              .takes_value (true)
+              .possible_values (&["text", "json"])
+              .global (true)
+              .help ("Prints the result of list, prune --explain, policy simulate, rto, cron-check, versions, doctor, provision and verify-local as versioned JSON"))
+        .subcommand (SubCommand::with_name ("doctor")
+                     .about ("Checks that the AWS region is valid, the credentials are accepted there and the vault exists in it"))
+        .subcommand (SubCommand::with_name ("provision")
+                     .about ("Creates the vault if missing and puts back its tags, notifications and access policy as configured")
+                     .arg (Arg::with_name ("check")
+                           .long ("check")
+                           .help ("Only reports the settings that differ from the configuration, changing nothing")))
+        .subcommand (SubCommand::with_name ("uploader")
+                     .about ("Uploads the archives queued by --enqueue runs, oldest first, and exits once the queue is empty"))
+        .subcommand (SubCommand::with_name ("manifest")
+                     .about ("Prints the manifest of a local archive, migrated to the current format")
+                     .arg (Arg::with_name ("ARCHIVE").required (true)))
+        .subcommand (SubCommand::with_name ("generate-signing-key")
+                     .about ("Creates a new ed25519 key for signing archives and prints its public key")
+                     .arg (Arg::with_name ("PATH").required (true)))
+        .subcommand (SubCommand::with_name ("verify-signature")
+                     .about ("Verifies the detached signature of a local archive against SIGNING_PUBLIC_KEY")
+                     .arg (Arg::with_name ("ARCHIVE").required (true)))
+        .subcommand (SubCommand::with_name ("verify-local")
+                     .about ("Checks any local tar.gz archive: its tree hash, that it reads to the end, its manifest, and the upload record of it if there is one")
+                     .arg (Arg::with_name ("ARCHIVE").required (true)))
+        .subcommand (SubCommand::with_name ("verify-attestations")
+                     .about ("Checks the hash chain of the attestation log and prints its head hash, to compare with the published one")
+                     .arg (Arg::with_name ("LOG").help ("defaults to ATTESTATION_LOG")))
+        .subcommand (SubCommand::with_name ("cron-check")
+                     .about ("Compares the cron schedule, key options and transients of the database with the snapshot in an archive, e.g. after a restore")
+                     .arg (Arg::with_name ("ARCHIVE").required_unless_one (&["save", "against"]).help ("path or name of an archive in BACKUPS_DIRECTORY"))
+                     .arg (Arg::with_name ("save").long ("save").takes_value (true).conflicts_with_all (&["ARCHIVE", "against"])
+                           .help ("writes a snapshot of the database to FILE instead, e.g. before a restore"))
+                     .arg (Arg::with_name ("against").long ("against").takes_value (true).conflicts_with ("ARCHIVE")
+                           .help ("compares with a snapshot written by --save rather than an archive's")))
+        .subcommand (SubCommand::with_name ("describe")
+                     .about ("Summarizes what a local archive contains and where it is stored")
+                     .arg (Arg::with_name ("ARCHIVE").required (true).help ("path or name of an archive in BACKUPS_DIRECTORY"))
+                     .arg (Arg::with_name ("human").long ("human").help ("plain language, suitable for an email")))
+        .subcommand (SubCommand::with_name ("list")
+                     .about ("Lists the local archives by their canonical {site}/{kind}/{timestamp} name")
+                     .arg (Arg::with_name ("site").long ("site").takes_value (true).help ("only archives of that site"))
+                     .arg (Arg::with_name ("kind").long ("kind").takes_value (true).help ("only archives of that kind")))
+        .subcommand (SubCommand::with_name ("prune")
+                     .about ("Removes local archives past the rolling period of their kind")
+                     .arg (Arg::with_name ("explain").long ("explain")
+                           .help ("only prints which rule keeps or deletes every local and uploaded archive, and from when on")))
+        .subcommand (SubCommand::with_name ("policy")
+                     .about ("Tunes the retention policy before applying it")
+                     .setting (AppSettings::SubcommandRequiredElseHelp)
+                     .subcommand (SubCommand::with_name ("simulate")
+                                  .about ("Replays the recorded runs against a proposed retention policy: the archives and bytes kept over time and the Glacier storage cost")
+                                  .arg (Arg::with_name ("days").long ("days").takes_value (true).default_value ("365").help ("how many days back to replay"))
+                                  .arg (Arg::with_name ("every").long ("every").takes_value (true).default_value ("7").help ("days between the reported points"))
+                                  .arg (Arg::with_name ("rolling-period").long ("rolling-period").takes_value (true).multiple (true).number_of_values (1)
+                                        .help ("KIND=DAYS to keep local archives of that kind for, defaults to the configured rolling period"))
+                                  .arg (Arg::with_name ("glacier-retention").long ("glacier-retention").takes_value (true)
+                                        .help ("days to keep uploaded archives for, defaults to indefinitely"))))
+        .subcommand (SubCommand::with_name ("leaves")
+                     .about ("Prints the stored 1 MB leaf hashes of a local archive, or checks a byte range of it against them")
+                     .arg (Arg::with_name ("ARCHIVE").required (true).help ("path or name of an archive in BACKUPS_DIRECTORY"))
+                     .arg (Arg::with_name ("range").long ("range").takes_value (true)
+                           .help ("START-END (inclusive) byte range to verify, prints its tree hash if megabyte aligned"))
+                     .arg (Arg::with_name ("rebuild").long ("rebuild").help ("hashes the archive and stores its leaf hashes, for archives created without them")))
+        .subcommand (SubCommand::with_name ("export-offline")
+                     .about ("Copies an archive, its sidecars and restore metadata onto a mounted drive and verifies the copy")
+                     .arg (Arg::with_name ("ARCHIVE").required (true).help ("path or name of an archive in BACKUPS_DIRECTORY"))
+                     .arg (Arg::with_name ("DEVICE").required (true).help ("where the drive is mounted")))
+        .subcommand (SubCommand::with_name ("versions")
+                     .about ("Reports the wordpress core, plugin and theme versions recorded with every backup and what changed between them")
+                     .arg (Arg::with_name ("at").long ("at").takes_value (true).help ("YYYY-MM-DD, lists everything live at the last backup on or before that day")))
+        .subcommand (SubCommand::with_name ("rto")
+                     .about ("Estimates how long restoring the latest archive of every kind takes, per Glacier retrieval tier"))
+        .subcommand (SubCommand::with_name ("retrieval-policy")
+                     .about ("Prints the Glacier data retrieval policy of the account")
+                     .arg (Arg::with_name ("set").long ("set").takes_value (true)
+                           .help ("replaces the policy: free-tier, max-retrieval-rate:<bytes per hour> or none")))
+        .subcommand (SubCommand::with_name ("restore")
+                     .about ("Restores a local archive: the wordpress files into TARGET, the sql dump next to it")
+                     .arg (Arg::with_name ("ARCHIVE").required (true).help ("path or name of an archive in BACKUPS_DIRECTORY"))
+                     .arg (Arg::with_name ("TARGET").required_unless_one (&["db-only", "list-only"]))
+                     .arg (Arg::with_name ("site").long ("site").takes_value (true).help ("refuse archives of any other site"))
+                     .arg (Arg::with_name ("db-only").long ("db-only").help ("only load the sql dump into the database"))
+                     .arg (Arg::with_name ("list-only").long ("list-only").conflicts_with_all (&["TARGET", "db-only"])
+                           .help ("print the path, size, modification time and permissions of every entry, writing nothing"))
+                     .arg (Arg::with_name ("target-db").long ("target-db").takes_value (true).requires ("db-only")
+                           .help ("database to load the dump into, created if needed, defaults to MYSQL_DATABASE"))
+                     .arg (Arg::with_name ("search-replace").long ("search-replace").takes_value (true).number_of_values (2)
+                           .value_names (&["SEARCH", "REPLACE"]).requires ("db-only")
+                           .help ("replaces e.g. the site url in the dump, keeping serialized values valid"))
+                     .arg (Arg::with_name ("grants").long ("grants").requires ("db-only")
+                           .help ("also create the database users of the archive missing on the server and apply their grants, MYSQL_USER needs to be allowed to"))
+                     .arg (Arg::with_name ("dump").long ("dump").takes_value (true).help ("where to write the sql dump, defaults to the parent of TARGET"))
+                     .arg (Arg::with_name ("recreate-symlinks").long ("recreate-symlinks")
+                           .help ("restore symlinked content directories into their original targets and link them again, rather than as plain directories"))
+                     .arg (Arg::with_name ("ignore-signature").long ("ignore-signature")
+                           .help ("restore even if the archive doesn't match its signature, or SIGNING_PUBLIC_KEY is set and it has none"))
+                     .arg (Arg::with_name ("resume").long ("resume").conflicts_with_all (&["db-only", "list-only"])
+                           .help ("continue an interrupted restore into TARGET, checking what it extracted by size and hash"))
+                     .arg (Arg::with_name ("preserve-owner").long ("preserve-owner").help ("keep the numeric owner and group ids recorded in the archive"))
+                     .arg (Arg::with_name ("owner").long ("owner").takes_value (true).help ("USER[:GROUP] owning every restored file"))
+                     .arg (Arg::with_name ("map-uid").long ("map-uid").takes_value (true).multiple (true).number_of_values (1)
+                           .help ("FROM:TO maps an owner id of the archive onto one of this host"))
+                     .arg (Arg::with_name ("map-gid").long ("map-gid").takes_value (true).multiple (true).number_of_values (1)
+                           .help ("FROM:TO maps a group id of the archive onto one of this host")))
+        .get_matches ();
+
+    let verbosity = get_env_var ("VERBOSITY", Some (String::from ("info")))?;
+    let log_filters_file = get_optional_env_var ("LOG_FILTERS_FILE");
+    logging::init (&verbosity, get_optional_env_var ("LOG_FILTERS"), log_filters_file.as_deref ())?;
+    if let Some (log_filters_file) = log_filters_file {
+        tokio::spawn (logging::reload_on_hangup (verbosity, log_filters_file));
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("restore") {
+        let archive_path = resolve_archive (matches.value_of ("ARCHIVE").unwrap ())?;
+        let backups_directory = get_env_var ("BACKUPS_DIRECTORY", Some (String::from ("backups")))?;
+        let file_name = Path::new (&archive_path).file_name ().map (|name| name.to_string_lossy ().to_string ()).unwrap_or_default ();
+        // keeps the archive from being pruned until the restore is done
+        let tracked = Path::new (&backups_directory).join (&file_name).exists ();
+        if tracked {
+            state::update (&backups_directory, |state| { state.restores.insert (file_name.clone (), Utc::now ()); })?;
+        }
+        let result = run_restore (matches, &archive_path).await;
+        if tracked {
+            state::update (&backups_directory, |state| { state.restores.remove (&file_name); })?;
+        }
+        return result;
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("retrieval-policy") {
+        let client = GlacierClient::new (aws::region ("glacier", &get_env_var ("AWS_REGION", Some (String::from ("us-east-2")))?,
+                                                         &get_optional_env_var ("AWS_GLACIER_ENDPOINT"))?);
+        if let Some (policy) = matches.value_of ("set") {
+            retrieval::set (&client, glacier_account_id ()?.as_deref (), policy.parse::<retrieval::Policy>()?).await?;
+        }
+        println!("Data retrieval policy: {}", retrieval::get (&client, glacier_account_id ()?.as_deref ()).await?);
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("rto") {
+        let backups_directory = get_env_var ("BACKUPS_DIRECTORY", Some (String::from ("backups")))?;
+        let download_speed = restore_download_speed ().classify (BackupError::Config)?;
+        let client = GlacierClient::new (aws::region ("glacier", &get_env_var ("AWS_REGION", Some (String::from ("us-east-2")))?,
+                                                         &get_optional_env_var ("AWS_GLACIER_ENDPOINT"))?);
+        // the estimates stay useful offline, just without the policy
+        let policy = match retrieval::get (&client, glacier_account_id ()?.as_deref ()).await {
+            Ok (policy) => Some (policy),
+            Err (err) => {
+                warn!("Couldn't read the data retrieval policy, ignoring it: {}", err);
+                None
+            }
+        };
+        let mut estimates = Vec::new ();
+        for kind in BackupKind::ALL {
+            if let Some ((archive, _)) = local_archives (&backups_directory, *kind)?.pop () {
+                let size = match upload_record::UploadRecord::read (&archive)? {
+                    Some (record) => record.size,
+                    None => fs::metadata (&archive)?.len ()
+                };
+                estimates.extend (rto::estimate (&kind.to_string (), &archive, size, download_speed, policy));
+            }
+        }
+        match output_format (matches)? {
+            output::Format::Text => println!("{}", rto::report (&estimates)),
+            output::Format::Json => println!("{}", output::document ("rto", output::estimates (&estimates))?)
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("policy").and_then (|matches| matches.subcommand_matches ("simulate")) {
+        let backups_directory = get_env_var ("BACKUPS_DIRECTORY", Some (String::from ("backups")))?;
+        let mut rolling_periods = BTreeMap::new ();
+        for kind in BackupKind::ALL {
+            rolling_periods.insert (*kind, rolling_period (*kind)?);
+        }
+        for proposed in matches.values_of ("rolling-period").into_iter ().flatten () {
+            let (kind, days) = proposed.split_once ('=')
+                .ok_or_else (|| anyhow::anyhow!("Invalid --rolling-period {}, expected KIND=DAYS", proposed))?;
+            rolling_periods.insert (kind.parse::<BackupKind>()?, days.parse::<u32>()?);
+        }
+        let policy = simulation::Policy {
+            rolling_periods,
+            glacier_retention: matches.value_of ("glacier-retention").map (str::parse::<u32>).transpose ()?,
+            price: get_env_var ("GLACIER_STORAGE_PRICE", Some (String::from ("0.0036")))?.parse::<f64>()?,
+        };
+        let intervals = schedules (false)?.into_iter ()
+            .filter_map (|schedule| schedule.interval.map (|interval| (schedule.kind, interval)))
+            .collect ();
+        // archives still around from before the state kept their sizes
+        let mut uploaded = Vec::new ();
+        for kind in BackupKind::ALL {
+            for (archive, _) in local_archives (&backups_directory, *kind)? {
+                if let Some (record) = upload_record::UploadRecord::read (&archive)? {
+                    uploaded.push ((*kind, state::Size { at: record.uploaded, size: record.size }));
+                }
+            }
+        }
+        let observed = simulation::observed (&state::load (&backups_directory)?, &uploaded);
+        let simulation = simulation::simulate (&observed, &intervals, &policy, Utc::now (),
+                                               matches.value_of ("days").unwrap ().parse::<u32>()?,
+                                               matches.value_of ("every").unwrap ().parse::<u32>()?);
+        match output_format (matches)? {
+            output::Format::Text => println!("{}", simulation::report (&simulation, &policy)),
+            output::Format::Json => println!("{}", output::document ("policy simulate", output::simulation (&simulation))?)
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("list") {
+        let backups_directory = get_env_var ("BACKUPS_DIRECTORY", Some (String::from ("backups")))?;
+        let kinds = match matches.value_of ("kind") {
+            Some (kind) => vec! [kind.parse::<BackupKind>()?],
+            None => BackupKind::ALL.to_vec ()
+        };
+        let format = output_format (matches)?;
+        let mut listed = Vec::new ();
+        for kind in kinds {
+            for (archive, _) in local_archives (&backups_directory, kind)? {
+                // the upload record saves reading the manifest from the end of the archive
+                let record = upload_record::UploadRecord::read (&archive)?;
+                let name = match record.as_ref ().and_then (|record| record.name.clone ()) {
+                    Some (name) => name,
+                    None => manifest::Manifest::read_from_archive (&archive)?.name ()
+                };
+                if let Some (site) = matches.value_of ("site") {
+                    if !name.starts_with (&format!("{}/", site)) {
+                        continue;
+                    }
+                }
+                let archive_id = record.map (|record| record.archive_id);
+                match format {
+                    output::Format::Text => println!("{}  {}  {}", name, archive, archive_id.as_deref ().unwrap_or ("not uploaded")),
+                    output::Format::Json => listed.push (serde_json::json!({ "name": name, "kind": kind, "archive": archive, "archive_id": archive_id }))
+                }
+            }
+        }
+        if format == output::Format::Json {
+            println!("{}", output::document ("list", serde_json::json!(listed))?);
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("doctor") {
+        let checks = doctor::run (&get_env_var ("AWS_REGION", Some (String::from ("us-east-2")))?,
+                                  &get_optional_env_var ("AWS_GLACIER_ENDPOINT"),
+                                  glacier_account_id ()?.as_deref (),
+                                  &get_env_var ("AWS_GLACIER_VAULT", None)?).await;
+        match output_format (matches)? {
+            output::Format::Text => println!("{}", doctor::report (&checks)),
+            output::Format::Json => println!("{}", output::document ("doctor", output::checks (&checks))?)
+        }
+        if checks.iter ().any (|check| check.result.is_err ()) {
+            std::process::exit (1);
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("provision") {
+        let region = aws::region ("glacier", &get_env_var ("AWS_REGION", Some (String::from ("us-east-2")))?, &get_optional_env_var ("AWS_GLACIER_ENDPOINT"))?;
+        let checks = provision::run (&rusoto_core::Client::shared (), &region, glacier_account_id ()?.as_deref (), &get_env_var ("AWS_GLACIER_VAULT", None)?,
+                                     &vault_provisioning ()?, matches.is_present ("check")).await;
+        match output_format (matches)? {
+            output::Format::Text => println!("{}", doctor::report (&checks)),
+            output::Format::Json => println!("{}", output::document ("provision", output::checks (&checks))?)
+        }
+        if checks.iter ().any (|check| check.result.is_err ()) {
+            std::process::exit (1);
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("prune") {
+        let backups_directory = get_env_var ("BACKUPS_DIRECTORY", Some (String::from ("backups")))?;
+        let today = Utc::now ();
+        let restore_grace = restore_grace ()?;
+        let restores = state::load (&backups_directory)?.restores;
+        let min_archive_size = min_archive_size ()?;
+        // deleting archives races a running daemon's uploads and pruning, explaining reads only
+        let _lock = if matches.is_present ("explain") {
+            None
+        } else {
+            Some (lock::RunLock::acquire (&backups_directory)?.ok_or_else (|| anyhow::anyhow!(
+                "mer-de-glace process {} is running on {}: it prunes after every backup, stop it to prune by hand (prune --explain works alongside it)",
+                lock::holder (&Path::new (&backups_directory).join (lock::LOCK_FILE)).map_or (String::from ("unknown"), |pid| pid.to_string ()),
+                backups_directory))?)
+        };
+        if !matches.is_present ("explain") {
+            quarantine::sweep (&backups_directory, min_archive_size)?;
+        }
+        let tiny = quarantine::find (&backups_directory, min_archive_size)?;
+        let mut decisions : Vec<retention::Decision> = tiny.iter ()
+            .map (|(archive, size)| retention::plan_quarantine (archive, *size, today))
+            .collect ();
+        for kind in BackupKind::ALL {
+            let rolling_period = rolling_period (*kind)?;
+            if !matches.is_present ("explain") {
+                cleanup (&backups_directory, *kind, &today, rolling_period, restore_grace)?;
+                continue;
+            }
+            let archives : Vec<_> = local_archives (&backups_directory, *kind)?.into_iter ()
+                .filter (|(archive, _)| !tiny.iter ().any (|(quarantined, _)| quarantined == archive))
+                .collect ();
+            decisions.extend (retention::plan_local (*kind, &archives, today, rolling_period, &restores, restore_grace));
+            for (archive, _) in &archives {
+                if let Some (record) = upload_record::UploadRecord::read (archive)? {
+                    decisions.push (retention::plan_glacier (archive, &record));
+                }
+            }
+        }
+        if matches.is_present ("explain") {
+            match output_format (matches)? {
+                output::Format::Text => println!("{}", retention::explain (&decisions)),
+                output::Format::Json => println!("{}", output::document ("prune", output::decisions (&decisions))?)
+            }
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("leaves") {
+        let archive_path = resolve_archive (matches.value_of ("ARCHIVE").unwrap ())?;
+        if matches.is_present ("rebuild") {
+            info!("Stored the leaf hashes of {}, tree hash {}", archive_path, leaves::rebuild (&archive_path)?);
+        }
+        let leaves = leaves::read_sidecar (&archive_path)?
+            .ok_or_else (|| anyhow::anyhow!("{} has no stored leaf hashes, store them with --rebuild", archive_path))?;
+        let tree_hash = tree_hash::to_hex_string (&tree_hash::tree_hash_of_leaves (&leaves));
+        // the leaf hashes are only as good as their agreement with what was uploaded
+        if let Some (record) = upload_record::UploadRecord::read (&archive_path)? {
+            if record.tree_hash != tree_hash {
+                return Err (anyhow::anyhow!("The leaf hashes of {} add up to {}, but {} was uploaded", archive_path, tree_hash, record.tree_hash));
+            }
+        }
+
+        match matches.value_of ("range") {
+            Some (range) => {
+                let (start, end) = range.split_once ('-')
+                    .ok_or_else (|| anyhow::anyhow!("Invalid range {}, expected START-END", range))?;
+                let (start, end) = (start.parse::<u64>()?, end.parse::<u64>()?);
+                let corrupt = leaves::verify_chunks (&archive_path, &leaves, leaves::chunks_of_range (start, end))?;
+                match leaves::range_tree_hash (&leaves, fs::metadata (&archive_path)?.len (), start, end) {
+                    Ok (hash) => println!("Tree hash of bytes {}-{}: {}", start, end, hash),
+                    Err (err) => info!("{}", err)
+                }
+                if !corrupt.is_empty () {
+                    return Err (anyhow::anyhow!("Chunks {:?} of {} don't match their stored hashes", corrupt, archive_path));
+                }
+                println!("Bytes {}-{} of {} match their stored hashes", start, end, archive_path);
+            },
+            None => println!("{}", serde_json::to_string_pretty (&serde_json::json!({
+                "archive": archive_path,
+                "tree_hash": tree_hash,
+                "chunk_size": tree_hash::ONE_MB,
+                "leaves": leaves.iter ().map (|leaf| tree_hash::to_hex_string (leaf)).collect::<Vec<_>>(),
+            }))?)
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("export-offline") {
+        let archive_path = resolve_archive (matches.value_of ("ARCHIVE").unwrap ())?;
+        let exported = export::export (&archive_path, Path::new (matches.value_of ("DEVICE").unwrap ()))?;
+        println!("Exported {} files, {} verified with tree hash {}", exported.files, exported.archive.display (), exported.tree_hash);
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("versions") {
+        let state = state::load (&get_env_var ("BACKUPS_DIRECTORY", Some (String::from ("backups")))?)?;
+        let at = match matches.value_of ("at") {
+            Some (day) => Some (format!("{} 23:59:59 +00:00", day).parse::<DateTime<Utc>>()?),
+            None => None
+        };
+        match output_format (matches)? {
+            output::Format::Text => println!("{}", versions::report (&state.versions, at)),
+            output::Format::Json => println!("{}", output::document ("versions", output::versions (&state.versions, at))?)
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("describe") {
+        let summary = describe::summarize (&resolve_archive (matches.value_of ("ARCHIVE").unwrap ())?)?;
+        if matches.is_present ("human") {
+            println!("{}", describe::human (&summary, &locale ()?));
+        } else {
+            println!("{}", serde_json::to_string_pretty (&summary)?);
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("manifest") {
+        let manifest = manifest::Manifest::read_from_archive (matches.value_of ("ARCHIVE").unwrap ())?;
+        println!("{}", serde_json::to_string_pretty (&manifest)?);
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("generate-signing-key") {
+        let path = matches.value_of ("PATH").unwrap ();
+        println!("Secret key written to {}, public key: {}", path, signature::generate_key (path)?);
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("verify-signature") {
+        let archive_path = matches.value_of ("ARCHIVE").unwrap ();
+        let public_key = get_env_var ("SIGNING_PUBLIC_KEY", None)?;
+        let hash = archive_tree_hash (archive_path)?;
+        signature::verify (&signature::read_sidecar (archive_path)?, &hash, &public_key)?;
+        println!("Signature of {} is valid", archive_path);
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("verify-local") {
+        let checks = verify::run (matches.value_of ("ARCHIVE").unwrap (),
+                                  &get_env_var ("BACKUPS_DIRECTORY", Some (String::from ("backups")))?,
+                                  get_optional_env_var ("SIGNING_PUBLIC_KEY").as_deref ());
+        match output_format (matches)? {
+            output::Format::Text => println!("{}", doctor::report (&checks)),
+            output::Format::Json => println!("{}", output::document ("verify-local", output::checks (&checks))?)
+        }
+        if checks.iter ().any (|check| check.result.is_err ()) {
+            std::process::exit (1);
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("verify-attestations") {
+        let path = match matches.value_of ("LOG") {
+            Some (path) => String::from (path),
+            None => get_env_var ("ATTESTATION_LOG", None)?
+        };
+        let (entries, head) = attest::verify (&path)?;
+        println!("{} entries of {} chain correctly, head {}", entries, path, head);
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("cron-check") {
+        let config = read_config (matches).classify (BackupError::Config)?;
+        let config = secrets::current (&config).await.classify (BackupError::Config)?;
+        if let Some (path) = matches.value_of ("save") {
+            let snapshot = cron::capture (&config, &config.cron_snapshot_options)?;
+            fs::write (path, serde_json::to_vec_pretty (&snapshot)?)?;
+            println!("Saved {} cron events and {} options to {}", snapshot.events.len (), snapshot.options.len (), path);
+            return Ok (());
+        }
+        let snapshot : cron::Snapshot = match matches.value_of ("against") {
+            Some (path) => serde_json::from_slice (&fs::read (path)?)?,
+            None => {
+                let archive_path = resolve_archive (matches.value_of ("ARCHIVE").unwrap ())?;
+                manifest::Manifest::read_from_archive (&archive_path)?.cron
+                    .ok_or_else (|| anyhow::anyhow!("{} has no cron snapshot, it was created without the database or before snapshots were taken", archive_path))?
+            }
+        };
+        let options : Vec<String> = snapshot.options.keys ().cloned ().collect ();
+        let checks = cron::compare (&snapshot, &cron::capture (&config, &options)?);
+        match output_format (matches)? {
+            output::Format::Text => println!("{}", doctor::report (&checks)),
+            output::Format::Json => println!("{}", output::document ("cron-check", output::checks (&checks))?)
+        }
+        if checks.iter ().any (|check| check.result.is_err ()) {
+            std::process::exit (1);
+        }
+        return Ok (());
+    }
+
+    if let Some (matches) = matches.subcommand_matches ("uploader") {
+        let mut config = read_config (matches).classify (BackupError::Config)?;
+        site::apply (&mut config).classify (BackupError::Config)?;
+        // a seeding goes on in the next drain, like in the next `--once` run
+        config.once = true;
+        let config = secrets::current (&config).await.classify (BackupError::Config)?.into_owned ();
+        check_aws (&config).await?;
+
+        // uploaders on several hosts may share the backups directory, one drains it at a time
+        let lease = lease::Lease::new (&Path::new (&config.backups_directory).join (outbox::UPLOADER_LEASE).display ().to_string (),
+                                       kind::parse_interval (&get_env_var ("LEADER_LEASE_DURATION", Some (String::from ("1m")))?)?);
+        if !lease.try_acquire ().await? {
+            info!("Another uploader holds the lease {}, nothing to do", outbox::UPLOADER_LEASE);
+            return Ok (());
+        }
+        let result = tokio::select! {
+            drained = scheduler::drain (&config) => drained,
+            kept = lease.clone ().keep () => kept,
+        };
+        lease.release ();
+        return result;
+    }
 
-    let config = Config {
+    let mut config = read_config (&matches).classify (BackupError::Config)?;
+    site::apply (&mut config).classify (BackupError::Config)?;
+
+    info!("mer-de-glace {}", version::LONG_VERSION);
+    info!("Running with {:#?}", &config);
+    // logged with just the references of its secrets
+    let config = secrets::current (&config).await.classify (BackupError::Config)?.into_owned ();
+    if !config.chaos.faults.is_empty () {
+        warn!("Rehearsing with injected failures, CHAOS={}", config.chaos.faults.iter ().map (|fault| fault.to_string ()).collect::<Vec<_>>().join (","));
+    }
+
+    // an archiving host needn't reach AWS at all
+    if !config.enqueue {
+        check_aws (&config).await?;
+    }
+
+    if config.update_check {
+        version::check_for_update ().await;
+    }
+
+    // ensure directory for backups
+    create_dir_all (&config.backups_directory)
+        .map_err (|err| BackupError::Config (format!("Couldn't create directory {}: {}", &config.backups_directory, err)))?;
+
+    let once = matches.is_present ("once");
+    if let (false, Some (address)) = (once, config.admin_address) {
+        let server = admin::bind (&config, address).await.classify (BackupError::Config)?;
+        tokio::spawn (admin::serve (config.clone (), server));
+    }
+
+    // of replicas sharing storage only the leader backs up, the others stand by
+    let keeper = match &config.lease {
+        Some (lease) if once => {
+            if !lease.try_acquire ().await? {
+                info!("Another replica holds the lease, nothing to do");
+                return Ok (());
+            }
+            None
+        },
+        Some (lease) => {
+            lease.acquire ().await?;
+            Some (tokio::spawn (lease.clone ().keep ()))
+        },
+        None => None
+    };
+
+    let _lock = match lock::RunLock::acquire (&config.backups_directory)? {
+        Some (lock) => lock,
+        None if once => {
+            info!("Another mer-de-glace run holds {}, nothing to do", lock::LOCK_FILE);
+            return Ok (());
+        },
+        None => return Err (anyhow::anyhow!("Another mer-de-glace process is backing up {}", &config.backups_directory))
+    };
+
+    // reclaim space taken by leftovers of crashed runs
+    remove_stale_files (&config.backups_directory, config.stale_file_threshold)?;
+    quarantine::sweep (&config.backups_directory, config.min_archive_size)?;
+
+    if once {
+        let result = scheduler::run_once (&config).await;
+        if let Some (lease) = &config.lease {
+            lease.release ();
+        }
+        return result;
+    }
+
+    queue::configure (config.backup_concurrency.unwrap_or (config.schedules.len ()));
+
+    if let Some (interval) = config.integrity_sample_interval {
+        tokio::spawn (integrity::monitor (config.clone (), interval));
+    }
+
+    if !config.slas.is_empty () {
+        tokio::spawn (sla::monitor (config.clone ()));
+    }
+
+    // every kind of backup runs on its own schedule, the loops (and keeping the lease) only ever return on error
+    let schedules = config.schedules.iter ()
+        .map (|schedule| tokio::spawn (scheduler::run_schedule (config.clone (), schedule.clone ())))
+        .chain (keeper);
+    let (result, _, _) = futures::future::select_all (schedules).await;
+    if let Some (lease) = &config.lease {
+        lease.release ();
+    }
+    result?
+
+}
+
+/// Fails now rather than on the first upload, hours into the run.
+async fn check_aws (config: &Config) -> AnyResult<()> {
+    for check in doctor::run (&config.aws_region, &config.aws_glacier_endpoint, config.aws_glacier_account_id.as_deref (), &config.aws_glacier_vault_name).await {
+        match check.result {
+            Ok (message) => info!("Checked {}: {}", check.name, message),
+            Err (message) => return Err (BackupError::Config (format!("Misconfigured {}: {}", check.name, message)).into ())
+        }
+    }
+    Ok (())
+}
+
+/// The daemon's configuration, from the environment and the global flags.
+fn read_config (matches: &ArgMatches) -> AnyResult<Config> {
+    let mysql_password = get_required_secret_env_var ("MYSQL_PASSWORD")?;
+    let signing_key = get_secret_env_var ("SIGNING_KEY")?.filter (|key| *key != secrets::Secret::Value (String::new ()));
+    let standby_mysql_host = get_optional_env_var ("STANDBY_MYSQL_HOST");
+    let standby_mysql_password = match standby_mysql_host {
+        Some (_) => Some (get_required_secret_env_var ("STANDBY_MYSQL_PASSWORD")?),
+        None => None
+    };
+    Ok (Config {
         wordpress_directory: get_env_var ("WORDPRESS_DIRECTORY", None)?,
         mysql_host: get_env_var ("MYSQL_HOST", None)?,
         mysql_port: get_env_var ("MYSQL_PORT", Some (String::from ("3306")))?,
         mysql_database: get_env_var ("MYSQL_DATABASE", None)?,
+        site: site_name ()?,
         mysql_user: get_env_var ("MYSQL_USER", None)?,
-        mysql_password: get_env_var ("MYSQL_PASSWORD", None)?,
-        interval: get_env_var ("BACKUP_INTERVAL", Some (String::from ("7")))?.parse::<u32>()?,
-        archive_rolling_period: get_env_var ("ARCHIVE_ROLLING_PERIOD", Some (String::from ("14")))?.parse::<u32>()?,
+        mysql_password: mysql_password.value (),
+        schedules: schedules (matches.is_present ("once"))?,
+        slas: slas ()?,
+        integrity_sample_interval: get_optional_env_var ("INTEGRITY_SAMPLE_INTERVAL").map (|interval| kind::parse_interval (&interval)).transpose ()?,
+        integrity_sample_chunks: get_env_var ("INTEGRITY_SAMPLE_CHUNKS", Some (String::from ("8")))?.parse::<usize>()?,
+        backup_concurrency: get_optional_env_var ("BACKUP_CONCURRENCY").map (|concurrency| concurrency.parse::<usize>()).transpose ()?,
+        restore_grace: restore_grace ()?,
+        lease: match get_optional_env_var ("LEADER_LEASE") {
+            Some (path) => Some (lease::Lease::new (&path, kind::parse_interval (&get_env_var ("LEADER_LEASE_DURATION", Some (String::from ("1m")))?)?)),
+            None => None
+        },
+        cloudwatch: cloudwatch_config ()?,
+        seeding: seeding ()?,
+        once: matches.is_present ("once"),
+        enqueue: matches.is_present ("enqueue"),
+        retention_tag: None,
+        update_wait: kind::parse_interval (&get_env_var ("WORDPRESS_UPDATE_WAIT", Some (String::from ("30m")))?)?,
+        notifications: notify::Notifications::new (&get_env_var ("AWS_REGION", Some (String::from ("us-east-2")))?,
+                                                   &get_optional_env_var ("AWS_SNS_ENDPOINT"),
+                                                   get_optional_env_var ("SNS_TOPIC_ARN"),
+                                                   get_optional_env_var ("SQS_QUEUE_URL"))?,
+        locale: Arc::new (locale ()?),
+        admin_address: get_optional_env_var ("ADMIN_ADDRESS").map (|address| address.parse::<SocketAddr>()).transpose ()?,
+        admin_access: access::Access {
+            allow: access::parse_allow (&get_env_var ("ADMIN_ALLOW", Some (String::new ()))?)?,
+            token: get_secret_env_var ("ADMIN_TOKEN")?,
+            basic: get_secret_env_var ("ADMIN_BASIC_AUTH")?,
+        },
+        admin_tls: admin_tls ()?,
+        blackouts: blackout::parse (&get_env_var ("BLACKOUT_PERIODS", Some (String::new ()))?)?,
         backups_directory: get_env_var ("BACKUPS_DIRECTORY", Some (String::from ("backups")))?,
         aws_region: get_env_var ("AWS_REGION", Some (String::from ("us-east-2")))?,
-        aws_glacier_vault_name: get_env_var ("AWS_GLACIER_VAULT", None)?
+        aws_glacier_vault_name: get_env_var ("AWS_GLACIER_VAULT", None)?,
+        aws_glacier_endpoint: get_optional_env_var ("AWS_GLACIER_ENDPOINT"),
+        aws_glacier_account_id: glacier_account_id ()?,
+        vault_provisioning: vault_provisioning ()?,
+        collision_policy: get_env_var ("ARCHIVE_COLLISION_POLICY", Some (String::from ("suffix")))?.parse::<CollisionPolicy>()?,
+        stale_file_threshold: get_env_var ("STALE_FILE_THRESHOLD", Some (String::from ("24")))?.parse::<u32>()?,
+        min_archive_size: min_archive_size ()?,
+        update_check: get_env_var ("UPDATE_CHECK", Some (String::from ("false")))?.parse::<bool>()?,
+        verify_command: get_optional_env_var ("ARCHIVE_VERIFY_COMMAND"),
+        // a key read from a store is the hex encoded key, it replaces this one before it is used
+        signing_key: signing_key.as_ref ().map (|key| match key {
+            secrets::Secret::Value (path) => signature::SigningKey::File (path.clone ()),
+            secrets::Secret::Stored (_) => signature::SigningKey::Hex (String::new ())
+        }),
+        embed_config: get_env_var ("EMBED_CONFIG", Some (String::from ("false")))?.parse::<bool>()?,
+        profile: matches.is_present ("profile"),
+        walk_threads: get_env_var ("ARCHIVE_WALK_THREADS", Some (String::from ("4")))?.parse::<usize>()?,
+        reproducible: get_env_var ("REPRODUCIBLE_ARCHIVES", Some (String::from ("false")))?.parse::<bool>()?,
+        read_limit: get_optional_env_var ("ARCHIVE_READ_LIMIT").map (|limit| seed::parse_size (&limit)).transpose ()?,
+        ssh: ssh_source ()?,
+        sts: sts ()?,
+        credentials: None,
+        attestation_log: get_optional_env_var ("ATTESTATION_LOG"),
+        dump_reuse_max_age: match get_env_var ("DUMP_REUSE_MAX_AGE", Some (String::from ("6h")))?.as_str () {
+            "0" => None,
+            max_age => Some (kind::parse_interval (max_age)?)
+        },
+        server_config_files: get_env_var ("CONFIG_BACKUP_FILES", Some (String::new ()))?
+            .split (',')
+            .map (|path| path.trim ().to_string ())
+            .filter (|path| !path.is_empty ())
+            .collect (),
+        cron_snapshot_options: get_env_var ("CRON_SNAPSHOT_OPTIONS", Some (String::from ("siteurl,home,db_version,active_plugins")))?
+            .split (',')
+            .map (|option| option.trim ().to_string ())
+            .filter (|option| !option.is_empty ())
+            .collect (),
+        secrets: secrets::Secrets {
+            providers: secret_providers ()?,
+            mysql_password: mysql_password.reference ().cloned (),
+            standby_mysql_password: standby_mysql_password.as_ref ().and_then (secrets::Secret::reference).cloned (),
+            signing_key: signing_key.as_ref ().and_then (secrets::Secret::reference).cloned (),
+        },
+        differential: differential ()?,
+        backup_grants: get_env_var ("BACKUP_GRANTS", Some (String::from ("false")))?.parse::<bool>()?,
+        chaos: chaos ()?,
+        filename_sanitization: get_env_var ("FILENAME_SANITIZATION", Some (String::from ("off")))?.parse::<sanitize::Mode>()?,
+        size_anomaly: match get_optional_env_var ("SIZE_ANOMALY_THRESHOLD") {
+            Some (threshold) => Some (anomaly::SizeAnomaly::new (threshold.trim_end_matches ('%').parse::<f64>()?,
+                                                                 get_env_var ("SIZE_ANOMALY_WINDOW", Some (String::from ("5")))?.parse::<usize>()?)?),
+            None => None
+        },
+        standby: standby::Standby {
+            rsync_target: get_optional_env_var ("STANDBY_RSYNC_TARGET"),
+            mysql: match (standby_mysql_host, standby_mysql_password) {
+                (Some (host), Some (password)) => Some (standby::StandbyMysql {
+                    host,
+                    port: get_env_var ("STANDBY_MYSQL_PORT", Some (String::from ("3306")))?,
+                    user: get_env_var ("STANDBY_MYSQL_USER", None)?,
+                    password: password.value (),
+                }),
+                _ => None
+            },
+        },
+    })
+}
+
+/// The configuration without credentials, to be embedded in archives so restoring doesn't depend on the original config.
+fn public_config (config: &Config) -> serde_json::Value {
+    serde_json::json!({
+        "site": config.site,
+        "wordpress_directory": config.wordpress_directory,
+        "mysql_host": config.mysql_host,
+        "mysql_port": config.mysql_port,
+        "mysql_database": config.mysql_database,
+        "mysql_user": config.mysql_user,
+        "backups_directory": config.backups_directory,
+        "aws_region": config.aws_region,
+        "aws_glacier_vault_name": config.aws_glacier_vault_name,
+        "aws_glacier_endpoint": config.aws_glacier_endpoint,
+        "schedules": config.schedules.iter ().map (|schedule| serde_json::json!({
+            "kind": schedule.kind,
+            "interval_seconds": schedule.interval.map (|interval| interval.as_secs ()),
+            "rolling_period": schedule.rolling_period,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+/// Reads the schedule of every enabled backup kind, full backups are always enabled.
+/// Without `BACKUP_INTERVAL` the daemon backs up weekly, `once` runs leave the interval to whoever invokes them.
+fn schedules (once: bool) -> AnyResult<Vec<Schedule>> {
+    let rolling_period = get_env_var ("ARCHIVE_ROLLING_PERIOD", Some (String::from ("14")))?.parse::<u32>()?;
+
+    let interval = match get_optional_env_var ("BACKUP_INTERVAL") {
+        Some (interval) => Some (kind::parse_interval (&interval)?),
+        None if once => None,
+        None => Some (kind::parse_interval ("7")?)
     };
+    let mut schedules = vec! [Schedule {
+        kind: BackupKind::Full,
+        interval,
+        rolling_period,
+    }];
 
-    env::set_var("RUST_LOG", get_env_var ("VERBOSITY", Some (String::from ("info")))?);
-    env_logger::init();
+    for (kind, prefix) in &[(BackupKind::Uploads, "UPLOADS"), (BackupKind::Code, "CODE"), (BackupKind::Config, "CONFIG")] {
+        if let Some (interval) = get_optional_env_var (&format!("{}_BACKUP_INTERVAL", prefix)) {
+            schedules.push (Schedule {
+                kind: *kind,
+                interval: Some (kind::parse_interval (&interval)?),
+                rolling_period: get_env_var (&format!("{}_ROLLING_PERIOD", prefix), Some (rolling_period.to_string ()))?.parse::<u32>()?,
+            });
+        }
+    }
 
-    info!("Running with {:#?}", &config);
+    Ok (schedules)
+}
 
-    // ensure directory for backups
-    create_dir_all (&config.backups_directory).unwrap_or_else(|_| panic!("Couldn't create directory: {}", &config.backups_directory));
+/// The rolling period archives of `kind` are kept for locally, as configured for its schedule.
+/// Kinds without a schedule of their own (database only archives, disabled kinds) fall back to the full backups' one.
+fn rolling_period (kind: BackupKind) -> AnyResult<u32> {
+    let schedules = schedules (false)?;
+    Ok (schedules.iter ()
+        .find (|schedule| schedule.kind == kind)
+        .unwrap_or (&schedules [0])
+        .rolling_period)
+}
+
+/// Restores the files or just the database of a local archive, as the `restore` subcommand asks.
+async fn run_restore (matches: &ArgMatches<'_>, archive_path: &str) -> AnyResult<()> {
+    if let Some (site) = matches.value_of ("site") {
+        let manifest = manifest::Manifest::read_from_archive (archive_path)?;
+        if manifest.site.as_deref () != Some (site) {
+            return Err (anyhow::anyhow!("{} is {}, not an archive of site {}", archive_path, manifest.name (), site));
+        }
+    }
+
+    if matches.is_present ("list-only") {
+        let renamed = manifest::Manifest::read_from_archive (archive_path)?.renamed;
+        for entry in restore::list (archive_path)? {
+            let mtime = chrono::TimeZone::timestamp (&Utc, entry.mtime as i64, 0);
+            println!("{} {:>5}/{:<5} {:>12} {} {}", entry.permissions (), entry.uid, entry.gid, entry.size, mtime.format ("%Y-%m-%d %H:%M:%S"),
+                     sanitize::listed (&sanitize::original (&entry.path, &renamed)));
+        }
+        return Ok (());
+    }
+
+    if let Err (err) = restore::check_signature (archive_path, get_optional_env_var ("SIGNING_PUBLIC_KEY").as_deref ()) {
+        if !matches.is_present ("ignore-signature") {
+            return Err (anyhow::anyhow!("Not restoring {}: {:#} (see --ignore-signature)", archive_path, err));
+        }
+        warn!("Restoring {} even though its signature doesn't check out: {:#}", archive_path, err);
+    }
+
+    if matches.is_present ("db-only") {
+        let target = restore::MysqlTarget {
+            host: get_env_var ("MYSQL_HOST", None)?,
+            port: get_env_var ("MYSQL_PORT", Some (String::from ("3306")))?,
+            user: get_env_var ("MYSQL_USER", None)?,
+            password: secrets::resolve (&secret_providers ()?, &get_required_secret_env_var ("MYSQL_PASSWORD")?).await?,
+            database: match matches.value_of ("target-db") {
+                Some (database) => String::from (database),
+                None => get_env_var ("MYSQL_DATABASE", None)?
+            },
+        };
+        let search_replace = matches.values_of ("search-replace").map (|values| values.collect::<Vec<_>>());
+        restore::restore_database (archive_path, &target, search_replace.as_ref ().map (|values| (values [0], values [1])))?;
+        println!("Database restored into {}", target.database);
+        if matches.is_present ("grants") {
+            let users = restore::restore_grants (archive_path, &target)?;
+            println!("Grants of {} applied", users.join (", "));
+        }
+        return Ok (());
+    }
+
+    let target = PathBuf::from (matches.value_of ("TARGET").unwrap ());
+    let manifest = manifest::Manifest::read_from_archive (archive_path)?;
+    let dump_path = match matches.value_of ("dump") {
+        Some (path) => PathBuf::from (path),
+        None => target.parent ().unwrap_or_else (|| Path::new (".")).join (manifest.sql_dump.unwrap_or_default ())
+    };
+    let ownership = restore::Ownership {
+        preserve: matches.is_present ("preserve-owner"),
+        owner: matches.value_of ("owner").map (restore::parse_owner).transpose ()?,
+        uid_map: matches.values_of ("map-uid").into_iter ().flatten ().map (restore::parse_mapping).collect::<AnyResult<_>>()?,
+        gid_map: matches.values_of ("map-gid").into_iter ().flatten ().map (restore::parse_mapping).collect::<AnyResult<_>>()?,
+    };
+    let restored = restore::restore (archive_path, &target, &dump_path, &ownership, matches.is_present ("recreate-symlinks"), matches.is_present ("resume"))?;
+    println!("Restored {} entries into {}", restored.files, target.display ());
+    if restored.verified > 0 {
+        println!("{} of them were extracted by the interrupted restore already", restored.verified);
+    }
+    if let Some (sql_dump) = restored.sql_dump {
+        println!("Database dump written to {}", sql_dump.display ());
+    }
+    Ok (())
+}
+
+/// `SEED_DAILY_BUDGET` and `SEED_WINDOW`, the initial full backup is uploaded at once unless one of them is set.
+fn seeding () -> AnyResult<Option<seed::Seeding>> {
+    let daily_budget = get_optional_env_var ("SEED_DAILY_BUDGET").map (|budget| seed::parse_size (&budget)).transpose ()?;
+    let window = get_optional_env_var ("SEED_WINDOW").map (|window| seed::parse_window (&window)).transpose ()?;
+    if daily_budget.is_none () && window.is_none () {
+        return Ok (None);
+    }
+    let part_size = seed::parse_size (&get_env_var ("SEED_PART_SIZE", Some (String::from ("64M")))?)?;
+    Ok (Some (seed::Seeding::new (daily_budget, window, part_size)?))
+}
+
+/// `STS_ROLE_ARN`, runs use the credentials of the daemon unless it is set.
+fn sts () -> AnyResult<Option<sts::Sts>> {
+    let role_arn = match get_optional_env_var ("STS_ROLE_ARN") {
+        Some (role_arn) => role_arn,
+        None => return Ok (None)
+    };
+    let duration = kind::parse_interval (&get_env_var ("STS_SESSION_DURATION", Some (String::from ("1h")))?)?;
+    if duration < sts::MIN_DURATION {
+        return Err (anyhow::anyhow!("STS_SESSION_DURATION must be at least {} seconds", sts::MIN_DURATION.as_secs ()));
+    }
+    let region = get_env_var ("AWS_REGION", Some (String::from ("us-east-2")))?;
+    Ok (Some (sts::Sts {
+        role_arn,
+        region: aws::region ("sts", &region, &get_optional_env_var ("AWS_STS_ENDPOINT"))?,
+        duration,
+    }))
+}
+
+/// `DIFFERENTIAL_TABLES` and `DIFFERENTIAL_FULL_INTERVAL`, every dump is a full one unless tables are set.
+fn differential () -> AnyResult<Option<differential::Differential>> {
+    let tables = differential::parse (&get_env_var ("DIFFERENTIAL_TABLES", Some (String::new ()))?)?;
+    if tables.is_empty () {
+        return Ok (None);
+    }
+    let full_interval = kind::parse_interval (&get_env_var ("DIFFERENTIAL_FULL_INTERVAL", Some (String::from ("7d")))?)?;
+    // pruning must not remove the full dump before the differential ones building on it
+    let rolling_period = rolling_period (BackupKind::Full)?;
+    if full_interval >= Duration::from_secs (rolling_period as u64 * 86400) {
+        return Err (anyhow::anyhow!("DIFFERENTIAL_FULL_INTERVAL must be shorter than the {} days full archives are kept for", rolling_period));
+    }
+    Ok (Some (differential::Differential { tables, full_interval }))
+}
+
+/// `ADMIN_TLS_CERT` and `ADMIN_TLS_KEY`, or `ADMIN_TLS=self-signed`.
+fn admin_tls () -> AnyResult<Option<tls::Tls>> {
+    match (get_optional_env_var ("ADMIN_TLS"), get_optional_env_var ("ADMIN_TLS_CERT"), get_optional_env_var ("ADMIN_TLS_KEY")) {
+        (None, None, None) => Ok (None),
+        (Some (tls), None, None) if tls == "self-signed" => Ok (Some (tls::Tls::SelfSigned)),
+        (None, Some (cert), Some (key)) => Ok (Some (tls::Tls::Files { cert, key })),
+        _ => Err (anyhow::anyhow!("Set both ADMIN_TLS_CERT and ADMIN_TLS_KEY, or just ADMIN_TLS=self-signed"))
+    }
+}
+
+/// `AWS_GLACIER_ACCOUNT_ID`.
+fn glacier_account_id () -> AnyResult<Option<String>> {
+    get_optional_env_var ("AWS_GLACIER_ACCOUNT_ID").map (|id| upload::parse_account_id (&id)).transpose ()
+}
+
+/// `VAULT_TAGS`, `VAULT_NOTIFICATION_TOPIC` with `VAULT_NOTIFICATION_EVENTS`, and `VAULT_ACCESS_POLICY`.
+fn vault_provisioning () -> AnyResult<provision::Provisioning> {
+    let notifications = match get_optional_env_var ("VAULT_NOTIFICATION_TOPIC") {
+        Some (topic) => Some (provision::Notifications {
+            topic,
+            events: provision::parse_events (&get_env_var ("VAULT_NOTIFICATION_EVENTS",
+                                                           Some (String::from ("ArchiveRetrievalCompleted,InventoryRetrievalCompleted")))?)?,
+        }),
+        None => None
+    };
+    Ok (provision::Provisioning {
+        tags: provision::parse_tags (&get_env_var ("VAULT_TAGS", Some (String::new ()))?)?,
+        notifications,
+        access_policy: get_optional_env_var ("VAULT_ACCESS_POLICY").map (|path| provision::read_policy (&path)).transpose ()?,
+    })
+}
+
+/// `LOCALE`, English unless set, and `LOCALE_FILE`.
+fn locale () -> AnyResult<locale::Locale> {
+    locale::Locale::new (&get_env_var ("LOCALE", Some (String::from ("en")))?, get_optional_env_var ("LOCALE_FILE").as_deref ())
+}
+
+/// `VAULT_ADDR`, `VAULT_TOKEN` and `SECRETS_CACHE_TTL`, with the region of the AWS secret stores.
+fn secret_providers () -> AnyResult<secrets::Providers> {
+    let region = get_env_var ("AWS_REGION", Some (String::from ("us-east-2")))?;
+    Ok (secrets::Providers {
+        vault_address: get_optional_env_var ("VAULT_ADDR"),
+        vault_token: get_optional_env_var ("VAULT_TOKEN"),
+        secretsmanager_region: aws::region ("secretsmanager", &region, &get_optional_env_var ("AWS_SECRETSMANAGER_ENDPOINT"))?,
+        ssm_region: aws::region ("ssm", &region, &get_optional_env_var ("AWS_SSM_ENDPOINT"))?,
+        ttl: kind::parse_interval (&get_env_var ("SECRETS_CACHE_TTL", Some (String::from ("5m")))?)?,
+    })
+}
+
+/// `SSH_SOURCE`, the site is archived from this host unless it is set.
+fn ssh_source () -> AnyResult<Option<ssh::Ssh>> {
+    let destination = match get_optional_env_var ("SSH_SOURCE") {
+        Some (destination) => destination,
+        None => return Ok (None)
+    };
+    // rsync can't copy from one remote host to another
+    if get_optional_env_var ("STANDBY_RSYNC_TARGET").is_some () {
+        return Err (anyhow::anyhow!("STANDBY_RSYNC_TARGET can't be used with SSH_SOURCE"));
+    }
+    Ok (Some (ssh::Ssh {
+        destination,
+        port: get_optional_env_var ("SSH_PORT"),
+        identity: get_optional_env_var ("SSH_IDENTITY"),
+    }))
+}
 
-    let mut interval = time::interval(Duration::from_secs(
-        86400 * config.interval as u64
-    ));
-    loop {
-        interval.tick().await;
-        create_backup (&config).await?;
+/// `CLOUDWATCH_NAMESPACE` and `EVENTBRIDGE_BUS`, publishing is off unless one of them is set.
+fn cloudwatch_config () -> AnyResult<Option<cloudwatch::CloudWatch>> {
+    let namespace = get_optional_env_var ("CLOUDWATCH_NAMESPACE");
+    let event_bus = get_optional_env_var ("EVENTBRIDGE_BUS");
+    if namespace.is_none () && event_bus.is_none () {
+        return Ok (None);
     }
+    let region = get_env_var ("AWS_REGION", Some (String::from ("us-east-2")))?;
+    Ok (Some (cloudwatch::CloudWatch {
+        namespace,
+        metrics_region: aws::region ("monitoring", &region, &get_optional_env_var ("AWS_CLOUDWATCH_ENDPOINT"))?,
+        event_bus,
+        events_region: aws::region ("events", &region, &get_optional_env_var ("AWS_EVENTBRIDGE_ENDPOINT"))?,
+    }))
+}
+
+/// `CHAOS` and `CHAOS_REPEAT`, refused against AWS itself unless `CHAOS_ALLOW_PRODUCTION` is set.
+fn chaos () -> AnyResult<chaos::Chaos> {
+    let faults = chaos::parse (&get_env_var ("CHAOS", Some (String::new ()))?)?;
+    if !faults.is_empty ()
+        && !chaos::is_rehearsal (get_optional_env_var ("AWS_GLACIER_ENDPOINT").as_deref ())
+        && !get_env_var ("CHAOS_ALLOW_PRODUCTION", Some (String::from ("false")))?.parse::<bool>()? {
+        return Err (anyhow::anyhow!("CHAOS is only for rehearsals, point AWS_GLACIER_ENDPOINT at a test endpoint or set CHAOS_ALLOW_PRODUCTION=true"));
+    }
+    Ok (chaos::Chaos {
+        faults,
+        repeat: get_env_var ("CHAOS_REPEAT", Some (String::from ("false")))?.parse::<bool>()?,
+    })
+}
+
+/// `RESTORE_GRACE`, the longest an archive being restored is kept past its rolling period.
+fn restore_grace () -> AnyResult<Duration> {
+    kind::parse_interval (&get_env_var ("RESTORE_GRACE", Some (String::from ("1d")))?)
+}
+
+/// `MIN_ARCHIVE_SIZE`, smaller archives are quarantined rather than counted as backups.
+fn min_archive_size () -> AnyResult<u64> {
+    seed::parse_size (&get_env_var ("MIN_ARCHIVE_SIZE", Some (String::from ("4K")))?)
+}
+
+/// `RESTORE_DOWNLOAD_SPEED`, how fast archives are downloaded from Glacier in MB/s for the restore time estimates.
+fn restore_download_speed () -> AnyResult<f64> {
+    let speed = get_env_var ("RESTORE_DOWNLOAD_SPEED", Some (String::from ("10")))?;
+    match speed.parse::<f64>() {
+        Ok (speed) if speed.is_finite () && speed > 0.0 => Ok (speed),
+        _ => Err (anyhow::anyhow!("Invalid RESTORE_DOWNLOAD_SPEED {}, expected a positive number of MB/s", speed))
+    }
+}
+
+/// `SITE_NAME`, defaulting to the database name which tells sites apart on most hosts.
+fn site_name () -> AnyResult<String> {
+    let site = get_env_var ("SITE_NAME", get_optional_env_var ("MYSQL_DATABASE"))?;
+    if !SITE_RE.is_match (&site) {
+        return Err (anyhow::anyhow!("Invalid site name: {}, use letters, digits, '.', '_' and '-'", site));
+    }
+    Ok (site)
+}
+
+/// Reads the freshness SLA of every backup kind that declares one.
+fn slas () -> AnyResult<Vec<sla::Sla>> {
+    let mut slas = Vec::new ();
+    for (kind, var) in &[(BackupKind::Full, "BACKUP_SLA"), (BackupKind::Uploads, "UPLOADS_SLA"), (BackupKind::Code, "CODE_SLA"), (BackupKind::Config, "CONFIG_SLA")] {
+        if let Some (max_age) = get_optional_env_var (var) {
+            slas.push (sla::Sla { kind: *kind, max_age: kind::parse_interval (&max_age)? });
+        }
+    }
+    Ok (slas)
+}
 
+/// What a backup run produced.
+struct Created {
+    archive: String,
+    tree_hash: String,
+    /// false while the archive is being seeded, or queued
+    uploaded: bool,
+    /// queued for `mer-de-glace uploader`
+    queued: bool,
+    /// the archive size
+    bytes: u64,
+    /// why the archive looks wrong despite the success, if it does
+    suspicious: Option<String>,
 }
 
-async fn create_backup (config: &Config) -> AnyResult<()> {
+/// Returns what the run produced.
+async fn create_backup (config: &Config, schedule: &Schedule) -> AnyResult<Created> {
 
+    // neither the files nor the database of a half-upgraded site are worth keeping
+    maintenance::wait_for_updates (config).await;
+
+    let kind = schedule.kind;
     let today = Utc::now ();
     let date = today.format("%Y-%m-%d");
+    let profile = Arc::new (profile::Profile::new (config.profile));
+    let started = Instant::now ();
 
-    let sql_dump_name = format!("dump_{}.sql", &date);
-    let sql_dump_path = format!("{}/{}", &config.backups_directory, &sql_dump_name);
+    info!("Creating {} backup", kind);
 
-    // create sql dump
-    let sql_dump = dump_sql (&config);
-    write_to_file (&sql_dump, &sql_dump_path);
+    // create sql dump, in the background while the files are archived, unless an interrupted run left a complete one behind
+    // what a leftover dump holds of differential tables is unknown
+    let reused = config.dump_reuse_max_age
+        .filter (|_| kind.includes_database () && config.differential.is_none ())
+        .and_then (|max_age| dump::leftover (&config.backups_directory, &config.mysql_database, max_age));
+    let reusing = reused.is_some ();
+    let (sql_dump_path, _sql_dump_claim) = match reused {
+        Some ((path, claim)) => (path, claim),
+        None => {
+            let path = format!("{}/dump_{}.sql", &config.backups_directory, &date);
+            let claim = dump::claim (&path);
+            (path, claim)
+        }
+    };
+    let sql_dump_name = Path::new (&sql_dump_path).file_name ().map (|name| name.to_string_lossy ().to_string ()).unwrap_or_default ();
+    let marks = match &config.differential {
+        Some (_) if kind.includes_database () => state::load (&config.backups_directory)?.dump_marks,
+        _ => None
+    };
+    let sql_dump = if kind.includes_database () && !reusing {
+        let (config, sql_dump_path, profile) = (config.clone (), sql_dump_path.clone (), profile.clone ());
+        Some (std::thread::spawn (move || -> AnyResult<Option<differential::Dumped>> {
+            config.chaos.dump ()?;
+            let started = Instant::now ();
+            let (sql_dump, dumped) = match &config.differential {
+                Some (differential) => {
+                    let mut dumped = differential::dump (&config, differential, differential::base (differential, marks.as_ref (), today), today)?;
+                    (std::mem::take (&mut dumped.sql), Some (dumped))
+                },
+                None => (dump_sql (&config, false)?, None)
+            };
+            write_to_file (&sql_dump, &sql_dump_path)?;
+            dump::validate (&sql_dump_path, &config.mysql_database)?;
+            profile.record ("backup;dump", started.elapsed (), Some (sql_dump.len () as u64));
+            Ok (dumped)
+        }))
+    } else {
+        None
+    };
 
     // create gzip archive
-    let archive_path = format!("{}/{}_{}.tar.gz", &config.backups_directory, ARCHIVE_ROOT, &date);
-    let mut tar = create_archive (&archive_path)?;
+    let archive_path = archive_path (&config.backups_directory, &format!("{}_{}", kind.archive_root (), &date), config.collision_policy).classify (BackupError::Archive)?;
+    // written under a temporary name until complete, so a crash never leaves a truncated archive behind
+    let partial_archive_path = format!("{}{}", &archive_path, PARTIAL_SUFFIX);
+    let mut tar = create_archive (&partial_archive_path).classify (BackupError::Archive)?;
+
+    // add the kind's part of the wordpress_directory to the archive
+    let html_entry = format!("wordpress-html_{}", &date);
+    let throttle = throttle::Throttle::new (config.read_limit);
+    let sanitizer = sanitize::Sanitizer::new (config.filename_sanitization);
+    // the web host's links are followed by its tar, but not recorded
+    let symlinks = match config.ssh {
+        Some (_) => Vec::new (),
+        None => symlinks::detect (&config.wordpress_directory, kind).classify (BackupError::Archive)?
+    };
+    let mut directories : Vec<String> = kind.directories ().iter ().map (|directory| String::from (*directory)).collect ();
+    if kind.is_config () {
+        // a broken database shouldn't cost the wp-config.php backup
+        match site::active_themes (config) {
+            Ok (themes) => directories.extend (themes),
+            Err (err) => warn!("Could not look up the active theme, archiving without it: {}", err)
+        }
+    }
+    for directory in &directories {
+        let source : PathBuf = Path::new (&config.wordpress_directory).join (directory);
+        let exists = match &config.ssh {
+            Some (ssh) => ssh.is_dir (&source).classify (BackupError::Archive)?,
+            None => source.is_dir ()
+        };
+        if !exists {
+            warn!("Directory {} does not exist, skipping it", source.display ());
+            continue;
+        }
+        let destination = Path::new (&html_entry).join (directory);
+        match &config.ssh {
+            Some (ssh) => profile.time ("backup;archive", || ssh.append_tree (&mut tar, &source, &destination, config.reproducible, &throttle, &sanitizer)).classify (BackupError::Archive)?,
+            None => append_directory (&mut tar, &destination, &source, config, &profile, &throttle, &sanitizer).classify (BackupError::Archive)?
+        }
+    }
+
+    for file in kind.files () {
+        let source = Path::new (&config.wordpress_directory).join (file);
+        if !append_single_file (&mut tar, config, &source, &Path::new (&html_entry).join (file), &throttle).classify (BackupError::Archive)? {
+            info!("There is no {}, skipping it", source.display ());
+        }
+    }
+
+    // server configuration outside the site, archived by its absolute path
+    let server_config_entry = format!("server-config_{}", &date);
+    let mut server_config = None;
+    for file in config.server_config_files.iter ().filter (|_| kind.is_config ()) {
+        let name = Path::new (&server_config_entry).join (file.trim_start_matches ('/'));
+        if append_single_file (&mut tar, config, Path::new (file), &name, &throttle).classify (BackupError::Archive)? {
+            server_config = Some (server_config_entry.clone ());
+        } else {
+            warn!("Server configuration file {} does not exist, skipping it", file);
+        }
+    }
 
-    // add wordpress_directory to the archive
-    tar.append_dir_all(format!("wordpress-html_{}", &date), &config.wordpress_directory)?;
+    // the schema is what manual edits of the database break, its content would make config backups expensive
+    let schema_dump_name = format!("schema_{}.sql", &date);
+    if kind.is_config () {
+        let schema_dump_path = format!("{}/{}", &config.backups_directory, &schema_dump_name);
+        let schema = dump_sql (config, true).classify (BackupError::Dump)?;
+        write_to_file (&schema, &schema_dump_path).classify (BackupError::Dump)?;
+        let appended = dump::validate (&schema_dump_path, &config.mysql_database).classify (BackupError::Dump)
+            .and_then (|_| File::open (&schema_dump_path).and_then (|mut file| tar.append_file (&schema_dump_name, &mut file)).classify (BackupError::Archive));
+        fs::remove_file (&schema_dump_path).unwrap_or_else (| why | { warn!("Could not remove {} {}", &schema_dump_path, why) });
+        appended?;
+    }
 
     // add the sql dump to the archive
-    let mut file = File::open(&sql_dump_path)?;
-    tar.append_file(&sql_dump_name, &mut file)?;
+    let dumped = match sql_dump {
+        Some (sql_dump) => sql_dump.join ().map_err (|_| BackupError::Dump (String::from ("Creating the sql dump failed")))?.classify (BackupError::Dump)?,
+        None => None
+    };
+    if kind.includes_database () {
+        let mut file = File::open(&sql_dump_path).classify (BackupError::Dump)?;
+        tar.append_file(&sql_dump_name, &mut file).classify (BackupError::Archive)?;
+    }
+    // not worth failing the backup over, the dump restores without them
+    let mut grants = None;
+    if kind.includes_database () && config.backup_grants {
+        match grants::capture (config) {
+            Ok ((sql, users)) => {
+                let entry = format!("grants_{}.sql", &date);
+                let mut header = tar::Header::new_gnu ();
+                header.set_size (sql.len () as u64);
+                // password hashes
+                header.set_mode (0o600);
+                header.set_mtime (today.timestamp () as u64);
+                header.set_cksum ();
+                tar.append_data (&mut header, &entry, sql.as_slice ()).classify (BackupError::Archive)?;
+                grants = Some (grants::Archived { entry, database: config.mysql_database.clone (), users });
+            },
+            Err (err) => warn!("Could not capture the database users and grants: {}", err)
+        }
+    }
+
+    // describe the archive content
+    let mut manifest = manifest::Manifest::new (today, kind, &config.site, &html_entry, Some (sql_dump_name.as_str ()).filter (|_| kind.includes_database ()));
+    manifest.symlinks = symlinks;
+    manifest.schema_dump = Some (schema_dump_name).filter (|_| kind.is_config ());
+    manifest.server_config = server_config;
+    manifest.renamed = sanitizer.renamed ();
+    manifest.differential = dumped.as_ref ().and_then (|dumped| dumped.differential.clone ());
+    manifest.grants = grants;
+    if config.embed_config {
+        manifest.config = Some (public_config (config));
+    }
+    // plugin and theme headers are read locally, not worth a round trip per file
+    if kind.includes_code () && config.ssh.is_none () {
+        manifest.versions = Some (versions::capture (&config.wordpress_directory));
+    }
+    // taken along with the dump, but not worth failing the backup over
+    if kind.includes_database () {
+        match cron::capture (config, &config.cron_snapshot_options) {
+            Ok (snapshot) => manifest.cron = Some (snapshot),
+            Err (err) => warn!("Could not capture the cron schedule: {}", err)
+        }
+    }
+    manifest.append_to (&mut tar).classify (BackupError::Archive)?;
+
+    // close the archive, it was hashed while being written
+    let written = (|| -> AnyResult<_> { tar.into_inner ()?.finish ()?.finish () }) ().classify (BackupError::Archive)?;
+    let hash = written.tree_hash;
+    profile.record ("backup;archive;hash", written.hashing, Some (written.bytes));
+    fs::rename (&partial_archive_path, &archive_path).classify (BackupError::Archive)?;
+    let floor = quarantine::floor (kind, config.min_archive_size);
+    if written.bytes < floor {
+        let moved = quarantine::isolate (&config.backups_directory, &archive_path).classify (BackupError::Archive)?;
+        return Err (BackupError::Archive (format!("The archive is only {} bytes, below the {} bytes of MIN_ARCHIVE_SIZE: quarantined to {}",
+                                                  written.bytes, floor, moved)).into ());
+    }
+    leaves::write_sidecar (&archive_path, &written.leaf_hashes).classify (BackupError::Archive)?;
+
+    if let Some (command) = &config.verify_command {
+        profile.time ("backup;verify", || verify_archive (command, &archive_path)).classify (BackupError::Archive)?;
+    }
+
+    info!("Archive content hash: {}", &hash);
+
+    let signature = match &config.signing_key {
+        Some (key) => {
+            let signature = key.keypair ()
+                .and_then (|keypair| signature::sign (&keypair, &hash))
+                .classify (BackupError::Archive)?;
+            let sidecar = signature::write_sidecar (&archive_path, &signature).classify (BackupError::Archive)?;
+            info!("Archive signed, signature written to {}", sidecar);
+            Some (signature.signature)
+        },
+        None => None
+    };
 
-    // close the archive
-    tar.finish ()?;
+    let queued = config.enqueue;
+    if queued {
+        // the uploader records the success
+        outbox::Queued { kind, enqueued: Utc::now (), tree_hash: hash.clone (), bytes: written.bytes }.write (&archive_path).classify (BackupError::Archive)?;
+        info!("Queued {} for the uploader", archive_path);
+    } else {
+        let upload_started = Instant::now ();
+        let uploaded = upload::upload (config, &archive_path, &hash, &manifest, signature.as_deref (), written.bytes).await.classify (BackupError::upload)?;
+        profile.record ("backup;upload", upload_started.elapsed (), Some (written.bytes));
+        if !uploaded {
+            // seeding goes on in later runs, which record the success
+            state::update (&config.backups_directory, |state| state.record (kind, "seeding"))?;
+            if kind.includes_database () {
+                fs::remove_file(&sql_dump_path).unwrap_or_else (| why | { warn!("Could not remove {} {}", &sql_dump_path, why) });
+            }
+            return Ok (Created { archive: archive_path, tree_hash: hash, uploaded: false, queued: false, bytes: written.bytes, suspicious: None });
+        }
+    }
 
-    let glacier_client = GlacierClient::new(Region::from_str (&config.aws_region)?);
+    let (bytes, mut suspicious) = (written.bytes, None);
+    state::update (&config.backups_directory, |state| {
+        if queued {
+            state.record (kind, "queued");
+        } else {
+            suspicious = record_upload (config, state, kind, bytes);
+        }
+        if let Some (versions) = &manifest.versions {
+            state.versions.push (versions::VersionsAt { created: today, archive: manifest.name (), versions: versions.clone () });
+        }
+        if let Some (dumped) = &dumped {
+            state.dump_marks = Some (differential::Marks {
+                full: dumped.full,
+                archive: Path::new (&archive_path).file_name ().map (|name| name.to_string_lossy ().to_string ()).unwrap_or_default (),
+                tables: dumped.marks.clone (),
+            });
+        }
+    })?;
 
-    ensure_vault (&glacier_client, &config.aws_glacier_vault_name).await?;
+    if kind == BackupKind::Full {
+        profile.time ("backup;standby", || standby::sync (&config.standby, &config.wordpress_directory, &sql_dump_path));
+    }
 
-    let result = send_to_glacier (&archive_path,
-                                  format!("Created: {}", &date),
-                                  &glacier_client,
-                                  &config.aws_glacier_vault_name).await?;
+    if kind.includes_database () {
+        fs::remove_file(&sql_dump_path).unwrap_or_else (| why | { warn!("Could not remove {} {}", &sql_dump_path, why) });
+    }
 
-    info!("Archive succesfully stored in glacier with id: {}",
-          &result.archive_id.unwrap_or_else(|| String::from ("unknown")));
+    cleanup (&config.backups_directory, kind, &today, schedule.rolling_period, config.restore_grace).classify (BackupError::Retention)?;
+    if kind == BackupKind::Full {
+        // left over from db-only blackouts
+        cleanup (&config.backups_directory, BackupKind::Database, &today, schedule.rolling_period, config.restore_grace).classify (BackupError::Retention)?;
+    }
 
-    cleanup (&sql_dump_path, &config.backups_directory, &today, config.archive_rolling_period)?;
+    profile.report (&format!("{}{}", &archive_path, profile::PROFILE_SUFFIX), started.elapsed ())?;
 
+    if let Some (reason) = &suspicious {
+        warn!("The {} backup succeeded but looks suspicious: {}", kind, reason);
+    }
     info!("Done");
 
-    Ok (())
+    Ok (Created { archive: archive_path, tree_hash: hash, uploaded: !queued, queued, bytes: written.bytes, suspicious })
 }
 
-fn cleanup (sql_dump_path: &str,
-            backups_directory: &str,
-            today: &DateTime<Utc>,
-            rolling_period : u32)
-            -> AnyResult<()> {
+/// Records the successful upload of a `kind` archive of `bytes`, returns why its size looks suspicious if it does.
+fn record_upload (config: &Config, state: &mut state::State, kind: BackupKind, bytes: u64) -> Option<String> {
+    let suspicious = config.size_anomaly.as_ref ().and_then (|anomaly| anomaly.check (&state.history, kind, bytes));
+    state.last_success.insert (kind, Utc::now ());
+    state.record_success (kind, bytes, suspicious.clone ());
+    suspicious
+}
+
+/// Uploads an archive an `--enqueue` run queued, recording the success and pruning like a run uploading it right away.
+async fn upload_queued (config: &Config, archive_path: &str, queued: &outbox::Queued) -> AnyResult<Created> {
+    let kind = queued.kind;
+    info!("Uploading {}, queued {}", archive_path, queued.enqueued);
+    let (manifest, signature) = upload::archived (config, archive_path).classify (BackupError::Archive)?;
+    let created = |uploaded, suspicious| Created {
+        archive: String::from (archive_path), tree_hash: queued.tree_hash.clone (), uploaded, queued: false, bytes: queued.bytes, suspicious
+    };
+    if !upload::upload (config, archive_path, &queued.tree_hash, &manifest, signature.as_deref (), queued.bytes).await.classify (BackupError::upload)? {
+        // seeding goes on in the next drain, the archive stays queued
+        state::update (&config.backups_directory, |state| state.record (kind, "seeding"))?;
+        return Ok (created (false, None));
+    }
+    outbox::Queued::remove (archive_path)?;
+
+    let mut suspicious = None;
+    state::update (&config.backups_directory, |state| { suspicious = record_upload (config, state, kind, queued.bytes); })?;
+    if let Some (schedule) = config.schedules.iter ().find (|schedule| schedule.kind == kind) {
+        cleanup (&config.backups_directory, kind, &Utc::now (), schedule.rolling_period, config.restore_grace).classify (BackupError::Retention)?;
+    }
+    if let Some (reason) = &suspicious {
+        warn!("The {} backup succeeded but looks suspicious: {}", kind, reason);
+    }
+    Ok (created (true, suspicious))
+}
 
-    fs::remove_file(sql_dump_path).unwrap_or_else (| why | { warn!("Could not remove {} {}", &sql_dump_path, why) });
+/// Archives of `kind` in the backups directory, with the date from their name, oldest first.
+fn local_archives (backups_directory: &str, kind: BackupKind) -> AnyResult<Vec<(String, DateTime<Utc>)>> {
 
+    let mut archives = Vec::new ();
     for entry in fs::read_dir(backups_directory)? {
         let path_buf = entry?.path ();
         let archive_name = path_buf.as_path ().display ().to_string ();
-        let d = &RE.captures_iter(&archive_name).next ().unwrap () [0];
+        let file_name = path_buf.file_name ().map (|name| name.to_string_lossy ().to_string ()).unwrap_or_default ();
+        if !file_name.starts_with (&format!("{}_", kind.archive_root ())) || !file_name.ends_with (".tar.gz") {
+            continue;
+        }
+        let d = match RE.captures_iter(&file_name).next () {
+            Some (captures) => captures [0].to_string (),
+            None => continue
+        };
         let d = &format!("{} 00:00:00 +00:00", d);
-        let archive_date = d.parse::<DateTime<Utc>>()?;
+        archives.push ((archive_name, d.parse::<DateTime<Utc>>()?));
+    }
 
-        let diff = (*today - archive_date).num_days ();
-        if diff as u32 >= rolling_period {
-            info! ("Archive {} is older than {} old, removing", archive_name, rolling_period);
-            fs::remove_file(&archive_name).unwrap_or_else (| why | { warn!("Could not remove {} {}", &archive_name, why) });
-        } else {
-            info! ("Archive {} is {} days old", archive_name, diff);
+    archives.sort_by (|(a_name, a_date), (b_name, b_date)| a_date.cmp (b_date).then (a_name.cmp (b_name)));
+    Ok (archives)
+}
+
+fn cleanup (backups_directory: &str,
+            kind: BackupKind,
+            today: &DateTime<Utc>,
+            rolling_period : u32,
+            restore_grace: Duration)
+            -> AnyResult<()> {
+
+    let restores = state::load (backups_directory)?.restores;
+    for decision in retention::plan_local (kind, &local_archives (backups_directory, kind)?, *today, rolling_period, &restores, restore_grace) {
+        let archive_name = decision.archive;
+        if decision.keep {
+            info! ("Keeping {}: {}", archive_name, decision.rule);
+            continue;
+        }
+        if outbox::Queued::read (&archive_name)?.is_some () {
+            info! ("Keeping {}: queued for the uploader", archive_name);
+            continue;
+        }
+
+        info! ("Removing {}: {}", archive_name, decision.rule);
+        fs::remove_file(&archive_name).unwrap_or_else (| why | { warn!("Could not remove {} {}", &archive_name, why) });
+        for suffix in SIDECAR_SUFFIXES {
+            let sidecar = format!("{}{}", &archive_name, suffix);
+            if Path::new (&sidecar).exists () {
+                fs::remove_file(&sidecar).unwrap_or_else (| why | { warn!("Could not remove {} {}", &sidecar, why) });
+            }
         }
+    }
+
+    Ok (())
+}
+
+/// Removes partial archives, sql dumps (schema only ones too) and our lock files older than `threshold` hours,
+/// which can only be leftovers of runs that crashed or were killed, locks only once the process holding them is gone.
+fn remove_stale_files (backups_directory: &str, threshold: u32) -> AnyResult<()> {
 
+    let threshold = Duration::from_secs (3600 * threshold as u64);
+    let mut reclaimed : u64 = 0;
+
+    for entry in fs::read_dir(backups_directory)? {
+        let entry = entry?;
+        let name = entry.file_name ().to_string_lossy ().to_string ();
+        let is_lock = name == lock::LOCK_FILE || name == state::UPDATE_LOCK;
+        // a lock may be held for long without being touched
+        if is_lock && !lock::is_stale (&entry.path ()) {
+            continue;
+        }
+        let is_leftover = name.ends_with (PARTIAL_SUFFIX)
+            || is_lock
+            || (name.starts_with ("dump_") && name.ends_with (".sql"))
+            || (name.starts_with ("schema_") && name.ends_with (".sql"));
+        if !is_leftover {
+            continue;
+        }
+
+        let metadata = entry.metadata ()?;
+        let age = metadata.modified ()?.elapsed ().unwrap_or_default ();
+        if age < threshold {
+            continue;
+        }
+
+        let path = entry.path ().display ().to_string ();
+        match fs::remove_file (&path) {
+            Ok (_) => {
+                info! ("Removed stale file {} ({} bytes, {} hours old)", path, metadata.len (), age.as_secs () / 3600);
+                reclaimed += metadata.len ();
+            },
+            Err (why) => warn!("Could not remove stale file {} {}", path, why)
+        }
+    }
+
+    if reclaimed > 0 {
+        info! ("Reclaimed {} bytes from stale files", reclaimed);
     }
 
     Ok (())
 }
 
-async fn send_to_glacier (file_path : &str,
-                          description : String,
-                          client : &GlacierClient,
-                          vault_name : &str)
-                          -> AnyResult<ArchiveCreationOutput> {
+fn archive_tree_hash (file_path : &str) -> AnyResult<String> {
+    tree_hash::tree_hash (file_path)
+        .map (|hash_bytes| tree_hash::to_hex_string (&hash_bytes))
+        .map_err (|err| anyhow::anyhow!("Error calculating the tree hash of {}: {:#}", file_path, err))
+        .classify (BackupError::Archive)
+}
 
-    let hash : String = match tree_hash::tree_hash(file_path) {
-        Ok(hash_bytes) => {
-            tree_hash::to_hex_string(&hash_bytes)
-        },
-        Err(_) => panic!("Error calculating tree hash")
-    };
+/// Runs the configured verification command with the archive path as its last argument,
+/// the upload only proceeds if it exits with 0.
+fn verify_archive (command: &str, archive_path: &str) -> AnyResult<()> {
 
-    info!("Archive content hash: {}", &hash);
+    info!("Verifying {} with: {}", archive_path, command);
 
-    let mut file : File = File::open(&file_path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let bytes : Bytes = Bytes::from (buffer);
-
-    let request = UploadArchiveInput {
-        account_id: "-".to_string(),
-        archive_description: Some (description),
-        body: Some (bytes),
-        checksum: Some (hash),
-        vault_name: String::from (vault_name)
-    };
+    let output : Output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", command))
+        .arg("mer-de-glace")
+        .arg(archive_path)
+        .env("ARCHIVE_PATH", archive_path)
+        .output()?;
 
-    let result = match client.upload_archive (request).await {
-        Ok (res) => res,
-        Err (err) => panic!("Error when uploading {} to glacier: {}", file_path, err)
-    };
+    for line in String::from_utf8_lossy (&output.stdout).lines () {
+        info!("[verify] {}", line);
+    }
+    for line in String::from_utf8_lossy (&output.stderr).lines () {
+        warn!("[verify] {}", line);
+    }
 
-    Ok (result)
+    if !output.status.success () {
+        return Err (anyhow::anyhow!("Verification of {} failed with {}, not uploading it", archive_path, output.status));
+    }
+
+    info!("Archive {} verified", archive_path);
+    Ok (())
 }
 
-async fn ensure_vault (client : &GlacierClient, vault_name : &str) -> AnyResult<()> {
+/// Picks the path of a new archive named `stem`, applying the collision policy if one already exists.
+fn archive_path (directory: &str, stem: &str, policy: CollisionPolicy) -> AnyResult<String> {
+    let path = format!("{}/{}.tar.gz", directory, stem);
+    if !Path::new (&path).exists () {
+        return Ok (path);
+    }
 
-    let request = DescribeVaultInput {
-        account_id: "-".to_string(),
-        vault_name: String::from (vault_name),
-    };
+    match policy {
+        CollisionPolicy::Overwrite => {
+            warn!("Archive {} already exists, overwriting it", path);
+            // the upload record, signature and leaf hashes of the old archive don't describe the new one
+            for suffix in SIDECAR_SUFFIXES {
+                let sidecar = format!("{}{}", path, suffix);
+                if Path::new (&sidecar).exists () {
+                    fs::remove_file (&sidecar)?;
+                }
+            }
+            Ok (path)
+        },
+        CollisionPolicy::Suffix => {
+            let path = (1..)
+                .map (|n| format!("{}/{}.{}.tar.gz", directory, stem, n))
+                .find (|candidate| !Path::new (candidate).exists ())
+                .unwrap ();
+            warn!("Archive {}/{}.tar.gz already exists, writing to {}", directory, stem, path);
+            Ok (path)
+        },
+        CollisionPolicy::Abort => Err (anyhow::anyhow!("Archive {} already exists, aborting", path))
+    }
+}
 
-    match client.describe_vault (request).await {
-        Ok (result) => {
-            info! ("Glacier vault exists: {:#?}", result);
+/// Appends the single file `source`, from the web host when archiving over SSH, as `name`.
+/// Returns false when there is no such file.
+fn append_single_file<W: Write> (tar: &mut tar::Builder<W>, config: &Config, source: &Path, name: &Path, throttle: &throttle::Throttle) -> AnyResult<bool> {
+    match &config.ssh {
+        Some (ssh) => match ssh.read (source) {
+            Ok (content) => {
+                let mut header = tar::Header::new_gnu ();
+                header.set_size (content.len () as u64);
+                // wp-config.php holds the database password
+                header.set_mode (0o640);
+                header.set_mtime (Utc::now ().timestamp () as u64);
+                tar.append_data (&mut header, name, content.as_slice ())?;
+                Ok (true)
+            },
+            Err (err) if err.kind () == std::io::ErrorKind::NotFound => Ok (false),
+            Err (err) => Err (err.into ())
         },
-        Err (err) => {
-            warn! ("Glacier vault {} not found: {:#?}", vault_name, err);
-            let request = CreateVaultInput {
-                account_id: "-".to_string(),
-                vault_name: String::from (vault_name),
-            };
-            match client.create_vault (request).await {
-                Ok (result) => {
-                    info! ("Created glacier vault: {:#?}", result);
-                },
-                Err (err) => {
-                    panic! ("Could not create glacier vault {}", err);
-                }
-            };
+        None if source.is_file () => {
+            walk::append_file (tar, source, name, throttle)?;
+            Ok (true)
+        },
+        None => Ok (false)
+    }
+}
+
+/// Appends `source` as `destination`, one top level entry at a time so that each is timed separately.
+fn append_directory<W: Write> (tar: &mut tar::Builder<W>,
+                               destination: &Path,
+                               source: &Path,
+                               config: &Config,
+                               profile: &profile::Profile,
+                               throttle: &throttle::Throttle,
+                               sanitizer: &sanitize::Sanitizer)
+                               -> AnyResult<()> {
+    tar.append_dir (destination, source)?;
+
+    let mut children = fs::read_dir (source)?.collect::<Result<Vec<_>, _>>()?;
+    children.sort_by_key (|child| child.file_name ());
+
+    for child in children {
+        let name = child.file_name ();
+        let entry = sanitizer.child (destination, &name);
+        let started = Instant::now ();
+        if child.path ().is_dir () {
+            tar.append_dir (&entry, child.path ())?;
+            walk::append_tree (tar, &child.path (), &entry, config.walk_threads, config.reproducible, throttle, sanitizer)?;
+        } else {
+            walk::append_file (tar, &child.path (), &entry, throttle)?;
         }
-    };
+        profile.record (&format!("backup;archive;{}", name.to_string_lossy ()), started.elapsed (), None);
+    }
 
     Ok (())
 }
 
 fn create_archive (path : &str)
-                   -> AnyResult<tar::Builder<flate2::write::GzEncoder<std::fs::File>>> {
-    let tar_gz = File::create(path)?;
+                   -> AnyResult<tar::Builder<flate2::write::GzEncoder<pipeline::HashingWriter>>> {
+    let tar_gz = pipeline::HashingWriter::create(path)?;
     let encoder = GzEncoder::new(tar_gz, Compression::default());
     Ok (tar::Builder::new(encoder))
 }
 
-// TODO : spawn as thread
-fn dump_sql (config: &Config) -> Vec<u8> {
+/// Dumps the site's database, just its tables' definitions when `schema_only`.
+fn dump_sql (config: &Config, schema_only: bool) -> AnyResult<Vec<u8>> {
 
-    let Config { mysql_host, mysql_port, mysql_user, mysql_password, mysql_database, .. } = config;
-
-    let output : Output = Command::new("mysqldump")
-        .arg("-h")
-        .arg(&mysql_host)
-        .arg("--port")
-        .arg(&mysql_port)
-        .arg("-u")
-        .arg(&mysql_user)
-        .arg(format!("-p{}", &mysql_password))
+    let mut command = dump_command (config);
+    command
         .arg("--databases")
-        .arg(&mysql_database)
-        .output()
-        .expect("Failed to execute mysqldump");
+        .arg(&config.mysql_database);
+    if schema_only {
+        command.arg("--no-data");
+    }
+    let sql_dump = run_dump (config, command)?;
 
     info!("Succesfully dumped SQL data");
 
-    output.stdout
+    Ok (sql_dump)
 }
 
-fn write_to_file (content: &[u8], path : &str) {
-    match File::create(Path::new(&path)) {
-        Err(why) => panic!("Couldn't create {:#?}: {}", path, why),
-        Ok(mut file) => {
-            match file.write_all(content) {
-                Err(why) => panic!("Couldn't write to {}: {}", path, why),
-                Ok(_) => info!("Successfully wrote to file {}", path),
-            };
-        }
+/// mysqldump connected to the site's database, for the caller to add what to dump.
+fn dump_command (config: &Config) -> Command {
+
+    let Config { mysql_host, mysql_port, mysql_user, mysql_password, .. } = config;
+
+    let mut command = Command::new("mysqldump");
+    command
+        .arg("-h")
+        .arg(mysql_host)
+        .arg("--port")
+        .arg(mysql_port)
+        .arg("-u")
+        .arg(mysql_user)
+        .arg(format!("-p{}", &mysql_password));
+    command
+}
+
+/// Runs a `dump_command`, on the web host if the site is archived from one.
+fn run_dump (config: &Config, command: Command) -> AnyResult<Vec<u8>> {
+    let output : Output = ssh::wrap (config.ssh.as_ref (), command)
+        .output()
+        .map_err (|err| anyhow::anyhow!("Failed to execute mysqldump: {}", err))?;
+    if !output.status.success () {
+        return Err (anyhow::anyhow!("mysqldump failed with {}: {}", output.status, String::from_utf8_lossy (&output.stderr).trim ()));
     }
+    Ok (output.stdout)
+}
+
+fn write_to_file (content: &[u8], path : &str) -> AnyResult<()> {
+    let mut file = File::create(Path::new(&path)).map_err (|why| anyhow::anyhow!("Couldn't create {:#?}: {}", path, why))?;
+    file.write_all(content).map_err (|why| anyhow::anyhow!("Couldn't write to {}: {}", path, why))?;
+    info!("Successfully wrote to file {}", path);
+    Ok (())
 }
 
 fn get_env_var (var : &str, default: Option<String> ) -> AnyResult<String> {
@@ -265,13 +1851,115 @@ fn get_env_var (var : &str, default: Option<String> ) -> AnyResult<String> {
         Ok (v) => Ok (v),
         Err (_) => {
             match default {
-                None => panic! ("Missing ENV variable: {} not defined in environment", var),
+                None => Err (anyhow::anyhow!("Missing ENV variable: {} not defined in environment", var)),
                 Some (d) => Ok (d)
             }
         }
     }
 }
 
-pub fn print_type_of<T>(_: &T) {
-    println!("{}", std::any::type_name::<T>())
+/// Archives can be given by path or by their name in the backups directory.
+fn resolve_archive (archive: &str) -> AnyResult<String> {
+    if Path::new (archive).exists () {
+        return Ok (String::from (archive));
+    }
+    let path = Path::new (&get_env_var ("BACKUPS_DIRECTORY", Some (String::from ("backups")))?).join (archive);
+    if path.exists () {
+        Ok (path.display ().to_string ())
+    } else {
+        Err (anyhow::anyhow!("No archive {} found", archive))
+    }
+}
+
+/// The `--output` format of a subcommand, text unless asked otherwise.
+fn output_format (matches: &ArgMatches) -> AnyResult<output::Format> {
+    matches.value_of ("output").unwrap_or ("text").parse::<output::Format>()
+}
+
+/// The secret `var` holds, or the one `{var}_REF` refers to in a secret store.
+fn get_secret_env_var (var : &str) -> AnyResult<Option<secrets::Secret>> {
+    let reference = format!("{}_REF", var);
+    match (env::var (var).ok (), get_optional_env_var (&reference)) {
+        (Some (_), Some (_)) => Err (anyhow::anyhow!("Both {} and {} are set, expected just one of them", var, reference)),
+        (Some (value), None) => Ok (Some (secrets::Secret::Value (value))),
+        (None, Some (stored)) => Ok (Some (secrets::Secret::Stored (secrets::Reference::parse (&stored)
+                                                                   .map_err (|err| anyhow::anyhow!("{}: {}", reference, err))?))),
+        (None, None) => Ok (None)
+    }
+}
+
+fn get_required_secret_env_var (var : &str) -> AnyResult<secrets::Secret> {
+    get_secret_env_var (var)?
+        .ok_or_else (|| anyhow::anyhow!("Missing ENV variable: neither {} nor {}_REF defined in environment", var, var))
+}
+
+fn get_optional_env_var (var : &str) -> Option<String> {
+    match env::var(var) {
+        Ok (v) if !v.is_empty () => Some (v),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for `test`.
+    fn directory (test: &str) -> String {
+        let directory = std::env::temp_dir ().join (format!("mer-de-glace-{}-{}", test, std::process::id ()));
+        let _ = fs::remove_dir_all (&directory);
+        fs::create_dir_all (&directory).unwrap ();
+        directory.display ().to_string ()
+    }
+
+    #[test]
+    fn overwriting_an_archive_removes_its_sidecars () {
+        let directory = directory ("overwrite");
+        let archive = format!("{}/wordpress_backup_2021-02-03.tar.gz", directory);
+        fs::write (&archive, b"old").unwrap ();
+        for suffix in SIDECAR_SUFFIXES {
+            fs::write (format!("{}{}", archive, suffix), b"old").unwrap ();
+        }
+        let other = format!("{}/wordpress_backup_2021-02-04.tar.gz{}", directory, upload_record::UPLOAD_RECORD_SUFFIX);
+        fs::write (&other, b"other").unwrap ();
+
+        assert_eq!(archive_path (&directory, "wordpress_backup_2021-02-03", CollisionPolicy::Overwrite).unwrap (), archive);
+        for suffix in SIDECAR_SUFFIXES {
+            assert!(!Path::new (&format!("{}{}", archive, suffix)).exists (), "{} kept", suffix);
+        }
+        assert!(Path::new (&other).exists ());
+        fs::remove_dir_all (&directory).unwrap ();
+    }
+
+    #[test]
+    fn only_leftovers_are_removed () {
+        let directory = directory ("stale");
+        let dead = "99999999";
+        let files = [("wordpress_backup_2021-02-03.tar.gz.partial", "", false), ("dump_2021-02-03.sql", "", false),
+                     ("wordpress_backup_2021-02-03.tar.gz", "", true), ("dump_2021-02-03.sql.gz", "", true),
+                     (lock::LOCK_FILE, dead, false), (state::UPDATE_LOCK, &std::process::id ().to_string (), true),
+                     ("other-tool.lock", dead, true)];
+        for (name, content, _) in files {
+            fs::write (format!("{}/{}", directory, name), content).unwrap ();
+        }
+        remove_stale_files (&directory, 0).unwrap ();
+        for (name, _, kept) in files {
+            assert_eq!(Path::new (&format!("{}/{}", directory, name)).exists (), kept, "{}", name);
+        }
+        fs::remove_dir_all (&directory).unwrap ();
+    }
+
+    #[test]
+    fn colliding_archives_get_a_suffix_or_abort () {
+        let directory = directory ("collision");
+        let archive = format!("{}/wordpress_backup_2021-02-03.tar.gz", directory);
+        fs::write (&archive, b"old").unwrap ();
+        fs::write (format!("{}{}", archive, upload_record::UPLOAD_RECORD_SUFFIX), b"old").unwrap ();
+
+        assert_eq!(archive_path (&directory, "wordpress_backup_2021-02-03", CollisionPolicy::Suffix).unwrap (),
+                   format!("{}/wordpress_backup_2021-02-03.1.tar.gz", directory));
+        assert!(archive_path (&directory, "wordpress_backup_2021-02-03", CollisionPolicy::Abort).is_err ());
+        assert!(Path::new (&format!("{}{}", archive, upload_record::UPLOAD_RECORD_SUFFIX)).exists ());
+        fs::remove_dir_all (&directory).unwrap ();
+    }
 }
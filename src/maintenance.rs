@@ -0,0 +1,61 @@
+// Keeps backups from capturing a half-upgraded site: waits while WordPress core, plugins or themes are being updated,
+// which WordPress signals with a `.maintenance` file in the site root and the `core_updater.lock` / `auto_updater.lock` options.
+
+use crate::{site, Config};
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::time;
+
+/// WordPress ignores a `.maintenance` file older than that, one left behind by an update that crashed
+const MAINTENANCE_STALE: Duration = Duration::from_secs (600);
+/// and update locks older than that
+const LOCK_STALE_SECONDS: u64 = 3600;
+const POLL_INTERVAL: Duration = Duration::from_secs (30);
+
+/// Why an update seems to be in progress, None when none is.
+pub fn update_in_progress (config: &Config) -> Option<String> {
+    let maintenance = Path::new (&config.wordpress_directory).join (".maintenance");
+    // Some (None) when it exists but its age is unknown
+    let modified = match &config.ssh {
+        Some (ssh) => ssh.modified (&maintenance).unwrap_or_else (|err| {
+            warn!("Could not check for {}: {}", maintenance.display (), err);
+            None
+        }).map (Some),
+        None => fs::metadata (&maintenance).ok ().map (|metadata| metadata.modified ().ok ())
+    };
+    if let Some (modified) = modified {
+        let age = modified
+            .and_then (|modified| SystemTime::now ().duration_since (modified).ok ())
+            .unwrap_or_default ();
+        if age < MAINTENANCE_STALE {
+            return Some (format!("{} exists", maintenance.display ()));
+        }
+        warn!("Ignoring {}, left behind by an update that didn't finish", maintenance.display ());
+    }
+
+    let statement = format!("SELECT option_name FROM {}options WHERE option_name IN ('core_updater.lock', 'auto_updater.lock') AND option_value > UNIX_TIMESTAMP() - {}",
+                            site::table_prefix (config), LOCK_STALE_SECONDS);
+    match site::query (config, &statement) {
+        Ok (locks) if !locks.is_empty () => Some (format!("{} held", locks.join (", "))),
+        Ok (_) => None,
+        Err (err) => {
+            warn!("Could not check the wordpress update locks: {}", err);
+            None
+        }
+    }
+}
+
+/// Waits up to `WORDPRESS_UPDATE_WAIT` for an update in progress to finish, then backs up regardless.
+pub async fn wait_for_updates (config: &Config) {
+    let started = Instant::now ();
+    while let Some (reason) = update_in_progress (config) {
+        if started.elapsed () >= config.update_wait {
+            warn!("A wordpress update still seems to be in progress ({}), backing up anyway", reason);
+            return;
+        }
+        info!("A wordpress update is in progress ({}), waiting for it to finish", reason);
+        time::sleep (POLL_INTERVAL).await;
+    }
+}
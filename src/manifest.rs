@@ -0,0 +1,361 @@
+// Versioned description of an archive's content, stored as `manifest.json` at the root of every archive.
+// Bump `MANIFEST_VERSION` on any format change and add the matching step to `migrate`,
+// so manifests of all older archives can still be read.
+
+use crate::cron;
+use crate::differential;
+use crate::grants;
+use crate::kind::BackupKind;
+use crate::symlinks::Symlink;
+use crate::versions::Versions;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub const MANIFEST_VERSION: u32 = 11;
+/// oldest mer-de-glace release able to read manifests of `MANIFEST_VERSION`
+pub const REQUIRED_TOOL_VERSION: &str = "0.1.0";
+/// archives are not encrypted (yet)
+pub const ENCRYPTION_NONE: &str = "none";
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub manifest_version: u32,
+    pub tool_version: String,
+    pub created: DateTime<Utc>,
+    pub kind: BackupKind,
+    /// entry holding the wordpress html directory
+    pub wordpress_directory: String,
+    /// entry holding the mysql dump, if the kind includes the database
+    pub sql_dump: Option<String>,
+    pub required_tool_version: String,
+    /// identifier of the scheme the archive content is encrypted with
+    pub encryption: String,
+    /// the non-secret configuration the archive was created with, if embedding it is enabled
+    pub config: Option<Value>,
+    /// the site the archive belongs to, telling apart archives of sites sharing a vault
+    pub site: Option<String>,
+    /// wordpress core, plugin and theme versions live when the archive was created, for kinds including the code
+    pub versions: Option<Versions>,
+    /// content directories that were symlinks, archived under their logical path
+    pub symlinks: Vec<Symlink>,
+    /// entry holding the schema only mysql dump of config backups, never loaded by a restore
+    pub schema_dump: Option<String>,
+    /// entry holding server configuration files from outside the wordpress directory, e.g. the nginx vhost
+    pub server_config: Option<String>,
+    /// entries whose names were escaped by `FILENAME_SANITIZATION`, with their original path (lossy if not utf-8)
+    pub renamed: BTreeMap<String, String>,
+    /// the cron schedule, key options and transients when the database was dumped, for kinds including it
+    pub cron: Option<cron::Snapshot>,
+    /// what the dump holds of the differential tables if it isn't a full one, see `DIFFERENTIAL_TABLES`
+    pub differential: Option<differential::Dump>,
+    /// the database users and their grants, if captured with the dump, see `BACKUP_GRANTS`
+    pub grants: Option<grants::Archived>,
+}
+
+impl Manifest {
+
+    pub fn new (created: DateTime<Utc>, kind: BackupKind, site: &str, wordpress_directory: &str, sql_dump: Option<&str>) -> Self {
+        Manifest {
+            manifest_version: MANIFEST_VERSION,
+            tool_version: String::from (crate::version::LONG_VERSION),
+            created,
+            kind,
+            wordpress_directory: String::from (wordpress_directory),
+            sql_dump: sql_dump.map (String::from),
+            required_tool_version: String::from (REQUIRED_TOOL_VERSION),
+            encryption: String::from (ENCRYPTION_NONE),
+            config: None,
+            site: Some (String::from (site)),
+            versions: None,
+            symlinks: Vec::new (),
+            schema_dump: None,
+            server_config: None,
+            renamed: BTreeMap::new (),
+            cron: None,
+            differential: None,
+            grants: None,
+        }
+    }
+
+    /// Canonical name of the archive, `{site}/{kind}/{timestamp}`, unique across the sites of a vault.
+    pub fn name (&self) -> String {
+        format!("{}/{}/{}", self.site.as_deref ().unwrap_or ("unknown"), self.kind, self.created.format ("%Y-%m-%dT%H:%M:%SZ"))
+    }
+
+    pub fn append_to<W: Write> (&self, tar: &mut tar::Builder<W>) -> Result<(), anyhow::Error> {
+        let content = serde_json::to_vec_pretty (self)?;
+        let mut header = tar::Header::new_gnu ();
+        header.set_size (content.len () as u64);
+        header.set_mode (0o644);
+        header.set_mtime (self.created.timestamp () as u64);
+        header.set_cksum ();
+        tar.append_data (&mut header, MANIFEST_NAME, content.as_slice ())?;
+        Ok (())
+    }
+
+    /// Parses a manifest of any version, migrating it to the current one.
+    pub fn from_json (content: &[u8]) -> Result<Self, anyhow::Error> {
+        let value : Value = serde_json::from_slice (content)?;
+        let version = value ["manifest_version"].as_u64 ().unwrap_or (0) as u32;
+        Ok (serde_json::from_value (migrate (value, version)?)?)
+    }
+
+    /// Reads the manifest of a local archive.
+    /// Archives created before manifests existed are described as version 0 and migrated.
+    pub fn read_from_archive (path: &str) -> Result<Self, anyhow::Error> {
+        let mut archive = tar::Archive::new (GzDecoder::new (File::open (path)?));
+        let mut dump_entry = None;
+        let mut html_entry = None;
+
+        for entry in archive.entries ()? {
+            let mut entry = entry?;
+            let name = entry.path ()?.display ().to_string ();
+            if name == MANIFEST_NAME {
+                let mut content = Vec::new ();
+                entry.read_to_end (&mut content)?;
+                return Manifest::from_json (&content);
+            }
+            if name.starts_with ("dump_") && dump_entry.is_none () {
+                dump_entry = Some (name);
+            } else if html_entry.is_none () {
+                html_entry = name.split ('/').next ().map (String::from);
+            }
+        }
+
+        let file_name = Path::new (path).file_name ().map (|name| name.to_string_lossy ().to_string ()).unwrap_or_default ();
+        let legacy = json!({
+            "archive_name": file_name,
+            "wordpress_directory": html_entry.unwrap_or_default (),
+            "sql_dump": dump_entry,
+        });
+
+        Ok (serde_json::from_value (migrate (legacy, 0)?)?)
+    }
+}
+
+/// Upgrades a manifest from `version` to `MANIFEST_VERSION`, one version at a time.
+fn migrate (mut value: Value, version: u32) -> Result<Value, anyhow::Error> {
+    if version > MANIFEST_VERSION {
+        return Err (anyhow::anyhow!("Manifest version {} is newer than the supported {}, upgrade mer-de-glace", version, MANIFEST_VERSION));
+    }
+
+    for from in version..MANIFEST_VERSION {
+        value = match from {
+            // archives without a manifest, the creation date is only known from the archive name
+            0 => {
+                let date = crate::RE.find (value ["archive_name"].as_str ().unwrap_or_default ())
+                    .map (|m| format!("{}T00:00:00Z", m.as_str ()))
+                    .ok_or_else (|| anyhow::anyhow!("Cannot date an archive without manifest: {}", value ["archive_name"]))?;
+                json!({
+                    "manifest_version": 1,
+                    "tool_version": "unknown",
+                    "created": date,
+                    "wordpress_directory": value ["wordpress_directory"],
+                    // without a dump entry there is no dump, not one with an empty name
+                    "sql_dump": value ["sql_dump"].as_str ().filter (|dump| !dump.is_empty ()),
+                })
+            },
+            // backup kinds were introduced, all earlier archives were full backups
+            1 => {
+                value ["manifest_version"] = json!(2);
+                value ["kind"] = json!("full");
+                value
+            },
+            // archives became self-describing
+            2 => {
+                value ["manifest_version"] = json!(3);
+                value ["required_tool_version"] = json!(REQUIRED_TOOL_VERSION);
+                value ["encryption"] = json!(ENCRYPTION_NONE);
+                value ["config"] = Value::Null;
+                value
+            },
+            // sites sharing a vault, which one older archives belong to is unknown
+            3 => {
+                value ["manifest_version"] = json!(4);
+                value ["site"] = Value::Null;
+                value
+            },
+            // versions were not captured yet
+            4 => {
+                value ["manifest_version"] = json!(5);
+                value ["versions"] = Value::Null;
+                value
+            },
+            // symlinked directories were not recorded, they were archived as plain directories
+            5 => {
+                value ["manifest_version"] = json!(6);
+                value ["symlinks"] = json!([]);
+                value
+            },
+            // config backups were introduced
+            6 => {
+                value ["manifest_version"] = json!(7);
+                value ["schema_dump"] = Value::Null;
+                value ["server_config"] = Value::Null;
+                value
+            },
+            // file names were archived as they were
+            7 => {
+                value ["manifest_version"] = json!(8);
+                value ["renamed"] = json!({});
+                value
+            },
+            // the cron schedule was not captured yet
+            8 => {
+                value ["manifest_version"] = json!(9);
+                value ["cron"] = Value::Null;
+                value
+            },
+            // every dump was a full one
+            9 => {
+                value ["manifest_version"] = json!(10);
+                value ["differential"] = Value::Null;
+                value
+            },
+            // users and grants were not captured yet
+            10 => {
+                value ["manifest_version"] = json!(11);
+                value ["grants"] = Value::Null;
+                value
+            },
+            _ => unreachable! ()
+        };
+    }
+
+    Ok (value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Migrates a manifest of `version` and checks it reads as the current one.
+    fn migrated (value: Value, version: u32) -> Value {
+        let migrated = migrate (value, version).unwrap ();
+        assert_eq!(migrated ["manifest_version"], json!(MANIFEST_VERSION));
+        serde_json::from_value::<Manifest> (migrated.clone ()).unwrap ();
+        migrated
+    }
+
+    /// A manifest as written at `version`, with every field known by then.
+    fn at (version: u32) -> Value {
+        let mut value = json!({
+            "manifest_version": version,
+            "tool_version": "0.1.0",
+            "created": "2021-02-03T04:05:06Z",
+            "wordpress_directory": "html",
+            "sql_dump": "dump_2021-02-03.sql",
+        });
+        let fields = [(2, "kind", json!("database")),
+                      (3, "required_tool_version", json!("0.1.0")), (3, "encryption", json!("none")), (3, "config", json!({ "site": "blog" })),
+                      (4, "site", json!("blog")),
+                      (5, "versions", Value::Null),
+                      (6, "symlinks", json!([])),
+                      (7, "schema_dump", json!("schema.sql")), (7, "server_config", json!("server")),
+                      (8, "renamed", json!({ "html/a%0Ab": "html/a\nb" })),
+                      (9, "cron", Value::Null),
+                      (10, "differential", Value::Null),
+                      (11, "grants", Value::Null)];
+        for (since, field, content) in fields {
+            if since <= version {
+                value [field] = content;
+            }
+        }
+        value
+    }
+
+    #[test]
+    fn migrates_archives_without_manifest () {
+        let legacy = |sql_dump: Value| json!({ "archive_name": "wordpress_backup_2021-02-03.tar.gz", "wordpress_directory": "html",
+                                               "sql_dump": sql_dump });
+        let with_dump = migrated (legacy (json!("dump_2021-02-03.sql")), 0);
+        assert_eq!(with_dump ["created"], json!("2021-02-03T00:00:00Z"));
+        assert_eq!(with_dump ["sql_dump"], json!("dump_2021-02-03.sql"));
+        assert_eq!(with_dump ["kind"], json!("full"));
+        for sql_dump in [json!(""), Value::Null] {
+            assert_eq!(migrated (legacy (sql_dump), 0) ["sql_dump"], Value::Null);
+        }
+        assert!(migrate (json!({ "archive_name": "backup.tar.gz" }), 0).is_err ());
+    }
+
+    #[test]
+    fn migrates_from_1 () {
+        let migrated = migrated (at (1), 1);
+        assert_eq!(migrated ["kind"], json!("full"));
+        assert_eq!(migrated ["sql_dump"], json!("dump_2021-02-03.sql"));
+    }
+
+    #[test]
+    fn migrates_from_2 () {
+        let migrated = migrated (at (2), 2);
+        assert_eq!(migrated ["kind"], json!("database"));
+        assert_eq!(migrated ["required_tool_version"], json!(REQUIRED_TOOL_VERSION));
+        assert_eq!(migrated ["encryption"], json!(ENCRYPTION_NONE));
+        assert_eq!(migrated ["config"], Value::Null);
+    }
+
+    #[test]
+    fn migrates_from_3 () {
+        let migrated = migrated (at (3), 3);
+        assert_eq!(migrated ["config"], json!({ "site": "blog" }));
+        assert_eq!(migrated ["site"], Value::Null);
+    }
+
+    #[test]
+    fn migrates_from_4 () {
+        let migrated = migrated (at (4), 4);
+        assert_eq!(migrated ["site"], json!("blog"));
+        assert_eq!(migrated ["versions"], Value::Null);
+    }
+
+    #[test]
+    fn migrates_from_5 () {
+        assert_eq!(migrated (at (5), 5) ["symlinks"], json!([]));
+    }
+
+    #[test]
+    fn migrates_from_6 () {
+        let migrated = migrated (at (6), 6);
+        assert_eq!(migrated ["schema_dump"], Value::Null);
+        assert_eq!(migrated ["server_config"], Value::Null);
+    }
+
+    #[test]
+    fn migrates_from_7 () {
+        let migrated = migrated (at (7), 7);
+        assert_eq!(migrated ["schema_dump"], json!("schema.sql"));
+        assert_eq!(migrated ["renamed"], json!({}));
+    }
+
+    #[test]
+    fn migrates_from_8 () {
+        let migrated = migrated (at (8), 8);
+        assert_eq!(migrated ["renamed"], json!({ "html/a%0Ab": "html/a\nb" }));
+        assert_eq!(migrated ["cron"], Value::Null);
+    }
+
+    #[test]
+    fn migrates_from_9 () {
+        assert_eq!(migrated (at (9), 9) ["differential"], Value::Null);
+    }
+
+    #[test]
+    fn migrates_from_10 () {
+        assert_eq!(migrated (at (10), 10) ["grants"], Value::Null);
+    }
+
+    #[test]
+    fn current_manifests_are_read_as_they_are () {
+        let manifest = Manifest::from_json (&serde_json::to_vec (&at (MANIFEST_VERSION)).unwrap ()).unwrap ();
+        assert_eq!(manifest.kind, BackupKind::Database);
+        assert_eq!(manifest.sql_dump.as_deref (), Some ("dump_2021-02-03.sql"));
+        assert!(migrate (at (MANIFEST_VERSION + 1), MANIFEST_VERSION + 1).is_err ());
+    }
+}
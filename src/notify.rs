@@ -0,0 +1,196 @@
+// Notifications of run outcomes and alerts (SLA breaches, bit rot) for downstream AWS automation (ticket creation, Lambda remediation):
+// published to an SNS topic and / or sent to an SQS queue. Failing to notify never fails a backup.
+
+use crate::{aws, AnyResult};
+use crate::cloudwatch::Run;
+use crate::describe::human_size;
+use crate::locale::Locale;
+use crate::rto::human_duration;
+use fluent::fluent_args;
+use log::{info, warn};
+use rusoto_core::credential::AwsCredentials;
+use rusoto_core::param::{Params, ServiceParams};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::Region;
+
+#[derive(Debug, Clone)]
+pub struct Notifications {
+    pub sns_topic_arn: Option<String>,
+    pub sns_region: Region,
+    pub sqs_queue_url: Option<String>,
+    /// the region of the queue, read from its url when that names one
+    pub sqs_region: Region,
+}
+
+impl Notifications {
+
+    /// None unless there is a topic or a queue, the topic is published to in its own region.
+    pub fn new (region: &str, sns_endpoint: &Option<String>, sns_topic_arn: Option<String>, sqs_queue_url: Option<String>) -> AnyResult<Option<Notifications>> {
+        if sns_topic_arn.is_none () && sqs_queue_url.is_none () {
+            return Ok (None);
+        }
+        let sns_region_name = sns_topic_arn.as_deref ()
+            .and_then (|arn| arn.split (':').nth (3))
+            .filter (|name| !name.is_empty ())
+            .unwrap_or (region);
+        let sns_region = aws::region ("sns", sns_region_name, sns_endpoint)?;
+        let sqs_region = match &sqs_queue_url {
+            Some (url) => {
+                let (scheme, rest) = url.split_once ("://").unwrap_or (("https", url));
+                let host = rest.split ('/').next ().unwrap_or_default ();
+                let name = host.strip_prefix ("sqs.").and_then (|rest| rest.split ('.').next ()).unwrap_or (region);
+                Region::Custom { name: String::from (name), endpoint: format!("{}://{}", scheme, host) }
+            },
+            None => aws::region ("sqs", region, &None)?
+        };
+        Ok (Some (Notifications {
+            sns_topic_arn,
+            sns_region,
+            sqs_queue_url,
+            sqs_region,
+        }))
+    }
+}
+
+/// The run in `locale`, the subject and the body of an email.
+fn email (locale: &Locale, run: &Run<'_>) -> (String, String) {
+    let (kind, site, duration) = (run.kind.to_string (), run.site, human_duration (run.duration));
+    let subject = locale.text (&format!("subject-{}", run.outcome ()), fluent_args!["kind" => kind.clone (), "site" => site]);
+    let mut body = match (&run.result, run.suspicious) {
+        (Ok (bytes), None) => locale.text ("email-success", fluent_args!["kind" => kind, "site" => site, "duration" => duration,
+                                                                          "size" => human_size (*bytes)]),
+        (Ok (bytes), Some (reason)) => locale.text ("email-suspicious", fluent_args!["kind" => kind, "site" => site, "duration" => duration,
+                                                                                      "size" => human_size (*bytes), "reason" => reason]),
+        (Err (err), _) => locale.text ("email-failure", fluent_args!["kind" => kind, "site" => site, "duration" => duration,
+                                                                      "error" => format!("{:#}", err)]),
+    };
+    if let Some (head) = run.attestation {
+        body.push_str (&format!("\n\n{}", locale.text ("email-attestation", fluent_args!["head" => head])));
+    }
+    (subject, body)
+}
+
+/// Signed with `credentials`, the run's temporary ones if it has some.
+pub async fn publish (notifications: &Notifications, credentials: Option<&AwsCredentials>, locale: &Locale, run: &Run<'_>) {
+    let (subject, body) = email (locale, run);
+    let what = format!("the {} backup outcome", run.kind);
+    send (notifications, credentials, &what, run.outcome (), &subject, &body, &run.detail ().to_string ()).await;
+}
+
+/// Something about the backups rather than a run, e.g. an SLA breach.
+pub struct Alert {
+    /// the `outcome` attribute of SNS messages, such as `sla-breached`
+    pub event: &'static str,
+    pub subject: String,
+    /// the body of emails
+    pub body: String,
+    /// the alert as JSON for the other subscriptions and SQS
+    pub detail: serde_json::Value,
+}
+
+/// Signed with the daemon's credentials, alerts aren't part of any run.
+pub async fn alert (notifications: &Notifications, alert: &Alert) {
+    let what = format!("the {} alert", alert.event);
+    send (notifications, None, &what, alert.event, &alert.subject, &alert.body, &alert.detail.to_string ()).await;
+}
+
+/// `subject` reduced to what SNS accepts: ASCII without control characters, at most 99 characters.
+fn sns_subject (subject: &str) -> String {
+    let mut ascii = String::with_capacity (subject.len ());
+    for c in subject.chars () {
+        let replacement = match c {
+            'à' | 'á' | 'â' | 'ã' | 'å' => "a",
+            'À' | 'Á' | 'Â' | 'Ã' | 'Å' => "A",
+            'ä' | 'æ' => "ae",
+            'Ä' | 'Æ' => "Ae",
+            'ç' => "c",
+            'Ç' => "C",
+            'è' | 'é' | 'ê' | 'ë' => "e",
+            'È' | 'É' | 'Ê' | 'Ë' => "E",
+            'ì' | 'í' | 'î' | 'ï' => "i",
+            'Ì' | 'Í' | 'Î' | 'Ï' => "I",
+            'ñ' => "n",
+            'Ñ' => "N",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ø' => "o",
+            'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ø' => "O",
+            'ö' | 'œ' => "oe",
+            'Ö' | 'Œ' => "Oe",
+            'ù' | 'ú' | 'û' => "u",
+            'Ù' | 'Ú' | 'Û' => "U",
+            'ü' => "ue",
+            'Ü' => "Ue",
+            'ý' | 'ÿ' => "y",
+            'ß' => "ss",
+            '«' | '»' | '“' | '”' | '„' => "\"",
+            '‘' | '’' => "'",
+            c if c.is_whitespace () || c.is_control () => " ",
+            c if c.is_ascii () => {
+                ascii.push (c);
+                continue;
+            },
+            _ => ""
+        };
+        ascii.push_str (replacement);
+    }
+    let ascii = ascii.trim ();
+    // ASCII, so bytes are characters
+    String::from (&ascii [.. ascii.len ().min (99)])
+}
+
+async fn send (notifications: &Notifications, credentials: Option<&AwsCredentials>, what: &str, outcome: &str, subject: &str, body: &str, message: &str) {
+    if let Some (topic_arn) = &notifications.sns_topic_arn {
+        let mut params = Params::new ();
+        params.put ("Action", "Publish");
+        params.put ("Version", "2010-03-31");
+        params.put ("TopicArn", topic_arn);
+        // SNS takes an ASCII subject under 100 characters, the translated one stays in the body
+        params.put ("Subject", sns_subject (subject));
+        // email subscriptions get the text, the others JSON
+        params.put ("Message", serde_json::json!({ "default": message, "email": body }).to_string ());
+        params.put ("MessageStructure", "json");
+        params.put ("MessageAttributes.entry.1.Name", "outcome");
+        params.put ("MessageAttributes.entry.1.Value.DataType", "String");
+        params.put ("MessageAttributes.entry.1.Value.StringValue", outcome);
+        let mut request = SignedRequest::new ("POST", "sns", &notifications.sns_region, "/");
+        request.set_params (params);
+        match aws::dispatch (credentials, request).await {
+            Ok (_) => info!("Published {} to {}", what, topic_arn),
+            Err (err) => warn!("Could not publish to SNS topic {}: {}", topic_arn, err)
+        }
+    }
+
+    if let Some (queue_url) = &notifications.sqs_queue_url {
+        let path = queue_url.split_once ("://").map_or (queue_url.as_str (), |(_, rest)| rest);
+        let path = path.find ('/').map_or ("/", |i| &path [i..]);
+        let mut params = Params::new ();
+        params.put ("Action", "SendMessage");
+        params.put ("Version", "2012-11-05");
+        params.put ("MessageBody", message);
+        let mut request = SignedRequest::new ("POST", "sqs", &notifications.sqs_region, path);
+        request.set_params (params);
+        match aws::dispatch (credentials, request).await {
+            Ok (_) => info!("Sent {} to {}", what, queue_url),
+            Err (err) => warn!("Could not send to SQS queue {}: {}", queue_url, err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sns_subject_is_ascii () {
+        assert_eq!(sns_subject ("mer-de-glace : échec de la sauvegarde de site.fr"), "mer-de-glace : echec de la sauvegarde de site.fr");
+        assert_eq!(sns_subject ("mer-de-glace: Sicherung von site.de erfolgreich, aber verdächtig"),
+                   "mer-de-glace: Sicherung von site.de erfolgreich, aber verdaechtig");
+        assert_eq!(sns_subject ("a\u{a0}b\nc 日本"), "a b c");
+    }
+
+    #[test]
+    fn sns_subject_is_capped () {
+        let subject = sns_subject (&"é".repeat (150));
+        assert_eq!(subject.len (), 99);
+        assert!(subject.is_ascii ());
+    }
+}
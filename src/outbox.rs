@@ -0,0 +1,73 @@
+// Archives waiting for `mer-de-glace uploader`, queued by `--enqueue` runs that only archive: a `<archive>.queued` sidecar,
+// removed once the archive is uploaded. Archiving and uploading then share just the backups directory, e.g. archiving on
+// the web host at night and uploading from another host over the day.
+
+use crate::kind::BackupKind;
+use crate::local_archives;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+pub const QUEUED_SUFFIX: &str = ".queued";
+/// the lease of the uploader draining the queue, in the backups directory
+pub const UPLOADER_LEASE: &str = "uploader.lease";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Queued {
+    pub kind: BackupKind,
+    pub enqueued: DateTime<Utc>,
+    /// as hashed while the archive was written, the uploader doesn't read it twice
+    pub tree_hash: String,
+    pub bytes: u64,
+}
+
+impl Queued {
+
+    /// Queues the archive at `archive_path`, the sidecar is replaced at once so an uploader never reads half of it.
+    pub fn write (&self, archive_path: &str) -> Result<(), anyhow::Error> {
+        let path = format!("{}{}", archive_path, QUEUED_SUFFIX);
+        let temporary = format!("{}.tmp", path);
+        fs::write (&temporary, serde_json::to_vec_pretty (self)?)?;
+        fs::rename (&temporary, &path)?;
+        Ok (())
+    }
+
+    /// `None` if the archive is not queued.
+    pub fn read (archive_path: &str) -> Result<Option<Self>, anyhow::Error> {
+        match fs::read (format!("{}{}", archive_path, QUEUED_SUFFIX)) {
+            Ok (content) => Ok (Some (serde_json::from_slice (&content)?)),
+            Err (err) if err.kind () == std::io::ErrorKind::NotFound => Ok (None),
+            Err (err) => Err (err.into ())
+        }
+    }
+
+    /// Takes the archive off the queue, once uploaded.
+    pub fn remove (archive_path: &str) -> Result<(), anyhow::Error> {
+        match fs::remove_file (format!("{}{}", archive_path, QUEUED_SUFFIX)) {
+            Err (err) if err.kind () != std::io::ErrorKind::NotFound => Err (err.into ()),
+            _ => Ok (())
+        }
+    }
+}
+
+/// Queued archives of every kind, in the order they were queued.
+pub fn pending (backups_directory: &str) -> Result<Vec<(String, Queued)>, anyhow::Error> {
+    let mut pending = Vec::new ();
+    for kind in BackupKind::ALL {
+        for (archive_path, _) in local_archives (backups_directory, *kind)? {
+            if let Some (queued) = Queued::read (&archive_path)? {
+                pending.push ((archive_path, queued));
+            }
+        }
+    }
+    pending.sort_by_key (|(_, queued)| queued.enqueued);
+    Ok (pending)
+}
+
+/// When the latest archive of `kind` still waiting to be uploaded was queued, a run needn't archive again before its interval elapsed since.
+pub fn latest (backups_directory: &str, kind: BackupKind) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    Ok (pending (backups_directory)?.into_iter ()
+        .filter (|(_, queued)| queued.kind == kind)
+        .map (|(_, queued)| queued.enqueued)
+        .max ())
+}
@@ -0,0 +1,124 @@
+// Machine readable output of the reporting subcommands, selected with `--output json`.
+// Every document is wrapped in an envelope naming the command and the `OUTPUT_VERSION` of its structure:
+// fields may be added within a version, any other change bumps it, so scripts never scrape the text tables.
+
+use crate::doctor::Check;
+use crate::retention::{Decision, Location};
+use crate::rto::Estimate;
+use crate::simulation::Simulation;
+use crate::versions::{self, VersionsAt};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+pub const OUTPUT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok (Format::Text),
+            "json" => Ok (Format::Json),
+            _ => Err (anyhow::anyhow!("Unknown output format: {}, expected one of text, json", s))
+        }
+    }
+}
+
+/// `data` in the versioned envelope, as printed.
+pub fn document (command: &str, data: Value) -> Result<String, anyhow::Error> {
+    Ok (serde_json::to_string_pretty (&json!({
+        "output_version": OUTPUT_VERSION,
+        "command": command,
+        "data": data,
+    }))?)
+}
+
+pub fn checks (checks: &[Check]) -> Value {
+    json!(checks.iter ()
+          .map (|check| {
+              let (ok, message) = match &check.result {
+                  Ok (message) => (true, message),
+                  Err (message) => (false, message)
+              };
+              json!({ "name": check.name, "ok": ok, "message": message })
+          })
+          .collect::<Vec<_>>())
+}
+
+pub fn decisions (decisions: &[Decision]) -> Value {
+    json!(decisions.iter ()
+          .map (|decision| json!({
+              "location": match decision.location { Location::Local => "local", Location::Glacier => "glacier" },
+              "action": if decision.keep { "keep" } else { "delete" },
+              "eligible": decision.eligible.map (|eligible| eligible.to_rfc3339 ()),
+              "archive": decision.archive,
+              "rule": decision.rule,
+          }))
+          .collect::<Vec<_>>())
+}
+
+pub fn estimates (estimates: &[Estimate]) -> Value {
+    json!(estimates.iter ()
+          .map (|estimate| json!({
+              "kind": estimate.kind,
+              "tier": estimate.tier,
+              "archive": estimate.archive,
+              "size": estimate.size,
+              "retrieval_seconds": estimate.retrieval.as_secs (),
+              "download_seconds": estimate.download.as_secs (),
+              "total_seconds": estimate.total ().as_secs (),
+              "warning": estimate.warning,
+          }))
+          .collect::<Vec<_>>())
+}
+
+pub fn simulation (simulation: &Simulation) -> Value {
+    json!({
+        "days": simulation.days,
+        "recorded_runs": simulation.recorded,
+        "extrapolated_runs": simulation.extrapolated,
+        "coverage": simulation.coverage.iter ()
+            .map (|(kind, coverage)| (kind.to_string (), json!({
+                "observed_days": coverage.observed_days,
+                "extrapolated_days": coverage.extrapolated_days,
+            })))
+            .collect::<serde_json::Map<_, _>>(),
+        "monthly_cost": simulation.monthly_cost,
+        "points": simulation.points.iter ()
+            .map (|point| json!({
+                "at": point.at.to_rfc3339 (),
+                "local_archives": point.local_archives,
+                "local_bytes": point.local_bytes,
+                "glacier_archives": point.glacier_archives,
+                "glacier_bytes": point.glacier_bytes,
+                "cost": point.cost,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Every backup with what changed since the previous one, or the one live at `at` (null if none).
+pub fn versions (history: &[VersionsAt], at: Option<DateTime<Utc>>) -> Value {
+    if let Some (at) = at {
+        return json!(history.iter ().rfind (|entry| entry.created <= at));
+    }
+    json!(history.iter ()
+          .enumerate ()
+          .map (|(index, entry)| json!({
+              "created": entry.created,
+              "archive": entry.archive,
+              "versions": entry.versions,
+              "changes": match index {
+                  0 => None,
+                  _ => Some (versions::changes (&history [index - 1].versions, &entry.versions))
+              },
+          }))
+          .collect::<Vec<_>>())
+}
@@ -0,0 +1,76 @@
+// Overlaps the phases of creating an archive: the compressed stream is handed over a bounded channel
+// to a thread that writes it to disk and tree-hashes it, so the finished archive never has to be re-read for hashing.
+// Uploading isn't overlapped: archives are verified before they leave the host, and the archive description, fixed when
+// an upload is initiated, carries the signature of the finished tree hash.
+
+use crate::tree_hash::{self, TreeHasher};
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// chunks of compressed data in flight, bounds the memory used when the disk is slower than the compressor
+const CHANNEL_CAPACITY: usize = 64;
+
+pub struct HashingWriter {
+    sender: SyncSender<Vec<u8>>,
+    worker: JoinHandle<io::Result<Written>>,
+}
+
+/// What reached the disk.
+pub struct Written {
+    pub tree_hash: String,
+    /// sha256 of every 1 MB chunk
+    pub leaf_hashes: Vec<Vec<u8>>,
+    pub bytes: u64,
+    /// time spent hashing, which overlapped with compressing
+    pub hashing: Duration,
+}
+
+impl HashingWriter {
+
+    pub fn create (path: &str) -> io::Result<Self> {
+        let mut file = File::create (path)?;
+        let (sender, receiver) = sync_channel::<Vec<u8>> (CHANNEL_CAPACITY);
+
+        let worker = thread::spawn (move || {
+            let mut hasher = TreeHasher::new ();
+            let mut bytes : u64 = 0;
+            let mut hashing = Duration::default ();
+            for chunk in receiver {
+                file.write_all (&chunk)?;
+                let started = Instant::now ();
+                hasher.update (&chunk);
+                hashing += started.elapsed ();
+                bytes += chunk.len () as u64;
+            }
+            file.sync_all ()?;
+            let (tree_hash, leaf_hashes) = hasher.finalize ();
+            Ok (Written { tree_hash: tree_hash::to_hex_string (&tree_hash), leaf_hashes, bytes, hashing })
+        });
+
+        Ok (HashingWriter { sender, worker })
+    }
+
+    /// Waits for everything written to reach the disk.
+    pub fn finish (self) -> Result<Written, anyhow::Error> {
+        let HashingWriter { sender, worker } = self;
+        drop (sender);
+        Ok (worker.join ().map_err (|_| anyhow::anyhow!("Archive writer thread panicked"))??)
+    }
+}
+
+impl Write for HashingWriter {
+
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        // the worker only hangs up on a write error, which `finish` reports
+        self.sender.send (buf.to_vec ())
+            .map_err (|_| io::Error::new (io::ErrorKind::BrokenPipe, "archive writer thread stopped"))?;
+        Ok (buf.len ())
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        Ok (())
+    }
+}
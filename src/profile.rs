@@ -0,0 +1,65 @@
+// Fine grained timings of a backup run, enabled with `--profile`.
+// Samples are written in the folded stacks format (`backup;archive;wp-content 1234`, in microseconds)
+// understood by flamegraph.pl and inferno, to compare runs across releases and hosts.
+
+use log::info;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const PROFILE_SUFFIX: &str = ".profile";
+
+struct Sample {
+    stack: String,
+    elapsed: Duration,
+    bytes: Option<u64>,
+}
+
+pub struct Profile {
+    enabled: bool,
+    samples: Mutex<Vec<Sample>>,
+}
+
+impl Profile {
+
+    pub fn new (enabled: bool) -> Self {
+        Profile { enabled, samples: Mutex::new (Vec::new ()) }
+    }
+
+    pub fn record (&self, stack: &str, elapsed: Duration, bytes: Option<u64>) {
+        if self.enabled {
+            self.samples.lock ().unwrap ().push (Sample { stack: String::from (stack), elapsed, bytes });
+        }
+    }
+
+    /// Times `f`, recorded under `stack`.
+    pub fn time<T> (&self, stack: &str, f: impl FnOnce () -> T) -> T {
+        let started = Instant::now ();
+        let result = f ();
+        self.record (stack, started.elapsed (), None);
+        result
+    }
+
+    /// Logs the samples and writes them in the folded stacks format to `path`.
+    pub fn report (&self, path: &str, total: Duration) -> Result<(), anyhow::Error> {
+        if !self.enabled {
+            return Ok (());
+        }
+
+        let samples = self.samples.lock ().unwrap ();
+        let mut folded = String::new ();
+        for sample in samples.iter () {
+            let seconds = sample.elapsed.as_secs_f64 ();
+            match sample.bytes {
+                Some (bytes) if seconds > 0.0 => info!("[profile] {}: {:.3}s, {} bytes, {:.2} MB/s",
+                                                       sample.stack, seconds, bytes, bytes as f64 / 1048576.0 / seconds),
+                _ => info!("[profile] {}: {:.3}s", sample.stack, seconds)
+            }
+            folded.push_str (&format!("{} {}\n", sample.stack, sample.elapsed.as_micros ()));
+        }
+
+        fs::write (path, folded)?;
+        info!("[profile] total: {:.3}s, written to {}", total.as_secs_f64 (), path);
+        Ok (())
+    }
+}
@@ -0,0 +1,309 @@
+// Vault settings kept as configured, like the vault itself is created when missing: its tags (`VAULT_TAGS=team=web,env=prod`),
+// the SNS topic notified of finished jobs (`VAULT_NOTIFICATION_TOPIC`, `VAULT_NOTIFICATION_EVENTS`) and its access policy
+// (`VAULT_ACCESS_POLICY=/config/vault-policy.json`). Only what is configured is managed: tags configured elsewhere stay.
+// Uploads put back a setting that drifted, `provision --check` just reports the drift. The vault of another account is never changed.
+
+use crate::doctor::Check;
+use crate::error::BackupError;
+use crate::AnyResult;
+use log::{info, warn};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{Client, Region, RusotoError};
+use rusoto_glacier::{AddTagsToVaultInput, CreateVaultInput, DescribeVaultError, DescribeVaultInput, Glacier, GlacierClient, ListTagsForVaultInput,
+                     SetVaultAccessPolicyInput, SetVaultNotificationsInput, VaultAccessPolicy, VaultNotificationConfig};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::fs;
+
+/// the jobs Glacier notifies of
+const EVENTS: &[&str] = &["ArchiveRetrievalCompleted", "InventoryRetrievalCompleted"];
+
+#[derive(Debug, Clone, Default)]
+pub struct Provisioning {
+    pub tags: BTreeMap<String, String>,
+    pub notifications: Option<Notifications>,
+    pub access_policy: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notifications {
+    pub topic: String,
+    pub events: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Setting {
+    Tags,
+    Notifications,
+    AccessPolicy,
+}
+
+impl Setting {
+    pub fn name (&self) -> &'static str {
+        match self {
+            Setting::Tags => "tags",
+            Setting::Notifications => "notifications",
+            Setting::AccessPolicy => "access policy",
+        }
+    }
+}
+
+/// A setting of the vault that is not as configured.
+#[derive(Debug, Clone)]
+pub struct Drift {
+    pub setting: Setting,
+    pub actual: String,
+    pub configured: String,
+}
+
+impl Provisioning {
+
+    pub fn is_empty (&self) -> bool {
+        self.tags.is_empty () && self.notifications.is_none () && self.access_policy.is_none ()
+    }
+
+    /// The settings managed, in the order they are checked.
+    pub fn settings (&self) -> Vec<Setting> {
+        let mut settings = Vec::new ();
+        if !self.tags.is_empty () {
+            settings.push (Setting::Tags);
+        }
+        if self.notifications.is_some () {
+            settings.push (Setting::Notifications);
+        }
+        if self.access_policy.is_some () {
+            settings.push (Setting::AccessPolicy);
+        }
+        settings
+    }
+}
+
+/// `key=value` pairs, comma separated.
+pub fn parse_tags (s: &str) -> AnyResult<BTreeMap<String, String>> {
+    s.split (',')
+        .map (str::trim)
+        .filter (|tag| !tag.is_empty ())
+        .map (|tag| match tag.split_once ('=') {
+            Some ((key, value)) if !key.trim ().is_empty () => Ok ((String::from (key.trim ()), String::from (value.trim ()))),
+            _ => Err (anyhow::anyhow!("Invalid vault tag {}, expected KEY=VALUE", tag))
+        })
+        .collect ()
+}
+
+/// Event names, comma separated.
+pub fn parse_events (s: &str) -> AnyResult<BTreeSet<String>> {
+    s.split (',')
+        .map (str::trim)
+        .filter (|event| !event.is_empty ())
+        .map (|event| match EVENTS.iter ().find (|known| known.eq_ignore_ascii_case (event)) {
+            Some (known) => Ok (String::from (*known)),
+            None => Err (anyhow::anyhow!("Unknown vault notification event {}, expected {}", event, EVENTS.join (" or ")))
+        })
+        .collect ()
+}
+
+pub fn read_policy (path: &str) -> AnyResult<Value> {
+    let content = fs::read (path).map_err (|err| anyhow::anyhow!("Could not read {}: {}", path, err))?;
+    serde_json::from_slice (&content).map_err (|err| anyhow::anyhow!("Invalid access policy {}: {}", path, err))
+}
+
+/// A setting of the vault as Glacier answers it, None if it has none. GetVaultNotifications and GetVaultAccessPolicy answer
+/// with the setting as the body, rusoto looks for it one level down and never finds it.
+async fn get (client: &Client, region: &Region, account_id: &str, vault_name: &str, setting: &str) -> AnyResult<Option<Value>> {
+    let mut request = SignedRequest::new ("GET", "glacier", region, &format!("/{}/vaults/{}/{}", account_id, vault_name, setting));
+    request.add_header ("x-amz-glacier-version", "2012-06-01");
+    let response = client.sign_and_dispatch (request).await
+        .map_err (|err| anyhow::anyhow!("{}", RusotoError::<Infallible>::from (err)))?
+        .buffer ().await?;
+    match response.status.as_u16 () {
+        404 => Ok (None),
+        status if response.status.is_success () => Ok (Some (serde_json::from_slice (&response.body)
+            .map_err (|err| anyhow::anyhow!("Unexpected {} of vault {}, {}: {}", setting, vault_name, status, err))?)),
+        _ => Err (anyhow::anyhow!("{}: {}", response.status, String::from_utf8_lossy (&response.body)))
+    }
+}
+
+/// How the vault differs from `provisioning`, in the settings it manages.
+pub async fn drift (client: &Client, region: &Region, account_id: &str, vault_name: &str, provisioning: &Provisioning) -> AnyResult<Vec<Drift>> {
+    let mut drifts = Vec::new ();
+
+    if !provisioning.tags.is_empty () {
+        let glacier = GlacierClient::new_with_client (client.clone (), region.clone ());
+        let actual = glacier.list_tags_for_vault (ListTagsForVaultInput {
+            account_id: String::from (account_id),
+            vault_name: String::from (vault_name),
+        }).await?.tags.unwrap_or_default ();
+        let differing = provisioning.tags.iter ()
+            .filter (|(key, value)| actual.get (*key) != Some (value))
+            .map (|(key, _)| key)
+            .collect::<Vec<_>>();
+        if !differing.is_empty () {
+            let describe = |tags: Vec<String>| if tags.is_empty () { String::from ("none") } else { tags.join (", ") };
+            drifts.push (Drift {
+                setting: Setting::Tags,
+                actual: describe (differing.iter ().filter_map (|key| actual.get (*key).map (|value| format!("{}={}", key, value))).collect ()),
+                configured: describe (differing.iter ().map (|key| format!("{}={}", key, provisioning.tags [*key])).collect ()),
+            });
+        }
+    }
+
+    if let Some (notifications) = &provisioning.notifications {
+        let actual = match get (client, region, account_id, vault_name, "notification-configuration").await? {
+            Some (config) => {
+                let config = serde_json::from_value::<VaultNotificationConfig>(config)?;
+                Some (Notifications {
+                    topic: config.sns_topic.unwrap_or_default (),
+                    events: config.events.unwrap_or_default ().into_iter ().collect (),
+                })
+            },
+            None => None
+        };
+        if actual.as_ref () != Some (notifications) {
+            let describe = |notifications: &Notifications| format!("{} on {}", notifications.topic,
+                                                                   notifications.events.iter ().cloned ().collect::<Vec<_>>().join (", "));
+            drifts.push (Drift {
+                setting: Setting::Notifications,
+                actual: actual.as_ref ().map_or (String::from ("none"), describe),
+                configured: describe (notifications),
+            });
+        }
+    }
+
+    if let Some (policy) = &provisioning.access_policy {
+        let actual = match get (client, region, account_id, vault_name, "access-policy").await? {
+            Some (policy) => serde_json::from_value::<VaultAccessPolicy>(policy)?.policy,
+            None => None
+        };
+        // compared as JSON, the formatting is not the vault's setting
+        let parsed = actual.as_deref ().and_then (|actual| serde_json::from_str::<Value>(actual).ok ());
+        if parsed.as_ref () != Some (policy) {
+            drifts.push (Drift {
+                setting: Setting::AccessPolicy,
+                actual: actual.unwrap_or_else (|| String::from ("none")),
+                configured: policy.to_string (),
+            });
+        }
+    }
+
+    Ok (drifts)
+}
+
+/// Puts back the settings that drifted from `provisioning`, returns what was changed.
+pub async fn apply (client: &Client, region: &Region, account_id: &str, vault_name: &str, provisioning: &Provisioning) -> AnyResult<Vec<Drift>> {
+    let drifts = drift (client, region, account_id, vault_name, provisioning).await?;
+    let client = GlacierClient::new_with_client (client.clone (), region.clone ());
+    for drift in &drifts {
+        match drift.setting {
+            Setting::Tags => {
+                client.add_tags_to_vault (AddTagsToVaultInput {
+                    account_id: String::from (account_id),
+                    tags: Some (provisioning.tags.clone ().into_iter ().collect ()),
+                    vault_name: String::from (vault_name),
+                }).await?;
+            },
+            Setting::Notifications => {
+                let notifications = provisioning.notifications.as_ref ().unwrap ();
+                client.set_vault_notifications (SetVaultNotificationsInput {
+                    account_id: String::from (account_id),
+                    vault_name: String::from (vault_name),
+                    vault_notification_config: Some (VaultNotificationConfig {
+                        events: Some (notifications.events.iter ().cloned ().collect ()),
+                        sns_topic: Some (notifications.topic.clone ()),
+                    }),
+                }).await?;
+            },
+            Setting::AccessPolicy => {
+                client.set_vault_access_policy (SetVaultAccessPolicyInput {
+                    account_id: String::from (account_id),
+                    policy: Some (VaultAccessPolicy { policy: provisioning.access_policy.as_ref ().map (Value::to_string) }),
+                    vault_name: String::from (vault_name),
+                }).await?;
+            },
+        }
+        info!("Provisioned the {} of vault {}: {}, was {}", drift.setting.name (), vault_name, drift.configured, drift.actual);
+    }
+    Ok (drifts)
+}
+
+/// Puts back the settings of the vault an upload goes to, or just warns about those of the vault of another account.
+pub async fn on_upload (client: &Client, region: &Region, account_id: Option<&str>, vault_name: &str, provisioning: &Provisioning) -> AnyResult<()> {
+    if provisioning.is_empty () {
+        return Ok (());
+    }
+    match account_id {
+        Some (account_id) => match drift (client, region, account_id, vault_name, provisioning).await {
+            Ok (drifts) => for drift in drifts {
+                warn!("The {} of vault {} of account {} are {}, configured {}", drift.setting.name (), vault_name, account_id, drift.actual, drift.configured);
+            },
+            // the owner's access policy need not let this account read them
+            Err (err) => warn!("Could not check the settings of vault {} of account {}: {}", vault_name, account_id, err)
+        },
+        None => {
+            apply (client, region, "-", vault_name, provisioning).await
+                .map_err (|err| BackupError::Config (format!("Could not provision glacier vault {}: {}", vault_name, err)))?;
+        }
+    }
+    Ok (())
+}
+
+/// Creates the vault if missing and puts back the settings that drifted, or with `check` just reports where the vault
+/// is not as configured. The vault of another account is only checked.
+pub async fn run (client: &Client, region: &Region, account_id: Option<&str>, vault_name: &str, provisioning: &Provisioning, check: bool) -> Vec<Check> {
+    let glacier = GlacierClient::new_with_client (client.clone (), region.clone ());
+    let mut checks = Vec::new ();
+    let check = check || account_id.is_some ();
+    let owner = account_id.map_or (String::new (), |account_id| format!(" of account {}", account_id));
+    let account_id = account_id.unwrap_or ("-");
+
+    let described = glacier.describe_vault (DescribeVaultInput {
+        account_id: String::from (account_id),
+        vault_name: String::from (vault_name),
+    }).await;
+    match described {
+        Ok (_) => checks.push (Check { name: "vault", result: Ok (format!("{}{} exists", vault_name, owner)) }),
+        Err (RusotoError::Service (DescribeVaultError::ResourceNotFound (_))) if check => {
+            checks.push (Check { name: "vault", result: Err (format!("{}{} does not exist", vault_name, owner)) });
+            return checks;
+        },
+        Err (RusotoError::Service (DescribeVaultError::ResourceNotFound (_))) => {
+            let created = glacier.create_vault (CreateVaultInput {
+                account_id: String::from (account_id),
+                vault_name: String::from (vault_name),
+            }).await;
+            match created {
+                Ok (_) => checks.push (Check { name: "vault", result: Ok (format!("{} created", vault_name)) }),
+                Err (err) => {
+                    checks.push (Check { name: "vault", result: Err (format!("creating {} failed: {}", vault_name, err)) });
+                    return checks;
+                }
+            }
+        },
+        Err (err) => {
+            checks.push (Check { name: "vault", result: Err (format!("describing {}{} failed: {}", vault_name, owner, err)) });
+            return checks;
+        }
+    }
+
+    let drifts = if check {
+        drift (client, region, account_id, vault_name, provisioning).await
+    } else {
+        apply (client, region, account_id, vault_name, provisioning).await
+    };
+    let drifts = match drifts {
+        Ok (drifts) => drifts,
+        Err (err) => {
+            checks.push (Check { name: "settings", result: Err (format!("reading or changing the settings of {} failed: {}", vault_name, err)) });
+            return checks;
+        }
+    };
+    for setting in provisioning.settings () {
+        let result = match drifts.iter ().find (|drift| drift.setting == setting) {
+            None => Ok (String::from ("as configured")),
+            Some (drift) if check => Err (format!("{}, configured {}", drift.actual, drift.configured)),
+            Some (drift) => Ok (format!("changed to {}, was {}", drift.configured, drift.actual)),
+        };
+        checks.push (Check { name: setting.name (), result });
+    }
+    checks
+}
@@ -0,0 +1,74 @@
+// Implausibly small archives, below `MIN_ARCHIVE_SIZE` (4K by default): the 0-byte or few-KB `.tar.gz` a crash leaves behind.
+// They are moved with their sidecars to `quarantine/` in the backups directory, where nothing counts them as backups:
+// they are never uploaded, restored as the latest or kept by the retention rules.
+
+use crate::kind::BackupKind;
+use crate::{archive_path, CollisionPolicy, SIDECAR_SUFFIXES};
+use log::warn;
+use std::fs;
+use std::path::Path;
+
+pub const QUARANTINE_DIRECTORY: &str = "quarantine";
+/// config archives are small by design, but always hold more than a manifest
+const CONFIG_FLOOR: u64 = 1024;
+
+/// The size below which an archive of `kind` is implausible.
+pub fn floor (kind: BackupKind, min_size: u64) -> u64 {
+    match kind {
+        BackupKind::Config => min_size.min (CONFIG_FLOOR),
+        _ => min_size
+    }
+}
+
+/// Archives in the backups directory smaller than `min_size` bytes, with their size.
+pub fn find (backups_directory: &str, min_size: u64) -> Result<Vec<(String, u64)>, anyhow::Error> {
+    let mut tiny = Vec::new ();
+    if min_size == 0 {
+        return Ok (tiny);
+    }
+    for entry in fs::read_dir (backups_directory)? {
+        let entry = entry?;
+        let name = entry.file_name ().to_string_lossy ().to_string ();
+        let metadata = entry.metadata ()?;
+        if !name.ends_with (".tar.gz") || !metadata.is_file () {
+            continue;
+        }
+        let kind = BackupKind::ALL.iter ().copied ().find (|kind| name.starts_with (&format!("{}_", kind.archive_root ())));
+        if metadata.len () >= kind.map_or (min_size, |kind| floor (kind, min_size)) {
+            continue;
+        }
+        tiny.push ((entry.path ().display ().to_string (), metadata.len ()));
+    }
+    tiny.sort ();
+    Ok (tiny)
+}
+
+/// Quarantines every archive smaller than `min_size` bytes, returns how many.
+pub fn sweep (backups_directory: &str, min_size: u64) -> Result<usize, anyhow::Error> {
+    let tiny = find (backups_directory, min_size)?;
+    for (archive, size) in &tiny {
+        let moved = isolate (backups_directory, archive)?;
+        warn!("Quarantined {} to {}: {} bytes is below MIN_ARCHIVE_SIZE, likely left by a crashed run", archive, moved, size);
+    }
+    Ok (tiny.len ())
+}
+
+/// Moves an archive and its sidecars to the quarantine directory, returns where the archive went.
+pub fn isolate (backups_directory: &str, archive: &str) -> Result<String, anyhow::Error> {
+    let directory = Path::new (backups_directory).join (QUARANTINE_DIRECTORY);
+    fs::create_dir_all (&directory)?;
+    let stem = Path::new (archive).file_name ()
+        .and_then (|name| name.to_string_lossy ().strip_suffix (".tar.gz").map (String::from))
+        .ok_or_else (|| anyhow::anyhow!("{} is not an archive", archive))?;
+    // an archive of the same name may have been quarantined before
+    let destination = archive_path (&directory.display ().to_string (), &stem, CollisionPolicy::Suffix)?;
+
+    for suffix in SIDECAR_SUFFIXES {
+        let sidecar = format!("{}{}", archive, suffix);
+        if Path::new (&sidecar).exists () {
+            fs::rename (&sidecar, format!("{}{}", destination, suffix))?;
+        }
+    }
+    fs::rename (archive, &destination)?;
+    Ok (destination)
+}
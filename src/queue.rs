@@ -0,0 +1,149 @@
+// Admission of backup jobs, configured as `BACKUP_CONCURRENCY=1` (one slot per schedule by default).
+// Scheduled and on-demand backups queue for a slot and are let in by priority, then in the order they queued;
+// two jobs writing the same archive or sql dump never run together. Running backups are never interrupted,
+// an upload can't be resumed, but low priority background work holds no slot and pauses while backups wait or run.
+
+use crate::kind::BackupKind;
+use log::info;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time;
+
+/// how often waiting jobs look again, in case they missed being notified
+const RECHECK: Duration = Duration::from_secs (1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Priority {
+    Scheduled,
+    OnDemand,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Job {
+    kind: BackupKind,
+    priority: Priority,
+    #[serde(skip)]
+    seq: u64,
+}
+
+struct Queue {
+    /// how many backups may run at once
+    limit: usize,
+    waiting: Vec<Job>,
+    running: Vec<Job>,
+    seq: u64,
+}
+
+lazy_static! {
+    static ref QUEUE: Mutex<Queue> = Mutex::new (Queue { limit: usize::MAX, waiting: Vec::new (), running: Vec::new (), seq: 0 });
+    static ref RELEASED: Notify = Notify::new ();
+}
+
+/// Leaves the queue when dropped, a job given up on while waiting doesn't hold back the others.
+struct Queued {
+    seq: u64,
+}
+
+impl Drop for Queued {
+    fn drop (&mut self) {
+        QUEUE.lock ().unwrap ().waiting.retain (|job| job.seq != self.seq);
+    }
+}
+
+/// Holds a slot until dropped.
+pub struct Ticket {
+    seq: u64,
+}
+
+impl Drop for Ticket {
+    fn drop (&mut self) {
+        QUEUE.lock ().unwrap ().running.retain (|job| job.seq != self.seq);
+        RELEASED.notify_waiters ();
+    }
+}
+
+impl Queue {
+    /// Lets `seq` in if it is the most urgent waiting job that can run now.
+    fn admit (&mut self, seq: u64) -> bool {
+        if self.running.len () >= self.limit {
+            return false;
+        }
+        let running = &self.running;
+        let next = self.waiting.iter ()
+            .filter (|job| !running.iter ().any (|other| conflicts (job.kind, other.kind)))
+            .max_by_key (|job| (job.priority, Reverse (job.seq)));
+        match next.and_then (|next| self.waiting.iter ().position (|job| job.seq == next.seq)) {
+            Some (position) if self.waiting [position].seq == seq => {
+                let job = self.waiting.remove (position);
+                self.running.push (job);
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+/// Archives and dumps of the same name: a kind with itself, the kinds dumping the whole database with each other.
+fn conflicts (a: BackupKind, b: BackupKind) -> bool {
+    a == b || (a.includes_database () && b.includes_database ())
+}
+
+pub fn configure (limit: usize) {
+    QUEUE.lock ().unwrap ().limit = limit.max (1);
+}
+
+/// Waits for a slot to back up `kind`.
+pub async fn admit (kind: BackupKind, priority: Priority) -> Ticket {
+    let queued = {
+        let mut queue = QUEUE.lock ().unwrap ();
+        queue.seq += 1;
+        let seq = queue.seq;
+        queue.waiting.push (Job { kind, priority, seq });
+        Queued { seq }
+    };
+
+    let mut waited = false;
+    loop {
+        if QUEUE.lock ().unwrap ().admit (queued.seq) {
+            if waited {
+                info!("The queued {} backup starts", kind);
+            }
+            return Ticket { seq: queued.seq };
+        }
+        if !waited {
+            info!("The {} backup is queued behind other backups", kind);
+            waited = true;
+        }
+        let _ = time::timeout (RECHECK, RELEASED.notified ()).await;
+    }
+}
+
+/// Whether any backup is waiting or running.
+pub fn busy () -> bool {
+    let queue = QUEUE.lock ().unwrap ();
+    !queue.waiting.is_empty () || !queue.running.is_empty ()
+}
+
+/// Blocks low priority work for as long as backups wait or run, call it where `what` is safe to pause.
+pub fn pause_while_busy (what: &str) {
+    if !busy () {
+        return;
+    }
+    info!("Pausing {} while backups run", what);
+    while busy () {
+        std::thread::sleep (RECHECK);
+    }
+    info!("Resuming {}", what);
+}
+
+/// The running and waiting jobs, for `/status`.
+pub fn snapshot () -> serde_json::Value {
+    let queue = QUEUE.lock ().unwrap ();
+    let mut waiting = queue.waiting.clone ();
+    waiting.sort_by_key (|job| (Reverse (job.priority), job.seq));
+    serde_json::json!({ "running": queue.running, "waiting": waiting })
+}
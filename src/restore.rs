@@ -0,0 +1,614 @@
+// Restores a local archive: the wordpress files into a target directory, the sql dump next to it,
+// optionally mapping file ownership onto the users and groups of the new host.
+// Alternatively just the database, loaded into a (new) database of choice, or only a listing of the entries.
+// An archive with a signature, or any archive once `SIGNING_PUBLIC_KEY` is set, is checked against it before anything is restored.
+// Extraction progress is saved next to the target, an interrupted restore is continued with `--resume`:
+// entries extracted before are checked by size and hash rather than written again.
+
+use crate::archive_tree_hash;
+use crate::grants;
+use crate::manifest::{Manifest, MANIFEST_NAME};
+use crate::sanitize;
+use crate::signature;
+use crate::symlinks::Symlink;
+use flate2::read::GzDecoder;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use regex::bytes::Regex;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// appended to the target's name for the progress file
+const PROGRESS_SUFFIX: &str = ".restore-progress";
+/// how often progress is saved, slow storage shouldn't be kept busy with it
+const SAVE_EVERY: Duration = Duration::from_secs (5);
+
+/// How extracted files get their owner and group.
+#[derive(Debug, Clone, Default)]
+pub struct Ownership {
+    /// keep the numeric ids recorded in the archive
+    pub preserve: bool,
+    /// owner and group for every extracted file, wins over everything else
+    pub owner: Option<(Option<u32>, Option<u32>)>,
+    /// numeric ids of the archive mapped onto ids of this host, e.g. 33 -> 82 for www-data
+    pub uid_map: HashMap<u32, u32>,
+    pub gid_map: HashMap<u32, u32>,
+}
+
+impl Ownership {
+
+    fn is_changed (&self) -> bool {
+        self.preserve || self.owner.is_some () || !self.uid_map.is_empty () || !self.gid_map.is_empty ()
+    }
+
+    /// The owner and group a file recorded with `uid` and `gid` gets, `None` leaves it to the restoring user.
+    fn resolve (&self, uid: u32, gid: u32) -> (Option<u32>, Option<u32>) {
+        if let Some ((owner, group)) = self.owner {
+            return (owner, group);
+        }
+        let mapped_uid = self.uid_map.get (&uid).copied ().or (Some (uid).filter (|_| self.preserve));
+        let mapped_gid = self.gid_map.get (&gid).copied ().or (Some (gid).filter (|_| self.preserve));
+        (mapped_uid, mapped_gid)
+    }
+}
+
+/// Parses `user[:group]`, both given by name or numerically.
+pub fn parse_owner (spec: &str) -> Result<(Option<u32>, Option<u32>), anyhow::Error> {
+    let mut parts = spec.splitn (2, ':');
+    let user = parts.next ().filter (|user| !user.is_empty ()).map (|user| lookup_id ("/etc/passwd", user)).transpose ()?;
+    let group = parts.next ().filter (|group| !group.is_empty ()).map (|group| lookup_id ("/etc/group", group)).transpose ()?;
+    Ok ((user, group))
+}
+
+/// Parses a `from:to` mapping of numeric ids.
+pub fn parse_mapping (spec: &str) -> Result<(u32, u32), anyhow::Error> {
+    let mut parts = spec.splitn (2, ':');
+    match (parts.next ().map (str::parse::<u32>), parts.next ().map (str::parse::<u32>)) {
+        (Some (Ok (from)), Some (Ok (to))) => Ok ((from, to)),
+        _ => Err (anyhow::anyhow!("Invalid id mapping {}, expected from:to, e.g. 33:82", spec))
+    }
+}
+
+fn lookup_id (database: &str, name: &str) -> Result<u32, anyhow::Error> {
+    if let Ok (id) = name.parse::<u32>() {
+        return Ok (id);
+    }
+    fs::read_to_string (database)?
+        .lines ()
+        .map (|line| line.split (':').collect::<Vec<_>>())
+        .find (|fields| fields.len () > 2 && fields [0] == name)
+        .and_then (|fields| fields [2].parse::<u32>().ok ())
+        .ok_or_else (|| anyhow::anyhow!("No {} found in {}", name, database))
+}
+
+pub struct Restored {
+    pub files: u64,
+    /// entries an interrupted restore extracted already
+    pub verified: u64,
+    pub sql_dump: Option<PathBuf>,
+}
+
+/// How far an extraction got.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Progress {
+    /// the canonical name and size of the archive being extracted
+    archive: String,
+    size: u64,
+    /// archive entries done, in order
+    entries: u64,
+}
+
+impl Progress {
+    fn path (target: &Path) -> PathBuf {
+        match target.file_name () {
+            Some (name) => target.with_file_name (format!("{}{}", name.to_string_lossy (), PROGRESS_SUFFIX)),
+            None => target.join (PROGRESS_SUFFIX)
+        }
+    }
+
+    fn read (target: &Path) -> Result<Option<Progress>, anyhow::Error> {
+        match fs::read (Progress::path (target)) {
+            Ok (content) => Ok (Some (serde_json::from_slice (&content)?)),
+            Err (err) if err.kind () == io::ErrorKind::NotFound => Ok (None),
+            Err (err) => Err (err.into ())
+        }
+    }
+
+    fn save (&self, target: &Path) -> Result<(), anyhow::Error> {
+        let path = Progress::path (target);
+        let temporary = format!("{}.tmp", path.display ());
+        fs::write (&temporary, serde_json::to_vec (self)?)?;
+        fs::rename (&temporary, &path)?;
+        Ok (())
+    }
+}
+
+/// Checks `archive_path` against its signature, if it has one or there is a `public_key` to check it with:
+/// its tree hash has to be the signed one, and with a `public_key` the signature has to be made with that key.
+pub fn check_signature (archive_path: &str, public_key: Option<&str>) -> Result<(), anyhow::Error> {
+    let signed = Path::new (&format!("{}{}", archive_path, signature::SIGNATURE_SUFFIX)).exists ();
+    match (signed, public_key) {
+        (false, None) => return Ok (()),
+        (false, Some (_)) => return Err (anyhow::anyhow!("{} has no signature to check against SIGNING_PUBLIC_KEY", archive_path)),
+        _ => ()
+    }
+    let sidecar = signature::read_sidecar (archive_path)?;
+    let hash = archive_tree_hash (archive_path)?;
+    match public_key {
+        Some (public_key) => {
+            signature::verify (&sidecar, &hash, public_key)?;
+            info!("Signature of {} is valid", archive_path);
+        },
+        None if sidecar.tree_hash != hash => return Err (anyhow::anyhow!("Tree hash {} of {} does not match the signed {}", hash, archive_path, sidecar.tree_hash)),
+        None => warn!("{} matches its signature by {}, set SIGNING_PUBLIC_KEY to check the signature itself", archive_path, sidecar.public_key)
+    }
+    Ok (())
+}
+
+/// Extracts `archive_path` into `target`, the sql dump (if the archive has one) is written to `dump_path`.
+/// With `recreate_symlinks` directories that were symlinks are linked again, their content extracted into the link targets.
+/// With `resume` an interrupted restore of the same archive into `target` is continued.
+pub fn restore (archive_path: &str,
+                target: &Path,
+                dump_path: &Path,
+                ownership: &Ownership,
+                recreate_symlinks: bool,
+                resume: bool)
+                -> Result<Restored, anyhow::Error> {
+    let manifest = Manifest::read_from_archive (archive_path)?;
+    let html_root = PathBuf::from (&manifest.wordpress_directory);
+    let mut archive = tar::Archive::new (GzDecoder::new (File::open (archive_path)?));
+    archive.set_preserve_permissions (true);
+
+    let mut progress = Progress { archive: manifest.name (), size: fs::metadata (archive_path)?.len (), entries: 0 };
+    let done = match (Progress::read (target)?, resume) {
+        (Some (previous), true) if previous.archive == progress.archive && previous.size == progress.size => {
+            info!("Resuming the restore of {} into {} after {} entries, checking them first", archive_path, target.display (), previous.entries);
+            previous.entries
+        },
+        (Some (previous), true) => return Err (anyhow::anyhow!("The interrupted restore into {} was of {}, not {}", target.display (), previous.archive, progress.archive)),
+        (None, true) => return Err (anyhow::anyhow!("There is no interrupted restore into {} to resume", target.display ())),
+        (Some (_), false) => {
+            warn!("Restoring into {} from the start, an interrupted restore can be continued with --resume", target.display ());
+            0
+        },
+        (None, false) => 0
+    };
+
+    fs::create_dir_all (target)?;
+    progress.save (target)?;
+    for symlink in &manifest.symlinks {
+        if recreate_symlinks {
+            let path = target.join (&symlink.path);
+            if resume && fs::read_link (&path).is_ok_and (|linked| linked == Path::new (&symlink.target)) {
+                continue;
+            }
+            link (target, symlink)?;
+        } else {
+            info!("{} was a symlink to {}, restoring it as a directory (see --recreate-symlinks)", symlink.path, symlink.target);
+        }
+    }
+    let mut restored = Restored { files: 0, verified: 0, sql_dump: None };
+    let mut ownership_failed = false;
+    let mut saved = Instant::now ();
+
+    for entry in archive.entries ()? {
+        if saved.elapsed () > SAVE_EVERY {
+            progress.save (target)?;
+            saved = Instant::now ();
+        }
+        progress.entries += 1;
+        let mut entry = entry?;
+        let path = entry.path ()?.to_path_buf ();
+
+        let destination = if path == Path::new (MANIFEST_NAME) {
+            continue;
+        } else if Some (path.display ().to_string ()) == manifest.schema_dump
+            || manifest.server_config.as_ref ().is_some_and (|server_config| path.starts_with (server_config)) {
+            // only ever loaded or put in place by hand, they'd clobber the data or another server's configuration
+            info!("Not restoring {}, extract it from the archive if needed", path.display ());
+            continue;
+        } else if Some (path.display ().to_string ()) == manifest.sql_dump {
+            restored.sql_dump = Some (dump_path.to_path_buf ());
+            dump_path.to_path_buf ()
+        } else {
+            // escaped names are restored as they were on the site
+            match sanitize::original (&path, &manifest.renamed).strip_prefix (&html_root) {
+                Ok (relative) if relative.components ().all (|component| matches!(component, Component::Normal (_))) => target.join (relative),
+                _ => {
+                    warn!("Skipping unexpected entry {}", path.display ());
+                    continue;
+                }
+            }
+        };
+
+        if destination == target {
+            continue;
+        }
+        if progress.entries <= done && is_extracted (&mut entry, &destination)? {
+            restored.verified += 1;
+        } else {
+            if let Some (parent) = destination.parent () {
+                fs::create_dir_all (parent)?;
+            }
+            entry.unpack (&destination)?;
+        }
+        restored.files += 1;
+
+        if ownership.is_changed () {
+            let (uid, gid) = ownership.resolve (entry.header ().uid ()? as u32, entry.header ().gid ()? as u32);
+            if let Err (err) = std::os::unix::fs::lchown (&destination, uid, gid) {
+                if !ownership_failed {
+                    warn!("Could not change ownership of {} (further failures are not logged): {}", destination.display (), err);
+                    ownership_failed = true;
+                }
+            }
+        }
+    }
+
+    fs::remove_file (Progress::path (target)).or_else (|err| if err.kind () == io::ErrorKind::NotFound { Ok (()) } else { Err (err) })?;
+    info!("Restored {} entries of {} into {}", restored.files, archive_path, target.display ());
+    Ok (restored)
+}
+
+/// Whether `entry` is at `destination` already: a directory, or a file of the same size and sha256.
+fn is_extracted<R: Read> (entry: &mut tar::Entry<R>, destination: &Path) -> Result<bool, anyhow::Error> {
+    let metadata = match fs::symlink_metadata (destination) {
+        Ok (metadata) => metadata,
+        Err (err) if err.kind () == io::ErrorKind::NotFound => return Ok (false),
+        Err (err) => return Err (err.into ())
+    };
+    let header = entry.header ();
+    if header.entry_type ().is_dir () {
+        return Ok (metadata.is_dir ());
+    }
+    if !header.entry_type ().is_file () || !metadata.is_file () || metadata.len () != header.size ()? {
+        return Ok (false);
+    }
+    let mut archived = Sha256::new ();
+    io::copy (entry, &mut archived)?;
+    let mut written = Sha256::new ();
+    io::copy (&mut File::open (destination)?, &mut written)?;
+    Ok (archived.finalize () == written.finalize ())
+}
+
+/// Links `symlink`'s path under `target` to its original target, extraction then goes through the link.
+/// A relative target is relative to the restored link, an absolute one is the very directory it pointed to.
+fn link (target: &Path, symlink: &Symlink) -> Result<(), anyhow::Error> {
+    let path = target.join (&symlink.path);
+    if fs::symlink_metadata (&path).is_ok () {
+        return Err (anyhow::anyhow!("Cannot link {} to {}, it exists already", path.display (), symlink.target));
+    }
+    let parent = path.parent ().unwrap_or (target);
+    fs::create_dir_all (parent)?;
+    fs::create_dir_all (parent.join (&symlink.target))?;
+    std::os::unix::fs::symlink (&symlink.target, &path)?;
+    info!("Linked {} to {}", path.display (), symlink.target);
+    Ok (())
+}
+
+/// The metadata of one archive entry, as recorded in its tar header.
+pub struct Listed {
+    pub path: PathBuf,
+    pub entry_type: tar::EntryType,
+    pub mode: u32,
+    pub uid: u64,
+    pub gid: u64,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+/// Reads the tar headers of `archive_path` without extracting anything, the manifest is left out.
+pub fn list (archive_path: &str) -> Result<Vec<Listed>, anyhow::Error> {
+    let mut archive = tar::Archive::new (GzDecoder::new (File::open (archive_path)?));
+    let mut listed = Vec::new ();
+    for entry in archive.entries ()? {
+        let entry = entry?;
+        let path = entry.path ()?.to_path_buf ();
+        if path == Path::new (MANIFEST_NAME) {
+            continue;
+        }
+        let header = entry.header ();
+        listed.push (Listed {
+            path,
+            entry_type: header.entry_type (),
+            mode: header.mode ()?,
+            uid: header.uid ()?,
+            gid: header.gid ()?,
+            size: header.size ()?,
+            mtime: header.mtime ()?,
+        });
+    }
+    Ok (listed)
+}
+
+impl Listed {
+
+    /// `ls -l` style permissions, e.g. `drwxr-xr-x`.
+    pub fn permissions (&self) -> String {
+        let kind = if self.entry_type.is_dir () {
+            'd'
+        } else if self.entry_type.is_symlink () {
+            'l'
+        } else {
+            '-'
+        };
+        let bits = "rwxrwxrwx".chars ().enumerate ()
+            .map (|(i, c)| if self.mode & (0o400 >> i) != 0 { c } else { '-' });
+        std::iter::once (kind).chain (bits).collect ()
+    }
+}
+
+/// Where a database dump gets loaded.
+#[derive(Debug, Clone)]
+pub struct MysqlTarget {
+    pub host: String,
+    pub port: String,
+    pub user: String,
+    pub password: String,
+    pub database: String,
+}
+
+lazy_static! {
+    // a php serialized string inside a mysqldump string literal: s:19:\"http://example.com/\";
+    // on bytes, dumps needn't be valid UTF-8
+    static ref SERIALIZED_RE: Regex = Regex::new (r#"(?-u)s:(\d+):\\"(.*?)\\";"#).unwrap ();
+}
+
+/// Loads just the sql dump of `archive_path` into `target.database`, creating it if needed.
+/// The dump's own `CREATE DATABASE` and `USE` statements are dropped so that it lands in the target database,
+/// `search_replace` rewrites e.g. the site url on the way (keeping php serialized values valid).
+pub fn restore_database (archive_path: &str, target: &MysqlTarget, search_replace: Option<(&str, &str)>) -> Result<(), anyhow::Error> {
+    let chain = dump_chain (archive_path)?;
+    run_mysql (target, None, Some (&format!("CREATE DATABASE IF NOT EXISTS `{}`", target.database.replace ('`', "``"))))?;
+    if chain.len () > 1 {
+        info!("{} holds a differential dump, loading the {} dumps it builds on first", archive_path, chain.len () - 1);
+    }
+    for (path, manifest) in &chain {
+        load_dump (path, manifest, target, search_replace)?;
+    }
+    Ok (())
+}
+
+/// Creates the users captured in `archive_path` that are missing on the target server and applies their grants,
+/// returns who they are. Grants on the archived database are moved to `target.database`.
+pub fn restore_grants (archive_path: &str, target: &MysqlTarget) -> Result<Vec<String>, anyhow::Error> {
+    let archived = Manifest::read_from_archive (archive_path)?.grants
+        .ok_or_else (|| anyhow::anyhow!("{} holds no grants, it was created without BACKUP_GRANTS", archive_path))?;
+    let mut archive = tar::Archive::new (GzDecoder::new (File::open (archive_path)?));
+    for entry in archive.entries ()? {
+        let mut entry = entry?;
+        if entry.path ()?.display ().to_string () != archived.entry {
+            continue;
+        }
+        let mut content = Vec::new ();
+        entry.read_to_end (&mut content)?;
+        grants::apply (&content, &archived.database, target)?;
+        info!("Applied the grants of {} from {}", archived.users.join (", "), archive_path);
+        return Ok (archived.users);
+    }
+    Err (anyhow::anyhow!("{} has no {} entry", archive_path, archived.entry))
+}
+
+/// The archives whose dumps make up the database as of `archive_path`, oldest first:
+/// the archive itself, preceded by the ones its differential dump builds on back to a full dump, all next to it.
+fn dump_chain (archive_path: &str) -> Result<Vec<(String, Manifest)>, anyhow::Error> {
+    let mut chain = vec! [(String::from (archive_path), Manifest::read_from_archive (archive_path)?)];
+    while let Some (differential) = &chain.last ().unwrap ().1.differential {
+        let previous = Path::new (archive_path).with_file_name (&differential.previous);
+        if !previous.exists () {
+            return Err (anyhow::anyhow!("{} holds a differential dump building on {}, which is not in {}: retrieve it from Glacier first",
+                                        chain.last ().unwrap ().0, differential.previous, previous.parent ().unwrap_or_else (|| Path::new (".")).display ()));
+        }
+        let previous = previous.display ().to_string ();
+        let manifest = Manifest::read_from_archive (&previous)?;
+        chain.push ((previous, manifest));
+    }
+    chain.reverse ();
+    Ok (chain)
+}
+
+fn load_dump (archive_path: &str, manifest: &Manifest, target: &MysqlTarget, search_replace: Option<(&str, &str)>) -> Result<(), anyhow::Error> {
+    let sql_dump = manifest.sql_dump.clone ()
+        .ok_or_else (|| anyhow::anyhow!("{} is a {} backup without a database dump", archive_path, manifest.kind))?;
+
+    let mut archive = tar::Archive::new (GzDecoder::new (File::open (archive_path)?));
+    for entry in archive.entries ()? {
+        let entry = entry?;
+        if entry.path ()?.display ().to_string () != sql_dump {
+            continue;
+        }
+
+        let mut mysql = mysql_command (target, Some (&target.database))
+            .stdin (Stdio::piped ())
+            .stderr (Stdio::piped ())
+            .spawn ()?;
+        // drained while the dump is written, a chatty mysql would block on a full pipe otherwise
+        let mut stderr = mysql.stderr.take ().unwrap ();
+        let errors = std::thread::spawn (move || {
+            let mut errors = Vec::new ();
+            stderr.read_to_end (&mut errors).map (|_| errors)
+        });
+        // the lines are passed on as they are, binary and latin1 column data included
+        let written = (|| -> io::Result<()> {
+            let mut stdin = BufWriter::new (mysql.stdin.take ().unwrap ());
+            for line in BufReader::new (entry).split (b'\n') {
+                let line = line?;
+                if line.starts_with (b"CREATE DATABASE ") || line.starts_with (b"USE `") {
+                    continue;
+                }
+                match search_replace {
+                    Some ((search, replace)) => stdin.write_all (&replace_preserving_serialized (&line, search.as_bytes (), replace.as_bytes ()))?,
+                    None => stdin.write_all (&line)?
+                }
+                stdin.write_all (b"\n")?;
+            }
+            stdin.flush ()
+        }) ();
+
+        let status = mysql.wait ()?;
+        let errors = errors.join ().map_err (|_| anyhow::anyhow!("Reading the errors of mysql panicked"))??;
+        if !status.success () {
+            return Err (anyhow::anyhow!("Loading {} into {} failed: {}", sql_dump, target.database, String::from_utf8_lossy (&errors).trim ()));
+        }
+        written?;
+        info!("Loaded {} from {} into database {}", sql_dump, archive_path, target.database);
+        return Ok (());
+    }
+
+    Err (anyhow::anyhow!("{} has no {} entry", archive_path, sql_dump))
+}
+
+/// Replaces `search` with `replace` in a line of a mysqldump,
+/// fixing up the lengths of php serialized strings whose content changes.
+pub fn replace_preserving_serialized (line: &[u8], search: &[u8], replace: &[u8]) -> Vec<u8> {
+    if find (line, search).is_none () {
+        return line.to_vec ();
+    }
+
+    let mut result = Vec::with_capacity (line.len ());
+    let mut last = 0;
+    for captures in SERIALIZED_RE.captures_iter (line) {
+        let whole = captures.get (0).unwrap ();
+        let content = &captures [2];
+        result.extend (replace_all (&line [last..whole.start ()], search, replace));
+        if find (content, search).is_some () {
+            let content = replace_all (content, search, replace);
+            result.extend (format!("s:{}:\\\"", unescaped_length (&content)).as_bytes ());
+            result.extend (&content);
+            result.extend (b"\\\";");
+        } else {
+            result.extend (whole.as_bytes ());
+        }
+        last = whole.end ();
+    }
+    result.extend (replace_all (&line [last..], search, replace));
+    result
+}
+
+/// Where `search` first occurs in `haystack`.
+fn find (haystack: &[u8], search: &[u8]) -> Option<usize> {
+    if search.is_empty () {
+        return None;
+    }
+    haystack.windows (search.len ()).position (|window| window == search)
+}
+
+fn replace_all (haystack: &[u8], search: &[u8], replace: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity (haystack.len ());
+    let mut rest = haystack;
+    while let Some (at) = find (rest, search) {
+        result.extend (&rest [..at]);
+        result.extend (replace);
+        rest = &rest [at + search.len ()..];
+    }
+    result.extend (rest);
+    result
+}
+
+/// Length in bytes of a mysqldump string literal once mysql has unescaped it.
+fn unescaped_length (escaped: &[u8]) -> usize {
+    let mut length = 0;
+    let mut bytes = escaped.iter ();
+    while let Some (byte) = bytes.next () {
+        if *byte == b'\\' {
+            bytes.next ();
+        }
+        length += 1;
+    }
+    length
+}
+
+pub fn mysql_command (target: &MysqlTarget, database: Option<&str>) -> Command {
+    let mut command = Command::new ("mysql");
+    command
+        .arg ("-h")
+        .arg (&target.host)
+        .arg ("--port")
+        .arg (&target.port)
+        .arg ("-u")
+        .arg (&target.user)
+        .arg (format!("-p{}", &target.password));
+    if let Some (database) = database {
+        command.arg (database);
+    }
+    command
+}
+
+fn run_mysql (target: &MysqlTarget, database: Option<&str>, statement: Option<&str>) -> Result<(), anyhow::Error> {
+    let mut command = mysql_command (target, database);
+    if let Some (statement) = statement {
+        command.arg ("-e").arg (statement);
+    }
+    let output = command.output ()?;
+    if output.status.success () {
+        Ok (())
+    } else {
+        Err (anyhow::anyhow!("mysql failed: {}", String::from_utf8_lossy (&output.stderr).trim ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replace (line: &str, search: &str, replace: &str) -> String {
+        String::from_utf8 (replace_preserving_serialized (line.as_bytes (), search.as_bytes (), replace.as_bytes ())).unwrap ()
+    }
+
+    #[test]
+    fn unescaped_length_counts_escapes_once () {
+        assert_eq!(unescaped_length (br#"say \"hi\""#), 8);
+        assert_eq!(unescaped_length (br#"C:\\wp"#), 5);
+        assert_eq!(unescaped_length (br#"a\nb\0"#), 4);
+        // bytes, as php counts them, not characters
+        assert_eq!(unescaped_length ("café".as_bytes ()), 5);
+        assert_eq!(unescaped_length ("日本".as_bytes ()), 6);
+    }
+
+    #[test]
+    fn replacing_fixes_up_the_length () {
+        assert_eq!(replace (r#"('siteurl','s:16:\"http://old.test\";')"#, "http://old.test", "https://www.new.example"),
+                   r#"('siteurl','s:23:\"https://www.new.example\";')"#);
+        assert_eq!(replace (r#"('siteurl','s:23:\"https://www.new.example\";')"#, "https://www.new.example", "http://old.test"),
+                   r#"('siteurl','s:15:\"http://old.test\";')"#);
+    }
+
+    #[test]
+    fn replacing_keeps_escaped_quotes_and_backslashes () {
+        // php `<a href="http://old.test">` and `C:\http://old.test`
+        assert_eq!(replace (r#"'s:26:\"<a href=\"http://old.test\">\";'"#, "http://old.test", "https://new.test"),
+                   r#"'s:27:\"<a href=\"https://new.test\">\";'"#);
+        assert_eq!(replace (r#"'s:18:\"C:\\http://old.test\";'"#, "http://old.test", "https://new.test"),
+                   r#"'s:19:\"C:\\https://new.test\";'"#);
+        // a string ending in a backslash
+        assert_eq!(replace (r#"'s:16:\"http://old.test\\\";'"#, "http://old.test", "https://new.test"),
+                   r#"'s:17:\"https://new.test\\\";'"#);
+    }
+
+    #[test]
+    fn replacing_counts_multibyte_characters_in_bytes () {
+        assert_eq!(replace (r#"'s:21:\"http://old.test/café\";'"#, "http://old.test", "https://nouveau.test"),
+                   r#"'s:26:\"https://nouveau.test/café\";'"#);
+        assert_eq!(replace (r#"'s:16:\"http://old.test/\";'"#, "old", "日本"),
+                   r#"'s:19:\"http://日本.test/\";'"#);
+    }
+
+    #[test]
+    fn replacing_handles_several_strings_on_a_line () {
+        assert_eq!(replace (r#"('a:2:{i:0;s:15:\"http://old.test\";i:1;s:4:\"keep\";}'),('http://old.test'),('s:18:\"http://old.test/wp\";')"#,
+                            "http://old.test", "https://new.test"),
+                   r#"('a:2:{i:0;s:16:\"https://new.test\";i:1;s:4:\"keep\";}'),('https://new.test'),('s:19:\"https://new.test/wp\";')"#);
+    }
+
+    #[test]
+    fn lines_without_the_search_are_left_alone () {
+        let line = r#"('s:3:\"abc\";','http://other.test')"#;
+        assert_eq!(replace (line, "http://old.test", "https://new.test"), line);
+    }
+}
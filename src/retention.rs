@@ -0,0 +1,157 @@
+// Retention decisions: which archives are kept and which are deleted, by which rule and from when on.
+// The same plan drives pruning and `prune --explain`, so what is explained is what gets deleted.
+
+use crate::kind::BackupKind;
+use crate::upload_record::UploadRecord;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    Local,
+    Glacier,
+}
+
+#[derive(Debug, Clone)]
+pub struct Decision {
+    pub archive: String,
+    pub location: Location,
+    pub keep: bool,
+    /// the rule deciding, in words
+    pub rule: String,
+    /// from when on the archive is deleted, None if never
+    pub eligible: Option<DateTime<Utc>>,
+}
+
+/// Local archives are kept for the rolling period of their kind, counted in days from the date in their name.
+/// Archives being restored are kept regardless, until the restore is done or `restore_grace` after it started
+/// (a restore that crashed never reports being done).
+pub fn plan_local (kind: BackupKind,
+                   archives: &[(String, DateTime<Utc>)],
+                   today: DateTime<Utc>,
+                   rolling_period: u32,
+                   restores: &BTreeMap<String, DateTime<Utc>>,
+                   restore_grace: std::time::Duration)
+                   -> Vec<Decision> {
+    let restore_grace = Duration::from_std (restore_grace).unwrap_or_else (|_| Duration::max_value ());
+    archives.iter ()
+        .map (|(archive, date)| {
+            let age = (today - *date).num_days ();
+            let file_name = Path::new (archive).file_name ().map (|name| name.to_string_lossy ().to_string ()).unwrap_or_default ();
+            if let Some (started) = restores.get (&file_name).filter (|started| today < **started + restore_grace) {
+                return Decision {
+                    archive: archive.clone (),
+                    location: Location::Local,
+                    keep: true,
+                    rule: format!("a restore of it is in progress since {}, kept until it completes", started.format ("%Y-%m-%d %H:%M")),
+                    eligible: Some ((*date + Duration::days (rolling_period as i64)).max (*started + restore_grace)),
+                };
+            }
+            Decision {
+                archive: archive.clone (),
+                location: Location::Local,
+                keep: age < rolling_period as i64,
+                rule: format!("{} archives are kept locally for {} days, this one is {} days old", kind, rolling_period, age),
+                eligible: Some (*date + Duration::days (rolling_period as i64)),
+            }
+        })
+        .collect ()
+}
+
+/// Archives below the size floor are moved out of the way before any other rule looks at them.
+pub fn plan_quarantine (archive: &str, size: u64, today: DateTime<Utc>) -> Decision {
+    Decision {
+        archive: String::from (archive),
+        location: Location::Local,
+        keep: false,
+        rule: format!("{} bytes is below MIN_ARCHIVE_SIZE, moved to {}/ rather than counted as a backup", size, crate::quarantine::QUARANTINE_DIRECTORY),
+        eligible: Some (today),
+    }
+}
+
+/// Nothing ever deletes archives from the vault, only local archives known to be uploaded are accounted for.
+pub fn plan_glacier (archive: &str, record: &UploadRecord) -> Decision {
+    Decision {
+        archive: format!("{} ({})", record.archive_id, archive),
+        location: Location::Glacier,
+        keep: true,
+        rule: format!("Glacier archives are kept indefinitely, this one is in vault {} since {}", record.vault_name, record.uploaded.format ("%Y-%m-%d")),
+        eligible: None,
+    }
+}
+
+pub fn explain (decisions: &[Decision]) -> String {
+    let mut lines = vec! [format!("{:<8} {:<7} {:<11}  {:<60}  {}", "LOCATION", "ACTION", "ELIGIBLE", "ARCHIVE", "RULE")];
+    for decision in decisions {
+        lines.push (format!("{:<8} {:<7} {:<11}  {:<60}  {}",
+                            match decision.location { Location::Local => "local", Location::Glacier => "glacier" },
+                            if decision.keep { "keep" } else { "delete" },
+                            decision.eligible.map (|eligible| eligible.format ("%Y-%m-%d").to_string ()).unwrap_or_else (|| String::from ("never")),
+                            decision.archive,
+                            decision.rule));
+    }
+    lines.join ("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn day (d: u32) -> DateTime<Utc> {
+        Utc.ymd (2021, 3, d).and_hms (12, 0, 0)
+    }
+
+    #[test]
+    fn local_archives_are_kept_for_the_rolling_period () {
+        let archives = vec! [(String::from ("backups/wordpress_backup_2021-03-01.tar.gz"), day (1)),
+                             (String::from ("backups/wordpress_backup_2021-03-03.tar.gz"), day (3)),
+                             (String::from ("backups/wordpress_backup_2021-03-08.tar.gz"), day (8))];
+        let decisions = plan_local (BackupKind::Full, &archives, day (10), 7, &BTreeMap::new (), std::time::Duration::from_secs (3600));
+
+        assert_eq!(decisions.iter ().map (|decision| decision.keep).collect::<Vec<_>>(), vec! [false, false, true]);
+        assert_eq!(decisions.iter ().map (|decision| decision.eligible).collect::<Vec<_>>(), vec! [Some (day (8)), Some (day (10)), Some (day (15))]);
+        assert!(decisions.iter ().all (|decision| decision.location == Location::Local));
+        assert_eq!(decisions [1].rule, "full archives are kept locally for 7 days, this one is 7 days old");
+    }
+
+    #[test]
+    fn glacier_archives_are_never_deleted () {
+        let record = UploadRecord {
+            region: String::from ("us-east-2"),
+            vault_name: String::from ("vault"),
+            account_id: None,
+            archive_id: String::from ("id"),
+            location: None,
+            tree_hash: String::new (),
+            size: 1,
+            uploaded: day (1),
+            name: None,
+        };
+        let decision = plan_glacier ("wordpress_backup_2021-03-01.tar.gz", &record);
+
+        assert!(decision.keep);
+        assert_eq!(decision.eligible, None);
+        assert_eq!(decision.archive, "id (wordpress_backup_2021-03-01.tar.gz)");
+    }
+
+    #[test]
+    fn archives_being_restored_are_kept_until_the_grace_runs_out () {
+        let archives = vec! [(String::from ("backups/wordpress_backup_2021-03-01.tar.gz"), day (1)),
+                             (String::from ("backups/wordpress_backup_2021-03-02.tar.gz"), day (2))];
+        let mut restores = BTreeMap::new ();
+        restores.insert (String::from ("wordpress_backup_2021-03-01.tar.gz"), day (9));
+        restores.insert (String::from ("wordpress_backup_2021-03-02.tar.gz"), day (5));
+        let grace = std::time::Duration::from_secs (2 * 86400);
+        let decisions = plan_local (BackupKind::Full, &archives, day (10), 7, &restores, grace);
+
+        // still within the grace, eligible once it runs out
+        assert!(decisions [0].keep);
+        assert_eq!(decisions [0].eligible, Some (day (11)));
+        assert!(decisions [0].rule.starts_with ("a restore of it is in progress since 2021-03-09"));
+        // a restore started past the grace no longer protects the archive
+        assert!(!decisions [1].keep);
+        assert_eq!(decisions [1].eligible, Some (day (9)));
+    }
+}
@@ -0,0 +1,102 @@
+// Glacier data retrieval policies: an account wide (per region) cap on how fast archives can be retrieved,
+// which a restore plan has to live with.
+
+use rusoto_glacier::{DataRetrievalPolicy, DataRetrievalRule, GetDataRetrievalPolicyInput, Glacier, GlacierClient, SetDataRetrievalPolicyInput};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// the free tier allowance is 10 GB a month, prorated daily
+pub const FREE_TIER_DAILY_BYTES: u64 = 10 * 1024 * 1024 * 1024 / 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// retrievals exceeding the free tier are rejected
+    FreeTier,
+    /// retrievals exceeding the rate are rejected
+    MaxRetrievalRate { bytes_per_hour: u64 },
+    /// no limits, everything retrieved is paid for
+    None,
+}
+
+impl fmt::Display for Policy {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Policy::FreeTier => write!(f, "free tier only"),
+            Policy::MaxRetrievalRate { bytes_per_hour } => write!(f, "at most {} bytes per hour", bytes_per_hour),
+            Policy::None => write!(f, "no retrieval limit"),
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    /// `free-tier`, `none` or `max-retrieval-rate:<bytes per hour>`
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        let lowercase = s.to_lowercase ();
+        match lowercase.split_once (':') {
+            Some (("max-retrieval-rate", rate)) => Ok (Policy::MaxRetrievalRate { bytes_per_hour: rate.parse::<u64>()? }),
+            None if lowercase == "free-tier" => Ok (Policy::FreeTier),
+            None if lowercase == "none" => Ok (Policy::None),
+            _ => Err (anyhow::anyhow!("Unknown data retrieval policy: {}, expected one of free-tier, max-retrieval-rate:<bytes per hour>, none", s))
+        }
+    }
+}
+
+/// The policy of the account owning the vaults, `-` for the account of the credentials.
+pub async fn get (client: &GlacierClient, account_id: Option<&str>) -> Result<Policy, anyhow::Error> {
+    let output = client.get_data_retrieval_policy (GetDataRetrievalPolicyInput {
+        account_id: String::from (account_id.unwrap_or ("-")),
+    }).await?;
+
+    let rule = output.policy
+        .and_then (|policy| policy.rules)
+        .and_then (|rules| rules.into_iter ().next ())
+        .unwrap_or_default ();
+    match rule.strategy.as_deref () {
+        Some ("FreeTier") => Ok (Policy::FreeTier),
+        Some ("BytesPerHour") => Ok (Policy::MaxRetrievalRate { bytes_per_hour: rule.bytes_per_hour.unwrap_or_default () as u64 }),
+        Some ("None") | None => Ok (Policy::None),
+        Some (strategy) => Err (anyhow::anyhow!("Unknown data retrieval strategy {}", strategy))
+    }
+}
+
+pub async fn set (client: &GlacierClient, account_id: Option<&str>, policy: Policy) -> Result<(), anyhow::Error> {
+    let rule = match policy {
+        Policy::FreeTier => DataRetrievalRule { strategy: Some (String::from ("FreeTier")), bytes_per_hour: None },
+        Policy::MaxRetrievalRate { bytes_per_hour } => DataRetrievalRule { strategy: Some (String::from ("BytesPerHour")), bytes_per_hour: Some (bytes_per_hour as i64) },
+        Policy::None => DataRetrievalRule { strategy: Some (String::from ("None")), bytes_per_hour: None },
+    };
+
+    client.set_data_retrieval_policy (SetDataRetrievalPolicyInput {
+        account_id: String::from (account_id.unwrap_or ("-")),
+        policy: Some (DataRetrievalPolicy { rules: Some (vec! [rule]) }),
+    }).await?;
+    Ok (())
+}
+
+/// How long the policy stretches the retrieval of `size` bytes, and a warning if it may get rejected outright.
+/// Policies only govern Standard retrievals.
+pub fn apply (policy: Policy, tier: &str, size: u64, retrieval: Duration) -> (Duration, Option<String>) {
+    if tier != "Standard" {
+        return (retrieval, None);
+    }
+
+    match policy {
+        Policy::FreeTier if size > FREE_TIER_DAILY_BYTES =>
+            (retrieval, Some (format!("exceeds the daily free tier allowance of {} bytes, the retrieval will be rejected under the free tier policy", FREE_TIER_DAILY_BYTES))),
+        Policy::MaxRetrievalRate { bytes_per_hour: 0 } =>
+            (retrieval, Some (String::from ("the retrieval rate is capped at 0 bytes per hour, the retrieval will be rejected"))),
+        Policy::MaxRetrievalRate { bytes_per_hour } => {
+            let capped = Duration::from_secs_f64 (size as f64 / bytes_per_hour as f64 * 3600.0);
+            if capped > retrieval {
+                (capped, Some (format!("exceeds the maximum retrieval rate of {} bytes per hour, retrieve it in ranges spread over {}",
+                                       bytes_per_hour, crate::rto::human_duration (capped))))
+            } else {
+                (retrieval, None)
+            }
+        },
+        _ => (retrieval, None)
+    }
+}
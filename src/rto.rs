@@ -0,0 +1,84 @@
+// Restore time objective estimates: how long getting the latest archive of every kind back out of Glacier takes, per retrieval tier.
+// Retrieval latencies are the upper bounds documented by AWS, the download speed is configured
+// (there is no history of past retrievals to learn it from).
+// The account's data retrieval policy, when known, can stretch or rule out a retrieval.
+
+use crate::describe::human_size;
+use crate::retrieval::{self, Policy};
+use std::time::Duration;
+
+/// retrieval tiers with the documented worst case time until the archive is ready for download
+const TIERS: &[(&str, Duration)] = &[
+    ("Expedited", Duration::from_secs (5 * 60)),
+    ("Standard", Duration::from_secs (5 * 3600)),
+    ("Bulk", Duration::from_secs (12 * 3600)),
+];
+
+pub struct Estimate {
+    pub kind: String,
+    pub archive: String,
+    pub size: u64,
+    pub tier: &'static str,
+    pub retrieval: Duration,
+    pub download: Duration,
+    pub warning: Option<String>,
+}
+
+impl Estimate {
+    pub fn total (&self) -> Duration {
+        self.retrieval + self.download
+    }
+}
+
+/// `download_speed` in MB/s.
+pub fn estimate (kind: &str, archive: &str, size: u64, download_speed: f64, policy: Option<Policy>) -> Vec<Estimate> {
+    let download = Duration::from_secs_f64 (size as f64 / 1048576.0 / download_speed);
+    TIERS.iter ()
+        .map (|(tier, retrieval)| {
+            let (retrieval, warning) = match policy {
+                Some (policy) => retrieval::apply (policy, tier, size, *retrieval),
+                None => (*retrieval, None)
+            };
+            Estimate {
+                kind: String::from (kind),
+                archive: String::from (archive),
+                size,
+                tier,
+                retrieval,
+                download,
+                warning,
+            }
+        })
+        .collect ()
+}
+
+pub fn report (estimates: &[Estimate]) -> String {
+    let mut lines = vec! [format!("{:<8} {:<10} {:>10} {:>10} {:>10} {:>10}  {}", "KIND", "TIER", "SIZE", "RETRIEVAL", "DOWNLOAD", "TOTAL", "ARCHIVE")];
+    for estimate in estimates {
+        lines.push (format!("{:<8} {:<10} {:>10} {:>10} {:>10} {:>10}  {}",
+                            estimate.kind,
+                            estimate.tier,
+                            human_size (estimate.size),
+                            human_duration (estimate.retrieval),
+                            human_duration (estimate.download),
+                            human_duration (estimate.total ()),
+                            estimate.archive));
+    }
+    for estimate in estimates {
+        if let Some (warning) = &estimate.warning {
+            lines.push (format!("WARNING: {} retrieval of {} {}", estimate.tier, estimate.archive, warning));
+        }
+    }
+    lines.join ("\n")
+}
+
+pub fn human_duration (duration: Duration) -> String {
+    let seconds = duration.as_secs ();
+    if seconds >= 3600 {
+        format!("{}h {:02}m", seconds / 3600, (seconds % 3600) / 60)
+    } else if seconds >= 60 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
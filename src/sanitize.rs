@@ -0,0 +1,221 @@
+// File names escaped on their way into an archive, configured as `FILENAME_SANITIZATION=portable`.
+// Uploads named by browsers and plugins may contain newlines, which break listings, or characters other
+// platforms can't create. Offending bytes are written as `%XX` (and a literal `%XX` as `%25XX`, so escaping
+// is reversible); the escaped entries are mapped to their original path in the manifest, restores put them back.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// names Windows reserves for devices, with any extension
+const RESERVED: &[&str] = &["CON", "PRN", "AUX", "NUL",
+                            "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+                            "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// names are archived as they are
+    Off,
+    /// control characters and bytes that aren't utf-8 are escaped
+    Control,
+    /// additionally what Windows and macOS can't create: `<>:"\|?*`, trailing dots and spaces, device names
+    Portable,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase ().as_str () {
+            "off" => Ok (Mode::Off),
+            "control" => Ok (Mode::Control),
+            "portable" => Ok (Mode::Portable),
+            _ => Err (anyhow::anyhow!("Unknown filename sanitization: {}, expected one of off, control, portable", s))
+        }
+    }
+}
+
+/// Names the entries of one archive, collecting the ones it escaped. Clones share what they collected.
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    mode: Mode,
+    /// escaped entry path to original path
+    renamed: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl Sanitizer {
+    pub fn new (mode: Mode) -> Self {
+        Sanitizer { mode, renamed: Arc::new (Mutex::new (BTreeMap::new ())) }
+    }
+
+    /// The entry path of `name` in the directory archived as `parent`.
+    pub fn child (&self, parent: &Path, name: &OsStr) -> PathBuf {
+        match escape (name.as_bytes (), self.mode) {
+            Some (escaped) => {
+                let path = parent.join (OsStr::from_bytes (&escaped));
+                let mut renamed = self.renamed.lock ().unwrap ();
+                // the parent is named first, escaped too if it had to be
+                let original = original (parent, &renamed).join (name);
+                renamed.insert (path.display ().to_string (), original.display ().to_string ());
+                path
+            },
+            None => parent.join (name)
+        }
+    }
+
+    /// The entry path of `relative` under `destination`, one name at a time.
+    pub fn path (&self, destination: &Path, relative: &Path) -> PathBuf {
+        relative.components ().fold (destination.to_path_buf (), |path, component| match component {
+            Component::Normal (name) => self.child (&path, name),
+            other => path.join (other)
+        })
+    }
+
+    pub fn renamed (&self) -> BTreeMap<String, String> {
+        self.renamed.lock ().unwrap ().clone ()
+    }
+}
+
+/// `name` with the bytes `mode` doesn't allow as `%XX`, None if it's fine as it is.
+fn escape (name: &[u8], mode: Mode) -> Option<Vec<u8>> {
+    if mode == Mode::Off {
+        return None;
+    }
+    let mut escaped = Vec::with_capacity (name.len ());
+    let mut rest = name;
+    while !rest.is_empty () {
+        let (valid, invalid) = match std::str::from_utf8 (rest) {
+            Ok (valid) => (valid, 0),
+            Err (err) => (std::str::from_utf8 (&rest [..err.valid_up_to ()]).unwrap (),
+                          err.error_len ().unwrap_or (rest.len () - err.valid_up_to ()))
+        };
+        for (index, c) in valid.char_indices () {
+            let literal_escape = c == '%' && is_escape (&valid.as_bytes () [index..]);
+            let mut buffer = [0; 4];
+            let bytes = c.encode_utf8 (&mut buffer).as_bytes ();
+            if literal_escape || c.is_control () || (mode == Mode::Portable && "<>:\"\\|?*".contains (c)) {
+                bytes.iter ().for_each (|byte| push_escaped (&mut escaped, *byte));
+            } else {
+                escaped.extend_from_slice (bytes);
+            }
+        }
+        rest [valid.len ()..valid.len () + invalid].iter ().for_each (|byte| push_escaped (&mut escaped, *byte));
+        rest = &rest [valid.len () + invalid..];
+    }
+
+    if mode == Mode::Portable {
+        // Windows drops them, `a.` and `a` would be the same file
+        if let Some (last) = escaped.last ().copied ().filter (|last| *last == b'.' || *last == b' ') {
+            escaped.pop ();
+            push_escaped (&mut escaped, last);
+        }
+        let stem = escaped.split (|byte| *byte == b'.').next ().unwrap_or_default ();
+        if RESERVED.iter ().any (|reserved| stem.eq_ignore_ascii_case (reserved.as_bytes ())) {
+            let first = escaped.remove (0);
+            let mut prefixed = Vec::with_capacity (escaped.len () + 3);
+            push_escaped (&mut prefixed, first);
+            prefixed.extend (escaped);
+            escaped = prefixed;
+        }
+    }
+
+    Some (escaped).filter (|escaped| escaped.as_slice () != name)
+}
+
+fn push_escaped (escaped: &mut Vec<u8>, byte: u8) {
+    escaped.extend_from_slice (format!("%{:02X}", byte).as_bytes ());
+}
+
+/// Whether `s` starts with `%XX`.
+fn is_escape (s: &[u8]) -> bool {
+    s.len () >= 3 && s [0] == b'%' && s [1].is_ascii_hexdigit () && s [2].is_ascii_hexdigit ()
+}
+
+/// Reverses `escape`.
+fn unescape (name: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity (name.len ());
+    let mut index = 0;
+    while index < name.len () {
+        if is_escape (&name [index..]) {
+            let hex = std::str::from_utf8 (&name [index + 1..index + 3]).unwrap ();
+            unescaped.push (u8::from_str_radix (hex, 16).unwrap ());
+            index += 3;
+        } else {
+            unescaped.push (name [index]);
+            index += 1;
+        }
+    }
+    unescaped
+}
+
+/// The original path of the entry at `path`, given the escaped entries of its archive.
+pub fn original (path: &Path, renamed: &BTreeMap<String, String>) -> PathBuf {
+    if renamed.is_empty () {
+        return path.to_path_buf ();
+    }
+    let mut archived = PathBuf::new ();
+    let mut original = PathBuf::new ();
+    for component in path.components () {
+        archived.push (component);
+        match component {
+            // the mapping is for reading, names that aren't utf-8 only come back exactly unescaped
+            Component::Normal (name) if renamed.contains_key (&archived.display ().to_string ()) =>
+                original.push (OsString::from_vec (unescape (name.as_bytes ()))),
+            other => original.push (other)
+        }
+    }
+    original
+}
+
+/// `path` on a single line: control characters as escape sequences, e.g. a newline as `\n`.
+pub fn listed (path: &Path) -> String {
+    path.to_string_lossy ().chars ()
+        .map (|c| if c.is_control () { c.escape_default ().to_string () } else { c.to_string () })
+        .collect ()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip (name: &[u8], mode: Mode) -> Vec<u8> {
+        let escaped = escape (name, mode).expect ("escaped");
+        assert!(escaped.iter ().all (|byte| !byte.is_ascii_control ()), "{:?}", escaped);
+        assert_eq!(unescape (&escaped), name);
+        escaped
+    }
+
+    #[test]
+    fn escaping_is_reversible () {
+        assert_eq!(round_trip (b"line\nbreak.jpg", Mode::Control), b"line%0Abreak.jpg");
+        assert_eq!(round_trip (b"nul\0.txt", Mode::Control), b"nul%00.txt");
+        assert_eq!(round_trip (b"\"quoted\".txt", Mode::Portable), b"%22quoted%22.txt");
+        assert_eq!(round_trip (b"back\\slash.txt", Mode::Portable), b"back%5Cslash.txt");
+        assert_eq!(round_trip (b"100%41\n", Mode::Control), b"100%2541%0A");
+        assert_eq!(round_trip (b"latin\xe9.txt", Mode::Control), b"latin%E9.txt");
+        assert_eq!(round_trip (b"trailing. ", Mode::Portable), b"trailing.%20");
+        assert_eq!(round_trip (b"con.txt", Mode::Portable), b"%63on.txt");
+    }
+
+    #[test]
+    fn names_without_offending_bytes_are_kept () {
+        for name in [&b"caf\xc3\xa9 100%.jpg"[..], b"\"quoted\" back\\slash", b"100%zz"] {
+            assert_eq!(escape (name, Mode::Control), None);
+        }
+        assert_eq!(escape (b"line\nbreak", Mode::Off), None);
+        assert_eq!(escape ("caf\u{e9} 100%.jpg".as_bytes (), Mode::Portable), None);
+    }
+
+    #[test]
+    fn originals_come_back_from_the_manifest () {
+        let sanitizer = Sanitizer::new (Mode::Portable);
+        let archived = sanitizer.path (Path::new ("wordpress"), Path::new ("wp-content/uploads/a\nb:c/d\0\".txt"));
+        assert_eq!(archived, Path::new ("wordpress/wp-content/uploads/a%0Ab%3Ac/d%00%22.txt"));
+        assert_eq!(original (&archived, &sanitizer.renamed ()), Path::new ("wordpress/wp-content/uploads/a\nb:c/d\0\".txt"));
+        let untouched = Path::new ("wordpress/wp-content/uploads/plain.txt");
+        assert_eq!(original (untouched, &sanitizer.renamed ()).as_os_str ().as_bytes (), untouched.as_os_str ().as_bytes ());
+    }
+}
@@ -0,0 +1,206 @@
+// When backups run: on their intervals as a daemon or once per invocation of an external scheduler,
+// around blackout periods
+
+use crate::kind::{BackupKind, Schedule};
+use crate::queue::{self, Priority};
+use crate::{attest, blackout, cloudwatch, create_backup, notify, outbox, secrets, state, sts, upload, upload_queued, AnyResult, Config, Created};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use rusoto_core::credential::AwsCredentials;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use tokio::time;
+
+/// how many schedules are looping, for the liveness probe
+static RUNNING: AtomicUsize = AtomicUsize::new (0);
+
+struct Running;
+
+impl Running {
+    fn start () -> Running {
+        RUNNING.fetch_add (1, Ordering::SeqCst);
+        Running
+    }
+}
+
+impl Drop for Running {
+    fn drop (&mut self) {
+        RUNNING.fetch_sub (1, Ordering::SeqCst);
+    }
+}
+
+pub fn running () -> usize {
+    RUNNING.load (Ordering::SeqCst)
+}
+
+pub async fn run_schedule (config: Config, schedule: Schedule) -> AnyResult<()> {
+    let _running = Running::start ();
+    let period = schedule.interval.expect ("the daemon always has an interval");
+    let mut interval = time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Some (ends) = scheduled_backup (&config, &schedule, false).await? {
+            // back up right after the blackout, then on the usual interval again
+            time::sleep ((ends - Utc::now ()).to_std ().unwrap_or_default ()).await;
+            interval = time::interval(period);
+        }
+    }
+}
+
+/// Backs up unless a blackout is in effect, skipped and limited runs are recorded in the run history.
+/// Returns when the blackout that skipped the backup ends, db-only blackouts keep the interval ticking.
+/// `once` runs back up the database at most once per interval during a db-only blackout.
+async fn scheduled_backup (config: &Config, schedule: &Schedule, once: bool) -> AnyResult<Option<DateTime<Utc>>> {
+    let kind = schedule.kind;
+    if state::load (&config.backups_directory)?.seeding.is_some_and (|seed| seed.kind == kind) {
+        if config.enqueue {
+            info!("The uploader is seeding the initial {} backup, not backing up again", kind);
+            return Ok (None);
+        }
+        info!("Seeding the initial {} backup, resuming it instead of backing up again", kind);
+        upload::upload_pending (&*sts::scoped (config, kind).await?, kind).await?;
+        return Ok (None);
+    }
+    let blackout = match blackout::active (&config.blackouts, Utc::now ()) {
+        Some (blackout) => blackout,
+        None => {
+            backup (config, schedule, Priority::Scheduled).await?;
+            return Ok (None);
+        }
+    };
+
+    if blackout.mode == blackout::BlackoutMode::DbOnly && kind.includes_database () {
+        // the full backup never succeeds during the blackout, so `once` runs are always due
+        let state = state::load (&config.backups_directory)?;
+        let recent = once && match (schedule.interval, state.last_success.get (&BackupKind::Database)) {
+            (Some (interval), Some (last_success)) => (Utc::now () - *last_success).to_std ().unwrap_or_default () < interval,
+            _ => false
+        };
+        if recent {
+            info!("Blackout until {}, the database was backed up less than an interval ago, skipping the {} backup", blackout.ends (), kind);
+            state::update (&config.backups_directory, |state| state.record (kind, "skipped, blackout"))?;
+        } else {
+            info!("Blackout until {}, backing up just the database instead of a {} backup", blackout.ends (), kind);
+            state::update (&config.backups_directory, |state| state.record (kind, "database only, blackout"))?;
+            backup (config, &Schedule { kind: BackupKind::Database, ..schedule.clone () }, Priority::Scheduled).await?;
+        }
+        return Ok (None);
+    }
+
+    info!("Blackout until {}, skipping the {} backup", blackout.ends (), kind);
+    state::update (&config.backups_directory, |state| state.record (kind, "skipped, blackout"))?;
+    Ok (Some (blackout.ends ()))
+}
+
+/// Backs up `kind` right away, ahead of any queued scheduled backup and regardless of blackouts,
+/// with the rolling period of its schedule (or the full backups' one).
+pub async fn on_demand (config: &Config, kind: BackupKind) -> AnyResult<()> {
+    let schedule = config.schedules.iter ()
+        .find (|schedule| schedule.kind == kind)
+        .unwrap_or (&config.schedules [0]);
+    info!("On-demand {} backup requested", kind);
+    backup (config, &Schedule { kind, ..schedule.clone () }, Priority::OnDemand).await
+}
+
+/// Backs up once admitted by the queue.
+async fn backup (config: &Config, schedule: &Schedule, priority: Priority) -> AnyResult<()> {
+    let _ticket = queue::admit (schedule.kind, priority).await;
+    let (started, started_at) = (Instant::now (), Utc::now ());
+    let (result, credentials) = match scoped (config, schedule.kind).await {
+        Ok (scoped) => (create_backup (&scoped, schedule).await, scoped.credentials),
+        Err (err) => (Err (err), None)
+    };
+    publish (config, credentials.as_ref (), schedule.kind, started, started_at, result).await
+}
+
+/// Records the outcome of a run, publishing it to CloudWatch, EventBridge, SNS and SQS as configured with the `credentials` of the run.
+/// A run that just queued its archive publishes nothing, the upload is what succeeds or fails.
+async fn publish (config: &Config, credentials: Option<&AwsCredentials>, kind: BackupKind, started: Instant, started_at: DateTime<Utc>, result: AnyResult<Created>) -> AnyResult<()> {
+    let run = cloudwatch::Run {
+        site: &config.site,
+        kind,
+        duration: started.elapsed (),
+        result: result.as_ref ().map (|created| created.bytes),
+        suspicious: result.as_ref ().ok ().and_then (|created| created.suspicious.as_deref ()),
+        attestation: None,
+    };
+    let queued = result.as_ref ().is_ok_and (|created| created.queued);
+    let outcome = match &result {
+        Ok (created) if created.queued => "queued",
+        Ok (created) if !created.uploaded => "seeding",
+        _ => run.outcome (),
+    };
+    let attestation = attest::record (config, kind, outcome, started_at,
+                                      result.as_ref ().ok ().map (|created| (created.archive.as_str (), created.tree_hash.as_str ())));
+    let run = cloudwatch::Run { attestation: attestation.as_deref (), ..run };
+    if let Err (err) = &result {
+        error!("{} backup failed with {}", kind, err);
+        secrets::invalidate ();
+        state::update (&config.backups_directory, |state| state.record_failure (kind, err))?;
+    }
+    if queued {
+        return Ok (());
+    }
+    if let Some (cloudwatch) = &config.cloudwatch {
+        cloudwatch::publish (cloudwatch, credentials, &run).await;
+    }
+    if let Some (notifications) = &config.notifications {
+        notify::publish (notifications, credentials, &config.locale, &run).await;
+    }
+    result.map (|_| ())
+}
+
+/// `config` with the current secrets, and credentials scoped to a `kind` run.
+async fn scoped (config: &Config, kind: BackupKind) -> AnyResult<Config> {
+    let current = secrets::current (config).await?;
+    Ok (sts::scoped (&current, kind).await?.into_owned ())
+}
+
+/// A single pass over the schedules for external schedulers: uploads whatever earlier runs left un-uploaded,
+/// then backs up every kind whose interval elapsed since its last success (catching up on missed runs).
+/// An archive still queued for the uploader counts as the last success.
+pub async fn run_once (config: &Config) -> AnyResult<()> {
+    for schedule in &config.schedules {
+        if !config.enqueue {
+            upload::upload_pending (&*sts::scoped (config, schedule.kind).await?, schedule.kind).await?;
+        }
+        let state = state::load (&config.backups_directory)?;
+        if !config.enqueue && state.seeding.is_some_and (|seed| seed.kind == schedule.kind) {
+            // resumed above, the next run goes on with it
+            continue;
+        }
+
+        let last = state.last_success.get (&schedule.kind).copied ().max (outbox::latest (&config.backups_directory, schedule.kind)?);
+        match (schedule.interval, last) {
+            (Some (interval), Some (last)) if (Utc::now () - last).to_std ().unwrap_or_default () < interval =>
+                info!("{} backup is not due yet, last one succeeded or was queued {}", schedule.kind, last),
+            _ => {
+                scheduled_backup (config, schedule, true).await?;
+            }
+        }
+    }
+    Ok (())
+}
+
+/// Uploads the archives `--enqueue` runs queued, oldest first, and returns once none is left.
+/// Stops at the first failure, the archive stays queued for the next drain.
+pub async fn drain (config: &Config) -> AnyResult<()> {
+    let pending = outbox::pending (&config.backups_directory)?;
+    if pending.is_empty () {
+        info!("No archive is queued for upload");
+    }
+    for (archive_path, queued) in pending {
+        let (started, started_at) = (Instant::now (), Utc::now ());
+        let (result, credentials) = match scoped (config, queued.kind).await {
+            Ok (scoped) => (upload_queued (&scoped, &archive_path, &queued).await, scoped.credentials),
+            Err (err) => (Err (err), None)
+        };
+        let seeding = result.as_ref ().is_ok_and (|created| !created.uploaded);
+        publish (config, credentials.as_ref (), queued.kind, started, started_at, result).await?;
+        if seeding {
+            // a later drain goes on with it, the archives queued after it wait
+            return Ok (());
+        }
+    }
+    Ok (())
+}
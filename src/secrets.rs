@@ -0,0 +1,306 @@
+// Secrets read from a secret store rather than the environment: `MYSQL_PASSWORD_REF`, `STANDBY_MYSQL_PASSWORD_REF`, `SIGNING_KEY_REF`,
+// `ADMIN_TOKEN_REF` and `ADMIN_BASIC_AUTH_REF` hold a reference such as `vault:secret/data/shop#db_password`, `secretsmanager:shop/db#password`,
+// `ssm:/shop/db-password` or `file:/run/secrets/db_password` instead of the value, the variables without `_REF` are always taken literally. Values are cached for `SECRETS_CACHE_TTL` (or a shorter Vault lease)
+// and every backup takes the current ones, so a rotated secret is picked up once the cache lapses, or right after a failed run.
+
+use crate::signature::SigningKey;
+use crate::{aws, AnyResult, Config};
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use log::info;
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::Region;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Clone)]
+pub struct Providers {
+    pub vault_address: Option<String>,
+    pub vault_token: Option<String>,
+    pub secretsmanager_region: Region,
+    pub ssm_region: Region,
+    /// how long a value is used before it is read again
+    pub ttl: Duration,
+}
+
+impl fmt::Debug for Providers {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct ("Providers")
+            .field ("vault_address", &self.vault_address)
+            .field ("vault_token", &self.vault_token.as_ref ().map (|_| "<redacted>"))
+            .field ("secretsmanager_region", &self.secretsmanager_region)
+            .field ("ssm_region", &self.ssm_region)
+            .field ("ttl", &self.ttl)
+            .finish ()
+    }
+}
+
+/// Where a secret is kept.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reference {
+    /// a field of a KV secret, version 1 or 2
+    Vault { path: String, field: String },
+    /// a secret string, or a key of the JSON object it holds
+    SecretsManager { id: String, key: Option<String> },
+    /// a (secure string) parameter
+    Ssm (String),
+    /// a file, e.g. a docker or kubernetes secret, without its trailing newline
+    File (String),
+}
+
+impl Reference {
+    /// The reference `value` holds, any other value is an error.
+    pub fn parse (value: &str) -> AnyResult<Reference> {
+        let (provider, rest) = value.split_once (':').unwrap_or ((value, ""));
+        Ok (match provider {
+            "vault" => match rest.rsplit_once ('#') {
+                Some ((path, field)) if !path.is_empty () && !field.is_empty () =>
+                    Reference::Vault { path: String::from (path), field: String::from (field) },
+                _ => return Err (anyhow::anyhow!("Invalid secret reference {}, expected vault:PATH#FIELD", value))
+            },
+            "secretsmanager" => match rest.rsplit_once ('#') {
+                Some ((id, key)) => Reference::SecretsManager { id: String::from (id), key: Some (String::from (key)) },
+                None => Reference::SecretsManager { id: String::from (rest), key: None }
+            },
+            "ssm" if !rest.is_empty () => Reference::Ssm (String::from (rest)),
+            "file" if !rest.is_empty () => Reference::File (String::from (rest)),
+            _ => return Err (anyhow::anyhow!("Invalid secret reference {}, expected one of vault:PATH#FIELD, secretsmanager:ID[#KEY], ssm:NAME, file:PATH",
+                                             value))
+        })
+    }
+}
+
+/// A secret given in the environment, or kept in a store.
+#[derive(Clone, PartialEq)]
+pub enum Secret {
+    Value (String),
+    Stored (Reference),
+}
+
+impl Secret {
+    /// The reference of a stored secret.
+    pub fn reference (&self) -> Option<&Reference> {
+        match self {
+            Secret::Value (_) => None,
+            Secret::Stored (reference) => Some (reference)
+        }
+    }
+
+    /// The value given in the environment, empty for a stored secret until it is read.
+    pub fn value (&self) -> String {
+        match self {
+            Secret::Value (value) => value.clone (),
+            Secret::Stored (_) => String::new ()
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Secret::Value (_) => write!(f, "<redacted>"),
+            Secret::Stored (reference) => write!(f, "{}", reference)
+        }
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reference::Vault { path, field } => write!(f, "vault:{}#{}", path, field),
+            Reference::SecretsManager { id, key: Some (key) } => write!(f, "secretsmanager:{}#{}", id, key),
+            Reference::SecretsManager { id, key: None } => write!(f, "secretsmanager:{}", id),
+            Reference::Ssm (name) => write!(f, "ssm:{}", name),
+            Reference::File (path) => write!(f, "file:{}", path),
+        }
+    }
+}
+
+/// The secrets of the config read from a store, the config holds their values as of the last time they were read.
+#[derive(Debug, Clone)]
+pub struct Secrets {
+    pub providers: Providers,
+    pub mysql_password: Option<Reference>,
+    pub standby_mysql_password: Option<Reference>,
+    /// the store holds the hex encoded key rather than the path of a key file
+    pub signing_key: Option<Reference>,
+}
+
+impl Secrets {
+    fn is_empty (&self) -> bool {
+        self.mysql_password.is_none () && self.standby_mysql_password.is_none () && self.signing_key.is_none ()
+    }
+}
+
+struct Cached {
+    value: String,
+    expires: Instant,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<HashMap<String, Cached>> = Mutex::new (HashMap::new ());
+}
+
+/// `config` with the current values of its secrets, or as it is if none are kept in a store.
+pub async fn current (config: &Config) -> AnyResult<Cow<'_, Config>> {
+    let secrets = &config.secrets;
+    if secrets.is_empty () {
+        return Ok (Cow::Borrowed (config));
+    }
+    let mut current = config.clone ();
+    if let Some (reference) = &secrets.mysql_password {
+        current.mysql_password = get (&secrets.providers, reference).await?;
+    }
+    if let (Some (reference), Some (mysql)) = (&secrets.standby_mysql_password, &mut current.standby.mysql) {
+        mysql.password = get (&secrets.providers, reference).await?;
+    }
+    if let Some (reference) = &secrets.signing_key {
+        current.signing_key = Some (SigningKey::Hex (get (&secrets.providers, reference).await?));
+    }
+    Ok (Cow::Owned (current))
+}
+
+/// The value of `secret`, read from its store if it is kept in one.
+pub async fn resolve (providers: &Providers, secret: &Secret) -> AnyResult<String> {
+    match secret {
+        Secret::Value (value) => Ok (value.clone ()),
+        Secret::Stored (reference) => get (providers, reference).await
+    }
+}
+
+/// Reads every secret again next time, e.g. after a run failed on one rotated since.
+pub fn invalidate () {
+    let now = Instant::now ();
+    CACHE.lock ().unwrap ().values_mut ().for_each (|cached| cached.expires = now);
+}
+
+async fn get (providers: &Providers, reference: &Reference) -> AnyResult<String> {
+    let key = reference.to_string ();
+    if let Some (cached) = CACHE.lock ().unwrap ().get (&key).filter (|cached| cached.expires > Instant::now ()) {
+        return Ok (cached.value.clone ());
+    }
+
+    let (value, lease) = fetch (providers, reference).await
+        .map_err (|err| anyhow::anyhow!("Could not read secret {}: {}", reference, err))?;
+    let ttl = lease.map_or (providers.ttl, |lease| lease.min (providers.ttl));
+    let previous = CACHE.lock ().unwrap ().insert (key, Cached { value: value.clone (), expires: Instant::now () + ttl });
+    match previous {
+        Some (previous) if previous.value != value => info!("Secret {} was rotated, using its new value", reference),
+        None => info!("Read secret {}", reference),
+        _ => ()
+    }
+    Ok (value)
+}
+
+/// The value of the secret, and how long it may be used if the store says.
+async fn fetch (providers: &Providers, reference: &Reference) -> AnyResult<(String, Option<Duration>)> {
+    match reference {
+        Reference::Vault { path, field } => vault (providers, path, field).await,
+        Reference::SecretsManager { id, key } => {
+            let response = aws_json (&providers.secretsmanager_region, "secretsmanager", "secretsmanager.GetSecretValue", json!({ "SecretId": id })).await?;
+            let secret = response ["SecretString"].as_str ()
+                .ok_or_else (|| anyhow::anyhow!("No SecretString, binary secrets are not supported"))?;
+            match key {
+                Some (key) => Ok ((field (&serde_json::from_str (secret)?, key)?, None)),
+                None => Ok ((String::from (secret), None))
+            }
+        },
+        Reference::Ssm (name) => {
+            let response = aws_json (&providers.ssm_region, "ssm", "AmazonSSM.GetParameter", json!({ "Name": name, "WithDecryption": true })).await?;
+            let value = response ["Parameter"]["Value"].as_str ()
+                .ok_or_else (|| anyhow::anyhow!("No value in the SSM response"))?;
+            Ok ((String::from (value), None))
+        },
+        Reference::File (path) => Ok ((fs::read_to_string (path)?.trim_end_matches (&['\r', '\n'][..]).to_string (), None))
+    }
+}
+
+async fn vault (providers: &Providers, path: &str, name: &str) -> AnyResult<(String, Option<Duration>)> {
+    let address = providers.vault_address.as_deref ().ok_or_else (|| anyhow::anyhow!("VAULT_ADDR is not set"))?;
+    let token = providers.vault_token.as_deref ().ok_or_else (|| anyhow::anyhow!("VAULT_TOKEN is not set"))?;
+    let client = Client::builder ().build::<_, Body>(HttpsConnector::new ());
+    let request = Request::get (format!("{}/v1/{}", address.trim_end_matches ('/'), path.trim_start_matches ('/')))
+        .header ("X-Vault-Token", token)
+        .body (Body::empty ())?;
+
+    let response = client.request (request).await?;
+    let status = response.status ();
+    let body = hyper::body::to_bytes (response.into_body ()).await?;
+    if !status.is_success () {
+        return Err (anyhow::anyhow!("Vault responded with {}: {}", status, String::from_utf8_lossy (&body).trim ()));
+    }
+    let secret : Value = serde_json::from_slice (&body)?;
+    // version 2 of the KV engine nests the secret's data next to its metadata
+    let data = if secret ["data"]["metadata"].is_object () { &secret ["data"]["data"] } else { &secret ["data"] };
+    let lease = secret ["lease_duration"].as_u64 ().filter (|lease| *lease > 0).map (Duration::from_secs);
+    Ok ((field (data, name)?, lease))
+}
+
+/// Calls `target` of an AWS JSON API.
+async fn aws_json (region: &Region, service: &str, target: &str, body: Value) -> AnyResult<Value> {
+    let mut request = SignedRequest::new ("POST", service, region, "/");
+    request.add_header ("x-amz-target", target);
+    request.set_content_type (String::from ("application/x-amz-json-1.1"));
+    request.set_payload (Some (serde_json::to_vec (&body)?));
+    Ok (serde_json::from_slice (&aws::dispatch (None, request).await?.body)?)
+}
+
+/// The `name` field of a JSON object, as text.
+fn field (object: &Value, name: &str) -> AnyResult<String> {
+    match &object [name] {
+        Value::Null => Err (anyhow::anyhow!("No field {} in the secret", name)),
+        Value::String (value) => Ok (value.clone ()),
+        value => Ok (value.to_string ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references () {
+        assert_eq!(Reference::parse ("vault:secret/data/shop#db_password").unwrap (),
+                   Reference::Vault { path: String::from ("secret/data/shop"), field: String::from ("db_password") });
+        assert_eq!(Reference::parse ("vault:secret/data/a#b#c").unwrap (),
+                   Reference::Vault { path: String::from ("secret/data/a#b"), field: String::from ("c") });
+        assert_eq!(Reference::parse ("secretsmanager:shop/db#password").unwrap (),
+                   Reference::SecretsManager { id: String::from ("shop/db"), key: Some (String::from ("password")) });
+        assert_eq!(Reference::parse ("secretsmanager:arn:aws:secretsmanager:eu-west-1:123456789012:secret:db").unwrap (),
+                   Reference::SecretsManager { id: String::from ("arn:aws:secretsmanager:eu-west-1:123456789012:secret:db"), key: None });
+        assert_eq!(Reference::parse ("ssm:/shop/db-password").unwrap (), Reference::Ssm (String::from ("/shop/db-password")));
+        assert_eq!(Reference::parse ("file:/run/secrets/db").unwrap (), Reference::File (String::from ("/run/secrets/db")));
+    }
+
+    #[test]
+    fn references_display_as_parsed () {
+        for reference in ["vault:secret/data/shop#db_password", "secretsmanager:shop/db#password", "secretsmanager:shop/db",
+                          "ssm:/shop/db-password", "file:/run/secrets/db"] {
+            assert_eq!(Reference::parse (reference).unwrap ().to_string (), reference);
+        }
+    }
+
+    #[test]
+    fn invalid_references () {
+        for reference in ["vault:secret/data/shop", "vault:#field", "vault:path#", "ssm:", "file:", "Xy#1", "vault", "https://vault/x#y", ""] {
+            assert!(Reference::parse (reference).is_err (), "{}", reference);
+        }
+    }
+
+    #[test]
+    fn only_stored_secrets_have_a_reference () {
+        // what a literal password may look like, taken as it is
+        let literal = Secret::Value (String::from ("vault:Xy#1"));
+        assert_eq!(literal.reference (), None);
+        assert_eq!(literal.value (), "vault:Xy#1");
+        assert_eq!(format!("{:?}", literal), "<redacted>");
+        let stored = Secret::Stored (Reference::Ssm (String::from ("/shop/db-password")));
+        assert_eq!(stored.reference (), Some (&Reference::Ssm (String::from ("/shop/db-password"))));
+        assert_eq!(stored.value (), "");
+    }
+}
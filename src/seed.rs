@@ -0,0 +1,303 @@
+// Seeding: the first full backup of a huge site over a slow uplink, uploaded in parts within a daily byte budget
+// and a time window, across as many sessions (and restarts) as it takes. Progress is kept in the state.
+
+use crate::journal::{Journal, Part};
+use crate::kind::BackupKind;
+use crate::rto::human_duration;
+use crate::state;
+use crate::tree_hash::{to_hex_string, TreeHasher, ONE_MB};
+use crate::{aws, upload, AnyResult, Config};
+use bytes::Bytes;
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, NaiveTime, Utc};
+use log::{info, warn};
+use rusoto_glacier::{ArchiveCreationOutput, CompleteMultipartUploadInput, Glacier, GlacierClient, InitiateMultipartUploadInput, UploadMultipartPartError,
+                     UploadMultipartPartInput};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Instant;
+use tokio::time;
+
+/// How seeding uploads, configured with `SEED_DAILY_BUDGET`, `SEED_WINDOW` and `SEED_PART_SIZE`.
+#[derive(Debug, Clone)]
+pub struct Seeding {
+    /// bytes uploaded per (UTC) day at most
+    pub daily_budget: Option<u64>,
+    /// (UTC) time of day uploading starts and stops, may wrap around midnight
+    pub window: Option<(NaiveTime, NaiveTime)>,
+    /// a power of two megabytes, as Glacier requires
+    pub part_size: u64,
+}
+
+/// A seeding upload in progress, persisted after every part.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Seed {
+    pub kind: BackupKind,
+    pub archive: String,
+    pub tree_hash: String,
+    pub upload_id: String,
+    pub part_size: u64,
+    /// bytes of the archive in Glacier
+    pub uploaded: u64,
+    pub day: NaiveDate,
+    pub uploaded_today: u64,
+}
+
+/// Sizes such as `512M` or `20G`, binary units.
+pub fn parse_size (s: &str) -> Result<u64, anyhow::Error> {
+    let s = s.trim ();
+    let (value, multiplier) = match s.chars ().last ().map (|c| c.to_ascii_uppercase ()) {
+        Some ('K') => (&s [..s.len () - 1], 1 << 10),
+        Some ('M') => (&s [..s.len () - 1], 1 << 20),
+        Some ('G') => (&s [..s.len () - 1], 1 << 30),
+        Some ('T') => (&s [..s.len () - 1], 1 << 40),
+        _ => (s, 1)
+    };
+    Ok (value.trim ().parse::<u64>()? * multiplier)
+}
+
+/// `HH:MM-HH:MM`.
+pub fn parse_window (s: &str) -> Result<(NaiveTime, NaiveTime), anyhow::Error> {
+    let (start, end) = s.split_once ('-')
+        .ok_or_else (|| anyhow::anyhow!("Invalid window {}, expected HH:MM-HH:MM", s))?;
+    Ok ((NaiveTime::parse_from_str (start.trim (), "%H:%M")?, NaiveTime::parse_from_str (end.trim (), "%H:%M")?))
+}
+
+impl Seeding {
+
+    pub fn new (daily_budget: Option<u64>, window: Option<(NaiveTime, NaiveTime)>, part_size: u64) -> Result<Seeding, anyhow::Error> {
+        if part_size < ONE_MB as u64 || !(part_size / ONE_MB as u64).is_power_of_two () || !part_size.is_multiple_of (ONE_MB as u64) {
+            return Err (anyhow::anyhow!("Part size {} is not a power of two megabytes", part_size));
+        }
+        if daily_budget.is_some_and (|budget| budget < part_size) {
+            return Err (anyhow::anyhow!("A daily budget smaller than the part size {} never uploads anything", part_size));
+        }
+        Ok (Seeding { daily_budget, window, part_size })
+    }
+
+    fn is_open (&self, at: DateTime<Utc>) -> bool {
+        match self.window {
+            Some ((start, end)) if start <= end => start <= at.time () && at.time () < end,
+            Some ((start, end)) => at.time () >= start || at.time () < end,
+            None => true
+        }
+    }
+
+    /// The first moment from `at` on the window is open.
+    fn next_open (&self, at: DateTime<Utc>) -> DateTime<Utc> {
+        match self.window {
+            Some ((start, _)) if !self.is_open (at) => {
+                let today = DateTime::<Utc>::from_utc (at.date ().naive_utc ().and_time (start), Utc);
+                if today > at { today } else { today + ChronoDuration::days (1) }
+            },
+            _ => at
+        }
+    }
+
+    /// When the next part may go, None if right away.
+    fn blocked_until (&self, seed: &Seed, at: DateTime<Utc>, part: u64) -> Option<DateTime<Utc>> {
+        if self.daily_budget.is_some_and (|budget| seed.uploaded_today + part > budget) {
+            let tomorrow = DateTime::<Utc>::from_utc ((at.date () + ChronoDuration::days (1)).naive_utc ().and_hms (0, 0, 0), Utc);
+            return Some (self.next_open (tomorrow));
+        }
+        let open = self.next_open (at);
+        if open > at { Some (open) } else { None }
+    }
+}
+
+/// Uploads (or resumes uploading) `archive_path` in parts. The daemon sleeps through closed windows and spent budgets
+/// until the archive is stored, a `--once` run returns None when its session is over and a later run resumes.
+/// Credentials AWS rejects are resolved anew once per part, a part failing otherwise is retried by the daemon and fails a `--once` run.
+pub async fn upload (config: &Config, seeding: &Seeding, client: &GlacierClient, kind: BackupKind,
+                     archive_path: &str, hash: &str, description: String) -> AnyResult<Option<ArchiveCreationOutput>> {
+    let (backups_directory, vault_name, wait) = (config.backups_directory.as_str (), config.aws_glacier_vault_name.as_str (), !config.once);
+    let size = fs::metadata (archive_path)?.len ();
+    let resumed = state::load (backups_directory)?.seeding
+        .filter (|seed| seed.archive == archive_path && seed.tree_hash == hash);
+    let mut seed = match resumed {
+        Some (seed) => {
+            info!("Resuming seeding {}, {} of {} bytes uploaded", archive_path, seed.uploaded, size);
+            seed
+        },
+        None => {
+            let output = client.initiate_multipart_upload (InitiateMultipartUploadInput {
+                account_id: upload::account_id (config),
+                archive_description: Some (description),
+                part_size: Some (seeding.part_size.to_string ()),
+                vault_name: String::from (vault_name),
+            }).await.map_err (|err| upload::glacier_error (archive_path, err))?;
+            let seed = Seed {
+                kind,
+                archive: String::from (archive_path),
+                tree_hash: String::from (hash),
+                upload_id: output.upload_id.ok_or_else (|| anyhow::anyhow!("Glacier returned no upload id"))?,
+                part_size: seeding.part_size,
+                uploaded: 0,
+                day: Utc::today ().naive_utc (),
+                uploaded_today: 0,
+            };
+            info!("Seeding {} ({} bytes) in parts of {} bytes", archive_path, size, seed.part_size);
+            state::update (backups_directory, |state| state.seeding = Some (seed.clone ()))?;
+            seed
+        }
+    };
+
+    let journal = Journal::start (archive_path);
+    let mut client = client.clone ();
+    let mut renewed = false;
+    let mut file = File::open (archive_path)?;
+    while seed.uploaded < size {
+        let now = Utc::now ();
+        if seed.day != now.date ().naive_utc () {
+            seed.day = now.date ().naive_utc ();
+            seed.uploaded_today = 0;
+        }
+        let length = seed.part_size.min (size - seed.uploaded);
+        if let Some (until) = seeding.blocked_until (&seed, now, length) {
+            if !wait {
+                info!("Seeding {} paused until {}, {} of {} bytes uploaded", archive_path, until, seed.uploaded, size);
+                return Ok (None);
+            }
+            let pause = (until - now).to_std ().unwrap_or_default ();
+            info!("Seeding {} paused for {}, {} of {} bytes uploaded", archive_path, human_duration (pause), seed.uploaded, size);
+            time::sleep (pause).await;
+            continue;
+        }
+
+        let mut part = vec! [0; length as usize];
+        file.seek (SeekFrom::Start (seed.uploaded))?;
+        file.read_exact (&mut part)?;
+        let mut hasher = TreeHasher::new ();
+        hasher.update (&part);
+        let checksum = to_hex_string (&hasher.finalize ().0);
+
+        let request = UploadMultipartPartInput {
+            account_id: upload::account_id (config),
+            body: Some (Bytes::from (part)),
+            checksum: Some (checksum.clone ()),
+            range: Some (format!("bytes {}-{}/*", seed.uploaded, seed.uploaded + length - 1)),
+            upload_id: seed.upload_id.clone (),
+            vault_name: String::from (vault_name),
+        };
+        let part = Part { part: seed.uploaded / seed.part_size + 1, offset: seed.uploaded, length, tree_hash: checksum };
+        // Glacier answers 204 No Content
+        let started = Instant::now ();
+        let result = match config.chaos.upload (part.part, &part.tree_hash, UploadMultipartPartError::InvalidParameterValue) {
+            Some (err) => Err (err),
+            None => client.upload_multipart_part (request).await
+        };
+        journal.record (part, started, &result, 204);
+        if let Err (err) = result {
+            warn!("Uploading bytes {}-{} of {} failed: {}", seed.uploaded, seed.uploaded + length - 1, archive_path, err);
+            if upload::is_credentials_error (&err) && !renewed {
+                // e.g. the session token expired partway through, the part is retried with new credentials
+                client = upload::renewed_client (config, kind, &aws::region ("glacier", &config.aws_region, &config.aws_glacier_endpoint)?).await?;
+                renewed = true;
+                continue;
+            }
+            if upload::is_credentials_error (&err) || !wait {
+                // the progress is kept, a later run resumes
+                return Err (upload::glacier_error (archive_path, err));
+            }
+            // the progress is kept, the part is retried
+            time::sleep (std::time::Duration::from_secs (60)).await;
+            continue;
+        }
+
+        renewed = false;
+        seed.uploaded += length;
+        seed.uploaded_today += length;
+        state::update (backups_directory, |state| state.seeding = Some (seed.clone ()))?;
+    }
+
+    let output = client.complete_multipart_upload (CompleteMultipartUploadInput {
+        account_id: upload::account_id (config),
+        archive_size: Some (size.to_string ()),
+        checksum: Some (String::from (hash)),
+        upload_id: seed.upload_id.clone (),
+        vault_name: String::from (vault_name),
+    }).await.map_err (|err| upload::glacier_error (archive_path, err))?;
+    state::update (backups_directory, |state| state.seeding = None)?;
+    info!("Seeding {} complete", archive_path);
+    Ok (Some (output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at (hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.ymd (2021, 3, 1).and_hms (hour, minute, 0)
+    }
+
+    fn seed (uploaded_today: u64) -> Seed {
+        Seed {
+            kind: BackupKind::Full,
+            archive: String::from ("backups/wordpress_backup_2021-03-01.tar.gz"),
+            tree_hash: String::new (),
+            upload_id: String::new (),
+            part_size: 1 << 20,
+            uploaded: uploaded_today,
+            day: at (0, 0).date ().naive_utc (),
+            uploaded_today,
+        }
+    }
+
+    #[test]
+    fn sizes_and_windows_are_parsed () {
+        assert_eq!(parse_size ("512").unwrap (), 512);
+        assert_eq!(parse_size ("64k").unwrap (), 64 << 10);
+        assert_eq!(parse_size ("512M").unwrap (), 512 << 20);
+        assert_eq!(parse_size (" 20G ").unwrap (), 20 << 30);
+        assert_eq!(parse_size ("1T").unwrap (), 1 << 40);
+        assert!(parse_size ("1.5G").is_err ());
+        assert!(parse_size ("G").is_err ());
+
+        assert_eq!(parse_window ("22:00 - 06:30").unwrap (), (NaiveTime::from_hms (22, 0, 0), NaiveTime::from_hms (6, 30, 0)));
+        assert!(parse_window ("22:00").is_err ());
+        assert!(parse_window ("22:00-25:00").is_err ());
+    }
+
+    #[test]
+    fn part_sizes_are_powers_of_two_megabytes () {
+        assert!(Seeding::new (None, None, 1 << 20).is_ok ());
+        assert!(Seeding::new (None, None, 64 << 20).is_ok ());
+        assert!(Seeding::new (None, None, 3 << 20).is_err ());
+        assert!(Seeding::new (None, None, 1 << 19).is_err ());
+        assert!(Seeding::new (Some (1 << 20), None, 8 << 20).is_err ());
+    }
+
+    #[test]
+    fn windows_may_wrap_around_midnight () {
+        let day = Seeding::new (None, Some (parse_window ("09:00-17:00").unwrap ()), 1 << 20).unwrap ();
+        assert!(!day.is_open (at (8, 59)));
+        assert!(day.is_open (at (9, 0)));
+        assert!(!day.is_open (at (17, 0)));
+        assert_eq!(day.next_open (at (8, 0)), at (9, 0));
+        assert_eq!(day.next_open (at (18, 0)), at (9, 0) + ChronoDuration::days (1));
+        assert_eq!(day.next_open (at (12, 0)), at (12, 0));
+
+        let night = Seeding::new (None, Some (parse_window ("22:00-06:00").unwrap ()), 1 << 20).unwrap ();
+        assert!(night.is_open (at (23, 0)));
+        assert!(night.is_open (at (5, 59)));
+        assert!(!night.is_open (at (6, 0)));
+        assert_eq!(night.next_open (at (12, 0)), at (22, 0));
+    }
+
+    #[test]
+    fn parts_wait_for_the_window_and_the_budget () {
+        let part = 1 << 20;
+        let unlimited = Seeding::new (None, None, part).unwrap ();
+        assert_eq!(unlimited.blocked_until (&seed (100 * part), at (12, 0), part), None);
+
+        let budgeted = Seeding::new (Some (4 * part), Some (parse_window ("22:00-06:00").unwrap ()), part).unwrap ();
+        assert_eq!(budgeted.blocked_until (&seed (3 * part), at (23, 0), part), None);
+        assert_eq!(budgeted.blocked_until (&seed (3 * part), at (12, 0), part), Some (at (22, 0)));
+        // a spent budget waits for the next day's window
+        assert_eq!(budgeted.blocked_until (&seed (4 * part), at (23, 0), part), Some (at (0, 0) + ChronoDuration::days (1)));
+
+        let daytime = Seeding::new (Some (4 * part), Some (parse_window ("09:00-17:00").unwrap ()), part).unwrap ();
+        assert_eq!(daytime.blocked_until (&seed (4 * part), at (10, 0), part), Some (at (9, 0) + ChronoDuration::days (1)));
+    }
+}
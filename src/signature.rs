@@ -0,0 +1,109 @@
+// Detached ed25519 signatures of archive tree hashes, stored next to the archive as `<archive>.sig`
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fs;
+
+pub const SIGNATURE_SUFFIX: &str = ".sig";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetachedSignature {
+    pub tree_hash: String,
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// The hex encoded 32 byte ed25519 secret key archives are signed with.
+#[derive(Clone)]
+pub enum SigningKey {
+    /// a key file, as written by `generate-signing-key`
+    File (String),
+    /// the key itself, as read from a secret store
+    Hex (String),
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt (&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SigningKey::File (path) => f.debug_tuple ("File").field (path).finish (),
+            SigningKey::Hex (_) => f.write_str ("Hex(<redacted>)")
+        }
+    }
+}
+
+impl SigningKey {
+    pub fn keypair (&self) -> Result<Keypair, anyhow::Error> {
+        match self {
+            SigningKey::File (path) => read_keypair (path),
+            SigningKey::Hex (key) => keypair (key).map_err (|why| anyhow::anyhow!("Invalid signing key from the secret store: {}", why))
+        }
+    }
+}
+
+/// Reads a secret key file, holding the hex encoded 32 byte ed25519 secret key.
+pub fn read_keypair (path: &str) -> Result<Keypair, anyhow::Error> {
+    let content = fs::read_to_string (path)
+        .map_err (|why| anyhow::anyhow!("Could not read signing key {}: {}", path, why))?;
+    keypair (&content).map_err (|why| anyhow::anyhow!("Invalid signing key {}: {}", path, why))
+}
+
+fn keypair (hex_key: &str) -> Result<Keypair, anyhow::Error> {
+    let secret = SecretKey::from_bytes (&hex::decode (hex_key.trim ())?)?;
+    let public = PublicKey::from (&secret);
+    Ok (Keypair { secret, public })
+}
+
+/// Creates a new secret key file, returns the hex encoded public key.
+pub fn generate_key (path: &str) -> Result<String, anyhow::Error> {
+    let keypair = Keypair::generate (&mut rand::rngs::OsRng);
+    fs::write (path, hex::encode (keypair.secret.as_bytes ()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions (path, fs::Permissions::from_mode (0o600))?;
+    }
+    Ok (hex::encode (keypair.public.as_bytes ()))
+}
+
+pub fn sign (keypair: &Keypair, tree_hash: &str) -> Result<DetachedSignature, anyhow::Error> {
+    let signature = keypair.sign (&hex::decode (tree_hash)?);
+    Ok (DetachedSignature {
+        tree_hash: String::from (tree_hash),
+        public_key: hex::encode (keypair.public.as_bytes ()),
+        signature: hex::encode (signature.to_bytes ()),
+    })
+}
+
+/// Checks that `signature` was made by `public_key` (hex) over `tree_hash`.
+/// The key in the signature itself is never trusted, it only has to match the configured one.
+pub fn verify (signature: &DetachedSignature, tree_hash: &str, public_key: &str) -> Result<(), anyhow::Error> {
+    if signature.tree_hash != tree_hash {
+        return Err (anyhow::anyhow!("Tree hash {} does not match the signed {}", tree_hash, signature.tree_hash));
+    }
+    if signature.public_key != public_key.trim () {
+        return Err (anyhow::anyhow!("Signed with key {}, expected {}", signature.public_key, public_key));
+    }
+
+    let public_key = PublicKey::from_bytes (&hex::decode (public_key.trim ())?)
+        .map_err (|why| anyhow::anyhow!("Invalid public key: {}", why))?;
+    let bytes = hex::decode (&signature.signature)?;
+    let ed25519_signature = Signature::try_from (bytes.as_slice ())
+        .map_err (|why| anyhow::anyhow!("Invalid signature: {}", why))?;
+
+    public_key.verify (&hex::decode (tree_hash)?, &ed25519_signature)
+        .map_err (|_| anyhow::anyhow!("Signature does not verify, the archive or its signature was tampered with"))
+}
+
+pub fn write_sidecar (archive_path: &str, signature: &DetachedSignature) -> Result<String, anyhow::Error> {
+    let path = format!("{}{}", archive_path, SIGNATURE_SUFFIX);
+    fs::write (&path, serde_json::to_vec_pretty (signature)?)?;
+    Ok (path)
+}
+
+pub fn read_sidecar (archive_path: &str) -> Result<DetachedSignature, anyhow::Error> {
+    let path = format!("{}{}", archive_path, SIGNATURE_SUFFIX);
+    let content = fs::read (&path)
+        .map_err (|why| anyhow::anyhow!("Could not read signature {}: {}", path, why))?;
+    Ok (serde_json::from_slice (&content)?)
+}
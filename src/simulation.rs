@@ -0,0 +1,294 @@
+// Replays the recorded runs against a proposed retention policy, for `policy simulate`: how many archives and how
+// many bytes every rule would have kept, locally and in Glacier, day by day, and what the Glacier storage would have cost.
+// The sizes come from the state, which keeps them for a bit over a year, and for older archives from their upload records;
+// before the first observed run of a kind, its runs are extrapolated from the observed ones (their average size, at the
+// scheduled interval), so a long window shows the steady state of the policy. The report says how much was observed.
+
+use crate::describe::human_size;
+use crate::kind::BackupKind;
+use crate::state::{Size, State};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+
+/// Glacier charges archives deleted earlier for the rest of this many days
+const MINIMUM_STORAGE_DAYS: i64 = 90;
+const GB: f64 = 1073741824.0;
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// days the local archives of each kind are kept for
+    pub rolling_periods: BTreeMap<BackupKind, u32>,
+    /// days uploaded archives are kept for, None keeps them indefinitely (as pruning does)
+    pub glacier_retention: Option<u32>,
+    /// USD per GB-month of Glacier storage
+    pub price: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Point {
+    pub at: DateTime<Utc>,
+    pub local_archives: usize,
+    pub local_bytes: u64,
+    pub glacier_archives: usize,
+    pub glacier_bytes: u64,
+    /// USD spent on Glacier storage since the start of the simulation
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub days: u32,
+    pub points: Vec<Point>,
+    /// successful runs replayed as recorded
+    pub recorded: usize,
+    /// runs assumed before the first recorded one
+    pub extrapolated: usize,
+    /// how many of the days had runs of each kind observed, and how many were extrapolated
+    pub coverage: BTreeMap<BackupKind, Coverage>,
+    /// USD a month for what Glacier holds at the end
+    pub monthly_cost: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coverage {
+    pub observed_days: u32,
+    pub extrapolated_days: u32,
+}
+
+struct Archive {
+    kind: BackupKind,
+    at: DateTime<Utc>,
+    size: u64,
+}
+
+/// The archive sizes of each kind, oldest first: those the state kept, the successful runs of its history before it kept them,
+/// and the `uploaded` archives (from their upload records) older than any of those.
+pub fn observed (state: &State, uploaded: &[(BackupKind, Size)]) -> BTreeMap<BackupKind, Vec<Size>> {
+    let mut observed = state.sizes.clone ();
+    for run in &state.history {
+        if let Some (size) = run.size {
+            let sizes = observed.entry (run.kind).or_default ();
+            if !sizes.iter ().any (|size| size.at == run.at) {
+                sizes.push (Size { at: run.at, size });
+            }
+        }
+    }
+    for (kind, size) in uploaded {
+        let sizes = observed.entry (*kind).or_default ();
+        if sizes.iter ().all (|observed| size.at < observed.at) {
+            sizes.push (*size);
+        }
+    }
+    observed.values_mut ().for_each (|sizes| sizes.sort_by_key (|size| size.at));
+    observed.retain (|_, sizes| !sizes.is_empty ());
+    observed
+}
+
+/// Simulates the `days` up to `today`, with a point every `every` days and on the last one.
+/// `intervals` are the scheduled ones, kinds without a schedule are replayed as recorded only.
+pub fn simulate (observed: &BTreeMap<BackupKind, Vec<Size>>,
+                 intervals: &BTreeMap<BackupKind, std::time::Duration>,
+                 policy: &Policy,
+                 today: DateTime<Utc>,
+                 days: u32,
+                 every: u32) -> Simulation {
+    let start = today - Duration::days (days as i64);
+    // archives from before the window still count while they are kept
+    let lookback = policy.rolling_periods.values ().copied ()
+        .chain (policy.glacier_retention)
+        .max ()
+        .unwrap_or (0);
+    let (archives, recorded, extrapolated) = replay (observed, intervals, start - Duration::days (lookback as i64), today);
+    let coverage = observed.iter ()
+        .filter_map (|(kind, sizes)| sizes.iter ().find (|size| size.at <= today).map (|first| (*kind, first.at)))
+        .map (|(kind, first)| {
+            let observed_days = (today - first.max (start)).num_days ().clamp (0, days as i64) as u32;
+            let extrapolated_days = if intervals.contains_key (&kind) { days - observed_days } else { 0 };
+            (kind, Coverage { observed_days, extrapolated_days })
+        })
+        .collect ();
+
+    let mut points = Vec::new ();
+    let mut cost = 0.0;
+    let mut glacier_bytes = 0;
+    for day in 0..=days {
+        let at = start + Duration::days (day as i64);
+        let mut point = Point { at, local_archives: 0, local_bytes: 0, glacier_archives: 0, glacier_bytes: 0, cost: 0.0 };
+        for archive in archives.iter ().filter (|archive| archive.at <= at) {
+            let age = (at - archive.at).num_days ();
+            let rolling_period = policy.rolling_periods.get (&archive.kind).copied ().unwrap_or (0) as i64;
+            if age < rolling_period {
+                point.local_archives += 1;
+                point.local_bytes += archive.size;
+            }
+            match policy.glacier_retention.map (i64::from) {
+                Some (retention) if age >= retention => {
+                    // deleted on this day, before the minimum storage duration
+                    if age == retention && retention < MINIMUM_STORAGE_DAYS && archive.at >= start {
+                        cost += archive.size as f64 / GB * policy.price * (MINIMUM_STORAGE_DAYS - retention) as f64 / 30.0;
+                    }
+                },
+                _ => {
+                    point.glacier_archives += 1;
+                    point.glacier_bytes += archive.size;
+                }
+            }
+        }
+        if day > 0 {
+            cost += point.glacier_bytes as f64 / GB * policy.price / 30.0;
+        }
+        point.cost = cost;
+        glacier_bytes = point.glacier_bytes;
+        if day % every.max (1) == 0 || day == days {
+            points.push (point);
+        }
+    }
+
+    Simulation {
+        days,
+        points,
+        recorded,
+        extrapolated,
+        coverage,
+        monthly_cost: glacier_bytes as f64 / GB * policy.price,
+    }
+}
+
+/// The successful runs up to `today` as archives, extrapolated back to `from`, oldest first.
+/// Returns how many were recorded and how many extrapolated.
+fn replay (observed: &BTreeMap<BackupKind, Vec<Size>>, intervals: &BTreeMap<BackupKind, std::time::Duration>, from: DateTime<Utc>, today: DateTime<Utc>)
+          -> (Vec<Archive>, usize, usize) {
+    let mut archives = Vec::new ();
+    let mut extrapolated = 0;
+    let mut recorded = 0;
+    for (kind, sizes) in observed {
+        let runs : Vec<(DateTime<Utc>, u64)> = sizes.iter ()
+            .filter (|size| size.at <= today)
+            .map (|size| (size.at, size.size))
+            .collect ();
+        let first = match runs.first () {
+            Some ((first, _)) => *first,
+            None => continue
+        };
+        // runs started by hand bunch up, only the schedule says how often the kind is backed up in the long run
+        let interval = intervals.get (kind)
+            .and_then (|interval| Duration::from_std (*interval).ok ())
+            .filter (|interval| *interval > Duration::zero ());
+        if let Some (interval) = interval {
+            let size = runs.iter ().map (|(_, size)| size).sum::<u64>() / runs.len () as u64;
+            let mut at = first - interval;
+            while at >= from {
+                archives.push (Archive { kind: *kind, at, size });
+                extrapolated += 1;
+                at = at - interval;
+            }
+        }
+        recorded += runs.len ();
+        archives.extend (runs.into_iter ().map (|(at, size)| Archive { kind: *kind, at, size }));
+    }
+    archives.sort_by_key (|archive| archive.at);
+    (archives, recorded, extrapolated)
+}
+
+pub fn report (simulation: &Simulation, policy: &Policy) -> String {
+    let mut lines = vec! [format!("{:<10} {:>6} {:>10} {:>8} {:>10} {:>12}", "DATE", "LOCAL", "SIZE", "GLACIER", "SIZE", "COST TO DATE")];
+    for point in &simulation.points {
+        lines.push (format!("{:<10} {:>6} {:>10} {:>8} {:>10} {:>12}",
+                            point.at.format ("%Y-%m-%d"),
+                            point.local_archives,
+                            human_size (point.local_bytes),
+                            point.glacier_archives,
+                            human_size (point.glacier_bytes),
+                            format!("${:.2}", point.cost)));
+    }
+
+    let local = policy.rolling_periods.iter ()
+        .map (|(kind, days)| format!("{} {} days", kind, days))
+        .collect::<Vec<_>>()
+        .join (", ");
+    let glacier = match policy.glacier_retention {
+        Some (days) => format!("for {} days", days),
+        None => String::from ("indefinitely")
+    };
+    lines.push (format!("Policy: locally {}; in Glacier {}", local, glacier));
+    lines.push (format!("Replayed {} recorded runs and {} extrapolated from them over {} days",
+                        simulation.recorded, simulation.extrapolated, simulation.days));
+    for (kind, coverage) in &simulation.coverage {
+        lines.push (format!("{}: {} days observed, {} extrapolated", kind, coverage.observed_days, coverage.extrapolated_days));
+    }
+    lines.push (format!("Glacier storage costs ${:.2} a month at the end, ${:.2} over the {} days, at ${} per GB-month",
+                        simulation.monthly_cost,
+                        simulation.points.last ().map_or (0.0, |point| point.cost),
+                        simulation.days,
+                        policy.price));
+    if policy.glacier_retention.is_none () {
+        lines.push (String::from ("Archives kept indefinitely which were uploaded before the simulated days are not counted"));
+    }
+    if policy.glacier_retention.is_some_and (|days| (days as i64) < MINIMUM_STORAGE_DAYS) {
+        lines.push (format!("Archives deleted from Glacier within {} days are charged for the rest of them", MINIMUM_STORAGE_DAYS));
+    }
+    lines.join ("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Run;
+    use chrono::TimeZone;
+
+    fn at (day: u32) -> DateTime<Utc> {
+        Utc.ymd (2021, 3, day).and_hms (2, 0, 0)
+    }
+
+    fn run (kind: BackupKind, at: DateTime<Utc>, size: Option<u64>) -> Run {
+        Run { kind, at, outcome: String::from ("success"), class: None, size, suspicious: None }
+    }
+
+    #[test]
+    fn observed_merges_the_sizes_history_and_upload_records () {
+        let mut state = State::default ();
+        state.sizes.insert (BackupKind::Full, vec! [Size { at: at (10), size: 10 }, Size { at: at (11), size: 11 }]);
+        state.history = vec! [run (BackupKind::Full, at (11), Some (11)), run (BackupKind::Full, at (12), Some (12)),
+                              run (BackupKind::Full, at (13), None), run (BackupKind::Database, at (12), Some (1))];
+        let uploaded = [(BackupKind::Full, Size { at: at (2), size: 2 }), (BackupKind::Full, Size { at: at (10), size: 99 }),
+                        (BackupKind::Database, Size { at: at (1), size: 1 })];
+        let observed = observed (&state, &uploaded);
+        let sizes = |kind| observed [&kind].iter ().map (|size: &Size| size.size).collect::<Vec<_>>();
+        assert_eq!(sizes (BackupKind::Full), vec! [2, 10, 11, 12]);
+        assert_eq!(sizes (BackupKind::Database), vec! [1, 1]);
+        assert_eq!(observed.len (), 2);
+    }
+
+    #[test]
+    fn runs_before_the_observed_ones_are_extrapolated () {
+        let observed = BTreeMap::from ([(BackupKind::Full, vec! [Size { at: at (20), size: 100 }, Size { at: at (27), size: 300 }]),
+                                        (BackupKind::Database, vec! [Size { at: at (30), size: 5 }])]);
+        let intervals = BTreeMap::from ([(BackupKind::Full, std::time::Duration::from_secs (7 * 86400))]);
+        let policy = Policy { rolling_periods: BTreeMap::from ([(BackupKind::Full, 10)]), glacier_retention: None, price: 0.0 };
+        let simulation = simulate (&observed, &intervals, &policy, at (30), 28, 7);
+
+        assert_eq!(simulation.recorded, 3);
+        // weekly back to the 2nd minus the 10 day rolling period: the 13th, 6th, 27th and 20th of February
+        assert_eq!(simulation.extrapolated, 4);
+        assert_eq!(simulation.coverage [&BackupKind::Full], Coverage { observed_days: 10, extrapolated_days: 18 });
+        // without a schedule nothing is extrapolated
+        assert_eq!(simulation.coverage [&BackupKind::Database], Coverage { observed_days: 0, extrapolated_days: 0 });
+
+        let last = simulation.points.last ().unwrap ();
+        assert_eq!(last.at, at (30));
+        // the full backup of the 20th is 10 days old
+        assert_eq!((last.local_archives, last.local_bytes), (1, 300));
+        // the extrapolated ones of the average size
+        assert_eq!((last.glacier_archives, last.glacier_bytes), (7, 4 * 200 + 100 + 300 + 5));
+        assert_eq!(simulation.points.len (), 5);
+    }
+
+    #[test]
+    fn archives_leave_glacier_after_the_retention () {
+        let observed = BTreeMap::from ([(BackupKind::Full, (1..=30).map (|day| Size { at: at (day), size: 1 }).collect ())]);
+        let policy = Policy { rolling_periods: BTreeMap::new (), glacier_retention: Some (7), price: 1.0 };
+        let simulation = simulate (&observed, &BTreeMap::new (), &policy, at (30), 20, 1);
+        assert!(simulation.points.iter ().all (|point| point.glacier_archives == 7 && point.local_archives == 0));
+        assert!(simulation.points.last ().unwrap ().cost > 0.0);
+    }
+}
@@ -0,0 +1,156 @@
+// Backup destination hints kept with the site itself, for agencies embedding site metadata in WordPress:
+// `mer-de-glace.json` in the site root or the `mer_de_glace` row of wp_options, both JSON such as
+// `{"vault": "acme-backups", "region": "eu-west-1", "retention": "7y"}`, override the daemon's defaults for the site.
+
+use crate::restore::{mysql_command, MysqlTarget};
+use crate::{ssh, upload, Config};
+use log::{info, warn};
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const OVERRIDES_FILE: &str = "mer-de-glace.json";
+pub const OVERRIDES_OPTION: &str = "mer_de_glace";
+
+lazy_static! {
+    static ref TABLE_PREFIX_RE: Regex = Regex::new (r#"\$table_prefix\s*=\s*['"]([A-Za-z0-9_]+)['"]"#).unwrap ();
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overrides {
+    pub vault: Option<String>,
+    pub region: Option<String>,
+    /// the account owning the vault, e.g. the client's
+    pub account: Option<String>,
+    /// recorded in the Glacier archive description, for lifecycle tooling to act on
+    pub retention: Option<String>,
+}
+
+impl Overrides {
+    /// `other` wins where it has a value.
+    fn merge (self, other: Overrides) -> Overrides {
+        Overrides {
+            vault: other.vault.or (self.vault),
+            region: other.region.or (self.region),
+            account: other.account.or (self.account),
+            retention: other.retention.or (self.retention),
+        }
+    }
+}
+
+/// A file in the site root, on the web host when archiving over SSH.
+pub fn read (config: &Config, name: &str) -> io::Result<Vec<u8>> {
+    let path = Path::new (&config.wordpress_directory).join (name);
+    match &config.ssh {
+        Some (ssh) => ssh.read (&path),
+        None => fs::read (&path)
+    }
+}
+
+/// The table prefix in wp-config.php, `wp_` by default.
+pub fn table_prefix (config: &Config) -> String {
+    read (config, "wp-config.php").ok ()
+        .and_then (|content| TABLE_PREFIX_RE.captures (&String::from_utf8_lossy (&content)).map (|captures| captures [1].to_string ()))
+        .unwrap_or_else (|| String::from ("wp_"))
+}
+
+/// Runs `statement` against the site's database, returns the rows tab separated.
+pub fn query (config: &Config, statement: &str) -> Result<Vec<String>, anyhow::Error> {
+    let target = MysqlTarget {
+        host: config.mysql_host.clone (),
+        port: config.mysql_port.clone (),
+        user: config.mysql_user.clone (),
+        password: config.mysql_password.clone (),
+        database: config.mysql_database.clone (),
+    };
+    let mut command = mysql_command (&target, Some (&target.database));
+    command
+        .arg ("--batch")
+        .arg ("--skip-column-names")
+        .arg ("-e")
+        .arg (statement);
+    let output = ssh::wrap (config.ssh.as_ref (), command).output ()?;
+    if !output.status.success () {
+        return Err (anyhow::anyhow!("mysql failed: {}", String::from_utf8_lossy (&output.stderr).trim ()));
+    }
+    Ok (String::from_utf8_lossy (&output.stdout).lines ().map (String::from).filter (|line| !line.is_empty ()).collect ())
+}
+
+/// A value as printed by `mysql --batch`, which escapes backslashes, newlines, tabs and nul bytes.
+pub fn unescape (value: &str) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity (value.len ());
+    let mut bytes = value.bytes ();
+    while let Some (byte) = bytes.next () {
+        if byte != b'\\' {
+            unescaped.push (byte);
+            continue;
+        }
+        match bytes.next () {
+            Some (b'n') => unescaped.push (b'\n'),
+            Some (b't') => unescaped.push (b'\t'),
+            Some (b'0') => unescaped.push (0),
+            Some (other) => unescaped.push (other),
+            None => unescaped.push (b'\\')
+        }
+    }
+    unescaped
+}
+
+/// Directories of the active theme and, for a child theme, of its parent, relative to the wordpress directory.
+pub fn active_themes (config: &Config) -> Result<Vec<String>, anyhow::Error> {
+    let mut themes : Vec<String> = query (config, &format!("SELECT option_value FROM {}options WHERE option_name IN ('stylesheet', 'template') ORDER BY option_name DESC",
+                                                           table_prefix (config)))?
+        .into_iter ()
+        .filter (|theme| !theme.contains ('/') && theme != "." && theme != "..")
+        .map (|theme| format!("wp-content/themes/{}", theme))
+        .collect ();
+    themes.dedup ();
+    Ok (themes)
+}
+
+/// The site's hints, the file winning over the option.
+pub fn overrides (config: &Config) -> Result<Overrides, anyhow::Error> {
+    let rows = query (config, &format!("SELECT option_value FROM {}options WHERE option_name = '{}'",
+                                       table_prefix (config), OVERRIDES_OPTION))
+        .unwrap_or_else (|err| {
+            warn!("Could not read the {} option: {}", OVERRIDES_OPTION, err);
+            Vec::new ()
+        });
+    let option = match rows.first () {
+        // mysql --batch escapes newlines and tabs in values
+        Some (value) => serde_json::from_str (&value.replace ("\\n", "\n").replace ("\\t", "\t"))
+            .map_err (|err| anyhow::anyhow!("Invalid {} option: {}", OVERRIDES_OPTION, err))?,
+        None => Overrides::default ()
+    };
+
+    let path = Path::new (&config.wordpress_directory).join (OVERRIDES_FILE);
+    let file = match read (config, OVERRIDES_FILE) {
+        Ok (content) => serde_json::from_slice (&content).map_err (|err| anyhow::anyhow!("Invalid {}: {}", path.display (), err))?,
+        Err (err) if err.kind () == std::io::ErrorKind::NotFound => Overrides::default (),
+        Err (err) => return Err (err.into ())
+    };
+
+    Ok (option.merge (file))
+}
+
+/// Applies the site's hints to the daemon's configuration.
+pub fn apply (config: &mut Config) -> Result<(), anyhow::Error> {
+    let overrides = overrides (config)?;
+    if let Some (vault) = overrides.vault {
+        info!("The site overrides the vault {} with {}", config.aws_glacier_vault_name, vault);
+        config.aws_glacier_vault_name = vault;
+    }
+    if let Some (region) = overrides.region {
+        info!("The site overrides the region {} with {}", config.aws_region, region);
+        config.aws_region = region;
+    }
+    if let Some (account) = overrides.account {
+        info!("The site's vault belongs to account {}", account);
+        config.aws_glacier_account_id = Some (upload::parse_account_id (&account)?);
+    }
+    config.retention_tag = overrides.retention;
+    Ok (())
+}
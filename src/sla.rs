@@ -0,0 +1,94 @@
+// Backup freshness SLAs: a background check that the last successful backup of every kind is recent enough.
+// Catches the daemon that is running but never succeeding. Breaches are sent as notifications too, if configured.
+
+use crate::kind::BackupKind;
+use crate::notify::{self, Alert};
+use crate::rto::human_duration;
+use crate::{state, Config};
+use chrono::{DateTime, Utc};
+use fluent::fluent_args;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs (3600);
+
+#[derive(Debug, Clone)]
+pub struct Sla {
+    pub kind: BackupKind,
+    /// longest acceptable time since the last successful backup
+    pub max_age: Duration,
+}
+
+/// How badly an SLA is breached: 0 met, 1 breached, 2 breached by half again, 3 breached twice over.
+fn escalation (age: Duration, max_age: Duration) -> u8 {
+    let ratio = age.as_secs_f64 () / max_age.as_secs_f64 ();
+    if ratio >= 2.0 {
+        3
+    } else if ratio >= 1.5 {
+        2
+    } else if ratio >= 1.0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// Never returns, checks every SLA of `config` once an hour and logs and notifies whenever a breach escalates or is resolved.
+/// Kinds without any successful backup yet are measured from when the daemon started.
+pub async fn monitor (config: Config) {
+    let started = Utc::now ();
+    let mut levels : HashMap<BackupKind, u8> = HashMap::new ();
+    let mut interval = time::interval (CHECK_INTERVAL);
+
+    loop {
+        interval.tick ().await;
+
+        let state = match state::load (&config.backups_directory) {
+            Ok (state) => state,
+            Err (err) => {
+                warn!("Could not read the state to check SLAs: {}", err);
+                continue;
+            }
+        };
+
+        for sla in &config.slas {
+            let last_success : DateTime<Utc> = state.last_success.get (&sla.kind).copied ().unwrap_or (started);
+            let age = (Utc::now () - last_success).to_std ().unwrap_or_default ();
+            let level = escalation (age, sla.max_age);
+            let previous = levels.insert (sla.kind, level).unwrap_or (0);
+
+            let event = if level > previous {
+                let message = format!("SLA breached: last successful {} backup was {} ago, the SLA is {}",
+                                      sla.kind, human_duration (age), human_duration (sla.max_age));
+                match level {
+                    1 => warn!("{}", message),
+                    _ => error!("{} (escalation level {})", message, level)
+                }
+                "sla-breached"
+            } else if level == 0 && previous > 0 {
+                info!("SLA for {} backups is met again", sla.kind);
+                "sla-met"
+            } else {
+                continue;
+            };
+            if let Some (notifications) = &config.notifications {
+                notify::alert (notifications, &alert (&config, sla, event, age, level)).await;
+            }
+        }
+    }
+}
+
+/// The `sla-breached` or `sla-met` alert of `sla`, in the locale of `config`.
+fn alert (config: &Config, sla: &Sla, event: &'static str, age: Duration, level: u8) -> Alert {
+    let (kind, site, age_text, max_age) = (sla.kind.to_string (), config.site.as_str (), human_duration (age), human_duration (sla.max_age));
+    Alert {
+        event,
+        subject: config.locale.text (&format!("subject-{}", event), fluent_args!["kind" => kind.clone (), "site" => site]),
+        body: config.locale.text (&format!("email-{}", event), fluent_args!["kind" => kind.clone (), "site" => site, "age" => age_text,
+                                                                            "sla" => max_age, "level" => level]),
+        detail: serde_json::json!({ "site": site, "kind": kind, "outcome": event, "age_seconds": age.as_secs (),
+                                    "sla_seconds": sla.max_age.as_secs (), "escalation_level": level }),
+    }
+}
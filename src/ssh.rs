@@ -0,0 +1,153 @@
+// Archiving from a remote web host over SSH, for a daemon running on a separate backup box, configured as `SSH_SOURCE=backup@web1`.
+// The site is streamed by the remote `tar` and re-written into the local archive, mysqldump runs on the web host,
+// no agent is needed there: just ssh, tar and the mysql client tools.
+
+use crate::sanitize::Sanitizer;
+use crate::throttle::Throttle;
+use log::warn;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// exit status of a remote read of a missing file
+const NOT_FOUND: i32 = 44;
+
+#[derive(Debug, Clone)]
+pub struct Ssh {
+    /// `[user@]host`
+    pub destination: String,
+    pub port: Option<String>,
+    /// private key file, the ssh client's defaults otherwise
+    pub identity: Option<String>,
+}
+
+impl Ssh {
+    /// Runs `remote`, a shell command line, on the remote host.
+    pub fn command (&self, remote: &str) -> Command {
+        let mut command = Command::new ("ssh");
+        // never prompt for a password or host key from a daemon
+        command.arg ("-o").arg ("BatchMode=yes");
+        if let Some (port) = &self.port {
+            command.arg ("-p").arg (port);
+        }
+        if let Some (identity) = &self.identity {
+            command.arg ("-i").arg (identity);
+        }
+        command.arg (&self.destination).arg ("--").arg (remote);
+        command
+    }
+
+    /// The same program and arguments, run on the remote host.
+    pub fn wrap (&self, local: &Command) -> Command {
+        let remote = std::iter::once (local.get_program ())
+            .chain (local.get_args ())
+            .map (quote)
+            .collect::<Vec<_>>()
+            .join (" ");
+        self.command (&remote)
+    }
+
+    pub fn is_dir (&self, path: &Path) -> io::Result<bool> {
+        let status = self.command (&format!("test -d {}", quote (path.as_os_str ()))).status ()?;
+        match status.code () {
+            Some (0) => Ok (true),
+            Some (1) => Ok (false),
+            _ => Err (io::Error::other (format!("ssh {} failed with {}", self.destination, status)))
+        }
+    }
+
+    /// The content of a remote file, an error of kind `NotFound` when there is none.
+    pub fn read (&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = quote (path.as_os_str ());
+        let output = self.command (&format!("test -e {} || exit {}; cat -- {}", path, NOT_FOUND, path)).output ()?;
+        match output.status.code () {
+            Some (0) => Ok (output.stdout),
+            Some (NOT_FOUND) => Err (io::Error::new (io::ErrorKind::NotFound, format!("{} does not exist on {}", path, self.destination))),
+            _ => Err (io::Error::other (format!("reading {} on {} failed: {}", path, self.destination,
+                                                String::from_utf8_lossy (&output.stderr).trim ())))
+        }
+    }
+
+    /// When a remote file was last modified, None when it doesn't exist.
+    pub fn modified (&self, path: &Path) -> io::Result<Option<SystemTime>> {
+        let path = quote (path.as_os_str ());
+        let output = self.command (&format!("test -e {} || exit {}; stat -c %Y -- {}", path, NOT_FOUND, path)).output ()?;
+        match output.status.code () {
+            Some (0) => String::from_utf8_lossy (&output.stdout).trim ().parse::<u64>()
+                .map (|seconds| Some (UNIX_EPOCH + Duration::from_secs (seconds)))
+                .map_err (|err| io::Error::new (io::ErrorKind::InvalidData, format!("stat of {} on {}: {}", path, self.destination, err))),
+            Some (NOT_FOUND) => Ok (None),
+            _ => Err (io::Error::other (format!("stat of {} on {} failed: {}", path, self.destination,
+                                                String::from_utf8_lossy (&output.stderr).trim ())))
+        }
+    }
+
+    /// Appends the remote directory `source` (itself included) as `destination`.
+    /// Symlinks and hard links are followed, like the local walk does, entries are named by `sanitizer`.
+    pub fn append_tree<W: Write> (&self,
+                                  tar: &mut tar::Builder<W>,
+                                  source: &Path,
+                                  destination: &Path,
+                                  reproducible: bool,
+                                  throttle: &Throttle,
+                                  sanitizer: &Sanitizer)
+                                  -> io::Result<()> {
+
+        // GNU tar, which every web host running WordPress has
+        let remote = format!("tar -C {} -c -h --hard-dereference {}-f - .",
+                             quote (source.as_os_str ()),
+                             if reproducible { "--sort=name " } else { "" });
+        let mut child = self.command (&remote)
+            .stdout (Stdio::piped ())
+            .spawn ()?;
+
+        let stdout = child.stdout.take ().expect ("stdout is piped");
+        let mut remote_archive = tar::Archive::new (throttle.reader (stdout));
+        let appended = remote_archive.entries ().and_then (|entries| {
+            for entry in entries {
+                let entry = entry?;
+                let name = sanitizer.path (destination, &relative (&entry.path ()?));
+                let mut header = entry.header ().clone ();
+                tar.append_data (&mut header, name, entry)?;
+            }
+            Ok (())
+        });
+        // the remote side may be blocked writing, don't wait for it before it's told to stop
+        if appended.is_err () {
+            let _ = child.kill ();
+        }
+
+        let status = child.wait ()?;
+        appended?;
+        match status.code () {
+            Some (0) => Ok (()),
+            // files changed while being read, which the local walk doesn't mind either
+            Some (1) => {
+                warn!("Files under {} on {} changed while being archived", source.display (), self.destination);
+                Ok (())
+            },
+            _ => Err (io::Error::other (format!("remote tar of {} on {} failed with {}",
+                                                source.display (), self.destination, status)))
+        }
+    }
+}
+
+/// `./wp-content/index.php` as `wp-content/index.php`, `./` as empty.
+fn relative (path: &Path) -> PathBuf {
+    path.components ().filter (|component| !matches!(component, std::path::Component::CurDir)).collect ()
+}
+
+/// Single quoted for the remote shell.
+fn quote (argument: &OsStr) -> String {
+    format!("'{}'", argument.to_string_lossy ().replace ('\'', "'\\''"))
+}
+
+/// `local`, run on the remote host when there is one.
+pub fn wrap (ssh: Option<&Ssh>, local: Command) -> Command {
+    match ssh {
+        Some (ssh) => ssh.wrap (&local),
+        None => local
+    }
+}
@@ -0,0 +1,72 @@
+// Warm standby: after every full backup the wordpress files are rsynced and the dump is loaded into a standby host,
+// so failing over doesn't have to wait for a Glacier retrieval
+
+use log::{info, warn};
+use std::fs::File;
+use std::process::{Command, Output, Stdio};
+
+#[derive(Debug, Clone)]
+pub struct StandbyMysql {
+    pub host: String,
+    pub port: String,
+    pub user: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Standby {
+    /// rsync destination, e.g. `www-data@standby:/var/www/html`
+    pub rsync_target: Option<String>,
+    pub mysql: Option<StandbyMysql>,
+}
+
+/// Brings the standby up to date, failures are logged but never fail the backup itself.
+pub fn sync (standby: &Standby, wordpress_directory: &str, sql_dump_path: &str) {
+    if let Some (target) = &standby.rsync_target {
+        match sync_files (wordpress_directory, target) {
+            Ok (_) => info!("Synced {} to standby {}", wordpress_directory, target),
+            Err (err) => warn!("Could not sync files to standby {}: {}", target, err)
+        }
+    }
+
+    if let Some (mysql) = &standby.mysql {
+        match load_dump (mysql, sql_dump_path) {
+            Ok (_) => info!("Loaded {} into standby database at {}", sql_dump_path, mysql.host),
+            Err (err) => warn!("Could not load the dump into standby database at {}: {}", mysql.host, err)
+        }
+    }
+}
+
+fn sync_files (wordpress_directory: &str, target: &str) -> Result<(), anyhow::Error> {
+    let output : Output = Command::new ("rsync")
+        .arg ("-a")
+        .arg ("--delete")
+        .arg ("-e")
+        .arg ("ssh -o BatchMode=yes")
+        .arg (format!("{}/", wordpress_directory.trim_end_matches ('/')))
+        .arg (target)
+        .output ()?;
+    check (output, "rsync")
+}
+
+fn load_dump (mysql: &StandbyMysql, sql_dump_path: &str) -> Result<(), anyhow::Error> {
+    let output : Output = Command::new ("mysql")
+        .arg ("-h")
+        .arg (&mysql.host)
+        .arg ("--port")
+        .arg (&mysql.port)
+        .arg ("-u")
+        .arg (&mysql.user)
+        .arg (format!("-p{}", &mysql.password))
+        .stdin (Stdio::from (File::open (sql_dump_path)?))
+        .output ()?;
+    check (output, "mysql")
+}
+
+fn check (output: Output, program: &str) -> Result<(), anyhow::Error> {
+    if output.status.success () {
+        Ok (())
+    } else {
+        Err (anyhow::anyhow!("{} exited with {}: {}", program, output.status, String::from_utf8_lossy (&output.stderr).trim ()))
+    }
+}
@@ -0,0 +1,170 @@
+// State persisted across runs and restarts, kept as `state.json` in the backups directory.
+// The file is only ever replaced, never written in place, so reading it needs no lock: CLI commands read it while the daemon runs.
+// Updates are serialized across processes by `state.json.lock`, an update waits for another process's for `UPDATE_WAIT` at most.
+
+use crate::differential::Marks;
+use crate::error::{self, BackupError};
+use crate::kind::BackupKind;
+use crate::lock::{self, RunLock};
+use crate::seed::Seed;
+use crate::versions::VersionsAt;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const STATE_FILE: &str = "state.json";
+/// runs kept in the history, oldest are dropped first
+const HISTORY_LENGTH: usize = 100;
+/// days the archive sizes of each kind are kept for, the default window of `policy simulate` and then some
+const SIZES_DAYS: i64 = 400;
+pub const UPDATE_LOCK: &str = "state.json.lock";
+/// updates take milliseconds, waiting longer means the other process is stuck
+const UPDATE_WAIT: Duration = Duration::from_secs (10);
+
+lazy_static! {
+    // the backup kinds run concurrently, updates must not overwrite each other
+    static ref LOCK: Mutex<()> = Mutex::new (());
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    /// when the last backup of each kind was successfully uploaded
+    #[serde(default)]
+    pub last_success: BTreeMap<BackupKind, DateTime<Utc>>,
+    /// the latest runs, oldest first
+    #[serde(default)]
+    pub history: Vec<Run>,
+    /// file names of the archives being restored, with when the restore started
+    #[serde(default)]
+    pub restores: BTreeMap<String, DateTime<Utc>>,
+    /// versions live at every backup including the code, oldest first
+    #[serde(default)]
+    pub versions: Vec<VersionsAt>,
+    /// the initial full backup being seeded, if any
+    #[serde(default)]
+    pub seeding: Option<Seed>,
+    /// how far the last database dump went in the differential tables, if they are configured
+    #[serde(default)]
+    pub dump_marks: Option<Marks>,
+    /// the archive sizes of the successful runs of each kind, oldest first, for longer than the history goes back
+    #[serde(default)]
+    pub sizes: BTreeMap<BackupKind, Vec<Size>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Size {
+    pub at: DateTime<Utc>,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub kind: BackupKind,
+    pub at: DateTime<Utc>,
+    pub outcome: String,
+    /// the class of a failure, see `error::BackupError`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    /// the archive size of a successful run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// why a successful run looks wrong nevertheless, see `anomaly::SizeAnomaly`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspicious: Option<String>,
+}
+
+impl State {
+    pub fn record (&mut self, kind: BackupKind, outcome: &str) {
+        self.push (Run { kind, at: Utc::now (), outcome: String::from (outcome), class: None, size: None, suspicious: None });
+    }
+
+    pub fn record_success (&mut self, kind: BackupKind, size: u64, suspicious: Option<String>) {
+        let outcome = if suspicious.is_some () { "success, suspicious" } else { "success" };
+        let at = Utc::now ();
+        self.push (Run { kind, at, outcome: String::from (outcome), class: None, size: Some (size), suspicious });
+        let sizes = self.sizes.entry (kind).or_default ();
+        sizes.push (Size { at, size });
+        sizes.retain (|size| at - size.at <= ChronoDuration::days (SIZES_DAYS));
+    }
+
+    pub fn record_failure (&mut self, kind: BackupKind, err: &anyhow::Error) {
+        let class = error::of (err).map_or ("unclassified", BackupError::class);
+        self.push (Run { kind, at: Utc::now (), outcome: format!("failure, {:#}", err), class: Some (String::from (class)), size: None, suspicious: None });
+    }
+
+    fn push (&mut self, run: Run) {
+        self.history.push (run);
+        if self.history.len () > HISTORY_LENGTH {
+            self.history.drain (..self.history.len () - HISTORY_LENGTH);
+        }
+    }
+}
+
+pub fn load (backups_directory: &str) -> Result<State, anyhow::Error> {
+    let _guard = LOCK.lock ().unwrap ();
+    read (backups_directory)
+}
+
+/// Applies `f` to the persisted state, the file is replaced atomically.
+pub fn update<F: FnOnce (&mut State)> (backups_directory: &str, f: F) -> Result<State, anyhow::Error> {
+    let _guard = LOCK.lock ().unwrap ();
+    let _lock = lock_updates (backups_directory)?;
+    let mut state = read (backups_directory)?;
+    f (&mut state);
+
+    let path = Path::new (backups_directory).join (STATE_FILE);
+    let temporary = Path::new (backups_directory).join (format!("{}.{}.tmp", STATE_FILE, process::id ()));
+    fs::write (&temporary, serde_json::to_vec_pretty (&state)?)?;
+    fs::rename (&temporary, &path)?;
+    Ok (state)
+}
+
+/// Waits for other processes' updates to finish, fails rather than wait for a stuck one.
+fn lock_updates (backups_directory: &str) -> Result<RunLock, anyhow::Error> {
+    let path = Path::new (backups_directory).join (UPDATE_LOCK);
+    let started = Instant::now ();
+    loop {
+        if let Some (lock) = RunLock::acquire_file (path.clone ())? {
+            return Ok (lock);
+        }
+        if started.elapsed () > UPDATE_WAIT {
+            return Err (anyhow::anyhow!("Process {} has been updating {} for over {} seconds, try again once it's done (or stop it)",
+                                        lock::holder (&path).map_or (String::from ("unknown"), |pid| pid.to_string ()),
+                                        Path::new (backups_directory).join (STATE_FILE).display (), UPDATE_WAIT.as_secs ()));
+        }
+        thread::sleep (Duration::from_millis (20));
+    }
+}
+
+fn read (backups_directory: &str) -> Result<State, anyhow::Error> {
+    match fs::read (Path::new (backups_directory).join (STATE_FILE)) {
+        Ok (content) => Ok (serde_json::from_slice (&content)?),
+        Err (err) if err.kind () == std::io::ErrorKind::NotFound => Ok (State::default ()),
+        Err (err) => Err (err.into ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_outlast_the_history () {
+        let mut state = State::default ();
+        let old = Utc::now () - ChronoDuration::days (SIZES_DAYS + 1);
+        state.sizes.insert (BackupKind::Full, vec! [Size { at: old, size: 1 }]);
+        for _ in 0..HISTORY_LENGTH + 10 {
+            state.record_success (BackupKind::Database, 2, None);
+        }
+        state.record_success (BackupKind::Full, 3, None);
+        assert_eq!(state.history.len (), HISTORY_LENGTH);
+        assert_eq!(state.sizes [&BackupKind::Database].len (), HISTORY_LENGTH + 10);
+        assert_eq!(state.sizes [&BackupKind::Full].iter ().map (|size| size.size).collect::<Vec<_>>(), vec! [3]);
+    }
+}
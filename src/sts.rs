@@ -0,0 +1,167 @@
+// Per-run temporary credentials, configured as `STS_ROLE_ARN=arn:aws:iam::123456789012:role/backups`.
+// Every run assumes the role with a session policy allowing just the Glacier operations that run needs on the configured vault,
+// and publishing its outcome to the configured metrics namespace, event bus, topic and queue,
+// so credentials leaked from a run are short-lived and can't touch anything else, whatever the role itself allows.
+
+use crate::kind::BackupKind;
+use crate::provision;
+use crate::{aws, AnyResult, Config};
+use chrono::{DateTime, Utc};
+use log::info;
+use regex::Regex;
+use rusoto_core::credential::AwsCredentials;
+use rusoto_core::param::{Params, ServiceParams};
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::Region;
+use std::borrow::Cow;
+use std::time::Duration;
+
+/// the shortest session STS grants
+pub const MIN_DURATION: Duration = Duration::from_secs (900);
+
+lazy_static! {
+    static ref SESSION_NAME_RE: Regex = Regex::new (r"[^\w+=,.@-]").unwrap ();
+}
+
+#[derive(Debug, Clone)]
+pub struct Sts {
+    pub role_arn: String,
+    pub region: Region,
+    /// how long the credentials of a run are valid, a run outlasting them fails retryably
+    pub duration: Duration,
+}
+
+/// `config` using temporary credentials scoped to a `kind` run, or as it is without a role to assume.
+pub async fn scoped (config: &Config, kind: BackupKind) -> AnyResult<Cow<'_, Config>> {
+    let sts = match &config.sts {
+        Some (sts) => sts,
+        None => return Ok (Cow::Borrowed (config))
+    };
+    let credentials = assume_role (sts, &session_name (&config.site, kind), &session_policy (config, kind)).await
+        .map_err (|err| anyhow::anyhow!("Could not assume {}: {}", sts.role_arn, err))?;
+    info!("Assumed {} for the {} backup, until {}", sts.role_arn, kind,
+          credentials.expires_at ().map_or (String::from ("unknown"), |expires| expires.to_rfc3339 ()));
+    Ok (Cow::Owned (Config { credentials: Some (credentials), ..config.clone () }))
+}
+
+/// Just the vault, and just the operations a `kind` run calls.
+fn session_policy (config: &Config, kind: BackupKind) -> String {
+    let mut actions = vec! ["glacier:DescribeVault", "glacier:UploadArchive"];
+    // the vault of another account is that account's to create
+    if config.aws_glacier_account_id.is_none () {
+        actions.push ("glacier:CreateVault");
+    }
+    // the settings provisioned are read, and put back in the own vault
+    let own = config.aws_glacier_account_id.is_none ();
+    for setting in config.vault_provisioning.settings () {
+        actions.extend (match (setting, own) {
+            (provision::Setting::Tags, true) => &["glacier:ListTagsForVault", "glacier:AddTagsToVault"][..],
+            (provision::Setting::Tags, false) => &["glacier:ListTagsForVault"][..],
+            (provision::Setting::Notifications, true) => &["glacier:GetVaultNotifications", "glacier:SetVaultNotifications"][..],
+            (provision::Setting::Notifications, false) => &["glacier:GetVaultNotifications"][..],
+            (provision::Setting::AccessPolicy, true) => &["glacier:GetVaultAccessPolicy", "glacier:SetVaultAccessPolicy"][..],
+            (provision::Setting::AccessPolicy, false) => &["glacier:GetVaultAccessPolicy"][..],
+        });
+    }
+    // only the initial full backup may be seeded in parts
+    if kind == BackupKind::Full && config.seeding.is_some () {
+        actions.extend (&["glacier:InitiateMultipartUpload", "glacier:UploadMultipartPart", "glacier:CompleteMultipartUpload"]);
+    }
+    let mut statements = vec! [serde_json::json!({
+        "Effect": "Allow",
+        "Action": actions,
+        "Resource": format!("arn:{}:glacier:{}:{}:vaults/{}", aws::partition (&config.aws_region), config.aws_region, config.aws_glacier_account_id.as_deref ().unwrap_or ("*"),
+                            config.aws_glacier_vault_name),
+    })];
+    // the outcome of the run is published with its credentials too
+    if let Some (cloudwatch) = &config.cloudwatch {
+        if let Some (namespace) = &cloudwatch.namespace {
+            // metrics have no ARN, the namespace is what can be restricted
+            statements.push (serde_json::json!({
+                "Effect": "Allow",
+                "Action": "cloudwatch:PutMetricData",
+                "Resource": "*",
+                "Condition": { "StringEquals": { "cloudwatch:namespace": namespace } },
+            }));
+        }
+        if let Some (event_bus) = &cloudwatch.event_bus {
+            let arn = if event_bus.starts_with ("arn:") {
+                event_bus.clone ()
+            } else {
+                let region = cloudwatch.events_region.name ();
+                format!("arn:{}:events:{}:*:event-bus/{}", aws::partition (region), region, event_bus)
+            };
+            statements.push (serde_json::json!({ "Effect": "Allow", "Action": "events:PutEvents", "Resource": arn }));
+        }
+    }
+    if let Some (notifications) = &config.notifications {
+        if let Some (topic_arn) = &notifications.sns_topic_arn {
+            statements.push (serde_json::json!({ "Effect": "Allow", "Action": "sns:Publish", "Resource": topic_arn }));
+        }
+        if let Some (queue_url) = &notifications.sqs_queue_url {
+            statements.push (serde_json::json!({ "Effect": "Allow", "Action": "sqs:SendMessage", "Resource": queue_arn (queue_url, notifications.sqs_region.name ()) }));
+        }
+    }
+    serde_json::json!({
+        "Version": "2012-10-17",
+        "Statement": statements,
+    }).to_string ()
+}
+
+/// `arn:{partition}:sqs:{region}:{account}:{queue}` of the queue at `https://sqs.{region}.amazonaws.com/{account}/{queue}`.
+fn queue_arn (queue_url: &str, region: &str) -> String {
+    let partition = aws::partition (region);
+    let path = queue_url.split_once ("://").map_or (queue_url, |(_, rest)| rest);
+    let mut segments = path.split ('/').skip (1).filter (|segment| !segment.is_empty ());
+    match (segments.next (), segments.next ()) {
+        (Some (account), Some (queue)) => format!("arn:{}:sqs:{}:{}:{}", partition, region, account, queue),
+        _ => format!("arn:{}:sqs:{}:*:*", partition, region)
+    }
+}
+
+/// `mer-de-glace-{site}-{kind}`, within what STS accepts as a session name.
+fn session_name (site: &str, kind: BackupKind) -> String {
+    let name = SESSION_NAME_RE.replace_all (&format!("mer-de-glace-{}-{}", site, kind), "-").to_string ();
+    name.chars ().take (64).collect ()
+}
+
+async fn assume_role (sts: &Sts, session_name: &str, policy: &str) -> AnyResult<AwsCredentials> {
+    let mut params = Params::new ();
+    params.put ("Action", "AssumeRole");
+    params.put ("Version", "2011-06-15");
+    params.put ("RoleArn", &sts.role_arn);
+    params.put ("RoleSessionName", session_name);
+    params.put ("Policy", policy);
+    params.put ("DurationSeconds", sts.duration.as_secs () as i64);
+    let mut request = SignedRequest::new ("POST", "sts", &sts.region, "/");
+    request.set_params (params);
+    let response = aws::dispatch (None, request).await?;
+
+    let body = String::from_utf8_lossy (&response.body);
+    let expires_at = element (&body, "Expiration")?.parse::<DateTime<Utc>>()?;
+    Ok (AwsCredentials::new (element (&body, "AccessKeyId")?, element (&body, "SecretAccessKey")?, Some (element (&body, "SessionToken")?), Some (expires_at)))
+}
+
+/// The text of the first `<name>` element of an STS response.
+fn element (body: &str, name: &str) -> AnyResult<String> {
+    let start = body.find (&format!("<{}>", name)).map (|start| start + name.len () + 2);
+    let end = body.find (&format!("</{}>", name));
+    match (start, end) {
+        (Some (start), Some (end)) if start <= end => Ok (body [start..end].trim ().to_string ()),
+        _ => Err (anyhow::anyhow!("No {} in the STS response", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_arn_is_in_the_partition_of_the_region () {
+        assert_eq!(queue_arn ("https://sqs.eu-west-1.amazonaws.com/123456789012/backups", "eu-west-1"),
+                   "arn:aws:sqs:eu-west-1:123456789012:backups");
+        assert_eq!(queue_arn ("https://sqs.cn-north-1.amazonaws.com.cn/123456789012/backups", "cn-north-1"),
+                   "arn:aws-cn:sqs:cn-north-1:123456789012:backups");
+        assert_eq!(queue_arn ("https://sqs.us-gov-west-1.amazonaws.com/", "us-gov-west-1"), "arn:aws-us-gov:sqs:us-gov-west-1:*:*");
+    }
+}
@@ -0,0 +1,68 @@
+// Content directories symlinked to another volume, e.g. `wp-content/uploads -> /mnt/uploads`.
+// Their targets are archived under the logical path like any other directory, the links themselves are recorded
+// in the manifest so a restore can put them back rather than flattening them into plain directories.
+
+use crate::kind::BackupKind;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// where symlinked directories are looked for: wp-content and the directories right under it
+const CONTENT_DIRECTORY: &str = "wp-content";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Symlink {
+    /// the logical path, relative to the wordpress directory
+    pub path: String,
+    /// the link as it reads, possibly relative
+    pub target: String,
+    /// where it pointed to when archived
+    pub resolved: String,
+}
+
+/// The symlinked content directories archived by `kind`.
+/// A dangling one is an error: its volume is likely not mounted and the backup would silently miss its content.
+pub fn detect (wordpress_directory: &str, kind: BackupKind) -> Result<Vec<Symlink>, anyhow::Error> {
+    let root = Path::new (wordpress_directory);
+    let content = root.join (CONTENT_DIRECTORY);
+    let mut candidates = vec! [String::from (CONTENT_DIRECTORY)];
+    if content.is_dir () {
+        let mut children = fs::read_dir (&content)?
+            .map (|child| child.map (|child| format!("{}/{}", CONTENT_DIRECTORY, child.file_name ().to_string_lossy ())))
+            .collect::<Result<Vec<_>, _>>()?;
+        children.sort ();
+        candidates.extend (children);
+    }
+
+    let mut symlinks = Vec::new ();
+    for path in candidates {
+        // the kind's directories, what's in them and what they are in
+        let archived = kind.directories ().iter ()
+            .any (|directory| Path::new (&path).starts_with (directory) || Path::new (directory).starts_with (&path));
+        if !archived {
+            continue;
+        }
+
+        let source = root.join (&path);
+        if !fs::symlink_metadata (&source).is_ok_and (|metadata| metadata.file_type ().is_symlink ()) {
+            continue;
+        }
+        let target = fs::read_link (&source)?;
+        let resolved = match fs::canonicalize (&source) {
+            Ok (resolved) if resolved.is_dir () => resolved,
+            // a symlinked file is archived like any other
+            Ok (_) => continue,
+            Err (err) => return Err (anyhow::anyhow!("{} links to {}, which is missing ({}): is its volume mounted?",
+                                                     source.display (), target.display (), err))
+        };
+        info!("Archiving {} from {}", path, resolved.display ());
+        symlinks.push (Symlink {
+            path,
+            target: target.to_string_lossy ().to_string (),
+            resolved: resolved.to_string_lossy ().to_string (),
+        });
+    }
+
+    Ok (symlinks)
+}
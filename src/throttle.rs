@@ -0,0 +1,70 @@
+// Read throughput cap for the archiving phase, configured as `ARCHIVE_READ_LIMIT=20M` (bytes per second).
+// On slow spinning disks the tar phase's sequential reads otherwise starve the database of I/O.
+// The cap is shared by all walker threads and the tar writer, readers sleep until their bytes fit into it.
+
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Default)]
+pub struct Throttle {
+    limit: Option<Arc<Limit>>,
+}
+
+struct Limit {
+    bytes_per_second: u64,
+    /// when the bytes consumed so far are paid for
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    /// No cap when `bytes_per_second` is `None`.
+    pub fn new (bytes_per_second: Option<u64>) -> Self {
+        Throttle {
+            limit: bytes_per_second.map (|bytes_per_second| Arc::new (Limit {
+                bytes_per_second: bytes_per_second.max (1),
+                next: Mutex::new (Instant::now ()),
+            })),
+        }
+    }
+
+    /// Accounts for `bytes` just read, sleeping for as long as they exceed the cap.
+    pub fn consume (&self, bytes: usize) {
+        let limit = match &self.limit {
+            Some (limit) => limit,
+            None => return,
+        };
+
+        let now = Instant::now ();
+        let until = {
+            let mut next = limit.next.lock ().unwrap ();
+            // idle time is not saved up for a burst later
+            let start = (*next).max (now);
+            *next = start + Duration::from_secs_f64 (bytes as f64 / limit.bytes_per_second as f64);
+            *next
+        };
+
+        if until > now {
+            thread::sleep (until - now);
+        }
+    }
+
+    pub fn reader<R: Read> (&self, inner: R) -> Throttled<R> {
+        Throttled { inner, throttle: self.clone () }
+    }
+}
+
+/// A reader whose reads count against a [`Throttle`].
+pub struct Throttled<R> {
+    inner: R,
+    throttle: Throttle,
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read (buf)?;
+        self.throttle.consume (read);
+        Ok (read)
+    }
+}
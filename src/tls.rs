@@ -0,0 +1,95 @@
+// TLS for the admin server, configured as `ADMIN_TLS_CERT` and `ADMIN_TLS_KEY` (PEM, the certificate file may hold the chain),
+// or `ADMIN_TLS=self-signed` for a certificate generated at every start, whose SHA-256 fingerprint is logged for clients to pin.
+
+use crate::AnyResult;
+use log::info;
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509, X509NameBuilder};
+use std::fs;
+use std::net::SocketAddr;
+
+/// days a self-signed certificate is valid, it is replaced by every start anyway
+const SELF_SIGNED_DAYS: u32 = 365;
+
+#[derive(Debug, Clone)]
+pub enum Tls {
+    Files { cert: String, key: String },
+    SelfSigned,
+}
+
+/// The acceptor of TLS connections to `address`.
+pub fn acceptor (tls: &Tls, address: &SocketAddr) -> AnyResult<tokio_native_tls::TlsAcceptor> {
+    let (key, mut chain) = match tls {
+        Tls::Files { cert, key } => {
+            let chain = X509::stack_from_pem (&fs::read (cert).map_err (|why| anyhow::anyhow!("Could not read {}: {}", cert, why))?)?;
+            let key = PKey::private_key_from_pem (&fs::read (key).map_err (|why| anyhow::anyhow!("Could not read {}: {}", key, why))?)?;
+            (key, chain)
+        },
+        Tls::SelfSigned => {
+            let (key, cert) = self_signed (address)?;
+            info!("Generated a self-signed certificate for the admin server, SHA-256 fingerprint {}",
+                  hex::encode (cert.digest (MessageDigest::sha256 ())?));
+            (key, vec! [cert])
+        }
+    };
+    if chain.is_empty () {
+        return Err (anyhow::anyhow!("No certificate in ADMIN_TLS_CERT"));
+    }
+
+    let cert = chain.remove (0);
+    let mut intermediates = Stack::new ()?;
+    for ca in chain {
+        intermediates.push (ca)?;
+    }
+    // native-tls takes the identity as PKCS #12 only
+    let identity = Pkcs12::builder ()
+        .name ("mer-de-glace")
+        .pkey (&key)
+        .cert (&cert)
+        .ca (intermediates)
+        .build2 ("")?
+        .to_der ()?;
+    let acceptor = native_tls::TlsAcceptor::new (native_tls::Identity::from_pkcs12 (&identity, "")?)?;
+    Ok (tokio_native_tls::TlsAcceptor::from (acceptor))
+}
+
+/// A P-256 key and a certificate for it, valid for localhost and the address served on.
+fn self_signed (address: &SocketAddr) -> AnyResult<(PKey<Private>, X509)> {
+    let group = EcGroup::from_curve_name (Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key (EcKey::generate (&group)?)?;
+
+    let mut name = X509NameBuilder::new ()?;
+    name.append_entry_by_text ("CN", "mer-de-glace")?;
+    let name = name.build ();
+
+    let mut serial = BigNum::new ()?;
+    serial.rand (128, MsbOption::MAYBE_ZERO, false)?;
+    let serial = serial.to_asn1_integer ()?;
+    let (not_before, not_after) = (Asn1Time::days_from_now (0)?, Asn1Time::days_from_now (SELF_SIGNED_DAYS)?);
+
+    let mut cert = X509::builder ()?;
+    cert.set_version (2)?;
+    cert.set_serial_number (&serial)?;
+    cert.set_subject_name (&name)?;
+    cert.set_issuer_name (&name)?;
+    cert.set_pubkey (&key)?;
+    cert.set_not_before (&not_before)?;
+    cert.set_not_after (&not_after)?;
+    let mut alternative_names = SubjectAlternativeName::new ();
+    alternative_names.dns ("localhost").ip ("127.0.0.1").ip ("::1");
+    if !address.ip ().is_unspecified () {
+        alternative_names.ip (&address.ip ().to_string ());
+    }
+    let alternative_names = alternative_names.build (&cert.x509v3_context (None, None))?;
+    cert.append_extension (alternative_names)?;
+    cert.sign (&key, MessageDigest::sha256 ())?;
+    Ok ((key, cert.build ()))
+}
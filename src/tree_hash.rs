@@ -10,7 +10,7 @@ use std::io;
  * Constants and Types
  ****************************************************************/
 
-const ONE_MB: usize = 1048576;
+pub const ONE_MB: usize = 1048576;
 
 struct TreeHashStackFrame {
     level: u64,
@@ -23,7 +23,7 @@ struct TreeHashStackFrame {
 
 pub fn run_sha256(bytes: &[u8]) -> Vec<u8> {
     let mut sha256 = Sha256::new();
-    sha256.update(&bytes);
+    sha256.update(bytes);
     let result = sha256.finalize();
 
     result.iter().copied().collect()
@@ -88,44 +88,101 @@ fn collapse_stack(stack: &mut Vec<TreeHashStackFrame>, force: bool) {
     }
 }
 
+/* TreeHasher computes the tree hash incrementally, for data that is produced
+ * in arbitrarily sized pieces (eg: the output of a compressor) rather than read from a file.
+ */
+pub struct TreeHasher {
+    stack: Vec<TreeHashStackFrame>,
+    chunk: Vec<u8>,
+    // hashes of every 1 MB chunk, in order
+    leaves: Vec<Vec<u8>>
+}
+
+impl TreeHasher {
+
+    pub fn new() -> Self {
+        TreeHasher {
+            stack: Vec::with_capacity(32),
+            chunk: Vec::with_capacity(ONE_MB),
+            leaves: Vec::new()
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = std::cmp::min(ONE_MB - self.chunk.len(), data.len());
+            self.chunk.extend_from_slice(&data[..take]);
+            data = &data[take..];
+
+            if self.chunk.len() == ONE_MB {
+                self.push_chunk();
+            }
+        }
+    }
+
+    fn push_chunk(&mut self) {
+        let leaf = run_sha256(&self.chunk);
+        self.leaves.push(leaf.clone());
+        self.stack.push(TreeHashStackFrame {
+            level: 0,
+            bytes: leaf
+        });
+        self.chunk.clear();
+        collapse_stack(&mut self.stack, false);
+    }
+
+    /* the tree hash along with the leaf hashes it was computed from */
+    pub fn finalize(mut self) -> (Vec<u8>, Vec<Vec<u8>>) {
+        if !self.chunk.is_empty() || self.stack.is_empty() {
+            self.push_chunk();
+        }
+        collapse_stack(&mut self.stack, true);
+        // the last frame contains the entire data's hash
+        (self.stack.pop().unwrap().bytes, self.leaves)
+    }
+}
+
+/* tree_hash_of_leaves computes the tree hash from stored leaf hashes,
+ * (eg: of a megabyte aligned range of an archive) without the data they were computed from.
+ */
+pub fn tree_hash_of_leaves(leaves: &[Vec<u8>]) -> Vec<u8> {
+    let mut stack: Vec<TreeHashStackFrame> = Vec::with_capacity(32);
+    for leaf in leaves {
+        stack.push(TreeHashStackFrame {
+            level: 0,
+            bytes: leaf.clone()
+        });
+        collapse_stack(&mut stack, false);
+    }
+    collapse_stack(&mut stack, true);
+    stack.pop().map(|frame| frame.bytes).unwrap_or_else(|| run_sha256(&[]))
+}
+
 pub fn tree_hash(
-    // file : &mut File
     filename: &str
 ) -> Result<Vec<u8>, anyhow::Error> {
+    Ok(tree_hash_with_leaves(filename)?.0)
+}
 
-    // 32 should handle pretty large (several gb) files without reallocating
-    let mut stack: Vec<TreeHashStackFrame> = Vec::with_capacity(32);
-    let mut buf: [u8; ONE_MB] = [0; ONE_MB];
+pub fn tree_hash_with_leaves(
+    filename: &str
+) -> Result<(Vec<u8>, Vec<Vec<u8>>), anyhow::Error> {
+
+    let mut hasher = TreeHasher::new();
+    let mut buf: Vec<u8> = vec![0; ONE_MB];
     let mut read_from: Box<dyn io::Read> = Box::new(
-        // file
         File::open(filename)?
     );
 
     loop {
 
-        let bytes_read = read_from.read(&mut buf).unwrap();
+        let bytes_read = read_from.read(&mut buf)?;
         if bytes_read == 0 {
             break;
         }
 
-        // read a <= 1MB chunk, compute the sha256, and push onto the stack
-        let data_slice = &buf[0..bytes_read];
-
-        stack.push(TreeHashStackFrame {
-            level: 0,
-            bytes: run_sha256(&data_slice)
-        });
-
-        // then optimize the stack (collapse like-levels into a higher level)
-        collapse_stack(&mut stack, false);
+        hasher.update(&buf[0..bytes_read]);
     }
 
-    // force-combine the last bits (eg: promote frames that don't have a pair at their own level)
-    collapse_stack(&mut stack, true);
-
-    // the last frame contains the entire file's hash
-    match stack.pop() {
-        Some(final_frame) => Ok(final_frame.bytes),
-        None => panic!("Something went horribly wrong")
-    }
+    Ok(hasher.finalize())
 }
@@ -0,0 +1,293 @@
+// Getting archives into Glacier: the vault, the upload itself and archives earlier runs left un-uploaded
+
+use crate::aws;
+use crate::error::{BackupError, Classify};
+use crate::kind::BackupKind;
+use crate::journal::{self, Journal, Part};
+use crate::manifest::Manifest;
+use crate::outbox;
+use crate::provision;
+use crate::{archive_tree_hash, attest, local_archives, seed, signature, state, sts, upload_record, version, AnyResult, Config};
+use bytes::Bytes;
+use chrono::Utc;
+use log::{info, warn};
+use rusoto_core::credential::DefaultCredentialsProvider;
+use rusoto_core::request::HttpClient;
+use rusoto_core::{Client, Region, RusotoError};
+use rusoto_glacier::{Glacier, GlacierClient, DescribeVaultError, DescribeVaultInput, CreateVaultInput, UploadArchiveInput, UploadArchiveError, ArchiveCreationOutput};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::time::Instant;
+
+/// The Glacier archive description: JSON naming the archive `{site}/{kind}/{timestamp}`,
+/// so that archives of several sites sharing a vault can be told apart.
+pub fn description (manifest: &Manifest, signature: Option<&str>, retention: Option<&str>) -> AnyResult<String> {
+    let mut description = serde_json::json!({
+        "name": manifest.name (),
+        "site": manifest.site,
+        "kind": manifest.kind,
+        "created": manifest.created,
+        "tool": format!("mer-de-glace {}", version::LONG_VERSION),
+    });
+    if let Some (signature) = signature {
+        description ["signature"] = serde_json::json!(signature);
+    }
+    if let Some (retention) = retention {
+        description ["retention"] = serde_json::json!(retention);
+    }
+    Ok (serde_json::to_string (&description)?)
+}
+
+/// The AWS client of a run, to sign requests with its credentials.
+pub fn aws_client (config: &Config) -> AnyResult<Client> {
+    aws::client (config.credentials.as_ref ())
+}
+
+/// A Glacier client with the credentials of the run resolved anew: the role assumed again with temporary credentials,
+/// the provider chain re-resolved without.
+pub async fn renewed_client (config: &Config, kind: BackupKind, region: &Region) -> AnyResult<GlacierClient> {
+    let client = if config.sts.is_some () {
+        aws_client (&*sts::scoped (config, kind).await.classify (BackupError::Credentials)?)?
+    } else {
+        // the shared client keeps the provider chain, and whatever it cached, for the life of the process,
+        // a new provider resolves them again (env, profile, container or instance metadata)
+        Client::new_with (DefaultCredentialsProvider::new ()?, HttpClient::new ()?)
+    };
+    Ok (GlacierClient::new_with_client (client, region.clone ()))
+}
+
+/// The account owning the vault as the Glacier API takes it, `-` for the account of the credentials.
+pub fn account_id (config: &Config) -> String {
+    config.aws_glacier_account_id.clone ().unwrap_or_else (|| String::from ("-"))
+}
+
+/// A 12 digit AWS account id.
+pub fn parse_account_id (id: &str) -> AnyResult<String> {
+    if id.len () != 12 || !id.bytes ().all (|c| c.is_ascii_digit ()) {
+        return Err (anyhow::anyhow!("Invalid account id {}, expected the 12 digit id of the account owning the vault", id));
+    }
+    Ok (String::from (id))
+}
+
+/// Sends a finished archive to the vault and records where it went next to it.
+/// False while the initial full backup is being seeded and a later run has to resume.
+pub async fn upload (config: &Config, archive_path: &str, hash: &str, manifest: &Manifest, signature: Option<&str>, size: u64) -> AnyResult<bool> {
+    let region = aws::region ("glacier", &config.aws_region, &config.aws_glacier_endpoint)?;
+    let aws_client = aws_client (config)?;
+    let glacier_client = GlacierClient::new_with_client (aws_client.clone (), region.clone ());
+
+    ensure_vault (&glacier_client, config.aws_glacier_account_id.as_deref (), &config.aws_glacier_vault_name).await?;
+    provision::on_upload (&aws_client, &region, config.aws_glacier_account_id.as_deref (), &config.aws_glacier_vault_name, &config.vault_provisioning).await?;
+
+    let seeding = match &config.seeding {
+        Some (seeding) if manifest.kind == BackupKind::Full
+            && !state::load (&config.backups_directory)?.last_success.contains_key (&BackupKind::Full) => Some (seeding),
+        _ => None
+    };
+    let result = match seeding {
+        Some (seeding) => seed::upload (config, seeding, &glacier_client, manifest.kind, archive_path, hash,
+                                        description (manifest, signature, config.retention_tag.as_deref ())?).await,
+        None => send_to_glacier (archive_path,
+                                 hash,
+                                 description (manifest, signature, config.retention_tag.as_deref ())?,
+                                 &glacier_client,
+                                 &region,
+                                 manifest.kind,
+                                 config).await.map (Some)
+    };
+    let result = match result {
+        Ok (Some (output)) => output,
+        Ok (None) => return Ok (false),
+        Err (err) => {
+            warn!("Upload attempts of {} are in {}", archive_path, journal::path (archive_path));
+            return Err (err);
+        }
+    };
+
+    let archive_id = result.archive_id.unwrap_or_else(|| String::from ("unknown"));
+    info!("Archive succesfully stored in glacier with id: {}", &archive_id);
+
+    upload_record::UploadRecord {
+        region: region.name ().to_string (),
+        vault_name: config.aws_glacier_vault_name.clone (),
+        account_id: config.aws_glacier_account_id.clone (),
+        archive_id,
+        location: result.location,
+        tree_hash: String::from (hash),
+        size,
+        uploaded: Utc::now (),
+        name: Some (manifest.name ()),
+    }.write (archive_path)?;
+    Ok (true)
+}
+
+/// The manifest of a local archive and its signature if it was signed, for uploading it after the run that created it.
+pub fn archived (config: &Config, archive_path: &str) -> AnyResult<(Manifest, Option<String>)> {
+    let mut manifest = Manifest::read_from_archive (archive_path)?;
+    // it is in this site's backups directory
+    manifest.site.get_or_insert_with (|| config.site.clone ());
+    let signature = if Path::new (&format!("{}{}", archive_path, signature::SIGNATURE_SUFFIX)).exists () {
+        Some (signature::read_sidecar (archive_path)?.signature)
+    } else {
+        None
+    };
+    Ok ((manifest, signature))
+}
+
+/// Uploads archives of `kind` that have no upload record, i.e. were created by a run that failed or was killed before uploading them.
+/// Archives queued by `--enqueue` runs are the uploader's.
+pub async fn upload_pending (config: &Config, kind: BackupKind) -> AnyResult<()> {
+    for (archive_path, _) in local_archives (&config.backups_directory, kind)? {
+        if upload_record::UploadRecord::read (&archive_path)?.is_some () || outbox::Queued::read (&archive_path)?.is_some () {
+            continue;
+        }
+
+        info!("Archive {} was never uploaded, uploading it now", archive_path);
+        let hash = archive_tree_hash (&archive_path)?;
+        let (manifest, signature) = archived (config, &archive_path)?;
+        let size = fs::metadata (&archive_path)?.len ();
+        let seeded = state::load (&config.backups_directory)?.seeding.is_some_and (|seed| seed.archive == archive_path);
+        let started = Utc::now ();
+        if upload (config, &archive_path, &hash, &manifest, signature.as_deref (), size).await? && seeded {
+            attest::record (config, kind, "success, seeded", started, Some ((&archive_path, &hash)));
+            state::update (&config.backups_directory, |state| {
+                state.last_success.insert (kind, manifest.created);
+                state.record (kind, "success, seeded");
+            })?;
+        }
+    }
+    Ok (())
+}
+
+async fn send_to_glacier (file_path : &str,
+                          hash : &str,
+                          description : String,
+                          client : &GlacierClient,
+                          region : &Region,
+                          kind : BackupKind,
+                          config : &Config)
+                          -> AnyResult<ArchiveCreationOutput> {
+
+    let mut file : File = File::open(file_path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    let part = Part { part: 0, offset: 0, length: buffer.len () as u64, tree_hash: String::from (hash) };
+    let bytes : Bytes = Bytes::from (buffer);
+
+    let request = UploadArchiveInput {
+        account_id: account_id (config),
+        archive_description: Some (description),
+        body: Some (bytes),
+        checksum: Some (String::from (hash)),
+        vault_name: config.aws_glacier_vault_name.clone ()
+    };
+
+    let journal = Journal::start (file_path);
+    // Glacier answers 201 Created
+    let started = Instant::now ();
+    let result = match config.chaos.upload (part.part, hash, UploadArchiveError::InvalidParameterValue) {
+        Some (err) => Err (err),
+        None => client.upload_archive (request.clone ()).await
+    };
+    journal.record (part.clone (), started, &result, 201);
+    match result {
+        Ok (res) => Ok (res),
+        Err (err) if is_credentials_error (&err) => {
+            // e.g. the session token of the run expired, the upload is retried once with new ones
+            warn!("AWS credentials rejected when uploading {}, resolving them anew: {}", file_path, err);
+            let client = renewed_client (config, kind, region).await?;
+            let started = Instant::now ();
+            let result = client.upload_archive (request).await;
+            journal.record (part, started, &result, 201);
+            result.map_err (|err| glacier_error (file_path, err))
+        },
+        Err (err) => Err (upload_error (file_path, err))
+    }
+}
+
+/// A credentials error if AWS rejected them, an upload error otherwise.
+pub fn glacier_error<E: std::error::Error + 'static> (file_path: &str, err: RusotoError<E>) -> anyhow::Error {
+    if is_credentials_error (&err) {
+        credentials_error (file_path, err)
+    } else {
+        upload_error (file_path, err)
+    }
+}
+
+fn credentials_error<E: std::error::Error + 'static> (file_path: &str, err: RusotoError<E>) -> anyhow::Error {
+    BackupError::Credentials (format!("AWS credentials expired or rejected when uploading {} to glacier: {}", file_path, err)).into ()
+}
+
+fn upload_error<E: std::error::Error + 'static> (file_path: &str, err: RusotoError<E>) -> anyhow::Error {
+    let retryable = is_retryable (&err);
+    BackupError::Upload { message: format!("Error when uploading {} to glacier: {}", file_path, err), retryable }.into ()
+}
+
+/// True for failures that say nothing about the upload itself: the connection, throttling or AWS' side of things.
+fn is_retryable<E> (err : &RusotoError<E>) -> bool {
+    match err {
+        RusotoError::HttpDispatch (_) => true,
+        RusotoError::Unknown (response) => {
+            let body = String::from_utf8_lossy (&response.body);
+            response.status.is_server_error ()
+                || ["Throttling", "RequestTimeout", "SlowDown"].iter ().any (|code| body.contains (code))
+        },
+        _ => false
+    }
+}
+
+/// True for errors caused by missing, expired or otherwise rejected AWS credentials.
+pub fn is_credentials_error<E> (err : &RusotoError<E>) -> bool {
+    match err {
+        RusotoError::Credentials (_) => true,
+        RusotoError::Unknown (response) => {
+            let body = String::from_utf8_lossy (&response.body);
+            response.status.as_u16 () == 403
+                && ["ExpiredToken", "InvalidClientTokenId", "UnrecognizedClientException", "InvalidSignatureException"]
+                .iter ()
+                .any (|code| body.contains (code))
+        },
+        _ => false
+    }
+}
+
+/// Creates the vault unless it exists, or just checks it is there in the vault of another account: that account's to create,
+/// and to grant this one access to it in its access policy.
+pub async fn ensure_vault (client : &GlacierClient, account_id : Option<&str>, vault_name : &str) -> AnyResult<()> {
+
+    let request = DescribeVaultInput {
+        account_id: String::from (account_id.unwrap_or ("-")),
+        vault_name: String::from (vault_name),
+    };
+
+    match (client.describe_vault (request).await, account_id) {
+        (Ok (result), _) => {
+            info! ("Glacier vault exists: {:#?}", result);
+        },
+        (Err (RusotoError::Service (DescribeVaultError::ResourceNotFound (_))), Some (account_id)) => {
+            return Err (BackupError::Config (format!("Glacier vault {} of account {} not found, it is created by that account", vault_name, account_id)).into ());
+        },
+        (Err (err), Some (account_id)) => {
+            return Err (BackupError::Config (format!("Could not describe glacier vault {} of account {}, check that its access policy grants this account access: {}",
+                                                     vault_name, account_id, err)).into ());
+        },
+        (Err (err), None) => {
+            warn! ("Glacier vault {} not found: {:#?}", vault_name, err);
+            let request = CreateVaultInput {
+                account_id: "-".to_string(),
+                vault_name: String::from (vault_name),
+            };
+            match client.create_vault (request).await {
+                Ok (result) => {
+                    info! ("Created glacier vault: {:#?}", result);
+                },
+                Err (err) => {
+                    return Err (BackupError::upload (format!("Could not create glacier vault {}: {}", vault_name, err)).into ());
+                }
+            };
+        }
+    };
+
+    Ok (())
+}
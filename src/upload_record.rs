@@ -0,0 +1,45 @@
+// Where an archive was stored, kept next to the local archive as `<archive>.glacier`
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+pub const UPLOAD_RECORD_SUFFIX: &str = ".glacier";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadRecord {
+    pub region: String,
+    pub vault_name: String,
+    /// the account owning the vault, None for the account of the credentials
+    #[serde(default)]
+    pub account_id: Option<String>,
+    pub archive_id: String,
+    pub location: Option<String>,
+    pub tree_hash: String,
+    pub size: u64,
+    pub uploaded: DateTime<Utc>,
+    /// canonical `{site}/{kind}/{timestamp}` name, also in the archive description
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl UploadRecord {
+
+    /// Replaces the record at once, `list` may be reading it.
+    pub fn write (&self, archive_path: &str) -> Result<(), anyhow::Error> {
+        let path = format!("{}{}", archive_path, UPLOAD_RECORD_SUFFIX);
+        let temporary = format!("{}.tmp", path);
+        fs::write (&temporary, serde_json::to_vec_pretty (self)?)?;
+        fs::rename (&temporary, &path)?;
+        Ok (())
+    }
+
+    /// The record of a local archive, `None` if it was never uploaded (or the record was lost).
+    pub fn read (archive_path: &str) -> Result<Option<Self>, anyhow::Error> {
+        match fs::read (format!("{}{}", archive_path, UPLOAD_RECORD_SUFFIX)) {
+            Ok (content) => Ok (Some (serde_json::from_slice (&content)?)),
+            Err (err) if err.kind () == std::io::ErrorKind::NotFound => Ok (None),
+            Err (err) => Err (err.into ())
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Checks of an arbitrary local archive, e.g. one downloaded from the AWS console rather than created here:
+// its Glacier tree hash, that it reads as a tar.gz to the end, what its manifest says it contains,
+// and how it compares to the upload records in the backups directory.
+
+use crate::doctor::Check;
+use crate::manifest::{Manifest, MANIFEST_NAME};
+use crate::upload_record::{UploadRecord, UPLOAD_RECORD_SUFFIX};
+use crate::{signature, tree_hash};
+use flate2::read::GzDecoder;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Component, Path};
+
+/// The last line mysqldump writes, unless told to leave out comments
+const COMPLETED_MARKER: &str = "-- Dump completed";
+
+/// What was read through the archive.
+struct Contents {
+    entries: usize,
+    bytes: u64,
+    /// top level directories and files
+    roots: HashSet<String>,
+    /// sql dumps, and whether they end like a complete one
+    dumps: Vec<(String, bool)>,
+}
+
+/// Runs every check on the archive at `archive_path`, looking for its upload record in `backups_directory`.
+/// Checks depending on an earlier failed one are left out.
+pub fn run (archive_path: &str, backups_directory: &str, public_key: Option<&str>) -> Vec<Check> {
+    let mut checks = Vec::new ();
+
+    let (hash, size) = match tree_hash::tree_hash (archive_path).and_then (|hash| Ok ((tree_hash::to_hex_string (&hash), fs::metadata (archive_path)?.len ()))) {
+        Ok (hashed) => hashed,
+        Err (err) => {
+            checks.push (Check { name: "tree hash", result: Err (format!("could not read {}: {}", archive_path, err)) });
+            return checks;
+        }
+    };
+    checks.push (Check { name: "tree hash", result: Ok (format!("{} of {} bytes", hash, size)) });
+
+    let contents = match read_contents (archive_path) {
+        Ok (contents) => contents,
+        Err (err) => {
+            checks.push (Check { name: "structure", result: Err (format!("not a readable tar.gz: {}", err)) });
+            return checks;
+        }
+    };
+    checks.push (Check {
+        name: "structure",
+        result: Ok (format!("{} entries, {} bytes uncompressed", contents.entries, contents.bytes)),
+    });
+
+    let manifest = match Manifest::read_from_archive (archive_path) {
+        Ok (manifest) => manifest,
+        Err (err) => {
+            checks.push (Check { name: "manifest", result: Err (format!("unreadable: {}", err)) });
+            return checks;
+        }
+    };
+    checks.push (Check { name: "manifest", result: check_manifest (&manifest, &contents) });
+
+    if !contents.dumps.is_empty () {
+        let truncated : Vec<&str> = contents.dumps.iter ().filter (|(_, complete)| !complete).map (|(name, _)| name.as_str ()).collect ();
+        let result = if truncated.is_empty () {
+            Ok (format!("{} complete", contents.dumps.iter ().map (|(name, _)| name.as_str ()).collect::<Vec<_>>().join (", ")))
+        } else {
+            Err (format!("{} truncated, not ending with \"{}\"", truncated.join (", "), COMPLETED_MARKER))
+        };
+        checks.push (Check { name: "sql dump", result });
+    }
+
+    checks.push (Check { name: "record", result: check_record (archive_path, backups_directory, &manifest, &hash, size) });
+
+    if let Some (result) = check_signature (archive_path, backups_directory, &hash, public_key) {
+        checks.push (Check { name: "signature", result });
+    }
+
+    checks
+}
+
+/// Reads every entry to the end, which is what catches a truncated or corrupt archive.
+fn read_contents (archive_path: &str) -> Result<Contents, anyhow::Error> {
+    let mut archive = tar::Archive::new (GzDecoder::new (File::open (archive_path)?));
+    let mut contents = Contents { entries: 0, bytes: 0, roots: HashSet::new (), dumps: Vec::new () };
+
+    for entry in archive.entries ()? {
+        let mut entry = entry.map_err (|err| anyhow::anyhow!("entry {}: {}", contents.entries + 1, err))?;
+        let path = entry.path ()?.into_owned ();
+        if path.is_absolute () || path.components ().any (|component| component == Component::ParentDir) {
+            return Err (anyhow::anyhow!("entry {} would be extracted outside the restore directory", path.display ()));
+        }
+        let name = path.display ().to_string ();
+        let read = if name.starts_with ("dump_") && !name.contains ('/') {
+            let mut last_line = String::new ();
+            let mut read = 0;
+            for line in BufReader::new (&mut entry).split (b'\n') {
+                let line = line.map_err (|err| anyhow::anyhow!("{}: {}", name, err))?;
+                read += line.len () as u64 + 1;
+                // dumps are not necessarily valid utf-8
+                let line = String::from_utf8_lossy (&line);
+                if !line.trim ().is_empty () {
+                    last_line = line.into_owned ();
+                }
+            }
+            contents.dumps.push ((name.clone (), last_line.starts_with (COMPLETED_MARKER)));
+            read
+        } else {
+            io::copy (&mut entry, &mut io::sink ()).map_err (|err| anyhow::anyhow!("{}: {}", name, err))?
+        };
+        contents.entries += 1;
+        contents.bytes += read;
+        if let Some (Component::Normal (root)) = path.components ().next () {
+            contents.roots.insert (root.to_string_lossy ().to_string ());
+        }
+    }
+
+    // the end of archive marker comes before the end of the gzip stream, whose checksum is only verified once it's read
+    io::copy (&mut archive.into_inner (), &mut io::sink ()).map_err (|err| anyhow::anyhow!("past the last entry: {}", err))?;
+
+    if contents.entries == 0 {
+        return Err (anyhow::anyhow!("the archive is empty"));
+    }
+    Ok (contents)
+}
+
+/// Whatever the manifest lists is in the archive.
+fn check_manifest (manifest: &Manifest, contents: &Contents) -> Result<String, String> {
+    let mut missing = Vec::new ();
+    if let Some (dump) = manifest.sql_dump.as_ref ().filter (|dump| !dump.is_empty ()) {
+        if !contents.dumps.iter ().any (|(name, _)| name == dump) {
+            missing.push (dump.clone ());
+        }
+    }
+    if let Some (schema) = manifest.schema_dump.as_ref ().filter (|schema| !contents.roots.contains (*schema)) {
+        missing.push (schema.clone ());
+    }
+    if !manifest.kind.directories ().is_empty ()
+        && !manifest.wordpress_directory.is_empty ()
+        && !contents.roots.contains (&manifest.wordpress_directory) {
+        missing.push (format!("{}/", manifest.wordpress_directory));
+    }
+
+    let described = format!("{} (version {}{})", manifest.name (), manifest.manifest_version,
+                            if contents.roots.contains (MANIFEST_NAME) { "" } else { ", legacy archive without one" });
+    if missing.is_empty () {
+        Ok (described)
+    } else {
+        Err (format!("{} lists {} which the archive doesn't contain", described, missing.join (", ")))
+    }
+}
+
+/// Compares with the upload record of the same archive: next to it, under its file name in the backups directory,
+/// or any record of the same name or tree hash there. Having no record at all is not a failure.
+fn check_record (archive_path: &str, backups_directory: &str, manifest: &Manifest, hash: &str, size: u64) -> Result<String, String> {
+    let record = find_record (archive_path, backups_directory, manifest, hash)
+        .map_err (|err| format!("could not read the upload records: {}", err))?;
+    let (record_path, record) = match record {
+        Some (found) => found,
+        None => return Ok (format!("no upload record of this archive in {}", backups_directory))
+    };
+
+    if record.tree_hash != hash || record.size != size {
+        return Err (format!("{} records tree hash {} of {} bytes: this is not the archive uploaded as {}",
+                            record_path, record.tree_hash, record.size, record.archive_id));
+    }
+    Ok (format!("matches {}, uploaded to {}/{} on {} as {}",
+                record_path, record.region, record.vault_name, record.uploaded.to_rfc3339 (), record.archive_id))
+}
+
+fn find_record (archive_path: &str, backups_directory: &str, manifest: &Manifest, hash: &str) -> Result<Option<(String, UploadRecord)>, anyhow::Error> {
+    for candidate in candidates (archive_path, backups_directory) {
+        if let Some (record) = UploadRecord::read (&candidate)? {
+            return Ok (Some ((format!("{}{}", candidate, UPLOAD_RECORD_SUFFIX), record)));
+        }
+    }
+
+    let directory = match fs::read_dir (backups_directory) {
+        Ok (directory) => directory,
+        Err (err) if err.kind () == io::ErrorKind::NotFound => return Ok (None),
+        Err (err) => return Err (err.into ())
+    };
+    let name = manifest.name ();
+    for entry in directory {
+        let path = entry?.path ().display ().to_string ();
+        let archive = match path.strip_suffix (UPLOAD_RECORD_SUFFIX) {
+            Some (archive) => archive,
+            None => continue
+        };
+        if let Some (record) = UploadRecord::read (archive)? {
+            if record.tree_hash == hash || record.name.as_ref () == Some (&name) {
+                return Ok (Some ((path, record)));
+            }
+        }
+    }
+    Ok (None)
+}
+
+/// A signature found next to the archive or in the backups directory, checked if there's a public key to check it with.
+fn check_signature (archive_path: &str, backups_directory: &str, hash: &str, public_key: Option<&str>) -> Option<Result<String, String>> {
+    let signed = candidates (archive_path, backups_directory).into_iter ()
+        .find (|candidate| Path::new (&format!("{}{}", candidate, signature::SIGNATURE_SUFFIX)).exists ())?;
+    let sidecar = match signature::read_sidecar (&signed) {
+        Ok (sidecar) => sidecar,
+        Err (err) => return Some (Err (format!("{}{} is unreadable: {}", signed, signature::SIGNATURE_SUFFIX, err)))
+    };
+    Some (match public_key {
+        Some (public_key) => signature::verify (&sidecar, hash, public_key)
+            .map (|_| format!("valid, signed by {}", public_key))
+            .map_err (|err| err.to_string ()),
+        None => Ok (format!("signed by {}, set SIGNING_PUBLIC_KEY to verify it", sidecar.public_key))
+    })
+}
+
+/// Where the sidecars of the archive may be: next to it, or in the backups directory under the same file name.
+fn candidates (archive_path: &str, backups_directory: &str) -> Vec<String> {
+    let mut candidates = vec! [String::from (archive_path)];
+    if let Some (file_name) = Path::new (archive_path).file_name () {
+        let in_backups = Path::new (backups_directory).join (file_name).display ().to_string ();
+        if in_backups != archive_path {
+            candidates.push (in_backups);
+        }
+    }
+    candidates
+}
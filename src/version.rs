@@ -0,0 +1,48 @@
+// Version reporting and the opt-in check for newer releases
+
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use log::{info, warn};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const LONG_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"), " ", env!("BUILD_DATE"), ")");
+
+const RELEASES_URL: &str = "https://api.github.com/repos/fbielejec/mer-de-glace/releases/latest";
+
+/// Logs when a newer release than the running one is published.
+/// Never fails the caller, an unreachable GitHub is only worth a warning.
+pub async fn check_for_update () {
+    match latest_release ().await {
+        Ok (Some (latest)) if is_newer (&latest, VERSION) => {
+            info!("A newer mer-de-glace release is available: {} (running {})", latest, LONG_VERSION);
+        },
+        Ok (_) => info!("mer-de-glace {} is up to date", LONG_VERSION),
+        Err (err) => warn!("Could not check for mer-de-glace updates: {}", err)
+    }
+}
+
+async fn latest_release () -> Result<Option<String>, anyhow::Error> {
+    let client = Client::builder ().build::<_, Body>(HttpsConnector::new ());
+    let request = Request::get (RELEASES_URL)
+        .header ("User-Agent", format!("mer-de-glace/{}", VERSION))
+        .header ("Accept", "application/vnd.github.v3+json")
+        .body (Body::empty ())?;
+
+    let response = client.request (request).await?;
+    if !response.status ().is_success () {
+        return Err (anyhow::anyhow!("GitHub responded with {}", response.status ()));
+    }
+
+    let body = hyper::body::to_bytes (response.into_body ()).await?;
+    let release : serde_json::Value = serde_json::from_slice (&body)?;
+
+    Ok (release ["tag_name"].as_str ().map (|tag| tag.trim_start_matches ('v').to_string ()))
+}
+
+/// Compares dotted numeric versions, anything unparsable counts as 0.
+fn is_newer (candidate : &str, current : &str) -> bool {
+    let parse = |version : &str| -> Vec<u64> {
+        version.split ('.').map (|part| part.parse::<u64>().unwrap_or (0)).collect ()
+    };
+    parse (candidate) > parse (current)
+}
@@ -0,0 +1,140 @@
+// WordPress core, plugin and theme versions live when a backup was taken, read from the files
+// (the `$wp_version` in wp-includes/version.php and the plugin and theme file headers) as wp-cli does
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+/// WordPress only looks for file headers in the first 8 KB
+const HEADER_SIZE: u64 = 8192;
+
+lazy_static! {
+    static ref WP_VERSION_RE: Regex = Regex::new(r#"\$wp_version\s*=\s*['"]([^'"]+)['"]"#).unwrap();
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Versions {
+    pub core: Option<String>,
+    /// by plugin directory (or file, for single file plugins)
+    pub plugins: BTreeMap<String, String>,
+    /// by theme directory
+    pub themes: BTreeMap<String, String>,
+}
+
+/// The versions of one backup, kept in the state so they outlive the local archives.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionsAt {
+    pub created: DateTime<Utc>,
+    pub archive: String,
+    pub versions: Versions,
+}
+
+pub fn capture (wordpress_directory: &str) -> Versions {
+    let root = Path::new (wordpress_directory);
+    let core = fs::read_to_string (root.join ("wp-includes/version.php")).ok ()
+        .and_then (|content| WP_VERSION_RE.captures (&content).map (|captures| captures [1].to_string ()));
+
+    let mut plugins = BTreeMap::new ();
+    for (name, path) in children (&root.join ("wp-content/plugins")) {
+        let candidates = if path.is_dir () {
+            children (&path).into_iter ().map (|(_, path)| path).filter (|path| path.extension ().is_some_and (|extension| extension == "php")).collect ()
+        } else if path.extension ().is_some_and (|extension| extension == "php") {
+            vec! [path]
+        } else {
+            vec! []
+        };
+        // the plugin's main file is the one with a `Plugin Name` header
+        if let Some (version) = candidates.iter ().find_map (|file| header (file, "Plugin Name").and_then (|_| header (file, "Version"))) {
+            plugins.insert (name, version);
+        }
+    }
+
+    let mut themes = BTreeMap::new ();
+    for (name, path) in children (&root.join ("wp-content/themes")) {
+        let style = path.join ("style.css");
+        if let Some (version) = header (&style, "Theme Name").and_then (|_| header (&style, "Version")) {
+            themes.insert (name, version);
+        }
+    }
+
+    Versions { core, plugins, themes }
+}
+
+fn children (directory: &Path) -> Vec<(String, std::path::PathBuf)> {
+    let mut children = fs::read_dir (directory).into_iter ().flatten ()
+        .filter_map (|entry| entry.ok ())
+        .map (|entry| (entry.file_name ().to_string_lossy ().to_string (), entry.path ()))
+        .collect::<Vec<_>>();
+    children.sort ();
+    children
+}
+
+/// The value of a `Name: value` file header.
+fn header (path: &Path, name: &str) -> Option<String> {
+    let mut content = String::new ();
+    File::open (path).ok ()?.take (HEADER_SIZE).read_to_string (&mut content).ok ()?;
+    content.lines ()
+        .filter_map (|line| {
+            let line = line.trim_start_matches (|c: char| c.is_whitespace () || c == '*' || c == '#' || c == '@');
+            line.strip_prefix (name)?.trim_start ().strip_prefix (':').map (|value| value.trim ().to_string ())
+        })
+        .find (|value| !value.is_empty ())
+}
+
+/// What changed from `before` to `after`, e.g. `akismet 4.1.8 -> 4.1.9`.
+pub fn changes (before: &Versions, after: &Versions) -> Vec<String> {
+    let mut changes = Vec::new ();
+    if before.core != after.core {
+        changes.push (format!("wordpress {} -> {}", before.core.as_deref ().unwrap_or ("none"), after.core.as_deref ().unwrap_or ("none")));
+    }
+    for (kind, before, after) in &[("plugin", &before.plugins, &after.plugins), ("theme", &before.themes, &after.themes)] {
+        let names : std::collections::BTreeSet<&String> = before.keys ().chain (after.keys ()).collect ();
+        for name in names {
+            match (before.get (name), after.get (name)) {
+                (Some (old), Some (new)) if old != new => changes.push (format!("{} {} {} -> {}", kind, name, old, new)),
+                (None, Some (new)) => changes.push (format!("{} {} {} added", kind, name, new)),
+                (Some (old), None) => changes.push (format!("{} {} {} removed", kind, name, old)),
+                _ => {}
+            }
+        }
+    }
+    changes
+}
+
+/// Every backup with what changed since the previous one, or everything live at `at`.
+pub fn report (history: &[VersionsAt], at: Option<DateTime<Utc>>) -> String {
+    if let Some (at) = at {
+        return match history.iter ().rfind (|entry| entry.created <= at) {
+            Some (entry) => {
+                let versions = &entry.versions;
+                let mut lines = vec! [format!("As of the backup {} ({}):", entry.created.format ("%Y-%m-%d %H:%M"), entry.archive),
+                                      format!("wordpress {}", versions.core.as_deref ().unwrap_or ("unknown"))];
+                lines.extend (versions.plugins.iter ().map (|(name, version)| format!("plugin {} {}", name, version)));
+                lines.extend (versions.themes.iter ().map (|(name, version)| format!("theme {} {}", name, version)));
+                lines.join ("\n")
+            },
+            None => format!("No backup with versions recorded on or before {}", at.format ("%Y-%m-%d"))
+        };
+    }
+
+    let mut lines = Vec::new ();
+    let mut previous : Option<&Versions> = None;
+    for entry in history {
+        let changes = match previous {
+            Some (previous) => changes (previous, &entry.versions),
+            None => vec! [String::from ("first recorded")]
+        };
+        lines.push (format!("{}  wordpress {:<8} {:>3} plugins {:>3} themes  {}",
+                            entry.created.format ("%Y-%m-%d %H:%M"),
+                            entry.versions.core.as_deref ().unwrap_or ("unknown"),
+                            entry.versions.plugins.len (),
+                            entry.versions.themes.len (),
+                            if changes.is_empty () { String::from ("unchanged") } else { changes.join (", ") }));
+        previous = Some (&entry.versions);
+    }
+    lines.join ("\n")
+}
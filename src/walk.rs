@@ -0,0 +1,194 @@
+// Parallel directory walking for sites with very many small files.
+// Worker threads traverse the tree and read small files, entries reach the (sequential) tar writer through a bounded queue.
+// In reproducible mode the walk stays parallel but entries are written sorted by path, so the archive layout is deterministic.
+
+use crate::sanitize::Sanitizer;
+use crate::throttle::Throttle;
+use std::collections::VecDeque;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// files up to this size are read by the walkers, bigger ones are streamed by the tar writer
+const PREFETCH_LIMIT: u64 = 128 * 1024;
+/// entries in flight between the walkers and the tar writer
+const QUEUE_CAPACITY: usize = 256;
+
+struct Entry {
+    source: PathBuf,
+    name: PathBuf,
+    metadata: Metadata,
+    content: Option<Vec<u8>>,
+}
+
+struct Queue {
+    directories: VecDeque<(PathBuf, PathBuf)>,
+    /// directories taken from the queue whose listing is not finished yet
+    in_progress: usize,
+}
+
+/// Appends everything under `source` (but not `source` itself) as `destination`, walking it with `threads` threads.
+/// Entries are named by `sanitizer`, `destination` is taken as it is.
+pub fn append_tree<W: Write> (tar: &mut tar::Builder<W>,
+                              source: &Path,
+                              destination: &Path,
+                              threads: usize,
+                              reproducible: bool,
+                              throttle: &Throttle,
+                              sanitizer: &Sanitizer)
+                              -> io::Result<()> {
+
+    let (sender, receiver) = sync_channel::<io::Result<Entry>> (QUEUE_CAPACITY);
+    let queue = Arc::new ((Mutex::new (Queue {
+        directories: VecDeque::from (vec! [(source.to_path_buf (), destination.to_path_buf ())]),
+        in_progress: 0,
+    }), Condvar::new ()));
+
+    let workers : Vec<_> = (0..threads.max (1))
+        .map (|_| {
+            let (queue, sender, throttle, sanitizer) = (queue.clone (), sender.clone (), throttle.clone (), sanitizer.clone ());
+            // in reproducible mode entries are sorted before writing, reading content ahead would hold all of it in memory
+            thread::spawn (move || walk (&queue, &sender, !reproducible, &throttle, &sanitizer))
+        })
+        .collect ();
+    drop (sender);
+
+    let mut result = Ok (());
+    if reproducible {
+        let mut entries = Vec::new ();
+        for entry in receiver {
+            match entry {
+                Ok (entry) => entries.push (entry),
+                Err (err) => { result = Err (err); break; }
+            }
+        }
+        if result.is_ok () {
+            entries.sort_by (|a, b| a.name.cmp (&b.name));
+            result = entries.into_iter ().try_for_each (|entry| append (tar, entry, throttle));
+        }
+    } else {
+        for entry in receiver {
+            if let Err (err) = entry.and_then (|entry| append (tar, entry, throttle)) {
+                result = Err (err);
+                break;
+            }
+        }
+    }
+
+    // stop the walkers early on error, they give up once the queue has nobody listening
+    {
+        let (lock, condvar) = &*queue;
+        let mut queue = lock.lock ().unwrap ();
+        if result.is_err () {
+            queue.directories.clear ();
+        }
+        condvar.notify_all ();
+    }
+    for worker in workers {
+        let _ = worker.join ();
+    }
+
+    result
+}
+
+fn walk (queue: &(Mutex<Queue>, Condvar), sender: &SyncSender<io::Result<Entry>>, prefetch: bool, throttle: &Throttle, sanitizer: &Sanitizer) {
+    let (lock, condvar) = queue;
+    loop {
+        let (source, name) = {
+            let mut state = lock.lock ().unwrap ();
+            loop {
+                if let Some (directory) = state.directories.pop_front () {
+                    state.in_progress += 1;
+                    break directory;
+                }
+                if state.in_progress == 0 {
+                    condvar.notify_all ();
+                    return;
+                }
+                state = condvar.wait (state).unwrap ();
+            }
+        };
+
+        let listed = list (&source, &name, prefetch, sender, queue, throttle, sanitizer);
+
+        let mut state = lock.lock ().unwrap ();
+        state.in_progress -= 1;
+        condvar.notify_all ();
+        drop (state);
+
+        if let Err (err) = listed {
+            let _ = sender.send (Err (err));
+            return;
+        }
+    }
+}
+
+fn list (source: &Path,
+         name: &Path,
+         prefetch: bool,
+         sender: &SyncSender<io::Result<Entry>>,
+         queue: &(Mutex<Queue>, Condvar),
+         throttle: &Throttle,
+         sanitizer: &Sanitizer)
+         -> io::Result<()> {
+
+    for child in fs::read_dir (source)? {
+        let child = child?;
+        let child_source = child.path ();
+        let child_name = sanitizer.child (name, &child.file_name ());
+        // symlinks are followed, like tar::Builder does by default
+        let metadata = fs::metadata (&child_source)?;
+
+        if metadata.is_dir () {
+            let (lock, condvar) = queue;
+            lock.lock ().unwrap ().directories.push_back ((child_source.clone (), child_name.clone ()));
+            condvar.notify_one ();
+        } else if !metadata.is_file () {
+            log::warn!("Skipping special file {}", child_source.display ());
+            continue;
+        }
+
+        let content = if prefetch && metadata.is_file () && metadata.len () <= PREFETCH_LIMIT {
+            let content = fs::read (&child_source)?;
+            throttle.consume (content.len ());
+            Some (content)
+        } else {
+            None
+        };
+
+        let entry = Entry { source: child_source, name: child_name, metadata, content };
+        if sender.send (Ok (entry)).is_err () {
+            // the writer gave up
+            return Ok (());
+        }
+    }
+
+    Ok (())
+}
+
+fn append<W: Write> (tar: &mut tar::Builder<W>, entry: Entry, throttle: &Throttle) -> io::Result<()> {
+    if entry.metadata.is_dir () {
+        return tar.append_dir (&entry.name, &entry.source);
+    }
+
+    match entry.content {
+        Some (content) => {
+            let mut header = tar::Header::new_gnu ();
+            header.set_metadata (&entry.metadata);
+            header.set_size (content.len () as u64);
+            tar.append_data (&mut header, &entry.name, content.as_slice ())
+        },
+        None => append_file (tar, &entry.source, &entry.name, throttle)
+    }
+}
+
+/// Streams the file at `source` into the archive as `name`, following symlinks like `tar::Builder::append_path_with_name`.
+pub fn append_file<W: Write> (tar: &mut tar::Builder<W>, source: &Path, name: &Path, throttle: &Throttle) -> io::Result<()> {
+    let file = File::open (source)?;
+    let mut header = tar::Header::new_gnu ();
+    header.set_metadata (&file.metadata ()?);
+    tar.append_data (&mut header, name, throttle.reader (file))
+}